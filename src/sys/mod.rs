@@ -107,11 +107,14 @@ feature! {
     pub mod sendfile;
 }
 
-#[cfg(any(bsd, target_os = "linux"))]
-feature! {
-    #![feature = "sysvipc"]
-    pub mod sem;
-    pub mod shm;
+#[cfg(any(apple_targets, freebsdlike, linux_android))]
+feature! {
+    #![any(
+        feature = "sysvipc_msg",
+        feature = "sysvipc_sem",
+        feature = "sysvipc_shm"
+    )]
+    pub mod system_v;
 }
 
 pub mod signal;
@@ -1,2 +0,0 @@
-//! Safe wrapper around a SystemV semaphore
-//! 
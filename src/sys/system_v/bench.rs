@@ -0,0 +1,184 @@
+//! Measures of the performance of SystemV IPC on the current host
+//!
+//! [`probe`] runs a short benchmark of each family, so that deployment
+//! tooling can check new kernels or instances programmatically.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use nix::errno::Errno;
+//! # use nix::sys::system_v::bench;
+//! let probe = bench::probe()?;
+//! println!("attach: {:?}", probe.attach_latency);
+//! println!("ring round trip: {:?}", probe.ring_round_trip);
+//! # Ok::<(), Errno>(())
+//! ```
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::errno::Errno;
+use crate::Result;
+
+use super::channel::ring::{Consumer, Producer};
+use super::shm::{AttachAddress, Shm, ShmatFlag};
+use super::{IpcMode, Key};
+
+/// Number of attachments timed by [`probe`].
+const ATTACHES: u32 = 100;
+
+/// Number of values sent back and forth through the rings by [`probe`].
+const ROUND_TRIPS: u32 = 1000;
+
+/// Number of messages sent through the queue by [`probe`].
+#[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux"))]
+#[cfg(feature = "sysvipc_msg")]
+const MESSAGES: u32 = 10_000;
+
+/// Results of [`probe`].
+///
+/// The measures of the message queues only exist with the `sysvipc_msg`
+/// feature.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Probe {
+    /// Mean time to attach a segment of a page, and detach it.
+    pub attach_latency: Duration,
+    /// Mean time for a value to go through a
+    /// [`ring`](super::channel::ring) and come back through another one,
+    /// between two threads polling them.
+    pub ring_round_trip: Duration,
+    /// Messages of 64 bytes sent through a message queue per second, from
+    /// one thread to another.
+    #[cfg(any(
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "linux"
+    ))]
+    #[cfg(feature = "sysvipc_msg")]
+    pub queue_throughput: f64,
+}
+
+/// Measures the latency of attachments and rings, and the throughput of
+/// message queues, taking a fraction of a second.
+///
+/// The resources are created for the measure, and removed afterwards. The
+/// measures run between threads of the calling process, which go through
+/// the kernel and the segments like separate processes would.
+pub fn probe() -> Result<Probe> {
+    Ok(Probe {
+        attach_latency: attach_latency()?,
+        ring_round_trip: ring_round_trip()?,
+        #[cfg(any(
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        ))]
+        #[cfg(feature = "sysvipc_msg")]
+        queue_throughput: queue_throughput()?,
+    })
+}
+
+// -- Private --
+
+fn attach_latency() -> Result<Duration> {
+    let shm = Shm::<[u8; 4096]>::create_and_connect(
+        Key::IPC_PRIVATE,
+        IpcMode::owner_rw(),
+    )?;
+    let res = (|| {
+        let start = Instant::now();
+        for _ in 0..ATTACHES {
+            shm.attach(AttachAddress::Any, ShmatFlag::empty())?
+                .detach()?;
+        }
+        Ok(start.elapsed() / ATTACHES)
+    })();
+    shm.remove()?;
+    res
+}
+
+fn ring_round_trip() -> Result<Duration> {
+    let (mut ping, mut pong_rx) = ring_pair()?;
+    let (mut pong, mut ping_rx) = match ring_pair() {
+        Ok(rings) => rings,
+        Err(e) => {
+            ping.remove()?;
+            return Err(e);
+        }
+    };
+    let elapsed = thread::scope(|s| {
+        s.spawn(|| {
+            for _ in 0..ROUND_TRIPS {
+                let value = spin(|| pong_rx.pop());
+                spin(|| pong.push(value).ok());
+            }
+        });
+        let start = Instant::now();
+        for i in 0..ROUND_TRIPS {
+            spin(|| ping.push(i).ok());
+            spin(|| ping_rx.pop());
+        }
+        start.elapsed()
+    });
+    ping.remove()?;
+    pong.remove()?;
+    Ok(elapsed / ROUND_TRIPS)
+}
+
+#[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux"))]
+#[cfg(feature = "sysvipc_msg")]
+fn queue_throughput() -> Result<f64> {
+    use super::msg::{MessageQueue, MsgctlArg, MsgrcvFlag, MsgsndFlag};
+
+    let queue = MessageQueue::create(Key::IPC_PRIVATE, IpcMode::owner_rw())?;
+    let res = thread::scope(|s| {
+        let sender = s.spawn(|| {
+            for _ in 0..MESSAGES {
+                queue.msgsnd(1, &[0; 64], MsgsndFlag::empty())?;
+            }
+            Ok(())
+        });
+        let start = Instant::now();
+        for _ in 0..MESSAGES {
+            queue.msgrcv(0, 64, MsgrcvFlag::empty())?;
+        }
+        let elapsed = start.elapsed();
+        sender.join().unwrap_or(Err(Errno::EINTR))?;
+        Ok(f64::from(MESSAGES) / elapsed.as_secs_f64())
+    });
+    queue.msgctl(MsgctlArg::IPC_RMID)?;
+    res
+}
+
+/// Creates a ring at a free random key, and returns its two sides.
+fn ring_pair() -> Result<(Producer<u32, 64>, Consumer<u32, 64>)> {
+    const ATTEMPTS: usize = 16;
+
+    for _ in 0..ATTEMPTS {
+        let key = Key::random();
+        let producer = match Producer::create(key, IpcMode::owner_rw()) {
+            Err(Errno::EEXIST) => continue,
+            res => res?,
+        };
+        // The ring was just created with the same type and capacity
+        return match unsafe { Consumer::open(key) } {
+            Ok(consumer) => Ok((producer, consumer)),
+            Err(e) => {
+                producer.remove()?;
+                Err(e)
+            }
+        };
+    }
+    Err(Errno::EEXIST)
+}
+
+/// Calls `f` until it returns a value, yielding in between.
+fn spin<T>(mut f: impl FnMut() -> Option<T>) -> T {
+    loop {
+        if let Some(value) = f() {
+            return value;
+        }
+        thread::yield_now();
+    }
+}
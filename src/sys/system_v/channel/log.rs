@@ -0,0 +1,313 @@
+//! Log records sent from a process to a collector through a shared memory
+//! ring
+//!
+//! A [`ShmLogWriter`] pushes records into a [`ring`](super::ring) without
+//! any system call, so that logging never blocks the application: when the
+//! ring is full, the record is dropped and counted. A collector process
+//! drains the records with a [`ShmLogReader`].
+//!
+//! The ring has a single producer: each process logging to the collector
+//! needs its own ring, and thus its own key, and the collector reads every
+//! ring in turn.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use nix::errno::Errno;
+//! # use nix::sys::system_v::channel::log::*;
+//! # use nix::sys::system_v::{IpcMode, Key};
+//! #
+//! const MY_KEY: Key = Key::new(1337);
+//! let mut reader = ShmLogReader::<1024>::create(MY_KEY, IpcMode::owner_rw())?;
+//!
+//! // In the application process
+//! let mut writer = unsafe { ShmLogWriter::<1024>::open(MY_KEY) }?;
+//! writer.write(LogLevel::Info, "server", "listening on port 8080");
+//!
+//! // In the collector
+//! while let Some(entry) = reader.read() {
+//!     println!("{} {}: {}", entry.level, entry.target, entry.message);
+//! }
+//! # Ok::<(), Errno>(())
+//! ```
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::unistd::Pid;
+use crate::Result;
+
+use super::super::{IpcMode, Key, SysvSafe};
+use super::ring::{Consumer, Producer};
+
+/// Maximum length of the target of a record, in bytes. Longer targets are
+/// truncated.
+pub const MAX_TARGET_LEN: usize = 48;
+
+/// Maximum length of the message of a record, in bytes. Longer messages are
+/// truncated.
+pub const MAX_MESSAGE_LEN: usize = 192;
+
+/// Severity of a log record, from the most to the least severe.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(u8)]
+pub enum LogLevel {
+    /// A failure.
+    Error = 1,
+    /// A problem that did not cause a failure.
+    Warn,
+    /// A notable event.
+    Info,
+    /// Information useful to debug the application.
+    Debug,
+    /// Very detailed information.
+    Trace,
+}
+
+impl LogLevel {
+    fn from_raw(level: u8) -> Option<Self> {
+        match level {
+            1 => Some(Self::Error),
+            2 => Some(Self::Warn),
+            3 => Some(Self::Info),
+            4 => Some(Self::Debug),
+            5 => Some(Self::Trace),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+            Self::Trace => "TRACE",
+        })
+    }
+}
+
+/// A log record, as read by a [`ShmLogReader`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct LogEntry {
+    /// Severity of the record.
+    pub level: LogLevel,
+    /// Process that wrote the record.
+    pub pid: Pid,
+    /// Time at which the record was written.
+    pub time: SystemTime,
+    /// Part of the application the record comes from, such as a module.
+    pub target: String,
+    /// Content of the record.
+    pub message: String,
+}
+
+/// Writing side of a log ring, used by the application.
+///
+/// The segment is not removed when the handle is dropped, see
+/// [`ShmLogWriter::remove`].
+#[derive(Debug)]
+pub struct ShmLogWriter<const N: usize> {
+    producer: Producer<Record, N>,
+    dropped: u64,
+}
+
+impl<const N: usize> ShmLogWriter<N> {
+    /// Creates a new ring of `N` records, and returns its writer.
+    ///
+    /// Fails with [`Errno::EEXIST`](crate::errno::Errno::EEXIST) if a
+    /// segment already exists for `key`.
+    pub fn create(key: Key, mode: impl Into<IpcMode>) -> Result<Self> {
+        Ok(Self {
+            producer: Producer::create(key, mode)?,
+            dropped: 0,
+        })
+    }
+
+    /// Opens the writer of an existing ring.
+    ///
+    /// # Safety
+    ///
+    /// The ring must have been created by [`ShmLogWriter::create`] or
+    /// [`ShmLogReader::create`] with the same `N`, and no other writer of
+    /// the ring may be used at the same time.
+    pub unsafe fn open(key: Key) -> Result<Self> {
+        Ok(Self {
+            producer: unsafe { Producer::open(key) }?,
+            dropped: 0,
+        })
+    }
+
+    /// Writes a record, truncating `target` and `message` to
+    /// [`MAX_TARGET_LEN`] and [`MAX_MESSAGE_LEN`] bytes, and returns
+    /// whether it was written.
+    ///
+    /// The record is dropped if the ring is full, see
+    /// [`ShmLogWriter::dropped`].
+    pub fn write(
+        &mut self,
+        level: LogLevel,
+        target: &str,
+        message: &str,
+    ) -> bool {
+        let record = Record::new(level, target, message);
+        let written = self.producer.push(record).is_ok();
+        if !written {
+            self.dropped += 1;
+        }
+        written
+    }
+
+    /// Returns the number of records dropped because the ring was full.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Marks the segment of the ring for destruction, once both sides have
+    /// detached from it.
+    pub fn remove(&self) -> Result<()> {
+        self.producer.remove()
+    }
+}
+
+/// Reading side of a log ring, used by the collector.
+///
+/// The segment is not removed when the handle is dropped, see
+/// [`ShmLogReader::remove`].
+#[derive(Debug)]
+pub struct ShmLogReader<const N: usize> {
+    consumer: Consumer<Record, N>,
+}
+
+impl<const N: usize> ShmLogReader<N> {
+    /// Creates a new ring of `N` records, and returns its reader.
+    ///
+    /// Fails with [`Errno::EEXIST`](crate::errno::Errno::EEXIST) if a
+    /// segment already exists for `key`.
+    pub fn create(key: Key, mode: impl Into<IpcMode>) -> Result<Self> {
+        Ok(Self {
+            consumer: Consumer::create(key, mode)?,
+        })
+    }
+
+    /// Opens the reader of an existing ring.
+    ///
+    /// # Safety
+    ///
+    /// The ring must have been created by [`ShmLogWriter::create`] or
+    /// [`ShmLogReader::create`] with the same `N`, and no other reader of
+    /// the ring may be used at the same time.
+    pub unsafe fn open(key: Key) -> Result<Self> {
+        Ok(Self {
+            consumer: unsafe { Consumer::open(key) }?,
+        })
+    }
+
+    /// Reads the oldest record of the ring, if any.
+    ///
+    /// Records of an unknown level, which a writer of this crate never
+    /// writes, are skipped.
+    pub fn read(&mut self) -> Option<LogEntry> {
+        loop {
+            if let Some(entry) = self.consumer.pop()?.entry() {
+                return Some(entry);
+            }
+        }
+    }
+
+    /// Returns the number of records in the ring.
+    pub fn len(&self) -> usize {
+        self.consumer.len()
+    }
+
+    /// Returns whether the ring is empty.
+    pub fn is_empty(&self) -> bool {
+        self.consumer.is_empty()
+    }
+
+    /// Marks the segment of the ring for destruction, once both sides have
+    /// detached from it.
+    pub fn remove(&self) -> Result<()> {
+        self.consumer.remove()
+    }
+}
+
+// -- Private --
+
+/// Layout of a record in the ring.
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Record {
+    /// Nanoseconds since the Unix epoch.
+    time: u64,
+    pid: i32,
+    level: u8,
+    target_len: u8,
+    message_len: u16,
+    target: [u8; MAX_TARGET_LEN],
+    message: [u8; MAX_MESSAGE_LEN],
+}
+
+// Only made of numbers and bytes
+unsafe impl SysvSafe for Record {}
+
+impl fmt::Debug for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Record")
+            .field("time", &self.time)
+            .field("pid", &self.pid)
+            .field("level", &self.level)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Record {
+    fn new(level: LogLevel, target: &str, message: &str) -> Self {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos().try_into().unwrap_or(u64::MAX));
+        let target = truncate(target, MAX_TARGET_LEN);
+        let message = truncate(message, MAX_MESSAGE_LEN);
+        let mut record = Self {
+            time,
+            pid: std::process::id() as i32,
+            level: level as u8,
+            target_len: target.len() as u8,
+            message_len: message.len() as u16,
+            target: [0; MAX_TARGET_LEN],
+            message: [0; MAX_MESSAGE_LEN],
+        };
+        record.target[..target.len()].copy_from_slice(target.as_bytes());
+        record.message[..message.len()].copy_from_slice(message.as_bytes());
+        record
+    }
+
+    /// Decodes the record, unless its level is unknown.
+    fn entry(&self) -> Option<LogEntry> {
+        // The lengths are clamped, as the record comes from another process
+        let target_len = usize::from(self.target_len).min(MAX_TARGET_LEN);
+        let message_len = usize::from(self.message_len).min(MAX_MESSAGE_LEN);
+        Some(LogEntry {
+            level: LogLevel::from_raw(self.level)?,
+            pid: Pid::from_raw(self.pid),
+            time: UNIX_EPOCH + Duration::from_nanos(self.time),
+            target: String::from_utf8_lossy(&self.target[..target_len])
+                .into_owned(),
+            message: String::from_utf8_lossy(&self.message[..message_len])
+                .into_owned(),
+        })
+    }
+}
+
+/// Returns the longest prefix of `s` of at most `max` bytes that ends on a
+/// character boundary.
+fn truncate(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
@@ -0,0 +1,21 @@
+//! Channels sending values between processes
+//!
+//! * [`channel`]: a channel like [`std::sync::mpsc`], over a message queue.
+//! * [`ring`]: a lock-free ring buffer in a shared memory segment, between
+//!   one producer and one consumer.
+//! * [`log`]: log records sent to a collector process over a [`ring`].
+
+#[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux"))]
+#[cfg(feature = "sysvipc_msg")]
+mod mpsc;
+feature! {
+#![feature = "sysvipc_shm"]
+pub mod log;
+pub mod ring;
+}
+
+#[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux"))]
+feature! {
+#![feature = "sysvipc_msg"]
+pub use self::mpsc::{channel, Receiver, Sender};
+}
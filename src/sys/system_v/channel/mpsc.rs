@@ -0,0 +1,186 @@
+//! Multi-producer single-consumer channel over a message queue
+
+use std::mem;
+use std::time::Duration;
+
+use crate::errno::Errno;
+use crate::Result;
+
+use super::super::msg::{MessageQueue, MsgctlArg, MsggetFlag, TypeSelector};
+use super::super::{IpcMode, Key, SysvSafe};
+
+/// Type of the messages of a channel.
+const MTYPE: libc::c_long = 1;
+
+/// Creates a new channel on the message queue associated with `key`, and
+/// returns its two halves.
+///
+/// Other processes connect to the channel with [`Sender::open`] and
+/// [`Receiver::open`]. Like [`std::sync::mpsc::channel`], there may be many
+/// senders but only one receiver.
+///
+/// Fails with [`Errno::EEXIST`] if a queue already exists for `key`.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::channel::{self, Sender};
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// let (_, receiver) = channel::channel::<u64>(MY_KEY, IpcMode::owner_rw())?;
+///
+/// // In another process
+/// let sender = unsafe { Sender::<u64>::open(MY_KEY) }?;
+/// sender.send(42)?;
+///
+/// assert_eq!(receiver.recv()?, 42);
+/// # Ok::<(), Errno>(())
+/// ```
+pub fn channel<T: SysvSafe>(
+    key: Key,
+    mode: impl Into<IpcMode>,
+) -> Result<(Sender<T>, Receiver<T>)> {
+    // Only senders of `T` write to the new queue
+    let queue = unsafe { MessageQueue::create(key, mode)?.with_type() };
+    let sender = Sender {
+        queue: queue.clone(),
+    };
+    let receiver = Receiver { queue };
+    Ok((sender, receiver))
+}
+
+/// Sending half of a channel, see [`channel`].
+///
+/// The queue is not removed when the handle is dropped, see
+/// [`Sender::remove`].
+#[derive(Debug)]
+pub struct Sender<T> {
+    queue: MessageQueue<T>,
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<T: SysvSafe> Sender<T> {
+    /// Connects to an existing channel to send values on it.
+    ///
+    /// Fails with [`Errno::ENOENT`] if no queue exists for `key`.
+    ///
+    /// # Safety
+    ///
+    /// The channel must have been created by [`channel`] with the same type
+    /// `T`.
+    pub unsafe fn open(key: Key) -> Result<Self> {
+        Ok(Self {
+            queue: unsafe { open_queue(key)?.with_type() },
+        })
+    }
+
+    /// Sends `value` to the receiver, blocking while the queue is full.
+    ///
+    /// Fails with [`Errno::EIDRM`] if the channel is removed.
+    pub fn send(&self, value: T) -> Result<()> {
+        loop {
+            match self.queue.send(MTYPE, &value) {
+                Err(Errno::EINTR) => continue,
+                res => {
+                    // The receiver now owns the value
+                    if res.is_ok() {
+                        mem::forget(value);
+                    }
+                    return res;
+                }
+            }
+        }
+    }
+
+    /// Removes the queue of the channel, losing the values not received yet.
+    ///
+    /// See [`Receiver::remove`].
+    pub fn remove(&self) -> Result<()> {
+        remove_queue(&self.queue)
+    }
+}
+
+/// Receiving half of a channel, see [`channel`].
+///
+/// The queue is not removed when the handle is dropped, see
+/// [`Receiver::remove`].
+#[derive(Debug)]
+pub struct Receiver<T> {
+    queue: MessageQueue<T>,
+}
+
+impl<T: SysvSafe> Receiver<T> {
+    /// Connects to an existing channel to receive its values.
+    ///
+    /// Fails with [`Errno::ENOENT`] if no queue exists for `key`.
+    ///
+    /// # Safety
+    ///
+    /// The channel must have been created by [`channel`] with the same type
+    /// `T`, and no other receiver of the channel may be used at the same
+    /// time.
+    pub unsafe fn open(key: Key) -> Result<Self> {
+        Ok(Self {
+            queue: unsafe { open_queue(key)?.with_type() },
+        })
+    }
+
+    /// Receives a value, blocking until one is sent.
+    ///
+    /// Fails with [`Errno::EIDRM`] if the channel is removed.
+    pub fn recv(&self) -> Result<T> {
+        loop {
+            match self.queue.recv_filtered(TypeSelector::Exactly(MTYPE)) {
+                Err(Errno::EINTR) => continue,
+                res => return res.map(|message| message.data),
+            }
+        }
+    }
+
+    /// Receives a value if one is waiting, without blocking.
+    pub fn try_recv(&self) -> Result<Option<T>> {
+        let message =
+            self.queue.try_recv_filtered(TypeSelector::Exactly(MTYPE))?;
+        Ok(message.map(|message| message.data))
+    }
+
+    /// Receives a value, blocking for at most `timeout`, and returns `None`
+    /// if none was sent in time.
+    ///
+    /// Message queues cannot be waited for with a timeout, so the queue is
+    /// polled, sleeping longer and longer between attempts, up to 10ms. A
+    /// value may thus be received up to 10ms after it was sent.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<T>> {
+        let selector = TypeSelector::Exactly(MTYPE);
+        let message = self.queue.recv_timeout(selector, timeout)?;
+        Ok(message.map(|message| message.data))
+    }
+
+    /// Removes the queue of the channel, losing the values not received yet.
+    ///
+    /// Processes blocked sending or receiving fail with [`Errno::EIDRM`].
+    pub fn remove(&self) -> Result<()> {
+        remove_queue(&self.queue)
+    }
+}
+
+fn open_queue(key: Key) -> Result<MessageQueue> {
+    Ok(MessageQueue::msgget(
+        key,
+        MsggetFlag::empty(),
+        IpcMode::owner_rw(),
+    )?)
+}
+
+fn remove_queue<T>(queue: &MessageQueue<T>) -> Result<()> {
+    queue.msgctl(MsgctlArg::IPC_RMID).map(drop)
+}
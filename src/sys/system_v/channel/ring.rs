@@ -0,0 +1,321 @@
+//! Single-producer single-consumer ring buffer in a shared memory segment
+//!
+//! The ring holds up to `N` values of type `T`, and lives entirely in the
+//! segment: pushing and popping only touch atomics and the slots, without
+//! any system call. `N` must be a power of two, which is checked at compile
+//! time. The process creating the ring takes one side, with
+//! [`Producer::create`] or [`Consumer::create`], and another process opens
+//! the other side with the same key.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use nix::errno::Errno;
+//! # use nix::sys::system_v::channel::ring::{Consumer, Producer};
+//! # use nix::sys::system_v::{IpcMode, Key};
+//! #
+//! const MY_KEY: Key = Key::new(1337);
+//! let mut producer = Producer::<u64, 1024>::create(MY_KEY, IpcMode::owner_rw())?;
+//! producer.push(42).expect("The ring is empty");
+//!
+//! // In another process
+//! let mut consumer = unsafe { Consumer::<u64, 1024>::open(MY_KEY) }?;
+//! assert_eq!(consumer.pop(), Some(42));
+//! # Ok::<(), Errno>(())
+//! ```
+
+use std::cell::UnsafeCell;
+use std::mem::{self, MaybeUninit};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::errno::Errno;
+use crate::Result;
+
+use super::super::shm::{
+    AttachAddress, SharedMemory, Shm, ShmatFlag, ShmgetFlag,
+};
+use super::super::{IpcMode, Key, SysvSafe};
+
+/// Sending half of a ring, pushing values for the [`Consumer`].
+///
+/// The segment is not removed when the handle is dropped, see
+/// [`Producer::remove`].
+#[derive(Debug)]
+pub struct Producer<T, const N: usize> {
+    shm: Shm<Ring<T, N>>,
+    ring: SharedMemory<Ring<T, N>>,
+}
+
+/// Receiving half of a ring, popping the values of the [`Producer`].
+///
+/// The segment is not removed when the handle is dropped, see
+/// [`Consumer::remove`].
+#[derive(Debug)]
+pub struct Consumer<T, const N: usize> {
+    shm: Shm<Ring<T, N>>,
+    ring: SharedMemory<Ring<T, N>>,
+}
+
+impl<T: SysvSafe, const N: usize> Producer<T, N> {
+    /// Creates a new empty ring, and returns its producer.
+    ///
+    /// Fails with [`Errno::EEXIST`] if a segment already exists for `key`.
+    pub fn create(key: Key, mode: impl Into<IpcMode>) -> Result<Self> {
+        let (shm, ring) = create_ring(key, mode.into())?;
+        Ok(Self { shm, ring })
+    }
+
+    /// Opens the producer of an existing ring.
+    ///
+    /// Fails with [`Errno::ENOENT`] if the ring does not exist, and with
+    /// [`Errno::EINVAL`] if its segment does not have the size of a ring of
+    /// `N` values of type `T`.
+    ///
+    /// # Safety
+    ///
+    /// The ring must have been created by [`Producer::create`] or
+    /// [`Consumer::create`] with the same `T` and `N`, and no other producer
+    /// of the ring may be used at the same time.
+    pub unsafe fn open(key: Key) -> Result<Self> {
+        let (shm, ring) = unsafe { open_ring(key) }?;
+        Ok(Self { shm, ring })
+    }
+
+    /// Pushes `value` at the end of the ring, or gives it back if the ring
+    /// is full.
+    pub fn push(&mut self, value: T) -> std::result::Result<(), T> {
+        let tail = self.ring.tail.0.load(Ordering::Relaxed);
+        let head = self.ring.head.0.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) == N {
+            return Err(value);
+        }
+        // The consumer does not read the slot until the tail moves past it
+        unsafe { (*self.ring.slot(tail)).write(value) };
+        self.ring
+            .tail
+            .0
+            .store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Returns the number of values the ring can take before being full.
+    pub fn free_len(&self) -> usize {
+        N - self.ring.len()
+    }
+
+    /// Returns whether the ring is full.
+    pub fn is_full(&self) -> bool {
+        self.free_len() == 0
+    }
+
+    /// Returns the maximum number of values in the ring, `N`.
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Marks the segment of the ring for destruction, once both sides have
+    /// detached from it.
+    ///
+    /// The values left in the ring are not dropped.
+    pub fn remove(&self) -> Result<()> {
+        self.shm.remove()
+    }
+}
+
+impl<T: SysvSafe + Copy, const N: usize> Producer<T, N> {
+    /// Pushes as many values of `values` as the ring can take, and returns
+    /// how many it pushed.
+    pub fn push_slice(&mut self, values: &[T]) -> usize {
+        let tail = self.ring.tail.0.load(Ordering::Relaxed);
+        let head = self.ring.head.0.load(Ordering::Acquire);
+        let count = values.len().min(N - tail.wrapping_sub(head));
+        for (i, value) in values[..count].iter().enumerate() {
+            unsafe { (*self.ring.slot(tail.wrapping_add(i))).write(*value) };
+        }
+        self.ring
+            .tail
+            .0
+            .store(tail.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
+
+impl<T: SysvSafe, const N: usize> Consumer<T, N> {
+    /// Creates a new empty ring, and returns its consumer.
+    ///
+    /// Fails with [`Errno::EEXIST`] if a segment already exists for `key`.
+    pub fn create(key: Key, mode: impl Into<IpcMode>) -> Result<Self> {
+        let (shm, ring) = create_ring(key, mode.into())?;
+        Ok(Self { shm, ring })
+    }
+
+    /// Opens the consumer of an existing ring.
+    ///
+    /// Fails with [`Errno::ENOENT`] if the ring does not exist, and with
+    /// [`Errno::EINVAL`] if its segment does not have the size of a ring of
+    /// `N` values of type `T`.
+    ///
+    /// # Safety
+    ///
+    /// The ring must have been created by [`Producer::create`] or
+    /// [`Consumer::create`] with the same `T` and `N`, and no other consumer
+    /// of the ring may be used at the same time.
+    pub unsafe fn open(key: Key) -> Result<Self> {
+        let (shm, ring) = unsafe { open_ring(key) }?;
+        Ok(Self { shm, ring })
+    }
+
+    /// Pops the value at the start of the ring, if any.
+    pub fn pop(&mut self) -> Option<T> {
+        let head = self.ring.head.0.load(Ordering::Relaxed);
+        let tail = self.ring.tail.0.load(Ordering::Acquire);
+        if head == tail {
+            return None;
+        }
+        // The producer wrote the slot before moving the tail past it, and
+        // does not reuse it until the head moves past it
+        let value = unsafe { (*self.ring.slot(head)).assume_init_read() };
+        self.ring
+            .head
+            .0
+            .store(head.wrapping_add(1), Ordering::Release);
+        Some(value)
+    }
+
+    /// Returns the number of values in the ring.
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    /// Returns whether the ring is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of values in the ring, `N`.
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Marks the segment of the ring for destruction, once both sides have
+    /// detached from it.
+    ///
+    /// The values left in the ring are not dropped.
+    pub fn remove(&self) -> Result<()> {
+        self.shm.remove()
+    }
+}
+
+impl<T: SysvSafe + Copy, const N: usize> Consumer<T, N> {
+    /// Pops values into `values` until it is full or the ring is empty, and
+    /// returns how many it popped.
+    pub fn pop_slice(&mut self, values: &mut [T]) -> usize {
+        let head = self.ring.head.0.load(Ordering::Relaxed);
+        let tail = self.ring.tail.0.load(Ordering::Acquire);
+        let count = values.len().min(tail.wrapping_sub(head));
+        for (i, value) in values[..count].iter_mut().enumerate() {
+            *value = unsafe {
+                (*self.ring.slot(head.wrapping_add(i))).assume_init()
+            };
+        }
+        self.ring
+            .head
+            .0
+            .store(head.wrapping_add(count), Ordering::Release);
+        count
+    }
+}
+
+// -- Private --
+
+/// Layout of the segment of a ring.
+///
+/// `head` and `tail` count the values popped and pushed since the creation
+/// of the ring, wrapping around: as `N` is a power of two, it divides the
+/// number of counter values, and the slot of a count stays the same across
+/// the wrap. They are only written by the consumer and
+/// the producer respectively, and live on separate cache lines so that the
+/// two sides do not slow each other down.
+#[repr(C)]
+struct Ring<T, const N: usize> {
+    head: CachePadded<AtomicUsize>,
+    tail: CachePadded<AtomicUsize>,
+    slots: [UnsafeCell<MaybeUninit<T>>; N],
+}
+
+// Only made of atomics and of values of `T`
+unsafe impl<T: SysvSafe, const N: usize> SysvSafe for Ring<T, N> {}
+
+// The slots are only accessed by one side at a time, see `Producer::push`
+// and `Consumer::pop`.
+unsafe impl<T: Send, const N: usize> Sync for Ring<T, N> {}
+
+impl<T, const N: usize> Ring<T, N> {
+    /// Fails to build rings whose capacity is not a power of two.
+    const CAPACITY: () =
+        assert!(N.is_power_of_two(), "The capacity must be a power of two");
+
+    fn len(&self) -> usize {
+        let tail = self.tail.0.load(Ordering::Acquire);
+        let head = self.head.0.load(Ordering::Acquire);
+        tail.wrapping_sub(head)
+    }
+
+    fn slot(&self, index: usize) -> *mut MaybeUninit<T> {
+        self.slots[index & (N - 1)].get()
+    }
+}
+
+impl<T, const N: usize> std::fmt::Debug for Ring<T, N> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Ring")
+            .field("head", &self.head.0)
+            .field("tail", &self.tail.0)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Segment of a ring, and its attachment.
+type Segment<T, const N: usize> = (Shm<Ring<T, N>>, SharedMemory<Ring<T, N>>);
+
+/// Aligns a value on a cache line.
+#[repr(C, align(64))]
+struct CachePadded<T>(T);
+
+/// Creates the segment of a new ring.
+///
+/// A new segment is filled with zeros, which makes an empty ring.
+fn create_ring<T: SysvSafe, const N: usize>(
+    key: Key,
+    mode: IpcMode,
+) -> Result<Segment<T, N>> {
+    let () = Ring::<T, N>::CAPACITY;
+    let shm = Shm::<Ring<T, N>>::create_and_connect(key, mode)?;
+    match shm.attach(AttachAddress::Any, ShmatFlag::empty()) {
+        Ok(ring) => Ok((shm, unsafe { ring.assume_init() })),
+        Err(e) => {
+            let _ = shm.remove();
+            Err(e.into())
+        }
+    }
+}
+
+/// Attaches the segment of an existing ring.
+///
+/// # Safety
+///
+/// The segment must hold a ring of `N` values of type `T`.
+unsafe fn open_ring<T: SysvSafe, const N: usize>(
+    key: Key,
+) -> Result<Segment<T, N>> {
+    let () = Ring::<T, N>::CAPACITY;
+    let shm = unsafe {
+        Shm::<Ring<T, N>>::shmget(key, ShmgetFlag::empty(), IpcMode::owner_rw())
+    }?;
+    // A ring of smaller values or of a smaller capacity fits in the segment
+    if shm.stat()?.size != mem::size_of::<Ring<T, N>>() {
+        return Err(Errno::EINVAL);
+    }
+    let ring = shm.attach(AttachAddress::Any, ShmatFlag::empty())?;
+    Ok((shm, unsafe { ring.assume_init() }))
+}
@@ -0,0 +1,360 @@
+//! Bindings to the SystemV IPC system calls
+//!
+//! This is libc, along with the `semun` union and a `semctl` that always
+//! takes it.
+//!
+//! The system calls go through an [`IpcBackend`], which is the kernel
+//! unless the `mock` feature is enabled and [`mock::install`] has been
+//! called, in which case they are emulated in memory.
+//!
+//! With the `tracing` feature, each system call is also recorded in a
+//! `debug` span named after it, holding its arguments and the calling
+//! process, with an event reporting its result or `errno`.
+//!
+//! [`mock::install`]: super::mock::install
+//!
+//! libc does not bind SystemV IPC on Android, whose application sandboxes
+//! forbid it. There, the system calls fail with `ENOSYS` without reaching
+//! the kernel, and the definitions missing from libc are stand-ins, see
+//! `android`.
+
+pub(super) use libc::*;
+
+#[cfg(target_os = "android")]
+pub(super) use self::android::*;
+
+/// Argument of [`semctl(2)`], used in place of the C `union semun`.
+///
+/// [`semctl(2)`]: https://man7.org/linux/man-pages/man2/semctl.2.html
+#[cfg(all(
+    feature = "sysvipc_sem",
+    any(
+        apple_targets,
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "linux"
+    )
+))]
+#[repr(C)]
+#[allow(non_camel_case_types)]
+pub(super) union semun {
+    pub(super) val: c_int,
+    pub(super) buf: *mut semid_ds,
+    pub(super) array: *mut c_ushort,
+}
+
+#[cfg(all(
+    feature = "sysvipc_sem",
+    any(
+        apple_targets,
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "linux"
+    )
+))]
+impl semun {
+    /// Argument of the commands ignoring it.
+    pub(super) const NONE: Self = Self { val: 0 };
+}
+
+/// The active member is unknown, so only `val` is shown: it is the only one
+/// that is always initialized.
+#[cfg(all(
+    feature = "sysvipc_sem",
+    feature = "tracing",
+    any(
+        apple_targets,
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "linux"
+    )
+))]
+impl std::fmt::Debug for semun {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("semun")
+            .field("val", unsafe { &self.val })
+            .finish_non_exhaustive()
+    }
+}
+
+/// Generates the [`IpcBackend`] trait from the signatures of the system
+/// calls, along with its implementation by the kernel, and a function for
+/// each system call, making it with the current backend.
+macro_rules! ipc_backend {
+    ($(
+        $(#[$attr:meta])*
+        fn $name:ident($($arg:ident: $ty:ty),* $(,)?) -> $ret:ty;
+    )*) => {
+        /// Implementation of the SystemV IPC system calls.
+        ///
+        /// Each method has the signature and the contract of the system call
+        /// it is named after, and reports errors through `errno`.
+        pub(super) trait IpcBackend {
+            $(
+                $(#[$attr])*
+                unsafe fn $name(&self, $($arg: $ty),*) -> $ret;
+            )*
+        }
+
+        /// Backend making the actual system calls.
+        struct Kernel;
+
+        #[cfg(not(target_os = "android"))]
+        impl IpcBackend for Kernel {
+            $(
+                $(#[$attr])*
+                unsafe fn $name(&self, $($arg: $ty),*) -> $ret {
+                    unsafe { libc::$name($($arg),*) }
+                }
+            )*
+        }
+
+        #[cfg(target_os = "android")]
+        impl IpcBackend for Kernel {
+            $(
+                $(#[$attr])*
+                unsafe fn $name(&self, $(_: $ty),*) -> $ret {
+                    crate::errno::Errno::ENOSYS.set();
+                    Returned::FAILED
+                }
+            )*
+        }
+
+        $(
+            $(#[$attr])*
+            pub(super) unsafe fn $name($($arg: $ty),*) -> $ret {
+                #[cfg(feature = "tracing")]
+                let _span = tracing::debug_span!(
+                    stringify!($name),
+                    pid = std::process::id(),
+                    $($arg = ?$arg),*
+                )
+                .entered();
+                let res = unsafe { backend().$name($($arg),*) };
+                #[cfg(feature = "tracing")]
+                trace_result(&res);
+                res
+            }
+        )*
+    };
+}
+
+ipc_backend! {
+    #[cfg(feature = "sysvipc_shm")]
+    fn shmget(key: key_t, size: size_t, shmflg: c_int) -> c_int;
+    #[cfg(feature = "sysvipc_shm")]
+    fn shmat(shmid: c_int, shmaddr: *const c_void, shmflg: c_int) -> *mut c_void;
+    #[cfg(feature = "sysvipc_shm")]
+    fn shmdt(shmaddr: *const c_void) -> c_int;
+    #[cfg(feature = "sysvipc_shm")]
+    fn shmctl(shmid: c_int, cmd: c_int, buf: *mut shmid_ds) -> c_int;
+
+    #[cfg(all(
+        feature = "sysvipc_sem",
+        any(apple_targets, target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    fn semget(key: key_t, nsems: c_int, semflg: c_int) -> c_int;
+    #[cfg(all(
+        feature = "sysvipc_sem",
+        any(apple_targets, target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    fn semop(semid: c_int, sops: *mut sembuf, nsops: size_t) -> c_int;
+    /// Performs the control operation `cmd` of `semctl`, whose optional
+    /// argument is always passed, as Rust functions cannot be variadic.
+    #[cfg(all(
+        feature = "sysvipc_sem",
+        any(apple_targets, target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    fn semctl(semid: c_int, semnum: c_int, cmd: c_int, arg: semun) -> c_int;
+
+    #[cfg(all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    fn msgget(key: key_t, msgflg: c_int) -> c_int;
+    #[cfg(all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    fn msgsnd(
+        msqid: c_int,
+        msgp: *const c_void,
+        msgsz: size_t,
+        msgflg: c_int,
+    ) -> c_int;
+    #[cfg(all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    fn msgrcv(
+        msqid: c_int,
+        msgp: *mut c_void,
+        msgsz: size_t,
+        msgtyp: c_long,
+        msgflg: c_int,
+    ) -> ssize_t;
+    #[cfg(all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    fn msgctl(msqid: c_int, cmd: c_int, buf: *mut msqid_ds) -> c_int;
+}
+
+/// Returns the backend the system calls are made with: the kernel, unless
+/// [`mock::install`](super::mock::install) has been called.
+fn backend() -> &'static dyn IpcBackend {
+    #[cfg(feature = "mock")]
+    if super::mock::is_installed() {
+        return &super::mock::Mock;
+    }
+    &Kernel
+}
+
+/// Value returned by a system call, failing with -1.
+#[cfg(any(feature = "tracing", target_os = "android"))]
+#[allow(dead_code)]
+trait Returned: std::fmt::Debug + Sized {
+    /// Value returned on failure.
+    const FAILED: Self;
+
+    fn failed(&self) -> bool;
+}
+
+#[cfg(any(feature = "tracing", target_os = "android"))]
+impl Returned for c_int {
+    const FAILED: Self = -1;
+
+    fn failed(&self) -> bool {
+        *self == -1
+    }
+}
+
+#[cfg(any(feature = "tracing", target_os = "android"))]
+impl Returned for ssize_t {
+    const FAILED: Self = -1;
+
+    fn failed(&self) -> bool {
+        *self == -1
+    }
+}
+
+#[cfg(any(feature = "tracing", target_os = "android"))]
+impl Returned for *mut c_void {
+    const FAILED: Self = usize::MAX as *mut c_void;
+
+    fn failed(&self) -> bool {
+        *self as isize == -1
+    }
+}
+
+/// Records the result of a system call in the current span, along with
+/// `errno` on failure.
+///
+/// `errno` is restored afterwards, as the subscribers may overwrite it.
+#[cfg(feature = "tracing")]
+fn trace_result(res: &impl Returned) {
+    use crate::errno::Errno;
+
+    if res.failed() {
+        let errno = Errno::last();
+        tracing::debug!(%errno, "failed");
+        errno.set();
+    } else {
+        tracing::debug!(ret = ?res, "succeeded");
+    }
+}
+
+/// Stand-ins for the SystemV IPC definitions missing from libc on Android.
+///
+/// They are never seen by the kernel, as the system calls fail with
+/// `ENOSYS` beforehand: they only let the wrappers build, and the `mock`
+/// backend run. The values are the ones of Linux.
+#[cfg(target_os = "android")]
+#[allow(dead_code, non_camel_case_types)]
+mod android {
+    use libc::{c_char, c_int, c_short, c_ulong, c_ushort, key_t, mode_t};
+    use libc::{gid_t, pid_t, size_t, time_t, uid_t};
+
+    use crate::errno::Errno;
+
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    #[repr(C)]
+    pub struct ipc_perm {
+        pub uid: uid_t,
+        pub gid: gid_t,
+        pub cuid: uid_t,
+        pub cgid: gid_t,
+        pub mode: mode_t,
+    }
+
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    #[repr(C)]
+    pub struct shmid_ds {
+        pub shm_perm: ipc_perm,
+        pub shm_segsz: size_t,
+        pub shm_atime: time_t,
+        pub shm_dtime: time_t,
+        pub shm_ctime: time_t,
+        pub shm_cpid: pid_t,
+        pub shm_lpid: pid_t,
+        pub shm_nattch: c_ulong,
+    }
+
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    #[repr(C)]
+    pub struct semid_ds {
+        pub sem_perm: ipc_perm,
+        pub sem_otime: time_t,
+        pub sem_ctime: time_t,
+        pub sem_nsems: c_ulong,
+    }
+
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    #[repr(C)]
+    pub struct sembuf {
+        pub sem_num: c_ushort,
+        pub sem_op: c_short,
+        pub sem_flg: c_short,
+    }
+
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+    #[repr(C)]
+    pub struct msqid_ds {
+        pub msg_perm: ipc_perm,
+        pub msg_stime: time_t,
+        pub msg_rtime: time_t,
+        pub msg_ctime: time_t,
+        pub msg_cbytes: c_ulong,
+        pub msg_qnum: c_ulong,
+        pub msg_qbytes: c_ulong,
+        pub msg_lspid: pid_t,
+        pub msg_lrpid: pid_t,
+    }
+
+    pub const IPC_PRIVATE: key_t = 0;
+    pub const IPC_CREAT: c_int = 0o1000;
+    pub const IPC_EXCL: c_int = 0o2000;
+    pub const IPC_NOWAIT: c_int = 0o4000;
+    pub const IPC_RMID: c_int = 0;
+    pub const IPC_SET: c_int = 1;
+    pub const IPC_STAT: c_int = 2;
+
+    pub const SHM_RDONLY: c_int = 0o10000;
+    pub const SHM_RND: c_int = 0o20000;
+
+    pub const GETPID: c_int = 11;
+    pub const GETVAL: c_int = 12;
+    pub const GETALL: c_int = 13;
+    pub const GETNCNT: c_int = 14;
+    pub const GETZCNT: c_int = 15;
+    pub const SETVAL: c_int = 16;
+    pub const SETALL: c_int = 17;
+    pub const SEM_UNDO: c_int = 0x1000;
+
+    pub const MSG_NOERROR: c_int = 0o10000;
+
+    pub unsafe fn ftok(_: *const c_char, _: c_int) -> key_t {
+        Errno::ENOSYS.set();
+        -1
+    }
+}
@@ -0,0 +1,522 @@
+//! System-wide listing of SystemV IPC resources, like `ipcs` does
+//!
+//! It also reports the limits the kernel enforces on them, removes the
+//! segments of the caller like `ipcrm` does, and sums up the resources in use
+//! in a [`snapshot`] for exporters and health checks.
+//!
+//! The resources are read from `/proc/sysvipc`, which also lists the
+//! resources the caller cannot read.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+use crate::errno::Errno;
+use crate::sys::stat::Mode;
+use crate::unistd::{geteuid, Gid, Pid, Uid};
+use crate::Result;
+
+#[cfg(feature = "sysvipc_sem")]
+use super::sem::SemaphoreSet;
+use super::shm::{self, ShmStat};
+use super::{optional_time, time, Key};
+
+use super::ffi::{c_int, mode_t, uid_t};
+
+/// A shared memory segment existing on the system, see
+/// [`shared_memory_segments`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ShmSegment {
+    /// Key the segment was created with.
+    pub key: Key,
+    /// Identifier of the segment.
+    pub id: c_int,
+    /// Status of the segment, including its size, owner, permissions and
+    /// number of attachments.
+    pub stat: ShmStat,
+}
+
+/// Returns every shared memory segment of the IPC namespace of the calling
+/// process.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::info;
+/// for segment in info::shared_memory_segments()? {
+///     println!(
+///         "{} {} {} bytes, {} attached",
+///         segment.key, segment.id, segment.stat.size, segment.stat.attachments
+///     );
+/// }
+/// # Ok::<(), Errno>(())
+/// ```
+pub fn shared_memory_segments() -> Result<Vec<ShmSegment>> {
+    read_proc_table("/proc/sysvipc/shm", parse_shm_line)
+}
+
+/// Marks every shared memory segment owned by the effective user of the
+/// calling process to be destroyed, like `ipcrm --all=shm` does for an
+/// unprivileged user, and returns them.
+///
+/// This is meant for test harnesses and daemons cleaning up after crashed
+/// runs: the segments of unrelated programs of the same user are removed
+/// too. Segments destroyed meanwhile by another process are skipped.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::info;
+/// for segment in info::purge_owned_segments()? {
+///     println!("Removed {} ({} bytes)", segment.id, segment.stat.size);
+/// }
+/// # Ok::<(), Errno>(())
+/// ```
+pub fn purge_owned_segments() -> Result<Vec<ShmSegment>> {
+    let uid = geteuid();
+    let mut removed = Vec::new();
+    for segment in shared_memory_segments()? {
+        if segment.stat.uid != uid {
+            continue;
+        }
+        match shm::remove_segment_by_id(segment.id) {
+            Ok(()) => removed.push(segment),
+            Err(Errno::EINVAL | Errno::EIDRM) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(removed)
+}
+
+/// System-wide limits on shared memory segments, see
+/// [`shared_memory_limits`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ShmLimits {
+    /// Maximum size of a segment, in bytes (`shmmax`).
+    pub max_size: usize,
+    /// Minimum size of a segment, in bytes (`shmmin`).
+    pub min_size: usize,
+    /// Maximum number of segments on the system (`shmmni`).
+    pub max_segments: usize,
+    /// Maximum number of segments a process can attach (`shmseg`).
+    pub max_attachments: usize,
+    /// Maximum size of all the segments on the system, in pages (`shmall`).
+    pub max_total_pages: usize,
+}
+
+impl ShmLimits {
+    /// Whether a segment of `size` bytes is within the size limits.
+    ///
+    /// Creating the segment may still fail, for example if the total size
+    /// of the segments would exceed `shmall`.
+    pub fn allows_size(&self, size: usize) -> bool {
+        (self.min_size..=self.max_size).contains(&size)
+    }
+}
+
+/// Returns the limits the kernel enforces on shared memory segments.
+///
+/// They are read from `/proc/sys/kernel`. The minimum size and the number
+/// of attachments per process are not tunable on Linux: a segment holds at
+/// least a byte, and a process can attach as many segments as the system
+/// holds.
+///
+/// Checking a size against them up front gives a clearer error than the
+/// [`Errno::EINVAL`] returned when creating the segment.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::info;
+/// let size = 1 << 30;
+/// let limits = info::shared_memory_limits()?;
+/// if !limits.allows_size(size) {
+///     eprintln!("Segments are limited to {} bytes", limits.max_size);
+/// }
+/// # Ok::<(), Errno>(())
+/// ```
+pub fn shared_memory_limits() -> Result<ShmLimits> {
+    let max_segments = read_kernel_limit("shmmni")?;
+    Ok(ShmLimits {
+        max_size: read_kernel_limit("shmmax")?,
+        min_size: 1,
+        max_segments,
+        max_attachments: max_segments,
+        max_total_pages: read_kernel_limit("shmall")?,
+    })
+}
+
+/// Usage of SystemV IPC resources across the system, returned by
+/// [`snapshot`].
+///
+/// The fields of the message queues and semaphore sets only exist with the
+/// matching features, so the struct cannot be built outside of this crate.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct IpcSnapshot {
+    /// Number of shared memory segments.
+    pub segments: usize,
+    /// Total size of the shared memory segments, in bytes.
+    pub segment_bytes: usize,
+    /// Total number of attachments of the shared memory segments.
+    pub attachments: usize,
+    /// Resources owned by each user, sorted by user.
+    pub users: Vec<UserUsage>,
+    /// Depth of each message queue.
+    #[cfg(feature = "sysvipc_msg")]
+    pub queues: Vec<QueueDepth>,
+    /// Values of each semaphore set.
+    #[cfg(feature = "sysvipc_sem")]
+    pub semaphore_sets: Vec<SemaphoreValues>,
+}
+
+/// Resources owned by a user, in an [`IpcSnapshot`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UserUsage {
+    /// Owner of the resources.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_impls::uid"))]
+    pub uid: Uid,
+    /// Number of shared memory segments.
+    pub segments: usize,
+    /// Total size of the shared memory segments, in bytes.
+    pub segment_bytes: usize,
+    /// Number of message queues.
+    pub queues: usize,
+    /// Number of semaphore sets.
+    pub semaphore_sets: usize,
+}
+
+/// Messages waiting in a message queue, in an [`IpcSnapshot`].
+#[cfg(feature = "sysvipc_msg")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueueDepth {
+    /// Key the queue was created with.
+    pub key: Key,
+    /// Identifier of the queue.
+    pub id: c_int,
+    /// Owner of the queue.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_impls::uid"))]
+    pub uid: Uid,
+    /// Number of messages in the queue.
+    pub messages: usize,
+    /// Number of bytes of the messages in the queue.
+    pub bytes: usize,
+}
+
+/// Values of the semaphores of a set, in an [`IpcSnapshot`].
+#[cfg(feature = "sysvipc_sem")]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SemaphoreValues {
+    /// Key the set was created with.
+    pub key: Key,
+    /// Identifier of the set.
+    pub id: c_int,
+    /// Owner of the set.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_impls::uid"))]
+    pub uid: Uid,
+    /// Value of each semaphore, or `None` if the caller cannot read the set.
+    pub values: Option<Vec<u16>>,
+}
+
+/// Returns the usage of the SystemV IPC resources of the IPC namespace of
+/// the calling process, in one call.
+///
+/// Message queues and semaphore sets are read from `/proc/sysvipc`, and only
+/// reported with the `sysvipc_msg` and `sysvipc_sem` features. Resources
+/// created or removed while the snapshot is taken may be missed.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::info;
+/// let snapshot = info::snapshot()?;
+/// println!("{} segments, {} bytes", snapshot.segments, snapshot.segment_bytes);
+/// // Served to Prometheus by an exporter
+/// let metrics = snapshot.to_prometheus();
+/// # Ok::<(), Errno>(())
+/// ```
+pub fn snapshot() -> Result<IpcSnapshot> {
+    let mut snapshot = IpcSnapshot::default();
+    let mut users = BTreeMap::new();
+    fn user(
+        users: &mut BTreeMap<uid_t, UserUsage>,
+        uid: Uid,
+    ) -> &mut UserUsage {
+        users.entry(uid.as_raw()).or_insert(UserUsage {
+            uid,
+            segments: 0,
+            segment_bytes: 0,
+            queues: 0,
+            semaphore_sets: 0,
+        })
+    }
+    for segment in shared_memory_segments()? {
+        let stat = segment.stat;
+        snapshot.segments += 1;
+        snapshot.segment_bytes += stat.size;
+        snapshot.attachments += stat.attachments;
+        let usage = user(&mut users, stat.uid);
+        usage.segments += 1;
+        usage.segment_bytes += stat.size;
+    }
+    #[cfg(feature = "sysvipc_msg")]
+    {
+        snapshot.queues = message_queues()?;
+        for queue in &snapshot.queues {
+            user(&mut users, queue.uid).queues += 1;
+        }
+    }
+    #[cfg(feature = "sysvipc_sem")]
+    {
+        snapshot.semaphore_sets = semaphore_sets()?;
+        for set in &snapshot.semaphore_sets {
+            user(&mut users, set.uid).semaphore_sets += 1;
+        }
+    }
+    snapshot.users = users.into_values().collect();
+    Ok(snapshot)
+}
+
+impl IpcSnapshot {
+    /// Formats the snapshot in the text exposition format of Prometheus.
+    ///
+    /// Every metric is a gauge prefixed with `sysv_`, labelled by `uid` for
+    /// the usage of each user, and by `key` and `id` for each queue and set.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        // Writing to a `String` cannot fail
+        let _ = self.write_prometheus(&mut out);
+        out
+    }
+
+    fn write_prometheus(&self, out: &mut String) -> fmt::Result {
+        fn header(out: &mut String, name: &str, help: &str) -> fmt::Result {
+            writeln!(out, "# HELP {name} {help}")?;
+            writeln!(out, "# TYPE {name} gauge")
+        }
+
+        header(
+            out,
+            "sysv_shm_segments",
+            "Number of shared memory segments.",
+        )?;
+        writeln!(out, "sysv_shm_segments {}", self.segments)?;
+        header(
+            out,
+            "sysv_shm_bytes",
+            "Total size of the shared memory segments, in bytes.",
+        )?;
+        writeln!(out, "sysv_shm_bytes {}", self.segment_bytes)?;
+        header(
+            out,
+            "sysv_shm_attachments",
+            "Total number of attachments of the shared memory segments.",
+        )?;
+        writeln!(out, "sysv_shm_attachments {}", self.attachments)?;
+
+        // Name, help and value of the metrics of each user. The queues and
+        // sets are only counted with their feature, don't report zeros
+        type UserMetric = (&'static str, &'static str, fn(&UserUsage) -> usize);
+        let per_user: &[UserMetric] = &[
+            (
+                "sysv_user_shm_segments",
+                "Number of shared memory segments of a user.",
+                |u| u.segments,
+            ),
+            (
+                "sysv_user_shm_bytes",
+                "Size of the shared memory segments of a user, in bytes.",
+                |u| u.segment_bytes,
+            ),
+            #[cfg(feature = "sysvipc_msg")]
+            (
+                "sysv_user_msg_queues",
+                "Number of message queues of a user.",
+                |u| u.queues,
+            ),
+            #[cfg(feature = "sysvipc_sem")]
+            (
+                "sysv_user_sem_sets",
+                "Number of semaphore sets of a user.",
+                |u| u.semaphore_sets,
+            ),
+        ];
+        for &(name, help, value) in per_user {
+            header(out, name, help)?;
+            for usage in &self.users {
+                writeln!(
+                    out,
+                    "{name}{{uid=\"{}\"}} {}",
+                    usage.uid,
+                    value(usage)
+                )?;
+            }
+        }
+
+        #[cfg(feature = "sysvipc_msg")]
+        {
+            header(
+                out,
+                "sysv_msg_queue_messages",
+                "Number of messages in a message queue.",
+            )?;
+            for queue in &self.queues {
+                writeln!(
+                    out,
+                    "sysv_msg_queue_messages{{key=\"{}\",id=\"{}\"}} {}",
+                    queue.key, queue.id, queue.messages
+                )?;
+            }
+            header(
+                out,
+                "sysv_msg_queue_bytes",
+                "Number of bytes of the messages in a message queue.",
+            )?;
+            for queue in &self.queues {
+                writeln!(
+                    out,
+                    "sysv_msg_queue_bytes{{key=\"{}\",id=\"{}\"}} {}",
+                    queue.key, queue.id, queue.bytes
+                )?;
+            }
+        }
+
+        #[cfg(feature = "sysvipc_sem")]
+        {
+            header(out, "sysv_sem_value", "Value of a semaphore.")?;
+            for set in &self.semaphore_sets {
+                for (num, value) in set.values.iter().flatten().enumerate() {
+                    writeln!(
+                        out,
+                        "sysv_sem_value{{key=\"{}\",id=\"{}\",num=\"{num}\"}} {value}",
+                        set.key, set.id
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Lists the message queues of the system.
+#[cfg(feature = "sysvipc_msg")]
+pub(super) fn message_queues() -> Result<Vec<QueueDepth>> {
+    read_proc_table("/proc/sysvipc/msg", parse_msg_line)
+}
+
+/// Lists the semaphore sets of the system, with their values.
+#[cfg(feature = "sysvipc_sem")]
+pub(super) fn semaphore_sets() -> Result<Vec<SemaphoreValues>> {
+    read_proc_table("/proc/sysvipc/sem", parse_sem_line)
+}
+
+/// Reads the tunable `name` of `/proc/sys/kernel`.
+fn read_kernel_limit(name: &str) -> Result<usize> {
+    let value = std::fs::read_to_string(format!("/proc/sys/kernel/{name}"))
+        .map_err(|e| Errno::try_from(e).unwrap_or(Errno::EIO))?;
+    parse_field(value.trim())
+}
+
+/// Parses every line of a table of `/proc/sysvipc`, after the header.
+fn read_proc_table<T>(
+    path: &str,
+    parse: fn(&str) -> Result<T>,
+) -> Result<Vec<T>> {
+    let table = std::fs::read_to_string(path)
+        .map_err(|e| Errno::try_from(e).unwrap_or(Errno::EIO))?;
+    table.lines().skip(1).map(parse).collect()
+}
+
+/// Parses a line of `/proc/sysvipc/msg`, whose columns are
+/// `key msqid perms cbytes qnum lspid lrpid uid gid cuid cgid stime rtime ctime`.
+#[cfg(feature = "sysvipc_msg")]
+fn parse_msg_line(line: &str) -> Result<QueueDepth> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let field = |i: usize| -> Result<&str> {
+        fields.get(i).copied().ok_or(Errno::EINVAL)
+    };
+    Ok(QueueDepth {
+        key: Key::new(parse_field(field(0)?)?),
+        id: parse_field(field(1)?)?,
+        bytes: parse_field(field(3)?)?,
+        messages: parse_field(field(4)?)?,
+        uid: Uid::from_raw(parse_field(field(7)?)?),
+    })
+}
+
+/// Parses a line of `/proc/sysvipc/sem`, whose columns are
+/// `key semid perms nsems uid gid cuid cgid otime ctime`, and reads the
+/// values of the set.
+#[cfg(feature = "sysvipc_sem")]
+fn parse_sem_line(line: &str) -> Result<SemaphoreValues> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let field = |i: usize| -> Result<&str> {
+        fields.get(i).copied().ok_or(Errno::EINVAL)
+    };
+    let key = Key::new(parse_field(field(0)?)?);
+    let id = parse_field(field(1)?)?;
+    let values = match SemaphoreSet::from_parts(key, id).values() {
+        Ok(values) => Some(values),
+        // Not readable by the caller, or removed meanwhile
+        Err(Errno::EACCES | Errno::EINVAL | Errno::EIDRM) => None,
+        Err(e) => return Err(e),
+    };
+    Ok(SemaphoreValues {
+        key,
+        id,
+        uid: Uid::from_raw(parse_field(field(4)?)?),
+        values,
+    })
+}
+
+/// Parses a decimal field of `/proc/sysvipc` or `/proc/sys/kernel`.
+fn parse_field<T: std::str::FromStr>(field: &str) -> Result<T> {
+    field.parse().map_err(|_| Errno::EINVAL)
+}
+
+/// Parses a line of `/proc/sysvipc/shm`, whose columns are
+/// `key shmid perms size cpid lpid nattch uid gid cuid cgid atime dtime ctime`,
+/// followed by the memory usage on recent kernels.
+fn parse_shm_line(line: &str) -> Result<ShmSegment> {
+    let mut fields = line.split_whitespace();
+    let mut next = || fields.next().ok_or(Errno::EINVAL);
+    let key = Key::new(parse_field(next()?)?);
+    let id = parse_field(next()?)?;
+    // The permissions are written in octal
+    let perms =
+        mode_t::from_str_radix(next()?, 8).map_err(|_| Errno::EINVAL)?;
+    let size = parse_field(next()?)?;
+    let creator_pid = Pid::from_raw(parse_field(next()?)?);
+    let last_pid = Pid::from_raw(parse_field(next()?)?);
+    let attachments = parse_field(next()?)?;
+    let uid = Uid::from_raw(parse_field(next()?)?);
+    let gid = Gid::from_raw(parse_field(next()?)?);
+    // Skip the creator uid and gid
+    next()?;
+    next()?;
+    let attach_time = optional_time(parse_field(next()?)?);
+    let detach_time = optional_time(parse_field(next()?)?);
+    let change_time = time(parse_field(next()?)?);
+    let stat = ShmStat {
+        size,
+        uid,
+        gid,
+        mode: Mode::from_bits_truncate(perms).into(),
+        attachments,
+        creator_pid,
+        last_pid,
+        attach_time,
+        detach_time,
+        change_time,
+    };
+    Ok(ShmSegment { key, id, stat })
+}
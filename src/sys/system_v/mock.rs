@@ -0,0 +1,1220 @@
+//! In-memory SystemV IPC, for tests
+//!
+//! Code using SystemV IPC needs a kernel supporting it, and the permission
+//! to use it, which containers and some CI runners lack. Once [`install`]
+//! has been called, every SystemV IPC operation of the process is emulated
+//! in memory instead, so that such code can be tested anywhere, without
+//! leaking resources into the system.
+//!
+//! The emulated resources are only visible to the process that created
+//! them, and are lost when it exits. They follow the semantics of Linux,
+//! with the following differences:
+//!
+//! * Permissions are recorded but never checked.
+//! * [`SemopFlag::SEM_UNDO`] is ignored.
+//! * The system-wide listing and limits of `info` are not supported.
+//!
+//! As the resources are process-wide, tests running concurrently still
+//! need distinct keys.
+//!
+//! [`SemopFlag::SEM_UNDO`]: super::sem::SemopFlag::SEM_UNDO
+//!
+//! # Example
+//!
+//! ```
+//! # use nix::errno::Errno;
+//! # use nix::sys::system_v::{mock, scope, IpcMode, Key};
+//! # use nix::sys::system_v::shm::*;
+//! mock::install();
+//!
+//! scope(|s| {
+//!     let shm = s.shared_memory::<u64>(Key::new(1337), IpcMode::owner_rw())?;
+//!     let memory = shm.attach(AttachAddress::Any, ShmatFlag::empty())?;
+//!     assert_eq!(*memory.write(42), 42);
+//!     Ok(())
+//! })?;
+//! # Ok::<(), Errno>(())
+//! ```
+
+use std::collections::BTreeMap;
+#[cfg(all(
+    feature = "sysvipc_msg",
+    any(target_os = "android", target_os = "freebsd", target_os = "linux")
+))]
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+
+#[cfg(feature = "sysvipc_shm")]
+use std::os::unix::io::{AsRawFd, OwnedFd};
+
+use crate::errno::Errno;
+#[cfg(feature = "sysvipc_shm")]
+use crate::unistd::ftruncate;
+
+#[cfg(feature = "sysvipc_shm")]
+use super::shm::ShmatFlag;
+use crate::unistd::{getegid, geteuid, getpid};
+use crate::Result;
+
+use super::ffi::{self as libc, c_int, gid_t, ipc_perm, key_t};
+use super::ffi::{mode_t, pid_t, time_t, uid_t, IpcBackend};
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+/// Resources emulated by the mock.
+static STATE: Mutex<State> = Mutex::new(State::new());
+
+#[cfg(any(
+    all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ),
+    all(
+        feature = "sysvipc_sem",
+        any(
+            apple_targets,
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        )
+    )
+))]
+/// Signaled whenever a resource changes, for blocked operations to retry.
+static CHANGED: Condvar = Condvar::new();
+
+/// Routes every SystemV IPC operation of the process to the mock, for the
+/// rest of its lifetime.
+///
+/// This should be called before any resource is created or attached: the
+/// resources obtained from the kernel beforehand cannot be reached anymore.
+pub fn install() {
+    INSTALLED.store(true, Ordering::Release);
+}
+
+/// Returns whether [`install`] has been called.
+pub fn is_installed() -> bool {
+    INSTALLED.load(Ordering::Acquire)
+}
+
+/// Backend emulating the SystemV IPC system calls in memory.
+pub(super) struct Mock;
+
+#[derive(Debug)]
+struct State {
+    next_id: c_int,
+    #[cfg(feature = "sysvipc_shm")]
+    segments: BTreeMap<c_int, Segment>,
+    /// Segment and size of each attachment, by address.
+    #[cfg(feature = "sysvipc_shm")]
+    attachments: BTreeMap<usize, (c_int, usize)>,
+    #[cfg(all(
+        feature = "sysvipc_sem",
+        any(
+            apple_targets,
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        )
+    ))]
+    sets: BTreeMap<c_int, SemaphoreSet>,
+    #[cfg(all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    queues: BTreeMap<c_int, Queue>,
+}
+
+impl State {
+    const fn new() -> Self {
+        Self {
+            next_id: 1,
+            #[cfg(feature = "sysvipc_shm")]
+            segments: BTreeMap::new(),
+            #[cfg(feature = "sysvipc_shm")]
+            attachments: BTreeMap::new(),
+            #[cfg(all(
+                feature = "sysvipc_sem",
+                any(
+                    apple_targets,
+                    target_os = "android",
+                    target_os = "freebsd",
+                    target_os = "linux"
+                )
+            ))]
+            sets: BTreeMap::new(),
+            #[cfg(all(
+                feature = "sysvipc_msg",
+                any(
+                    target_os = "android",
+                    target_os = "freebsd",
+                    target_os = "linux"
+                )
+            ))]
+            queues: BTreeMap::new(),
+        }
+    }
+}
+
+/// Owner, permissions and key of an emulated resource.
+#[derive(Clone, Copy, Debug)]
+struct Perm {
+    key: key_t,
+    uid: uid_t,
+    gid: gid_t,
+    cuid: uid_t,
+    cgid: gid_t,
+    mode: mode_t,
+}
+
+impl Perm {
+    /// Permissions of a resource created by the calling process with
+    /// `flags`.
+    fn new(key: key_t, flags: c_int) -> Self {
+        let uid = geteuid().as_raw();
+        let gid = getegid().as_raw();
+        Self {
+            key,
+            uid,
+            gid,
+            cuid: uid,
+            cgid: gid,
+            mode: flags as mode_t & 0o777,
+        }
+    }
+
+    /// Fills `perm` for `IPC_STAT`.
+    fn write(&self, perm: &mut ipc_perm) {
+        perm.uid = self.uid;
+        perm.gid = self.gid;
+        perm.cuid = self.cuid;
+        perm.cgid = self.cgid;
+        perm.mode = self.mode as _;
+    }
+
+    /// Updates the owner and permissions from `perm`, for `IPC_SET`.
+    fn read(&mut self, perm: &ipc_perm) {
+        self.uid = perm.uid;
+        self.gid = perm.gid;
+        self.mode = perm.mode as mode_t & 0o777;
+    }
+}
+
+/// Resource looked up by key.
+trait Resource {
+    fn perm(&self) -> &Perm;
+}
+
+/// Returns the id of the resource of `resources` with `key`, after checking
+/// it with `open`, or creates it with `create`, as the `*get` system calls
+/// do.
+fn get<R: Resource>(
+    resources: &mut BTreeMap<c_int, R>,
+    next_id: &mut c_int,
+    key: key_t,
+    flags: c_int,
+    open: impl FnOnce(&R) -> Result<()>,
+    create: impl FnOnce(Perm) -> Result<R>,
+) -> Result<c_int> {
+    if key != libc::IPC_PRIVATE {
+        let existing = resources.iter().find(|(_, r)| r.perm().key == key);
+        if let Some((&id, resource)) = existing {
+            let exclusive = libc::IPC_CREAT | libc::IPC_EXCL;
+            if flags & exclusive == exclusive {
+                return Err(Errno::EEXIST);
+            }
+            open(resource)?;
+            return Ok(id);
+        }
+        if flags & libc::IPC_CREAT == 0 {
+            return Err(Errno::ENOENT);
+        }
+    }
+    let resource = create(Perm::new(key, flags))?;
+    let id = *next_id;
+    *next_id += 1;
+    resources.insert(id, resource);
+    Ok(id)
+}
+
+fn lock() -> MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(any(
+    all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ),
+    all(
+        feature = "sysvipc_sem",
+        any(
+            apple_targets,
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        )
+    )
+))]
+/// Waits for a resource to change, then locks the state again.
+fn wait(state: MutexGuard<'static, State>) -> MutexGuard<'static, State> {
+    CHANGED.wait(state).unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(any(
+    all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ),
+    all(
+        feature = "sysvipc_sem",
+        any(
+            apple_targets,
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        )
+    )
+))]
+/// Wakes up the blocked operations, to retry after a change.
+fn notify() {
+    CHANGED.notify_all();
+}
+
+fn now() -> time_t {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as time_t)
+}
+
+fn pid() -> pid_t {
+    getpid().as_raw()
+}
+
+/// Returns the result of a system call, setting `errno` on failure.
+fn ret<T: From<i8>>(res: Result<T>) -> T {
+    res.unwrap_or_else(|errno| {
+        errno.set();
+        T::from(-1)
+    })
+}
+
+// -- Shared memory --
+
+#[cfg(feature = "sysvipc_shm")]
+#[derive(Debug)]
+struct Segment {
+    perm: Perm,
+    /// Memory of the segment, mapped by each attachment.
+    memory: OwnedFd,
+    size: usize,
+    attachments: usize,
+    removed: bool,
+    cpid: pid_t,
+    lpid: pid_t,
+    atime: time_t,
+    dtime: time_t,
+    ctime: time_t,
+}
+
+#[cfg(feature = "sysvipc_shm")]
+impl Resource for Segment {
+    fn perm(&self) -> &Perm {
+        &self.perm
+    }
+}
+
+#[cfg(feature = "sysvipc_shm")]
+impl Segment {
+    fn new(perm: Perm, size: usize) -> Result<Self> {
+        if size == 0 {
+            return Err(Errno::EINVAL);
+        }
+        let memory = anonymous_memory()?;
+        let len = libc::off_t::try_from(size).map_err(|_| Errno::EINVAL)?;
+        ftruncate(&memory, len).map_err(|_| Errno::ENOMEM)?;
+        Ok(Self {
+            perm,
+            memory,
+            size,
+            attachments: 0,
+            removed: false,
+            cpid: pid(),
+            lpid: 0,
+            atime: 0,
+            dtime: 0,
+            ctime: now(),
+        })
+    }
+
+    /// Marks the segment as removed, returning whether it can be freed
+    /// right away.
+    fn remove(&mut self) -> bool {
+        self.removed = true;
+        self.perm.key = libc::IPC_PRIVATE;
+        self.attachments == 0
+    }
+
+    fn stat(&self) -> libc::shmid_ds {
+        let mut ds: libc::shmid_ds = unsafe { std::mem::zeroed() };
+        self.perm.write(&mut ds.shm_perm);
+        ds.shm_segsz = self.size as _;
+        ds.shm_nattch = self.attachments as _;
+        ds.shm_cpid = self.cpid;
+        ds.shm_lpid = self.lpid;
+        ds.shm_atime = self.atime;
+        ds.shm_dtime = self.dtime;
+        ds.shm_ctime = self.ctime;
+        ds
+    }
+}
+
+/// Creates an anonymous file, holding the memory of a segment.
+#[cfg(all(feature = "sysvipc_shm", any(linux_android, target_os = "freebsd")))]
+fn anonymous_memory() -> Result<OwnedFd> {
+    use crate::sys::memfd::{memfd_create, MemFdCreateFlag};
+
+    let name = std::ffi::CStr::from_bytes_with_nul(b"nix-sysv-mock\0")
+        .expect("nul-terminated name");
+    memfd_create(name, MemFdCreateFlag::MFD_CLOEXEC)
+}
+
+/// Creates an anonymous file, holding the memory of a segment.
+///
+/// This is a POSIX shared memory object, unlinked as soon as it is opened.
+#[cfg(all(
+    feature = "sysvipc_shm",
+    not(any(linux_android, target_os = "freebsd"))
+))]
+fn anonymous_memory() -> Result<OwnedFd> {
+    use std::os::unix::io::FromRawFd;
+    use std::sync::atomic::AtomicU32;
+
+    static COUNTER: AtomicU32 = AtomicU32::new(0);
+
+    let name = std::ffi::CString::new(format!(
+        "/nix-sysv-mock-{}-{}",
+        pid(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+    .expect("name without nul");
+    let oflag = libc::O_RDWR | libc::O_CREAT | libc::O_EXCL | libc::O_CLOEXEC;
+    #[cfg(apple_targets)]
+    let fd =
+        unsafe { libc::shm_open(name.as_ptr(), oflag, 0o600 as libc::c_uint) };
+    #[cfg(not(apple_targets))]
+    let fd = unsafe { libc::shm_open(name.as_ptr(), oflag, 0o600 as mode_t) };
+    let fd = Errno::result(fd)?;
+    unsafe { libc::shm_unlink(name.as_ptr()) };
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+#[cfg(feature = "sysvipc_shm")]
+impl Mock {
+    fn shm_get(&self, key: key_t, size: usize, flags: c_int) -> Result<c_int> {
+        let state = &mut *lock();
+        get(
+            &mut state.segments,
+            &mut state.next_id,
+            key,
+            flags,
+            |segment| {
+                if size > segment.size {
+                    return Err(Errno::EINVAL);
+                }
+                Ok(())
+            },
+            |perm| Segment::new(perm, size),
+        )
+    }
+
+    /// Maps the segment `id` at `addr`, or anywhere if it is null.
+    fn shm_attach(
+        &self,
+        id: c_int,
+        addr: *const libc::c_void,
+        flags: c_int,
+    ) -> Result<usize> {
+        let state = &mut *lock();
+        let segment = state.segments.get_mut(&id).ok_or(Errno::EINVAL)?;
+
+        let mut prot = libc::PROT_READ;
+        if flags & ShmatFlag::SHM_RDONLY.bits() == 0 {
+            prot |= libc::PROT_WRITE;
+        }
+        #[cfg(target_os = "linux")]
+        if flags & ShmatFlag::SHM_EXEC.bits() != 0 {
+            prot |= libc::PROT_EXEC;
+        }
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let mut addr = addr as usize;
+        if flags & ShmatFlag::SHM_RND.bits() != 0 {
+            addr -= addr % page_size;
+        }
+        if addr % page_size != 0 {
+            return Err(Errno::EINVAL);
+        }
+        #[allow(unused_mut)]
+        let mut map_flags = libc::MAP_SHARED;
+        #[cfg(target_os = "linux")]
+        if flags & ShmatFlag::SHM_REMAP.bits() != 0 {
+            if addr == 0 {
+                return Err(Errno::EINVAL);
+            }
+            map_flags |= libc::MAP_FIXED;
+        }
+
+        let ptr = unsafe {
+            libc::mmap(
+                addr as *mut libc::c_void,
+                segment.size,
+                prot,
+                map_flags,
+                segment.memory.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(Errno::last());
+        }
+        // The address is only a hint without `MAP_FIXED`, while `shmat`
+        // never maps over an existing mapping without `SHM_REMAP`.
+        if addr != 0 && ptr as usize != addr {
+            unsafe { libc::munmap(ptr, segment.size) };
+            return Err(Errno::EINVAL);
+        }
+        segment.attachments += 1;
+        segment.lpid = pid();
+        segment.atime = now();
+        let replaced =
+            state.attachments.insert(ptr as usize, (id, segment.size));
+        // `SHM_REMAP` replaced another attachment
+        if let Some((replaced, _)) = replaced {
+            state.detached(replaced);
+        }
+        Ok(ptr as usize)
+    }
+
+    fn shm_detach(&self, addr: *const libc::c_void) -> Result<()> {
+        let mut state = lock();
+        let (id, size) = state
+            .attachments
+            .remove(&(addr as usize))
+            .ok_or(Errno::EINVAL)?;
+        unsafe { libc::munmap(addr as *mut libc::c_void, size) };
+        state.detached(id);
+        Ok(())
+    }
+
+    fn shm_control(
+        &self,
+        id: c_int,
+        cmd: c_int,
+        buf: *mut libc::shmid_ds,
+    ) -> Result<()> {
+        let mut state = lock();
+        let segment = state.segments.get_mut(&id).ok_or(Errno::EINVAL)?;
+        match cmd {
+            libc::IPC_STAT => unsafe { buf.write(segment.stat()) },
+            libc::IPC_SET => {
+                segment.perm.read(unsafe { &(*buf).shm_perm });
+                segment.ctime = now();
+            }
+            libc::IPC_RMID => {
+                if segment.remove() {
+                    state.segments.remove(&id);
+                }
+            }
+            #[cfg(target_os = "linux")]
+            libc::SHM_LOCK | libc::SHM_UNLOCK => segment.ctime = now(),
+            _ => return Err(Errno::EINVAL),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "sysvipc_shm")]
+impl State {
+    /// Records that an attachment of the segment `id` is gone, freeing the
+    /// segment if it was the last one of a removed segment.
+    fn detached(&mut self, id: c_int) {
+        if let Some(segment) = self.segments.get_mut(&id) {
+            segment.attachments -= 1;
+            segment.lpid = pid();
+            segment.dtime = now();
+            if segment.removed && segment.attachments == 0 {
+                self.segments.remove(&id);
+            }
+        }
+    }
+}
+
+// -- Semaphores --
+
+/// Maximum value of a semaphore.
+#[cfg(all(
+    feature = "sysvipc_sem",
+    any(
+        apple_targets,
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "linux"
+    )
+))]
+const SEMVMX: i32 = 32767;
+
+/// Maximum number of semaphores in a set.
+#[cfg(all(
+    feature = "sysvipc_sem",
+    any(
+        apple_targets,
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "linux"
+    )
+))]
+const SEMMSL: c_int = 32000;
+
+#[cfg(all(
+    feature = "sysvipc_sem",
+    any(
+        apple_targets,
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "linux"
+    )
+))]
+#[derive(Clone, Copy, Debug, Default)]
+struct Semaphore {
+    value: i32,
+    pid: pid_t,
+    /// Number of operations waiting for the value to increase.
+    ncnt: usize,
+    /// Number of operations waiting for the value to be 0.
+    zcnt: usize,
+}
+
+#[cfg(all(
+    feature = "sysvipc_sem",
+    any(
+        apple_targets,
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "linux"
+    )
+))]
+#[derive(Debug)]
+struct SemaphoreSet {
+    perm: Perm,
+    sems: Vec<Semaphore>,
+    otime: time_t,
+    ctime: time_t,
+}
+
+#[cfg(all(
+    feature = "sysvipc_sem",
+    any(
+        apple_targets,
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "linux"
+    )
+))]
+impl Resource for SemaphoreSet {
+    fn perm(&self) -> &Perm {
+        &self.perm
+    }
+}
+
+#[cfg(all(
+    feature = "sysvipc_sem",
+    any(
+        apple_targets,
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "linux"
+    )
+))]
+impl SemaphoreSet {
+    /// Performs every operation of `sops`, or none of them.
+    ///
+    /// Returns the operation that would block, if any.
+    fn apply<'a>(
+        &mut self,
+        sops: &'a [libc::sembuf],
+    ) -> Result<Option<&'a libc::sembuf>> {
+        let mut values: Vec<_> = self.sems.iter().map(|s| s.value).collect();
+        for sop in sops {
+            let value = &mut values[usize::from(sop.sem_num)];
+            let new = *value + i32::from(sop.sem_op);
+            if (sop.sem_op == 0 && *value != 0) || new < 0 {
+                return Ok(Some(sop));
+            }
+            if new > SEMVMX {
+                return Err(Errno::ERANGE);
+            }
+            *value = new;
+        }
+        let pid = pid();
+        for (sem, value) in self.sems.iter_mut().zip(values) {
+            sem.value = value;
+        }
+        for sop in sops {
+            self.sems[usize::from(sop.sem_num)].pid = pid;
+        }
+        self.otime = now();
+        Ok(None)
+    }
+
+    /// Returns the counter of the operations blocked like `sop`.
+    fn waiters(&mut self, sop: &libc::sembuf) -> &mut usize {
+        let sem = &mut self.sems[usize::from(sop.sem_num)];
+        if sop.sem_op == 0 {
+            &mut sem.zcnt
+        } else {
+            &mut sem.ncnt
+        }
+    }
+
+    #[cfg(not(target_os = "freebsd"))]
+    fn semaphore(&mut self, semnum: c_int) -> Result<&mut Semaphore> {
+        usize::try_from(semnum)
+            .ok()
+            .and_then(|semnum| self.sems.get_mut(semnum))
+            .ok_or(Errno::EINVAL)
+    }
+
+    fn stat(&self) -> libc::semid_ds {
+        let mut ds: libc::semid_ds = unsafe { std::mem::zeroed() };
+        self.perm.write(&mut ds.sem_perm);
+        ds.sem_nsems = self.sems.len() as _;
+        ds.sem_otime = self.otime;
+        ds.sem_ctime = self.ctime;
+        ds
+    }
+}
+
+#[cfg(all(
+    feature = "sysvipc_sem",
+    any(
+        apple_targets,
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "linux"
+    )
+))]
+impl Mock {
+    fn sem_get(&self, key: key_t, nsems: c_int, flags: c_int) -> Result<c_int> {
+        let state = &mut *lock();
+        get(
+            &mut state.sets,
+            &mut state.next_id,
+            key,
+            flags,
+            |set| {
+                if nsems < 0 || nsems as usize > set.sems.len() {
+                    return Err(Errno::EINVAL);
+                }
+                Ok(())
+            },
+            |perm| {
+                if !(1..=SEMMSL).contains(&nsems) {
+                    return Err(Errno::EINVAL);
+                }
+                Ok(SemaphoreSet {
+                    perm,
+                    sems: vec![Semaphore::default(); nsems as usize],
+                    otime: 0,
+                    ctime: now(),
+                })
+            },
+        )
+    }
+
+    fn sem_op(&self, id: c_int, sops: &[libc::sembuf]) -> Result<()> {
+        if sops.is_empty() {
+            return Err(Errno::EINVAL);
+        }
+        let mut state = lock();
+        let mut removed = Errno::EINVAL;
+        loop {
+            let set = state.sets.get_mut(&id).ok_or(removed)?;
+            if sops
+                .iter()
+                .any(|sop| usize::from(sop.sem_num) >= set.sems.len())
+            {
+                return Err(Errno::EFBIG);
+            }
+            let Some(sop) = set.apply(sops)? else {
+                notify();
+                return Ok(());
+            };
+            if c_int::from(sop.sem_flg) & libc::IPC_NOWAIT != 0 {
+                return Err(Errno::EAGAIN);
+            }
+            *set.waiters(sop) += 1;
+            state = wait(state);
+            // Ids are never reused, so a missing set has been removed
+            removed = Errno::EIDRM;
+            if let Some(set) = state.sets.get_mut(&id) {
+                *set.waiters(sop) -= 1;
+            }
+        }
+    }
+
+    #[cfg_attr(target_os = "freebsd", allow(unused_variables))]
+    fn sem_control(
+        &self,
+        id: c_int,
+        semnum: c_int,
+        cmd: c_int,
+        arg: libc::semun,
+    ) -> Result<c_int> {
+        let mut state = lock();
+        let set = state.sets.get_mut(&id).ok_or(Errno::EINVAL)?;
+        let res = match cmd {
+            #[cfg(not(target_os = "freebsd"))]
+            libc::GETVAL => set.semaphore(semnum)?.value,
+            #[cfg(not(target_os = "freebsd"))]
+            libc::GETPID => set.semaphore(semnum)?.pid,
+            #[cfg(not(target_os = "freebsd"))]
+            libc::GETNCNT => set.semaphore(semnum)?.ncnt as c_int,
+            #[cfg(not(target_os = "freebsd"))]
+            libc::GETZCNT => set.semaphore(semnum)?.zcnt as c_int,
+            #[cfg(not(target_os = "freebsd"))]
+            libc::SETVAL => {
+                let value = unsafe { arg.val };
+                if !(0..=SEMVMX).contains(&value) {
+                    return Err(Errno::ERANGE);
+                }
+                let sem = set.semaphore(semnum)?;
+                sem.value = value;
+                sem.pid = pid();
+                set.ctime = now();
+                notify();
+                0
+            }
+            #[cfg(not(target_os = "freebsd"))]
+            libc::GETALL => {
+                let array = unsafe { arg.array };
+                for (i, sem) in set.sems.iter().enumerate() {
+                    unsafe { array.add(i).write(sem.value as _) };
+                }
+                0
+            }
+            #[cfg(not(target_os = "freebsd"))]
+            libc::SETALL => {
+                let array = unsafe { arg.array };
+                let values: Vec<i32> = (0..set.sems.len())
+                    .map(|i| i32::from(unsafe { array.add(i).read() }))
+                    .collect();
+                if values.iter().any(|&value| value > SEMVMX) {
+                    return Err(Errno::ERANGE);
+                }
+                for (sem, value) in set.sems.iter_mut().zip(values) {
+                    sem.value = value;
+                }
+                set.ctime = now();
+                notify();
+                0
+            }
+            libc::IPC_STAT => {
+                unsafe { arg.buf.write(set.stat()) };
+                0
+            }
+            libc::IPC_SET => {
+                set.perm.read(unsafe { &(*arg.buf).sem_perm });
+                set.ctime = now();
+                0
+            }
+            libc::IPC_RMID => {
+                state.sets.remove(&id);
+                notify();
+                0
+            }
+            _ => return Err(Errno::EINVAL),
+        };
+        Ok(res)
+    }
+}
+
+// -- Message queues --
+
+/// Maximum size of a message.
+#[cfg(all(
+    feature = "sysvipc_msg",
+    any(target_os = "android", target_os = "freebsd", target_os = "linux")
+))]
+const MSGMAX: usize = 8192;
+
+/// Default capacity of a queue, in bytes.
+#[cfg(all(
+    feature = "sysvipc_msg",
+    any(target_os = "android", target_os = "freebsd", target_os = "linux")
+))]
+const MSGMNB: usize = 16384;
+
+#[cfg(all(
+    feature = "sysvipc_msg",
+    any(target_os = "android", target_os = "freebsd", target_os = "linux")
+))]
+#[derive(Debug)]
+struct Queue {
+    perm: Perm,
+    messages: VecDeque<(libc::c_long, Vec<u8>)>,
+    bytes: usize,
+    max_bytes: usize,
+    lspid: pid_t,
+    lrpid: pid_t,
+    stime: time_t,
+    rtime: time_t,
+    ctime: time_t,
+}
+
+#[cfg(all(
+    feature = "sysvipc_msg",
+    any(target_os = "android", target_os = "freebsd", target_os = "linux")
+))]
+impl Resource for Queue {
+    fn perm(&self) -> &Perm {
+        &self.perm
+    }
+}
+
+#[cfg(all(
+    feature = "sysvipc_msg",
+    any(target_os = "android", target_os = "freebsd", target_os = "linux")
+))]
+impl Queue {
+    /// Returns the position of the message selected by `msgtyp`, as
+    /// documented by `msgrcv`.
+    fn select(&self, msgtyp: libc::c_long, except: bool) -> Option<usize> {
+        let mut messages = self.messages.iter().map(|(mtype, _)| *mtype);
+        match msgtyp {
+            0 => (!self.messages.is_empty()).then_some(0),
+            _ if msgtyp > 0 => {
+                messages.position(|mtype| (mtype == msgtyp) != except)
+            }
+            _ => messages
+                .enumerate()
+                .filter(|&(_, mtype)| mtype <= -msgtyp)
+                .min_by_key(|&(i, mtype)| (mtype, i))
+                .map(|(i, _)| i),
+        }
+    }
+
+    fn stat(&self) -> libc::msqid_ds {
+        let mut ds: libc::msqid_ds = unsafe { std::mem::zeroed() };
+        self.perm.write(&mut ds.msg_perm);
+        ds.msg_qnum = self.messages.len() as _;
+        #[cfg(target_os = "linux")]
+        {
+            ds.__msg_cbytes = self.bytes as _;
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            ds.msg_cbytes = self.bytes as _;
+        }
+        ds.msg_qbytes = self.max_bytes as _;
+        ds.msg_lspid = self.lspid;
+        ds.msg_lrpid = self.lrpid;
+        ds.msg_stime = self.stime;
+        ds.msg_rtime = self.rtime;
+        ds.msg_ctime = self.ctime;
+        ds
+    }
+}
+
+#[cfg(all(
+    feature = "sysvipc_msg",
+    any(target_os = "android", target_os = "freebsd", target_os = "linux")
+))]
+impl Mock {
+    fn msg_get(&self, key: key_t, flags: c_int) -> Result<c_int> {
+        let state = &mut *lock();
+        get(
+            &mut state.queues,
+            &mut state.next_id,
+            key,
+            flags,
+            |_| Ok(()),
+            |perm| {
+                Ok(Queue {
+                    perm,
+                    messages: VecDeque::new(),
+                    bytes: 0,
+                    max_bytes: MSGMNB,
+                    lspid: 0,
+                    lrpid: 0,
+                    stime: 0,
+                    rtime: 0,
+                    ctime: now(),
+                })
+            },
+        )
+    }
+
+    fn msg_send(
+        &self,
+        id: c_int,
+        mtype: libc::c_long,
+        data: &[u8],
+        flags: c_int,
+    ) -> Result<()> {
+        if mtype < 1 || data.len() > MSGMAX {
+            return Err(Errno::EINVAL);
+        }
+        let mut state = lock();
+        let mut removed = Errno::EINVAL;
+        loop {
+            let queue = state.queues.get_mut(&id).ok_or(removed)?;
+            if data.len() > queue.max_bytes {
+                return Err(Errno::EINVAL);
+            }
+            if queue.bytes + data.len() <= queue.max_bytes {
+                queue.messages.push_back((mtype, data.to_vec()));
+                queue.bytes += data.len();
+                queue.lspid = pid();
+                queue.stime = now();
+                notify();
+                return Ok(());
+            }
+            if flags & libc::IPC_NOWAIT != 0 {
+                return Err(Errno::EAGAIN);
+            }
+            state = wait(state);
+            // Ids are never reused, so a missing queue has been removed
+            removed = Errno::EIDRM;
+        }
+    }
+
+    /// Receives the message selected by `msgtyp`, writing its type and at
+    /// most `msgsz` bytes of it to `msgp`, and returns its size.
+    fn msg_receive(
+        &self,
+        id: c_int,
+        msgp: *mut libc::c_void,
+        msgsz: usize,
+        msgtyp: libc::c_long,
+        flags: c_int,
+    ) -> Result<usize> {
+        #[cfg(target_os = "linux")]
+        let (copy, except) =
+            (flags & libc::MSG_COPY != 0, flags & libc::MSG_EXCEPT != 0);
+        #[cfg(not(target_os = "linux"))]
+        let (copy, except) = (false, false);
+        if copy && (flags & libc::IPC_NOWAIT == 0 || except) {
+            return Err(Errno::EINVAL);
+        }
+        let mut state = lock();
+        let mut removed = Errno::EINVAL;
+        loop {
+            let queue = state.queues.get_mut(&id).ok_or(removed)?;
+            let selected = if copy {
+                // The type is the position of the message to copy
+                usize::try_from(msgtyp)
+                    .ok()
+                    .filter(|&index| index < queue.messages.len())
+            } else {
+                queue.select(msgtyp, except)
+            };
+            if let Some(index) = selected {
+                let (mtype, data) = &queue.messages[index];
+                if data.len() > msgsz && flags & libc::MSG_NOERROR == 0 {
+                    return Err(Errno::E2BIG);
+                }
+                let len = data.len().min(msgsz);
+                unsafe {
+                    let mtext = msgp.cast::<libc::c_long>().add(1).cast::<u8>();
+                    msgp.cast::<libc::c_long>().write_unaligned(*mtype);
+                    mtext.copy_from_nonoverlapping(data.as_ptr(), len);
+                }
+                if !copy {
+                    if let Some((_, data)) = queue.messages.remove(index) {
+                        queue.bytes -= data.len();
+                    }
+                    queue.lrpid = pid();
+                    queue.rtime = now();
+                    notify();
+                }
+                return Ok(len);
+            }
+            if flags & libc::IPC_NOWAIT != 0 {
+                return Err(Errno::ENOMSG);
+            }
+            state = wait(state);
+            // Ids are never reused, so a missing queue has been removed
+            removed = Errno::EIDRM;
+        }
+    }
+
+    fn msg_control(
+        &self,
+        id: c_int,
+        cmd: c_int,
+        buf: *mut libc::msqid_ds,
+    ) -> Result<()> {
+        let mut state = lock();
+        let queue = state.queues.get_mut(&id).ok_or(Errno::EINVAL)?;
+        match cmd {
+            libc::IPC_STAT => unsafe { buf.write(queue.stat()) },
+            libc::IPC_SET => {
+                let ds = unsafe { &*buf };
+                queue.perm.read(&ds.msg_perm);
+                queue.max_bytes = ds.msg_qbytes as usize;
+                queue.ctime = now();
+                notify();
+            }
+            libc::IPC_RMID => {
+                state.queues.remove(&id);
+                notify();
+            }
+            _ => return Err(Errno::EINVAL),
+        }
+        Ok(())
+    }
+}
+
+impl IpcBackend for Mock {
+    #[cfg(feature = "sysvipc_shm")]
+    unsafe fn shmget(&self, key: key_t, size: usize, shmflg: c_int) -> c_int {
+        ret(self.shm_get(key, size, shmflg))
+    }
+
+    #[cfg(feature = "sysvipc_shm")]
+    unsafe fn shmat(
+        &self,
+        shmid: c_int,
+        shmaddr: *const libc::c_void,
+        shmflg: c_int,
+    ) -> *mut libc::c_void {
+        let res = self.shm_attach(shmid, shmaddr, shmflg);
+        ret(res.map(|addr| addr as isize)) as *mut libc::c_void
+    }
+
+    #[cfg(feature = "sysvipc_shm")]
+    unsafe fn shmdt(&self, shmaddr: *const libc::c_void) -> c_int {
+        ret(self.shm_detach(shmaddr).map(|_| 0))
+    }
+
+    #[cfg(feature = "sysvipc_shm")]
+    unsafe fn shmctl(
+        &self,
+        shmid: c_int,
+        cmd: c_int,
+        buf: *mut libc::shmid_ds,
+    ) -> c_int {
+        ret(self.shm_control(shmid, cmd, buf).map(|_| 0))
+    }
+
+    #[cfg(all(
+        feature = "sysvipc_sem",
+        any(
+            apple_targets,
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        )
+    ))]
+    unsafe fn semget(&self, key: key_t, nsems: c_int, semflg: c_int) -> c_int {
+        ret(self.sem_get(key, nsems, semflg))
+    }
+
+    #[cfg(all(
+        feature = "sysvipc_sem",
+        any(
+            apple_targets,
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        )
+    ))]
+    unsafe fn semop(
+        &self,
+        semid: c_int,
+        sops: *mut libc::sembuf,
+        nsops: usize,
+    ) -> c_int {
+        let sops = unsafe { std::slice::from_raw_parts(sops, nsops) };
+        ret(self.sem_op(semid, sops).map(|_| 0))
+    }
+
+    #[cfg(all(
+        feature = "sysvipc_sem",
+        any(
+            apple_targets,
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        )
+    ))]
+    unsafe fn semctl(
+        &self,
+        semid: c_int,
+        semnum: c_int,
+        cmd: c_int,
+        arg: libc::semun,
+    ) -> c_int {
+        ret(self.sem_control(semid, semnum, cmd, arg))
+    }
+
+    #[cfg(all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    unsafe fn msgget(&self, key: key_t, msgflg: c_int) -> c_int {
+        ret(self.msg_get(key, msgflg))
+    }
+
+    #[cfg(all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    unsafe fn msgsnd(
+        &self,
+        msqid: c_int,
+        msgp: *const libc::c_void,
+        msgsz: usize,
+        msgflg: c_int,
+    ) -> c_int {
+        let msgp = msgp.cast::<libc::c_long>();
+        let (mtype, data) = unsafe {
+            let mtext = msgp.add(1).cast::<u8>();
+            (
+                msgp.read_unaligned(),
+                std::slice::from_raw_parts(mtext, msgsz),
+            )
+        };
+        ret(self.msg_send(msqid, mtype, data, msgflg).map(|_| 0))
+    }
+
+    #[cfg(all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    unsafe fn msgrcv(
+        &self,
+        msqid: c_int,
+        msgp: *mut libc::c_void,
+        msgsz: usize,
+        msgtyp: libc::c_long,
+        msgflg: c_int,
+    ) -> libc::ssize_t {
+        let res = self.msg_receive(msqid, msgp, msgsz, msgtyp, msgflg);
+        ret(res.map(|len| len as libc::ssize_t))
+    }
+
+    #[cfg(all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    unsafe fn msgctl(
+        &self,
+        msqid: c_int,
+        cmd: c_int,
+        buf: *mut libc::msqid_ds,
+    ) -> c_int {
+        ret(self.msg_control(msqid, cmd, buf).map(|_| 0))
+    }
+}
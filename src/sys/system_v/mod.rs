@@ -0,0 +1,1092 @@
+//! SystemV inter-process communication
+//!
+//! SystemV IPC resources live in the kernel, independently of the processes
+//! using them, and are identified system-wide by a key:
+//!
+//! * [`shm`]: shared memory segments.
+//! * [`sem`]: semaphore sets.
+//! * [`msg`]: message queues.
+//!
+//! [`sync`] builds locks shared between processes on top of them,
+//! [`channel`] sends values between processes, [`bench`] measures their
+//! performance on the host, and, on Linux, [`info`] lists the resources
+//! existing on the system.
+//!
+//! Resources must be removed explicitly, see [`scope`] to tie their lifetime
+//! to a block of code.
+//!
+//! Each family sits behind its own feature: `sysvipc_shm`, `sysvipc_sem` and
+//! `sysvipc_msg`. `sysvipc_sync` enables [`sync`], on top of the first two,
+//! and `sysvipc` enables all of them. [`channel`] provides the channels whose
+//! family is enabled, and the `async` feature adds adapters for the tokio
+//! runtime to the enabled families. The `serde` feature implements
+//! `Serialize` and `Deserialize` for the plain-data types, such as the status
+//! of the resources, with the owners, processes and permissions as raw
+//! numbers. The `sysvipc_serialized` feature adds `shm::SerializedShm`,
+//! storing any serializable value in a segment with `bincode`. The `mock`
+//! feature adds the `mock` module, emulating every resource in memory so that
+//! code using them can be tested without kernel support, and the `testing`
+//! feature adds the `testing` module, giving each test keys of its own. The
+//! `tracing` feature records every system call, with its arguments and
+//! result, as a span of the [`tracing`] crate.
+//!
+//! [`tracing`]: https://docs.rs/tracing
+//!
+//! Kernels can be built without SystemV IPC, and jails or sandboxes can
+//! forbid it, in which case every operation fails with [`Errno::ENOSYS`]:
+//! check [`is_supported`] before relying on it. On Android, the module
+//! builds, but the operations always fail this way, as applications are not
+//! allowed to use SystemV IPC.
+//!
+//! The module only exists on Apple targets, DragonFly BSD, FreeBSD, Android
+//! and Linux. It is left out, rather than stubbed, on the targets where libc
+//! has no bindings for SystemV IPC: Redox, which lacks it, and Cygwin, which
+//! emulates it through `cygserver`.
+
+use std::mem::MaybeUninit;
+use std::time::{Duration, SystemTime};
+use std::{cell::RefCell, fmt};
+
+use crate::errno::Errno;
+use crate::sys::stat::Mode;
+use crate::{NixPath, Result};
+
+use self::ffi::{self as libc, c_int, key_t};
+
+#[cfg(any(
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "linux"
+))]
+#[cfg(feature = "sysvipc_msg")]
+use self::msg::MessageQueue;
+#[cfg(any(
+    apple_targets,
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "linux"
+))]
+#[cfg(feature = "sysvipc_sem")]
+use self::sem::SemaphoreSet;
+#[cfg(feature = "sysvipc_shm")]
+use self::shm::Shm;
+
+feature! {
+#![feature = "sysvipc_shm"]
+pub mod bench;
+}
+feature! {
+#![any(feature = "sysvipc_msg", feature = "sysvipc_shm")]
+pub mod channel;
+}
+#[cfg(target_os = "linux")]
+feature! {
+#![feature = "sysvipc_shm"]
+pub mod info;
+}
+#[cfg(any(target_os = "android", target_os = "freebsd", target_os = "linux"))]
+feature! {
+#![feature = "sysvipc_msg"]
+pub mod msg;
+}
+feature! {
+#![feature = "mock"]
+pub mod mock;
+}
+#[cfg(any(
+    apple_targets,
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "linux"
+))]
+feature! {
+#![feature = "sysvipc_sem"]
+pub mod sem;
+}
+feature! {
+#![feature = "sysvipc_shm"]
+pub mod shm;
+}
+#[cfg(any(apple_targets, target_os = "linux"))]
+feature! {
+#![feature = "sysvipc_sync"]
+pub mod sync;
+}
+feature! {
+#![feature = "testing"]
+pub mod testing;
+}
+
+mod ffi;
+#[cfg(feature = "serde")]
+mod serde_impls;
+
+/// Whether the system allows SystemV IPC.
+///
+/// Kernels can be built without it, and jails or sandboxes can forbid the
+/// related system calls, in which case every operation fails with
+/// [`SysvError::Unsupported`], or [`Errno::ENOSYS`]. This is always the
+/// case on Android. This always returns `true` once the `mock` backend is
+/// installed.
+///
+/// This only queries a nonexistent segment, without creating any resource.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::sys::system_v;
+/// if !system_v::is_supported() {
+///     eprintln!("SystemV IPC is unavailable, falling back to pipes");
+/// }
+/// ```
+pub fn is_supported() -> bool {
+    #[cfg(feature = "mock")]
+    if mock::is_installed() {
+        return true;
+    }
+    #[cfg(target_os = "android")]
+    return false;
+    // The kernel checks the identifier, which never matches a segment, once
+    // it knows the system call
+    #[cfg(not(target_os = "android"))]
+    match Errno::result(unsafe {
+        ::libc::shmctl(-1, libc::IPC_STAT, std::ptr::null_mut())
+    }) {
+        Ok(_) => true,
+        Err(e) => !matches!(e, Errno::ENOSYS | Errno::EPERM),
+    }
+}
+
+/// Key identifying a SystemV IPC resource system-wide.
+///
+/// Keys are usually derived from an existing file with [`Key::ftok`], so
+/// that unrelated processes agree on them without any other coordination.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::Key;
+/// let key = Key::ftok("/tmp", b'N')?;
+/// # Ok::<(), Errno>(())
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Key(key_t);
+
+impl Key {
+    /// Key always creating a new resource, only reachable through its id.
+    pub const IPC_PRIVATE: Self = Self(libc::IPC_PRIVATE);
+
+    /// Creates a key from its raw value.
+    pub const fn new(key: key_t) -> Self {
+        Self(key)
+    }
+
+    /// Derives a key from the identity of the existing file `path` and from
+    /// `proj_id`, which must not be 0.
+    ///
+    /// For more information, see [`ftok(3)`].
+    ///
+    /// [`ftok(3)`]: https://man7.org/linux/man-pages/man3/ftok.3.html
+    pub fn ftok<P: ?Sized + NixPath>(path: &P, proj_id: u8) -> Result<Self> {
+        if proj_id == 0 {
+            return Err(Errno::EINVAL);
+        }
+        let key = path.with_nix_path(|cstr| unsafe {
+            libc::ftok(cstr.as_ptr(), c_int::from(proj_id))
+        })?;
+        Errno::result(key).map(Self)
+    }
+
+    /// Returns the raw value of the key.
+    pub const fn as_raw(self) -> key_t {
+        self.0
+    }
+
+    /// Returns a random key, which is never [`Key::IPC_PRIVATE`].
+    #[cfg(any(feature = "sysvipc_shm", feature = "testing"))]
+    fn random() -> Self {
+        use std::collections::hash_map::RandomState;
+        use std::hash::{BuildHasher, Hasher};
+
+        loop {
+            // Each `RandomState` is seeded differently, and the seeds of
+            // other processes are unrelated
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u32(std::process::id());
+            let key = hasher.finish() as key_t;
+            if key != libc::IPC_PRIVATE {
+                return Self(key);
+            }
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    /// Formats the key in hexadecimal, as `ipcs` does.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#010x}", self.0)
+    }
+}
+
+/// Permissions of a SystemV IPC resource.
+///
+/// This is a thin layer over [`Mode`], only keeping the permission bits the
+/// kernel stores for IPC resources, with constructors for the usual cases.
+/// Every function creating a resource, or changing its permissions, accepts
+/// either an `IpcMode` or a [`Mode`], and the status of a resource reports
+/// its permissions as an `IpcMode`. Attaching or controlling a resource never
+/// takes permissions.
+///
+/// # Example
+///
+/// ```
+/// # use nix::sys::system_v::IpcMode;
+/// # use nix::sys::stat::Mode;
+/// let mode = IpcMode::owner_rw().with(Mode::S_IRGRP);
+/// assert_eq!(mode, IpcMode::group_ro());
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct IpcMode(Mode);
+
+impl IpcMode {
+    /// Read and write for the owner only, `0o600`.
+    pub const fn owner_rw() -> Self {
+        Self(Mode::from_bits_truncate(0o600))
+    }
+
+    /// Read only for the owner only, `0o400`.
+    pub const fn owner_ro() -> Self {
+        Self(Mode::from_bits_truncate(0o400))
+    }
+
+    /// Read and write for the owner, read only for its group, `0o640`.
+    pub const fn group_ro() -> Self {
+        Self(Mode::from_bits_truncate(0o640))
+    }
+
+    /// Read and write for the owner and its group, `0o660`.
+    pub const fn group_rw() -> Self {
+        Self(Mode::from_bits_truncate(0o660))
+    }
+
+    /// Read and write for the owner, read only for everyone else, `0o644`.
+    pub const fn world_ro() -> Self {
+        Self(Mode::from_bits_truncate(0o644))
+    }
+
+    /// Read and write for everyone, `0o666`.
+    pub const fn world_rw() -> Self {
+        Self(Mode::from_bits_truncate(0o666))
+    }
+
+    /// Returns these permissions, extended with `mode`.
+    pub const fn with(self, mode: Mode) -> Self {
+        Self::from_mode(self.0.union(mode))
+    }
+
+    /// Returns these permissions, without the ones in `mode`.
+    pub const fn without(self, mode: Mode) -> Self {
+        Self(self.0.difference(mode))
+    }
+
+    /// Returns the permissions as a [`Mode`].
+    pub const fn mode(self) -> Mode {
+        self.0
+    }
+
+    const fn from_mode(mode: Mode) -> Self {
+        Self(mode.intersection(Mode::from_bits_truncate(0o777)))
+    }
+}
+
+impl From<Mode> for IpcMode {
+    /// Keeps the permission bits of `mode`, ignoring the file type and the
+    /// set-user-ID, set-group-ID and sticky bits.
+    fn from(mode: Mode) -> Self {
+        Self::from_mode(mode)
+    }
+}
+
+impl From<IpcMode> for Mode {
+    fn from(mode: IpcMode) -> Self {
+        mode.0
+    }
+}
+
+/// Kind of a SystemV IPC resource.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum IpcKind {
+    /// Shared memory segment.
+    #[cfg(feature = "sysvipc_shm")]
+    SharedMemory,
+    /// Semaphore set.
+    #[cfg(all(
+        feature = "sysvipc_sem",
+        any(
+            apple_targets,
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        )
+    ))]
+    SemaphoreSet,
+    /// Message queue.
+    #[cfg(all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    MessageQueue,
+}
+
+impl fmt::Display for IpcKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "sysvipc_shm")]
+            IpcKind::SharedMemory => f.write_str("shm"),
+            #[cfg(all(
+                feature = "sysvipc_sem",
+                any(
+                    apple_targets,
+                    target_os = "android",
+                    target_os = "freebsd",
+                    target_os = "linux"
+                )
+            ))]
+            IpcKind::SemaphoreSet => f.write_str("sem"),
+            #[cfg(all(
+                feature = "sysvipc_msg",
+                any(
+                    target_os = "android",
+                    target_os = "freebsd",
+                    target_os = "linux"
+                )
+            ))]
+            IpcKind::MessageQueue => f.write_str("msg"),
+        }
+    }
+}
+
+/// Unambiguous identity of a SystemV IPC resource.
+///
+/// Keys and ids are only unique within an IPC namespace, so the identity also
+/// records the namespace of the calling process, when it is known. This keeps
+/// logs collected from several hosts or containers unambiguous.
+///
+/// The [`Display`](fmt::Display) implementation follows the `ipcs` notation,
+/// for example `shm key=0x00000539 id=32768 ipcns=4026531839`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IpcIdentity {
+    /// Kind of the resource.
+    pub kind: IpcKind,
+    /// Key the resource was created or retrieved with.
+    pub key: Key,
+    /// Identifier of the resource, as returned by the kernel.
+    pub id: c_int,
+    /// Inode number of the IPC namespace of the calling process, if known.
+    pub namespace: Option<u64>,
+}
+
+impl IpcIdentity {
+    pub(super) fn new(kind: IpcKind, key: Key, id: c_int) -> Self {
+        Self {
+            kind,
+            key,
+            id,
+            namespace: ipc_namespace(),
+        }
+    }
+}
+
+impl fmt::Display for IpcIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} key={} id={}", self.kind, self.key, self.id)?;
+        if let Some(namespace) = self.namespace {
+            write!(f, " ipcns={namespace}")?;
+        }
+        Ok(())
+    }
+}
+
+/// System call of a failed SystemV IPC operation, see [`SysvError`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Operation {
+    /// Getting or creating a shared memory segment.
+    Shmget,
+    /// Attaching a shared memory segment.
+    Shmat,
+    /// Detaching a shared memory segment.
+    Shmdt,
+    /// Controlling a shared memory segment.
+    Shmctl,
+    /// Getting or creating a semaphore set.
+    Semget,
+    /// Operating on the semaphores of a set.
+    Semop,
+    /// Controlling a semaphore set.
+    Semctl,
+    /// Getting or creating a message queue.
+    Msgget,
+    /// Sending a message.
+    Msgsnd,
+    /// Receiving a message.
+    Msgrcv,
+    /// Controlling a message queue.
+    Msgctl,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operation::Shmget => "shmget",
+            Operation::Shmat => "shmat",
+            Operation::Shmdt => "shmdt",
+            Operation::Shmctl => "shmctl",
+            Operation::Semget => "semget",
+            Operation::Semop => "semop",
+            Operation::Semctl => "semctl",
+            Operation::Msgget => "msgget",
+            Operation::Msgsnd => "msgsnd",
+            Operation::Msgrcv => "msgrcv",
+            Operation::Msgctl => "msgctl",
+        })
+    }
+}
+
+/// Error of a SystemV IPC operation.
+///
+/// The kernel reports unrelated failures with the same [`Errno`]: `shmat`
+/// fails with [`Errno::EINVAL`] both when the segment was removed and when
+/// the address is invalid, and `shmget` when an existing segment is too
+/// small. The variants tell them apart, and record the system call that
+/// failed.
+///
+/// It converts back to the [`Errno`] the system call failed with, so `?`
+/// still works in functions returning a [`nix::Result`](crate::Result), and
+/// it compares equal to that [`Errno`].
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::sys::system_v::shm::*;
+/// # use nix::sys::system_v::{IpcMode, Key, SysvError};
+/// const MY_KEY: Key = Key::new(1337);
+/// match SharedMemory::<[u64; 16]>::attach_uninit(MY_KEY, IpcMode::owner_rw()) {
+///     Ok(_) => println!("Attached"),
+///     Err(SysvError::SizeMismatch { expected, actual, .. }) => {
+///         eprintln!("Expected {expected} bytes, the segment has {actual}")
+///     }
+///     Err(e) => eprintln!("{e}"),
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum SysvError {
+    /// The resource was removed, or its identifier is not valid anymore.
+    SegmentRemoved {
+        /// Failed system call.
+        operation: Operation,
+        /// Either [`Errno::EIDRM`] or [`Errno::EINVAL`].
+        errno: Errno,
+    },
+    /// The existing segment is smaller than requested, which the kernel
+    /// reports as [`Errno::EINVAL`].
+    SizeMismatch {
+        /// Failed system call.
+        operation: Operation,
+        /// Size requested, in bytes.
+        expected: usize,
+        /// Size of the segment, in bytes.
+        actual: usize,
+    },
+    /// The caller lacks the permissions, or the capability, to perform the
+    /// operation.
+    PermissionDenied {
+        /// Failed system call.
+        operation: Operation,
+        /// Either [`Errno::EACCES`] or [`Errno::EPERM`].
+        errno: Errno,
+    },
+    /// The system does not support SystemV IPC, which the kernel reports as
+    /// [`Errno::ENOSYS`]. See [`is_supported`].
+    Unsupported {
+        /// Failed system call.
+        operation: Operation,
+    },
+    /// Any other failure.
+    Other {
+        /// Failed system call.
+        operation: Operation,
+        /// Error of the system call.
+        errno: Errno,
+    },
+}
+
+impl SysvError {
+    /// Returns the error the system call failed with.
+    pub fn errno(&self) -> Errno {
+        match *self {
+            SysvError::SegmentRemoved { errno, .. }
+            | SysvError::PermissionDenied { errno, .. }
+            | SysvError::Other { errno, .. } => errno,
+            SysvError::SizeMismatch { .. } => Errno::EINVAL,
+            SysvError::Unsupported { .. } => Errno::ENOSYS,
+        }
+    }
+
+    /// Returns the system call that failed.
+    pub fn operation(&self) -> Operation {
+        match *self {
+            SysvError::SegmentRemoved { operation, .. }
+            | SysvError::SizeMismatch { operation, .. }
+            | SysvError::PermissionDenied { operation, .. }
+            | SysvError::Unsupported { operation }
+            | SysvError::Other { operation, .. } => operation,
+        }
+    }
+
+    /// Classifies the failure of `operation` with `errno`, when it does not
+    /// take a second look at the resource to tell it apart.
+    fn new(operation: Operation, errno: Errno) -> Self {
+        match errno {
+            Errno::EIDRM => SysvError::SegmentRemoved { operation, errno },
+            Errno::EACCES | Errno::EPERM => {
+                SysvError::PermissionDenied { operation, errno }
+            }
+            Errno::ENOSYS => SysvError::Unsupported { operation },
+            errno => SysvError::Other { operation, errno },
+        }
+    }
+}
+
+impl fmt::Display for SysvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            SysvError::SegmentRemoved { operation, errno } => {
+                write!(f, "{operation}: the resource was removed ({errno})")
+            }
+            SysvError::SizeMismatch {
+                operation,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{operation}: {expected} bytes requested, but the segment \
+                 holds {actual}"
+            ),
+            SysvError::PermissionDenied { operation, errno } => {
+                write!(f, "{operation}: permission denied ({errno})")
+            }
+            SysvError::Unsupported { operation } => {
+                write!(f, "{operation}: SystemV IPC is not supported")
+            }
+            SysvError::Other { operation, errno } => {
+                write!(f, "{operation}: {errno}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SysvError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SysvError::SegmentRemoved { errno, .. }
+            | SysvError::PermissionDenied { errno, .. }
+            | SysvError::Other { errno, .. } => Some(errno),
+            SysvError::SizeMismatch { .. } => None,
+            SysvError::Unsupported { .. } => Some(&Errno::ENOSYS),
+        }
+    }
+}
+
+impl From<SysvError> for Errno {
+    fn from(error: SysvError) -> Self {
+        error.errno()
+    }
+}
+
+impl PartialEq<Errno> for SysvError {
+    fn eq(&self, other: &Errno) -> bool {
+        self.errno() == *other
+    }
+}
+
+impl PartialEq<SysvError> for Errno {
+    fn eq(&self, other: &SysvError) -> bool {
+        *self == other.errno()
+    }
+}
+
+/// Result of the SystemV IPC operations failing with a [`SysvError`].
+pub type SysvResult<T> = std::result::Result<T, SysvError>;
+
+/// Types that keep their meaning when shared with other processes, and may
+/// thus be stored in a System V shared memory segment.
+///
+/// Every constructor of [`shm::SharedMemory`], and every typed
+/// [`msg::MessageQueue`], requires it, to reject types whose values only
+/// make sense in the process that made them, such as a `String`, a `Box` or
+/// a [`std::sync::Mutex`].
+///
+/// # Safety
+///
+/// The type must not hold pointers, references, or resources owned by a
+/// process, like heap allocations, file descriptors or locks that are not
+/// process-shared.
+///
+/// # Example
+///
+/// ```
+/// # use nix::sys::system_v::SysvSafe;
+/// #[repr(C)]
+/// struct Position {
+///     x: f64,
+///     y: f64,
+///     frame: u64,
+/// }
+///
+/// // Only made of numbers, which are meaningful in any process
+/// unsafe impl SysvSafe for Position {}
+/// ```
+///
+/// A `String` points to memory of the process that allocated it, so this
+/// fails to compile:
+///
+/// ```compile_fail
+/// # use nix::sys::system_v::shm::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// const MY_KEY: Key = Key::new(1337);
+/// let name = String::from("shared");
+/// let memory = SharedMemory::new_init(MY_KEY, IpcMode::owner_rw(), name);
+/// ```
+pub unsafe trait SysvSafe {}
+
+macro_rules! impl_sysv_safe {
+    ($($t:ty),*) => {
+        $(unsafe impl SysvSafe for $t {})*
+    };
+}
+
+impl_sysv_safe!(
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64,
+    bool,
+    char,
+    ()
+);
+impl_sysv_safe!(
+    std::sync::atomic::AtomicBool,
+    std::sync::atomic::AtomicU8,
+    std::sync::atomic::AtomicU16,
+    std::sync::atomic::AtomicU32,
+    std::sync::atomic::AtomicUsize,
+    std::sync::atomic::AtomicI8,
+    std::sync::atomic::AtomicI16,
+    std::sync::atomic::AtomicI32,
+    std::sync::atomic::AtomicIsize
+);
+#[cfg(target_has_atomic = "64")]
+impl_sysv_safe!(std::sync::atomic::AtomicU64, std::sync::atomic::AtomicI64);
+unsafe impl<T: SysvSafe> SysvSafe for MaybeUninit<T> {}
+unsafe impl<T: SysvSafe, const N: usize> SysvSafe for [T; N] {}
+unsafe impl<T: SysvSafe> SysvSafe for [T] {}
+
+/// Types for which a value made of zero bytes is valid, such as the fresh
+/// content of a segment.
+///
+/// # Safety
+///
+/// An all-zero bit pattern must be a valid value of the type.
+pub unsafe trait Zeroable {}
+
+macro_rules! impl_zeroable {
+    ($($t:ty),*) => {
+        $(unsafe impl Zeroable for $t {})*
+    };
+}
+
+impl_zeroable!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64,
+    bool, char
+);
+unsafe impl<T> Zeroable for MaybeUninit<T> {}
+unsafe impl<T: Zeroable, const N: usize> Zeroable for [T; N] {}
+unsafe impl<T: Zeroable> Zeroable for [T] {}
+
+/// Plain old data, whose values are nothing but their bytes, see
+/// [`shm::SharedMemory::as_bytes`].
+///
+/// # Safety
+///
+/// The type must not have padding bytes, and any bit pattern must be a
+/// valid value of the type.
+///
+/// # Example
+///
+/// ```
+/// # use nix::sys::system_v::{Pod, SysvSafe, Zeroable};
+/// #[repr(C)]
+/// struct Header {
+///     version: u32,
+///     flags: u32,
+///     length: u64,
+/// }
+///
+/// unsafe impl SysvSafe for Header {}
+/// unsafe impl Zeroable for Header {}
+/// // No padding, and only integers
+/// unsafe impl Pod for Header {}
+/// ```
+pub unsafe trait Pod: SysvSafe + Zeroable {}
+
+macro_rules! impl_pod {
+    ($($t:ty),*) => {
+        $(unsafe impl Pod for $t {})*
+    };
+}
+
+impl_pod!(
+    u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64
+);
+unsafe impl<T: Pod, const N: usize> Pod for [T; N] {}
+unsafe impl<T: Pod> Pod for [T] {}
+
+/// Returns the inode number of the IPC namespace of the calling process.
+fn ipc_namespace() -> Option<u64> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::metadata("/proc/self/ns/ipc").ok().map(|m| m.ino())
+    }
+    #[cfg(not(target_os = "linux"))]
+    None
+}
+
+/// Converts a time in seconds since the epoch.
+///
+/// Times before the epoch are kept, and the times `SystemTime` cannot
+/// represent are clamped to the epoch rather than panicking, as they can come
+/// from a corrupted or forged `/proc` entry.
+fn time(secs: i64) -> SystemTime {
+    let offset = Duration::from_secs(secs.unsigned_abs());
+    let time = if secs < 0 {
+        SystemTime::UNIX_EPOCH.checked_sub(offset)
+    } else {
+        SystemTime::UNIX_EPOCH.checked_add(offset)
+    };
+    time.unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// Converts a time in seconds since the epoch, where 0 stands for never.
+fn optional_time(secs: i64) -> Option<SystemTime> {
+    (secs != 0).then(|| time(secs))
+}
+
+/// Delays between the attempts of an operation polled with `IPC_NOWAIT`,
+/// for the resources that cannot be waited for with a timeout.
+///
+/// The delay doubles from 50µs up to 10ms, trading latency for CPU time as
+/// the wait gets longer.
+#[cfg(any(
+    all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ),
+    all(
+        feature = "sysvipc_sem",
+        any(
+            apple_targets,
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        )
+    )
+))]
+#[derive(Debug)]
+struct Backoff(std::time::Duration);
+
+#[cfg(any(
+    all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ),
+    all(
+        feature = "sysvipc_sem",
+        any(
+            apple_targets,
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        )
+    )
+))]
+impl Backoff {
+    const MIN: std::time::Duration = std::time::Duration::from_micros(50);
+    const MAX: std::time::Duration = std::time::Duration::from_millis(10);
+
+    fn new() -> Self {
+        Self(Self::MIN)
+    }
+
+    /// Returns the delay before the next attempt.
+    fn next_delay(&mut self) -> std::time::Duration {
+        let delay = self.0;
+        self.0 = (delay * 2).min(Self::MAX);
+        delay
+    }
+}
+
+/// Attempts `f` until it does not fail with `would_block`, sleeping with
+/// [`Backoff`] in between without blocking the tokio runtime.
+#[cfg(all(
+    feature = "async",
+    any(
+        all(
+            feature = "sysvipc_msg",
+            any(
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "linux"
+            )
+        ),
+        all(
+            feature = "sysvipc_sem",
+            any(
+                apple_targets,
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "linux"
+            )
+        )
+    )
+))]
+async fn poll_nowait<R>(
+    would_block: Errno,
+    mut f: impl FnMut() -> Result<R>,
+) -> Result<R> {
+    let mut backoff = Backoff::new();
+    loop {
+        match f() {
+            Err(e) if e == would_block || e == Errno::EINTR => {
+                tokio::time::sleep(backoff.next_delay()).await
+            }
+            res => return res,
+        }
+    }
+}
+
+/// Owner of every SystemV resource created during a call to [`scope`].
+///
+/// Resources created through the scope, or adopted with [`Scope::register`],
+/// are removed when the scope exits, in the reverse order of their
+/// registration.
+#[derive(Debug, Default)]
+pub struct Scope {
+    resources: RefCell<Vec<(IpcKind, c_int)>>,
+}
+
+impl Scope {
+    /// Creates a new System V shared memory segment owned by this scope.
+    ///
+    /// See [`Shm::create_and_connect`].
+    #[cfg(feature = "sysvipc_shm")]
+    pub fn shared_memory<T>(
+        &self,
+        key: Key,
+        mode: impl Into<IpcMode>,
+    ) -> Result<Shm<T>> {
+        let shm = Shm::<T>::create_and_connect(key, mode)?;
+        self.register(shm.identity());
+        Ok(shm)
+    }
+
+    /// Creates a new System V semaphore set owned by this scope.
+    ///
+    /// See [`SemaphoreSet::create`].
+    #[cfg(all(
+        feature = "sysvipc_sem",
+        any(
+            apple_targets,
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        )
+    ))]
+    pub fn semaphore_set(
+        &self,
+        key: Key,
+        nsems: usize,
+        mode: impl Into<IpcMode>,
+    ) -> Result<SemaphoreSet> {
+        let sem = SemaphoreSet::create(key, nsems, mode)?;
+        self.register(sem.identity());
+        Ok(sem)
+    }
+
+    /// Creates a new System V message queue owned by this scope.
+    ///
+    /// See [`MessageQueue::create`].
+    #[cfg(all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    pub fn message_queue(
+        &self,
+        key: Key,
+        mode: impl Into<IpcMode>,
+    ) -> Result<MessageQueue> {
+        let queue = MessageQueue::create(key, mode)?;
+        self.register(queue.identity());
+        Ok(queue)
+    }
+
+    /// Hands over the removal of an already existing resource to this scope.
+    pub fn register(&self, identity: IpcIdentity) {
+        self.resources
+            .borrow_mut()
+            .push((identity.kind, identity.id));
+    }
+
+    // -- Private --
+
+    /// Removes every registered resource, most recent first.
+    ///
+    /// Every resource is processed even if one of them fails, and the first
+    /// error is returned.
+    fn remove_all(&self) -> Result<()> {
+        let mut result = Ok(());
+        while let Some((kind, id)) = self.resources.borrow_mut().pop() {
+            result = result.and(remove_resource(kind, id));
+        }
+        result
+    }
+}
+
+/// Marks the resource `id` of kind `kind` to be destroyed.
+pub(super) fn remove_resource(kind: IpcKind, id: c_int) -> Result<()> {
+    Errno::result(unsafe {
+        match kind {
+            #[cfg(feature = "sysvipc_shm")]
+            IpcKind::SharedMemory => {
+                libc::shmctl(id, libc::IPC_RMID, std::ptr::null_mut())
+            }
+            #[cfg(all(
+                feature = "sysvipc_sem",
+                any(
+                    apple_targets,
+                    target_os = "android",
+                    target_os = "freebsd",
+                    target_os = "linux"
+                )
+            ))]
+            IpcKind::SemaphoreSet => {
+                libc::semctl(id, 0, libc::IPC_RMID, ffi::semun::NONE)
+            }
+            #[cfg(all(
+                feature = "sysvipc_msg",
+                any(
+                    target_os = "android",
+                    target_os = "freebsd",
+                    target_os = "linux"
+                )
+            ))]
+            IpcKind::MessageQueue => {
+                libc::msgctl(id, libc::IPC_RMID, std::ptr::null_mut())
+            }
+        }
+    })
+    .map(drop)
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        // Only reached with registered resources if the closure panicked.
+        let _ = self.remove_all();
+    }
+}
+
+/// Runs `f` with a [`Scope`] that tears down every resource registered with it.
+///
+/// Once `f` returns, successfully or not, every resource registered with the
+/// scope is removed with `IPC_RMID`, in the reverse order of their
+/// registration. This also happens if `f` panics.
+///
+/// Shared memory segments are only destroyed once every attachment is gone.
+/// Attachments created inside `f` detach themselves when they are dropped.
+/// Handles to the resources must not escape `f`, as they would refer to
+/// removed resources.
+///
+/// If `f` fails, its error is returned. Otherwise, the first error
+/// encountered while removing the resources is returned.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::{scope, IpcMode, Key};
+/// # use nix::sys::system_v::shm::*;
+/// #
+/// struct Header(u64);
+/// struct Payload([u8; 4096]);
+/// # unsafe impl SysvSafe for Header {}
+/// # unsafe impl SysvSafe for Payload {}
+/// const HEADER_KEY: Key = Key::new(1337);
+/// const PAYLOAD_KEY: Key = Key::new(1338);
+///
+/// scope(|s| {
+///     let mode = IpcMode::owner_rw();
+///     let header = s.shared_memory::<Header>(HEADER_KEY, mode)?;
+///     let payload = s.shared_memory::<Payload>(PAYLOAD_KEY, mode)?;
+///     let payload = payload
+///         .attach(AttachAddress::Any, ShmatFlag::empty())?
+///         .write(Payload([0; 4096]));
+///     header
+///         .attach(AttachAddress::Any, ShmatFlag::empty())?
+///         .write(Header(payload.0.len() as u64));
+///     Ok(())
+/// })?;
+/// // Both segments have been removed here
+/// # Ok::<(), Errno>(())
+/// ```
+pub fn scope<F, R>(f: F) -> Result<R>
+where
+    F: FnOnce(&Scope) -> Result<R>,
+{
+    let scope = Scope::default();
+    let res = f(&scope);
+    let removed = scope.remove_all();
+    let ret = res?;
+    removed.map(|_| ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_handles_negative_and_huge_secs() {
+        let before_epoch = SystemTime::UNIX_EPOCH - Duration::from_secs(10);
+        assert_eq!(time(-10), before_epoch);
+        assert_eq!(time(10), SystemTime::UNIX_EPOCH + Duration::from_secs(10));
+        // Not representable on every platform, must not panic either way
+        let _ = time(i64::MIN);
+        let _ = time(i64::MAX);
+    }
+}
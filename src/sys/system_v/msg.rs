@@ -0,0 +1,1113 @@
+//! Safe wrapper around a SystemV message queue
+
+use std::marker::PhantomData;
+use std::ops::{ControlFlow, RangeInclusive};
+use std::time::{Duration, Instant, SystemTime};
+use std::{fmt, mem, ptr, thread};
+
+use crate::errno::Errno;
+use crate::sys::stat::Mode;
+use crate::unistd::{Gid, Pid, Uid};
+use crate::Result;
+
+use super::{
+    optional_time, time, Backoff, IpcIdentity, IpcKind, IpcMode, Key,
+    Operation, SysvError, SysvResult, SysvSafe,
+};
+
+use super::ffi::{self as libc, c_int, c_long, c_void, mode_t, msqid_ds};
+
+/// Safe wrapper to create and connect to a SystemV message queue.
+///
+/// The queue carries bytes, unless converted with
+/// [`MessageQueue::with_type`] to carry values of type `T`.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::msg::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// let queue = MessageQueue::create(MY_KEY, IpcMode::owner_rw())?;
+/// queue.msgsnd(1, b"hello", MsgsndFlag::empty())?;
+/// let message = queue.msgrcv(0, 64, MsgrcvFlag::empty())?;
+/// assert_eq!(message.data, b"hello");
+/// # Ok::<(), Errno>(())
+/// ```
+pub struct MessageQueue<T: ?Sized = [u8]> {
+    key: Key,
+    id: c_int,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T: ?Sized> Clone for MessageQueue<T> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key,
+            id: self.id,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for MessageQueue<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MessageQueue")
+            .field("key", &self.key)
+            .field("id", &self.id)
+            .finish()
+    }
+}
+
+/// A message received from a [`MessageQueue`].
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Message<T = Vec<u8>> {
+    /// Type of the message, as given when sending it.
+    pub mtype: c_long,
+    /// Content of the message.
+    pub data: T,
+}
+
+impl<T> Message<T> {
+    /// Whether the message is the sentinel sent by
+    /// [`MessageQueue::close_graceful`], after which the consumer should
+    /// stop receiving.
+    pub fn is_close(&self) -> bool {
+        self.mtype == CLOSE_MTYPE
+    }
+}
+
+/// Type of the sentinel message sent by [`MessageQueue::close_graceful`].
+pub const CLOSE_MTYPE: c_long = c_long::MAX;
+
+/// Status of a message queue, as reported by `IPC_STAT`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MsgStat {
+    /// Number of messages in the queue.
+    pub messages: usize,
+    /// Number of bytes of the messages in the queue.
+    pub bytes: usize,
+    /// Maximum number of bytes of the messages in the queue, see
+    /// [`MessageQueue::set_max_bytes`].
+    pub max_bytes: usize,
+    /// Owner of the queue.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_impls::uid"))]
+    pub uid: Uid,
+    /// Group of the queue.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_impls::gid"))]
+    pub gid: Gid,
+    /// Permissions of the queue.
+    pub mode: IpcMode,
+    /// Process that sent the last message, if any.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "super::serde_impls::optional_pid")
+    )]
+    pub last_send_pid: Option<Pid>,
+    /// Process that received the last message, if any.
+    #[cfg_attr(
+        feature = "serde",
+        serde(with = "super::serde_impls::optional_pid")
+    )]
+    pub last_receive_pid: Option<Pid>,
+    /// Time of the last message sent, if any.
+    pub send_time: Option<SystemTime>,
+    /// Time of the last message received, if any.
+    pub receive_time: Option<SystemTime>,
+    /// Time of the creation or of the last `IPC_SET`.
+    pub change_time: SystemTime,
+}
+
+impl MsgStat {
+    /// Converts the structure filled by `IPC_STAT`.
+    fn from_raw(ds: &msqid_ds) -> Self {
+        let pid = |pid| (pid != 0).then(|| Pid::from_raw(pid));
+        #[allow(clippy::unnecessary_cast)]
+        Self {
+            messages: ds.msg_qnum as usize,
+            #[cfg(target_os = "linux")]
+            bytes: ds.__msg_cbytes as usize,
+            #[cfg(not(target_os = "linux"))]
+            bytes: ds.msg_cbytes as usize,
+            max_bytes: ds.msg_qbytes as usize,
+            uid: Uid::from_raw(ds.msg_perm.uid),
+            gid: Gid::from_raw(ds.msg_perm.gid),
+            mode: Mode::from_bits_truncate(ds.msg_perm.mode as mode_t).into(),
+            last_send_pid: pid(ds.msg_lspid),
+            last_receive_pid: pid(ds.msg_lrpid),
+            send_time: optional_time(ds.msg_stime as i64),
+            receive_time: optional_time(ds.msg_rtime as i64),
+            change_time: time(ds.msg_ctime as i64),
+        }
+    }
+}
+
+/// Selects the message to receive by its type, see
+/// [`MessageQueue::recv_filtered`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TypeSelector {
+    /// The first message of the queue.
+    Any,
+    /// The first message of this type.
+    Exactly(c_long),
+    /// The first message of the lowest type that is not greater than this
+    /// one.
+    AtMost(c_long),
+}
+
+impl TypeSelector {
+    /// Returns the `msgtyp` argument of `msgrcv` selecting the messages.
+    ///
+    /// Fails with [`Errno::EINVAL`] if the type is not strictly positive.
+    pub fn msgtyp(self) -> Result<c_long> {
+        match self {
+            Self::Any => Ok(0),
+            Self::Exactly(mtype) if mtype > 0 => Ok(mtype),
+            Self::AtMost(mtype) if mtype > 0 => Ok(-mtype),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+}
+
+impl MessageQueue {
+    /// Creates a new System V message queue.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::msg::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let queue = MessageQueue::create(MY_KEY, IpcMode::owner_rw())?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn create(key: Key, mode: impl Into<IpcMode>) -> SysvResult<Self> {
+        Self::msgget(key, MsggetFlag::IPC_CREAT | MsggetFlag::IPC_EXCL, mode)
+    }
+
+    /// Creates and returns a new, or returns an existing, System V message
+    /// queue identifier.
+    ///
+    /// For more information, see [`msgget(2)`].
+    ///
+    /// # Example
+    ///
+    /// ## Connecting to an existing message queue
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::msg::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let queue = MessageQueue::msgget(
+    ///     MY_KEY,
+    ///     MsggetFlag::empty(),
+    ///     IpcMode::owner_rw(),
+    /// )?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    ///
+    /// [`msgget(2)`]: https://man7.org/linux/man-pages/man2/msgget.2.html
+    pub fn msgget(
+        key: Key,
+        msgget_flag: MsggetFlag,
+        mode: impl Into<IpcMode>,
+    ) -> SysvResult<Self> {
+        let flags = mode.into().mode().bits() as i32 | msgget_flag.bits();
+        let id = Errno::result(unsafe { libc::msgget(key.as_raw(), flags) })
+            .map_err(|e| SysvError::new(Operation::Msgget, e))?;
+        Ok(Self {
+            key,
+            id,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Appends a message of type `mtype` to the queue.
+    ///
+    /// `mtype` must be strictly positive. The call blocks while the queue is
+    /// full, unless [`MsgsndFlag::IPC_NOWAIT`] is used.
+    ///
+    /// For more information, see [`msgsnd(2)`].
+    ///
+    /// [`msgsnd(2)`]: https://man7.org/linux/man-pages/man2/msgsnd.2.html
+    pub fn msgsnd(
+        &self,
+        mtype: c_long,
+        data: &[u8],
+        msgsnd_flag: MsgsndFlag,
+    ) -> Result<()> {
+        if mtype <= 0 {
+            return Err(Errno::EINVAL);
+        }
+        let mut buf = MessageBuffer::new(data.len());
+        buf.set_mtype(mtype);
+        buf.mtext_mut()[..data.len()].copy_from_slice(data);
+        let res = unsafe {
+            libc::msgsnd(self.id, buf.as_ptr(), data.len(), msgsnd_flag.bits())
+        };
+        Errno::result(res).map(drop)
+    }
+
+    /// Removes a message from the queue, and returns it.
+    ///
+    /// `mtype` selects the message:
+    /// * 0 reads the first message of the queue.
+    /// * A positive value reads the first message of that type, or of any
+    ///   other type with `MSG_EXCEPT`.
+    /// * A negative value reads the first message of the lowest type that is
+    ///   not greater than `-mtype`.
+    ///
+    /// Messages longer than `max_size` bytes fail with `E2BIG`, unless
+    /// [`MsgrcvFlag::MSG_NOERROR`] is used to truncate them.
+    ///
+    /// The call blocks until a message is selected, unless
+    /// [`MsgrcvFlag::IPC_NOWAIT`] is used, see [`MessageQueue::try_msgrcv`].
+    ///
+    /// For more information, see [`msgrcv(2)`].
+    ///
+    /// [`msgrcv(2)`]: https://man7.org/linux/man-pages/man2/msgrcv.2.html
+    pub fn msgrcv(
+        &self,
+        mtype: c_long,
+        max_size: usize,
+        msgrcv_flag: MsgrcvFlag,
+    ) -> Result<Message> {
+        let mut buf = MessageBuffer::new(max_size);
+        let res = unsafe {
+            libc::msgrcv(
+                self.id,
+                buf.as_mut_ptr(),
+                max_size,
+                mtype,
+                msgrcv_flag.bits(),
+            )
+        };
+        let len = Errno::result(res)? as usize;
+        Ok(Message {
+            mtype: buf.mtype(),
+            data: buf.mtext_mut()[..len].to_vec(),
+        })
+    }
+
+    /// Removes a message from the queue and returns it, or returns `None`
+    /// instead of blocking if no message is selected.
+    ///
+    /// This is [`MessageQueue::msgrcv`] with [`MsgrcvFlag::IPC_NOWAIT`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::msg::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let queue = MessageQueue::create(MY_KEY, IpcMode::owner_rw())?;
+    /// while let Some(message) = queue.try_msgrcv(0, 64, MsgrcvFlag::empty())? {
+    ///     println!("{}: {:?}", message.mtype, message.data);
+    /// }
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn try_msgrcv(
+        &self,
+        mtype: c_long,
+        max_size: usize,
+        msgrcv_flag: MsgrcvFlag,
+    ) -> Result<Option<Message>> {
+        let msgrcv_flag = msgrcv_flag | MsgrcvFlag::IPC_NOWAIT;
+        match self.msgrcv(mtype, max_size, msgrcv_flag) {
+            Ok(message) => Ok(Some(message)),
+            Err(Errno::ENOMSG | Errno::EAGAIN) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Removes a message from the queue and returns it, blocking for at most
+    /// `timeout`, or returns `None` if no message was selected in time.
+    ///
+    /// `msgrcv` cannot wait with a timeout, so the queue is polled with
+    /// [`MsgrcvFlag::IPC_NOWAIT`], sleeping longer and longer between
+    /// attempts, up to 10ms. A message may thus be received up to 10ms after
+    /// it was sent, which trades latency for CPU time.
+    ///
+    /// See [`MessageQueue::msgrcv`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::msg::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// # use std::time::Duration;
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let queue = MessageQueue::create(MY_KEY, IpcMode::owner_rw())?;
+    /// let timeout = Duration::from_secs(1);
+    /// match queue.msgrcv_timeout(0, 64, MsgrcvFlag::empty(), timeout)? {
+    ///     Some(message) => println!("{}: {:?}", message.mtype, message.data),
+    ///     None => println!("Nothing received for a second"),
+    /// }
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn msgrcv_timeout(
+        &self,
+        mtype: c_long,
+        max_size: usize,
+        msgrcv_flag: MsgrcvFlag,
+        timeout: Duration,
+    ) -> Result<Option<Message>> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => {
+                self.msgrcv_deadline(mtype, max_size, msgrcv_flag, deadline)
+            }
+            None => self.msgrcv(mtype, max_size, msgrcv_flag).map(Some),
+        }
+    }
+
+    /// Removes a message from the queue and returns it, blocking until
+    /// `deadline` at the latest, or returns `None` if no message was
+    /// selected in time.
+    ///
+    /// See [`MessageQueue::msgrcv_timeout`].
+    pub fn msgrcv_deadline(
+        &self,
+        mtype: c_long,
+        max_size: usize,
+        msgrcv_flag: MsgrcvFlag,
+        deadline: Instant,
+    ) -> Result<Option<Message>> {
+        poll_until(deadline, || self.try_msgrcv(mtype, max_size, msgrcv_flag))
+    }
+
+    /// Returns a copy of the message at `index` in the queue, counting from
+    /// 0, or `None` if the queue is shorter. The message stays in the queue.
+    ///
+    /// This is [`MessageQueue::msgrcv`] with [`MsgrcvFlag::MSG_COPY`]. It
+    /// fails with [`Errno::ENOSYS`] if the kernel was built without
+    /// `CONFIG_CHECKPOINT_RESTORE`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::msg::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let queue = MessageQueue::create(MY_KEY, IpcMode::owner_rw())?;
+    /// queue.msgsnd(1, b"hello", MsgsndFlag::empty())?;
+    /// let message = queue.peek(0, 64)?;
+    /// assert_eq!(message.map(|m| m.data), Some(b"hello".to_vec()));
+    /// # Ok::<(), Errno>(())
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn peek(
+        &self,
+        index: usize,
+        max_size: usize,
+    ) -> Result<Option<Message>> {
+        let index = c_long::try_from(index).map_err(|_| Errno::EINVAL)?;
+        self.try_msgrcv(index, max_size, MsgrcvFlag::MSG_COPY)
+    }
+
+    /// Tells the consumers to stop, waits for them to drain the queue, and
+    /// removes it, so that no message is destroyed by the removal.
+    ///
+    /// An empty message of type [`CLOSE_MTYPE`] is sent after the pending
+    /// ones: the consumer receiving it, see [`Message::is_close`], should
+    /// stop, and tell the other consumers if there are several. Once the
+    /// queue is empty, including the sentinel, it is removed.
+    ///
+    /// Fails with [`Errno::ETIMEDOUT`] if the sentinel could not be sent or
+    /// the queue drained within `timeout`, in which case the queue is left
+    /// as it is, with its messages. The queue is polled, sleeping longer
+    /// and longer in between, up to 10ms.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::msg::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let queue = MessageQueue::create(MY_KEY, IpcMode::owner_rw())?;
+    /// // In the consumer
+    /// loop {
+    ///     let message = queue.msgrcv(0, 64, MsgrcvFlag::empty())?;
+    ///     if message.is_close() {
+    ///         break;
+    ///     }
+    ///     // Handle the message...
+    /// }
+    /// // In the producer
+    /// queue.close_graceful(Duration::from_secs(5))?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn close_graceful(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let sent = poll_until(deadline, || {
+            match self.msgsnd(CLOSE_MTYPE, &[], MsgsndFlag::IPC_NOWAIT) {
+                Ok(()) => Ok(Some(())),
+                Err(Errno::EAGAIN) => Ok(None),
+                Err(e) => Err(e),
+            }
+        })?;
+        sent.ok_or(Errno::ETIMEDOUT)?;
+        let drained = poll_until(deadline, || {
+            Ok((self.stat()?.messages == 0).then_some(()))
+        })?;
+        drained.ok_or(Errno::ETIMEDOUT)?;
+        self.msgctl(MsgctlArg::IPC_RMID).map(drop)
+    }
+
+    /// Gives the type of the values carried by the queue.
+    ///
+    /// # Safety
+    ///
+    /// The messages received as values of type `U` must hold the bytes of
+    /// valid values, usually because they were sent by
+    /// [`MessageQueue::send`] on a queue of the same type.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::msg::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let queue = MessageQueue::create(MY_KEY, IpcMode::owner_rw())?;
+    /// let queue = unsafe { queue.with_type::<[f64; 3]>() };
+    /// queue.send(2, &[1.0, 2.0, 3.0])?;
+    /// let message = queue.recv_filtered(TypeSelector::AtMost(2))?;
+    /// assert_eq!(message.data, [1.0, 2.0, 3.0]);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub unsafe fn with_type<U: SysvSafe>(self) -> MessageQueue<U> {
+        MessageQueue {
+            key: self.key,
+            id: self.id,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> MessageQueue<T> {
+    /// Performs control operation specified by `cmd` on the current System V
+    /// message queue.
+    ///
+    /// For more information, see [`msgctl(2)`].
+    ///
+    /// # Example
+    ///
+    /// ## Deleting a message queue
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::msg::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let queue = MessageQueue::create(MY_KEY, IpcMode::owner_rw())?;
+    /// let _ = queue.msgctl(MsgctlArg::IPC_RMID)?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    ///
+    /// [`msgctl(2)`]: https://man7.org/linux/man-pages/man2/msgctl.2.html
+    pub fn msgctl(&self, cmd: MsgctlArg<'_>) -> Result<c_int> {
+        let res = unsafe {
+            match cmd {
+                MsgctlArg::IPC_SET(buf) => libc::msgctl(
+                    self.id,
+                    libc::IPC_SET,
+                    // IPC_SET only reads the structure
+                    buf as *const msqid_ds as *mut msqid_ds,
+                ),
+                MsgctlArg::IPC_STAT(buf) => {
+                    libc::msgctl(self.id, libc::IPC_STAT, buf)
+                }
+                MsgctlArg::IPC_RMID => {
+                    libc::msgctl(self.id, libc::IPC_RMID, ptr::null_mut())
+                }
+            }
+        };
+        Errno::result(res)
+    }
+
+    /// Returns the status of the queue.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::msg::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let queue = MessageQueue::create(MY_KEY, IpcMode::owner_rw())?;
+    /// queue.msgsnd(1, b"hello", MsgsndFlag::empty())?;
+    /// assert_eq!(queue.stat()?.messages, 1);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn stat(&self) -> Result<MsgStat> {
+        let mut ds = mem::MaybeUninit::<msqid_ds>::uninit();
+        Errno::result(unsafe {
+            libc::msgctl(self.id, libc::IPC_STAT, ds.as_mut_ptr())
+        })?;
+        Ok(MsgStat::from_raw(unsafe { &ds.assume_init() }))
+    }
+
+    /// Sets the maximum number of bytes of the messages in the queue,
+    /// `msg_qbytes`, past which sending blocks.
+    ///
+    /// Raising it above the system-wide `msgmnb` limit requires
+    /// `CAP_SYS_RESOURCE`, and fails with [`Errno::EPERM`] otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::msg::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let queue = MessageQueue::create(MY_KEY, IpcMode::owner_rw())?;
+    /// queue.set_max_bytes(1024)?;
+    /// assert_eq!(queue.stat()?.max_bytes, 1024);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn set_max_bytes(&self, max_bytes: usize) -> Result<()> {
+        let mut ds = mem::MaybeUninit::<msqid_ds>::uninit();
+        Errno::result(unsafe {
+            libc::msgctl(self.id, libc::IPC_STAT, ds.as_mut_ptr())
+        })?;
+        let mut ds = unsafe { ds.assume_init() };
+        ds.msg_qbytes = max_bytes.try_into().map_err(|_| Errno::EINVAL)?;
+        Errno::result(unsafe { libc::msgctl(self.id, libc::IPC_SET, &mut ds) })
+            .map(drop)
+    }
+
+    /// Returns the identity of the queue, to tell it apart in logs.
+    pub fn identity(&self) -> IpcIdentity {
+        IpcIdentity::new(IpcKind::MessageQueue, self.key, self.id)
+    }
+}
+
+impl<T: SysvSafe> MessageQueue<T> {
+    /// Appends a message of type `mtype` holding `value` to the queue.
+    ///
+    /// `mtype` must be strictly positive. The call blocks while the queue is
+    /// full.
+    pub fn send(&self, mtype: c_long, value: &T) -> Result<()> {
+        self.send_with(mtype, value, MsgsndFlag::empty())
+    }
+
+    /// Removes the first message selected by `selector` from the queue, and
+    /// returns it, blocking until there is one.
+    ///
+    /// Fails with [`Errno::E2BIG`] if the message is longer than a `T`, and
+    /// with [`Errno::EINVAL`] if it is shorter, in which case it is lost.
+    pub fn recv_filtered(&self, selector: TypeSelector) -> Result<Message<T>> {
+        self.receive(selector, MsgrcvFlag::empty())
+    }
+
+    /// Removes the first message selected by `selector` from the queue, and
+    /// returns it, or returns `None` if there is none.
+    ///
+    /// See [`MessageQueue::recv_filtered`].
+    pub fn try_recv_filtered(
+        &self,
+        selector: TypeSelector,
+    ) -> Result<Option<Message<T>>> {
+        match self.receive(selector, MsgrcvFlag::IPC_NOWAIT) {
+            Ok(message) => Ok(Some(message)),
+            Err(Errno::ENOMSG) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Removes the first message selected by `selector` from the queue, and
+    /// returns it, blocking for at most `timeout`, or returns `None` if
+    /// there was none in time.
+    ///
+    /// See [`MessageQueue::recv_filtered`], and
+    /// [`MessageQueue::msgrcv_timeout`] for the precision of the timeout.
+    pub fn recv_timeout(
+        &self,
+        selector: TypeSelector,
+        timeout: Duration,
+    ) -> Result<Option<Message<T>>> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.recv_deadline(selector, deadline),
+            None => self.recv_filtered(selector).map(Some),
+        }
+    }
+
+    /// Removes the first message selected by `selector` from the queue, and
+    /// returns it, blocking until `deadline` at the latest, or returns
+    /// `None` if there was none in time.
+    ///
+    /// See [`MessageQueue::recv_timeout`].
+    pub fn recv_deadline(
+        &self,
+        selector: TypeSelector,
+        deadline: Instant,
+    ) -> Result<Option<Message<T>>> {
+        poll_until(deadline, || self.try_recv_filtered(selector))
+    }
+
+    // -- Private --
+
+    fn send_with(
+        &self,
+        mtype: c_long,
+        value: &T,
+        msgsnd_flag: MsgsndFlag,
+    ) -> Result<()> {
+        if mtype <= 0 {
+            return Err(Errno::EINVAL);
+        }
+        let size = mem::size_of::<T>();
+        let mut buf = MessageBuffer::new(size);
+        buf.set_mtype(mtype);
+        // Copy the padding bytes of `value` as they are
+        unsafe {
+            ptr::copy_nonoverlapping(
+                (value as *const T).cast::<u8>(),
+                buf.mtext_mut().as_mut_ptr(),
+                size,
+            )
+        };
+        let res = unsafe {
+            libc::msgsnd(self.id, buf.as_ptr(), size, msgsnd_flag.bits())
+        };
+        Errno::result(res).map(drop)
+    }
+
+    fn receive(
+        &self,
+        selector: TypeSelector,
+        msgrcv_flag: MsgrcvFlag,
+    ) -> Result<Message<T>> {
+        let size = mem::size_of::<T>();
+        let mut buf = MessageBuffer::new(size);
+        let res = unsafe {
+            libc::msgrcv(
+                self.id,
+                buf.as_mut_ptr(),
+                size,
+                selector.msgtyp()?,
+                msgrcv_flag.bits(),
+            )
+        };
+        if Errno::result(res)? as usize != size {
+            return Err(Errno::EINVAL);
+        }
+        // The type of the queue was given by `with_type`
+        let data =
+            unsafe { buf.mtext_mut().as_ptr().cast::<T>().read_unaligned() };
+        Ok(Message {
+            mtype: buf.mtype(),
+            data,
+        })
+    }
+}
+
+/// Handler of the messages of a [`MsgRouter`].
+type Handler<'a> = Box<dyn FnMut(Message) -> ControlFlow<()> + 'a>;
+
+/// Receive loop dispatching the messages of a queue to handlers by type,
+/// so that a single consumer serves several logical channels.
+///
+/// Each message goes to the handler of the first route whose range holds
+/// its type, or else to the fallback handler. Messages matching neither are
+/// discarded. On Linux, [`MsgRouter::except`] leaves the messages of a type
+/// in the queue, for another consumer, with [`MsgrcvFlag::MSG_EXCEPT`].
+///
+/// The loop stops once a handler returns [`ControlFlow::Break`].
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::ops::ControlFlow;
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::msg::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// let queue = MessageQueue::create(MY_KEY, IpcMode::owner_rw())?;
+/// MsgRouter::new(64)
+///     .route(1..=9, |message| {
+///         println!("command {}: {:?}", message.mtype, message.data);
+///         ControlFlow::Continue(())
+///     })
+///     .route(10..=10, |_| ControlFlow::Break(()))
+///     .fallback(|message| {
+///         eprintln!("unexpected message of type {}", message.mtype);
+///         ControlFlow::Continue(())
+///     })
+///     .run(&queue)?;
+/// # Ok::<(), Errno>(())
+/// ```
+pub struct MsgRouter<'a> {
+    max_size: usize,
+    routes: Vec<(RangeInclusive<c_long>, Handler<'a>)>,
+    fallback: Option<Handler<'a>>,
+    except: Option<c_long>,
+}
+
+impl fmt::Debug for MsgRouter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let routes: Vec<_> = self.routes.iter().map(|(r, _)| r).collect();
+        f.debug_struct("MsgRouter")
+            .field("max_size", &self.max_size)
+            .field("routes", &routes)
+            .field("fallback", &self.fallback.is_some())
+            .field("except", &self.except)
+            .finish()
+    }
+}
+
+impl<'a> MsgRouter<'a> {
+    /// Creates a router without routes, receiving messages of at most
+    /// `max_size` bytes, longer ones being truncated.
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            routes: Vec::new(),
+            fallback: None,
+            except: None,
+        }
+    }
+
+    /// Dispatches the messages whose type is in `mtypes` to `handler`,
+    /// unless an earlier route holds their type.
+    pub fn route(
+        mut self,
+        mtypes: RangeInclusive<c_long>,
+        handler: impl FnMut(Message) -> ControlFlow<()> + 'a,
+    ) -> Self {
+        self.routes.push((mtypes, Box::new(handler)));
+        self
+    }
+
+    /// Dispatches the messages matching no route to `handler`.
+    pub fn fallback(
+        mut self,
+        handler: impl FnMut(Message) -> ControlFlow<()> + 'a,
+    ) -> Self {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Leaves the messages of type `mtype` in the queue, for another
+    /// consumer, receiving the others with [`MsgrcvFlag::MSG_EXCEPT`].
+    ///
+    /// `mtype` must be strictly positive.
+    #[cfg(target_os = "linux")]
+    pub fn except(mut self, mtype: c_long) -> Self {
+        self.except = Some(mtype);
+        self
+    }
+
+    /// Receives and dispatches messages, blocking while the queue is empty,
+    /// until a handler returns [`ControlFlow::Break`].
+    pub fn run(&mut self, queue: &MessageQueue) -> Result<()> {
+        while self.dispatch(queue)?.is_continue() {}
+        Ok(())
+    }
+
+    /// Receives a message, blocking until there is one, dispatches it, and
+    /// returns the result of its handler.
+    ///
+    /// A discarded message continues the loop.
+    pub fn dispatch(
+        &mut self,
+        queue: &MessageQueue,
+    ) -> Result<ControlFlow<()>> {
+        let message = self.receive(queue, MsgrcvFlag::empty())?;
+        Ok(self.handle(message))
+    }
+
+    /// Receives a message and dispatches it, returning the result of its
+    /// handler, or returns `None` if there is no message.
+    ///
+    /// See [`MsgRouter::dispatch`].
+    pub fn try_dispatch(
+        &mut self,
+        queue: &MessageQueue,
+    ) -> Result<Option<ControlFlow<()>>> {
+        match self.receive(queue, MsgrcvFlag::IPC_NOWAIT) {
+            Ok(message) => Ok(Some(self.handle(message))),
+            Err(Errno::ENOMSG | Errno::EAGAIN) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    // -- Private --
+
+    fn receive(
+        &self,
+        queue: &MessageQueue,
+        msgrcv_flag: MsgrcvFlag,
+    ) -> Result<Message> {
+        let msgrcv_flag = msgrcv_flag | MsgrcvFlag::MSG_NOERROR;
+        match self.except {
+            #[cfg(target_os = "linux")]
+            Some(mtype) if mtype > 0 => queue.msgrcv(
+                mtype,
+                self.max_size,
+                msgrcv_flag | MsgrcvFlag::MSG_EXCEPT,
+            ),
+            Some(_) => Err(Errno::EINVAL),
+            None => queue.msgrcv(0, self.max_size, msgrcv_flag),
+        }
+    }
+
+    fn handle(&mut self, message: Message) -> ControlFlow<()> {
+        let route = self
+            .routes
+            .iter_mut()
+            .find(|(mtypes, _)| mtypes.contains(&message.mtype));
+        match (route, &mut self.fallback) {
+            (Some((_, handler)), _) | (None, Some(handler)) => handler(message),
+            (None, None) => ControlFlow::Continue(()),
+        }
+    }
+}
+
+feature! {
+#![feature = "async"]
+
+/// Message queue used from asynchronous code, with the tokio runtime.
+///
+/// Blocking in `msgrcv` or `msgsnd` would stall the runtime, so the
+/// operations are attempted with [`MsgrcvFlag::IPC_NOWAIT`] and
+/// [`MsgsndFlag::IPC_NOWAIT`], sleeping with [`tokio::time::sleep`] in
+/// between. The sleeps get longer while the operation cannot be performed,
+/// up to 10ms: a message may thus be received up to 10ms after it was sent.
+///
+/// The futures are cancel safe: a message is either received or left in the
+/// queue when they are dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::msg::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// # async fn run() -> Result<(), Errno> {
+/// const MY_KEY: Key = Key::new(1337);
+/// let queue = MessageQueue::create(MY_KEY, IpcMode::owner_rw())?;
+/// let queue = AsyncMessageQueue::new(unsafe { queue.with_type::<u64>() });
+/// queue.send(1, &42).await?;
+/// let message = queue.recv(TypeSelector::Any).await?;
+/// assert_eq!(message.data, 42);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct AsyncMessageQueue<T: ?Sized = [u8]> {
+    queue: MessageQueue<T>,
+}
+
+impl<T: ?Sized> AsyncMessageQueue<T> {
+    /// Wraps `queue` to use it from asynchronous code.
+    pub fn new(queue: MessageQueue<T>) -> Self {
+        Self { queue }
+    }
+
+    /// Returns the wrapped queue, to use its synchronous methods.
+    pub fn get_ref(&self) -> &MessageQueue<T> {
+        &self.queue
+    }
+
+    /// Returns the wrapped queue.
+    pub fn into_inner(self) -> MessageQueue<T> {
+        self.queue
+    }
+}
+
+impl AsyncMessageQueue {
+    /// Appends a message of type `mtype` to the queue, waiting while the
+    /// queue is full.
+    ///
+    /// See [`MessageQueue::msgsnd`].
+    pub async fn msgsnd(&self, mtype: c_long, data: &[u8]) -> Result<()> {
+        super::poll_nowait(Errno::EAGAIN, || {
+            self.queue.msgsnd(mtype, data, MsgsndFlag::IPC_NOWAIT)
+        })
+        .await
+    }
+
+    /// Removes a message from the queue and returns it, waiting until one
+    /// is selected.
+    ///
+    /// See [`MessageQueue::msgrcv`].
+    pub async fn msgrcv(
+        &self,
+        mtype: c_long,
+        max_size: usize,
+        msgrcv_flag: MsgrcvFlag,
+    ) -> Result<Message> {
+        let msgrcv_flag = msgrcv_flag | MsgrcvFlag::IPC_NOWAIT;
+        super::poll_nowait(Errno::ENOMSG, || {
+            self.queue.msgrcv(mtype, max_size, msgrcv_flag)
+        })
+        .await
+    }
+}
+
+impl<T: SysvSafe> AsyncMessageQueue<T> {
+    /// Appends a message of type `mtype` holding `value` to the queue,
+    /// waiting while the queue is full.
+    ///
+    /// See [`MessageQueue::send`].
+    pub async fn send(&self, mtype: c_long, value: &T) -> Result<()> {
+        super::poll_nowait(Errno::EAGAIN, || {
+            self.queue.send_with(mtype, value, MsgsndFlag::IPC_NOWAIT)
+        })
+        .await
+    }
+
+    /// Removes the first message selected by `selector` from the queue, and
+    /// returns it, waiting until there is one.
+    ///
+    /// See [`MessageQueue::recv_filtered`].
+    pub async fn recv(&self, selector: TypeSelector) -> Result<Message<T>> {
+        super::poll_nowait(Errno::ENOMSG, || {
+            self.queue.receive(selector, MsgrcvFlag::IPC_NOWAIT)
+        })
+        .await
+    }
+}
+}
+
+/// Attempts `f`, which does not block, until it returns a message or
+/// `deadline` passes, sleeping with [`Backoff`] in between.
+fn poll_until<M>(
+    deadline: Instant,
+    mut f: impl FnMut() -> Result<Option<M>>,
+) -> Result<Option<M>> {
+    let mut backoff = Backoff::new();
+    loop {
+        if let Some(message) = f()? {
+            return Ok(Some(message));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(backoff.next_delay().min(deadline - now));
+    }
+}
+
+/// Storage for the C `struct msgbuf`: a `long` message type, followed by
+/// the content of the message.
+struct MessageBuffer(Vec<c_long>);
+
+impl MessageBuffer {
+    fn new(size: usize) -> Self {
+        let words = size / mem::size_of::<c_long>()
+            + usize::from(size % mem::size_of::<c_long>() != 0);
+        Self(vec![0; 1 + words])
+    }
+
+    fn mtype(&self) -> c_long {
+        self.0[0]
+    }
+
+    fn set_mtype(&mut self, mtype: c_long) {
+        self.0[0] = mtype;
+    }
+
+    fn mtext_mut(&mut self) -> &mut [u8] {
+        let len = (self.0.len() - 1) * mem::size_of::<c_long>();
+        unsafe {
+            std::slice::from_raw_parts_mut(
+                self.0[1..].as_mut_ptr().cast::<u8>(),
+                len,
+            )
+        }
+    }
+
+    fn as_ptr(&self) -> *const c_void {
+        self.0.as_ptr().cast()
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut c_void {
+        self.0.as_mut_ptr().cast()
+    }
+}
+
+libc_bitflags!(
+    /// Valid flags for the second parameter of the function
+    /// [`MessageQueue::msgget`].
+    pub struct MsggetFlag: c_int
+    {
+        /// Create a new queue if none is associated with the key.
+        IPC_CREAT;
+        /// This flag is used with IPC_CREAT to ensure that this call creates
+        /// the queue.  If the queue already exists, the call fails.
+        IPC_EXCL;
+    }
+);
+
+libc_bitflags!(
+    /// Valid flags for the function [`MessageQueue::msgsnd`].
+    pub struct MsgsndFlag: c_int
+    {
+        /// Fail with `EAGAIN` instead of blocking when the queue is full.
+        IPC_NOWAIT;
+    }
+);
+
+libc_bitflags!(
+    /// Valid flags for the function [`MessageQueue::msgrcv`].
+    pub struct MsgrcvFlag: c_int
+    {
+        /// Fail with `ENOMSG` instead of blocking when no message of the
+        /// requested type is in the queue.
+        IPC_NOWAIT;
+        /// Read the first message whose type differs from the requested
+        /// one. Only meaningful with a positive message type.
+        #[cfg(target_os = "linux")]
+        MSG_EXCEPT;
+        /// Truncate the messages longer than the buffer instead of failing.
+        MSG_NOERROR;
+        /// Copy the message at the position given as message type, counting
+        /// from 0, instead of removing a message. Requires `IPC_NOWAIT`, and
+        /// excludes `MSG_EXCEPT`. See [`MessageQueue::peek`].
+        #[cfg(target_os = "linux")]
+        MSG_COPY;
+    }
+);
+
+/// Control operations of [`MessageQueue::msgctl`], with their argument.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum MsgctlArg<'a> {
+    /// Write the `msg_perm.uid`, `msg_perm.gid`, `msg_perm.mode` and
+    /// `msg_qbytes` members of the structure to the kernel data structure
+    /// associated with this queue, updating also its `msg_ctime` member.
+    IPC_SET(&'a msqid_ds),
+    /// Copy information from the kernel data structure associated with the
+    /// queue into the structure.
+    /// The caller must have read permission on the queue.
+    IPC_STAT(&'a mut msqid_ds),
+    /// Immediately remove the message queue, awakening every reader and
+    /// writer blocked on it with `EIDRM`.
+    IPC_RMID,
+}
@@ -0,0 +1,963 @@
+//! Safe wrapper around a SystemV semaphore set
+//!
+//! On FreeBSD, libc binds neither `SEM_UNDO` nor the `semctl` commands
+//! reading and setting the semaphores: only [`SemaphoreSet::semop`], its
+//! timed variants, [`SemaphoreSet::stat`] and `IPC_RMID` are available.
+
+use std::mem;
+use std::time::{Duration, SystemTime};
+
+use crate::errno::Errno;
+use crate::sys::stat::Mode;
+#[cfg(not(target_os = "freebsd"))]
+use crate::unistd::Pid;
+use crate::unistd::{Gid, Uid};
+use crate::Result;
+
+use super::{
+    optional_time, time, IpcIdentity, IpcKind, IpcMode, Key, Operation,
+    SysvError, SysvResult,
+};
+
+#[cfg(not(target_os = "freebsd"))]
+use super::ffi::c_ushort;
+use super::ffi::{self as libc, c_int, c_short, mode_t, semun};
+
+#[derive(Debug)]
+/// Safe wrapper to create and connect to a SystemV semaphore set.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::sem::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// let set = SemaphoreSet::create(MY_KEY, 1, IpcMode::owner_rw())?;
+/// set.semctl(0, SemctlArg::SETVAL(1))?;
+/// {
+///     let _guard = set.acquire(0)?;
+///     // Only one process at a time gets here...
+/// }
+/// set.semctl(0, SemctlArg::IPC_RMID)?;
+/// # Ok::<(), Errno>(())
+/// ```
+#[allow(missing_copy_implementations)]
+pub struct SemaphoreSet {
+    key: Key,
+    id: c_int,
+}
+
+impl SemaphoreSet {
+    /// Handle to no set, failing every operation with `EINVAL`.
+    #[cfg(all(
+        feature = "sysvipc_sync",
+        any(apple_targets, target_os = "linux")
+    ))]
+    pub(super) const INVALID: Self = Self {
+        key: Key::IPC_PRIVATE,
+        id: -1,
+    };
+
+    /// Handle to the existing set `id`, listed by [`super::info`].
+    #[cfg(all(target_os = "linux", feature = "sysvipc_shm"))]
+    pub(super) fn from_parts(key: Key, id: c_int) -> Self {
+        Self { key, id }
+    }
+
+    /// Creates a new System V semaphore set of `nsems` semaphores.
+    ///
+    /// The semaphores are initialized to 0.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::sem::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let set = SemaphoreSet::create(MY_KEY, 4, IpcMode::owner_rw())?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn create(
+        key: Key,
+        nsems: usize,
+        mode: impl Into<IpcMode>,
+    ) -> SysvResult<Self> {
+        Self::semget(
+            key,
+            nsems,
+            SemgetFlag::IPC_CREAT | SemgetFlag::IPC_EXCL,
+            mode,
+        )
+    }
+
+    /// Creates and returns a new, or returns an existing, System V semaphore
+    /// set identifier.
+    ///
+    /// When connecting to an existing set, `nsems` may be 0.
+    ///
+    /// For more information, see [`semget(2)`].
+    ///
+    /// # Example
+    ///
+    /// ## Connecting to an existing semaphore set
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::sem::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let set = SemaphoreSet::semget(
+    ///     MY_KEY,
+    ///     0,
+    ///     SemgetFlag::empty(),
+    ///     IpcMode::owner_rw(),
+    /// )?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    ///
+    /// [`semget(2)`]: https://man7.org/linux/man-pages/man2/semget.2.html
+    pub fn semget(
+        key: Key,
+        nsems: usize,
+        semget_flag: SemgetFlag,
+        mode: impl Into<IpcMode>,
+    ) -> SysvResult<Self> {
+        let nsems = c_int::try_from(nsems)
+            .map_err(|_| SysvError::new(Operation::Semget, Errno::EINVAL))?;
+        let flags = mode.into().mode().bits() as i32 | semget_flag.bits();
+        let id =
+            Errno::result(unsafe { libc::semget(key.as_raw(), nsems, flags) })
+                .map_err(|e| SysvError::new(Operation::Semget, e))?;
+        Ok(Self { key, id })
+    }
+
+    /// Performs atomically every operation of `sops` on the semaphores of
+    /// the set.
+    ///
+    /// The call blocks until all the operations can be performed, unless
+    /// one of them uses [`SemopFlag::IPC_NOWAIT`].
+    ///
+    /// For more information, see [`semop(2)`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::sem::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let set = SemaphoreSet::create(MY_KEY, 2, IpcMode::owner_rw())?;
+    /// // Moves one unit from the second semaphore to the first one
+    /// set.semop(&[
+    ///     SemOp::new(1, -1, SemopFlag::empty()),
+    ///     SemOp::new(0, 1, SemopFlag::empty()),
+    /// ])?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    ///
+    /// [`semop(2)`]: https://man7.org/linux/man-pages/man2/semop.2.html
+    pub fn semop(&self, sops: &[SemOp]) -> Result<()> {
+        // The kernel copies the operations and never writes them back, so
+        // handing out a mutable pointer to shared data is sound.
+        let res = unsafe {
+            libc::semop(
+                self.id,
+                sops.as_ptr() as *mut libc::sembuf,
+                sops.len() as _,
+            )
+        };
+        Errno::result(res).map(drop)
+    }
+
+    /// Performs atomically every operation of `sops`, like
+    /// [`SemaphoreSet::semop`], but blocks for at most `timeout`, and returns
+    /// whether the operations were performed.
+    ///
+    /// As libc does not bind [`semtimedop(2)`], the operations are attempted
+    /// with [`SemopFlag::IPC_NOWAIT`] until the timeout expires, sleeping
+    /// longer and longer in between, up to 10ms: they may thus be performed
+    /// up to 10ms later than possible, and processes waiting with
+    /// [`SemaphoreSet::semop`] get the semaphores first.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::sem::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let set = SemaphoreSet::create(MY_KEY, 1, IpcMode::owner_rw())?;
+    /// let sops = [SemOp::new(0, -1, SemopFlag::empty())];
+    /// if !set.semtimedop(&sops, Duration::from_secs(1))? {
+    ///     eprintln!("The semaphore is still 0 after a second");
+    /// }
+    /// # Ok::<(), Errno>(())
+    /// ```
+    ///
+    /// [`semtimedop(2)`]: https://man7.org/linux/man-pages/man2/semop.2.html
+    pub fn semtimedop(
+        &self,
+        sops: &[SemOp],
+        timeout: Duration,
+    ) -> Result<bool> {
+        let res = self.semop_timeout(sops, timeout);
+        match res {
+            Ok(()) => Ok(true),
+            Err(Errno::EAGAIN) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Decrements the semaphore `semnum`, blocking for at most `timeout`
+    /// while it is 0, and returns whether it did.
+    ///
+    /// See [`SemaphoreSet::semtimedop`].
+    pub fn wait_timeout(&self, semnum: u16, timeout: Duration) -> Result<bool> {
+        self.semtimedop(&[SemOp::new(semnum, -1, SemopFlag::empty())], timeout)
+    }
+
+    /// Returns a watcher of the semaphore `semnum`, to wait until it reaches
+    /// a value or changes.
+    ///
+    /// See [`SemWatch`].
+    pub fn watch(&self, semnum: u16) -> SemWatch<'_> {
+        SemWatch { set: self, semnum }
+    }
+
+    /// Starts a transaction, gathering operations on several semaphores of
+    /// the set to perform them atomically with a single
+    /// [`SemaphoreSet::semop`].
+    ///
+    /// See [`SemTransaction`].
+    pub fn transaction(&self) -> SemTransaction<'_> {
+        SemTransaction {
+            set: self,
+            sops: Vec::new(),
+        }
+    }
+
+    /// Performs the control operation `cmd` on the semaphore `semnum` of the
+    /// set, or on the whole set for the operations ignoring `semnum`.
+    ///
+    /// For more information, see [`semctl(2)`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::sem::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let set = SemaphoreSet::create(MY_KEY, 1, IpcMode::owner_rw())?;
+    /// set.semctl(0, SemctlArg::SETVAL(3))?;
+    /// assert_eq!(set.semctl(0, SemctlArg::GETVAL)?, 3);
+    /// set.semctl(0, SemctlArg::IPC_RMID)?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    ///
+    /// [`semctl(2)`]: https://man7.org/linux/man-pages/man2/semctl.2.html
+    pub fn semctl(&self, semnum: u16, cmd: SemctlArg) -> Result<c_int> {
+        let semnum = c_int::from(semnum);
+        let (cmd, arg) = match cmd {
+            SemctlArg::IPC_RMID => (libc::IPC_RMID, semun::NONE),
+            #[cfg(not(target_os = "freebsd"))]
+            SemctlArg::GETVAL => (libc::GETVAL, semun::NONE),
+            #[cfg(not(target_os = "freebsd"))]
+            SemctlArg::SETVAL(val) => (libc::SETVAL, semun { val }),
+            #[cfg(not(target_os = "freebsd"))]
+            SemctlArg::GETPID => (libc::GETPID, semun::NONE),
+            #[cfg(not(target_os = "freebsd"))]
+            SemctlArg::GETNCNT => (libc::GETNCNT, semun::NONE),
+            #[cfg(not(target_os = "freebsd"))]
+            SemctlArg::GETZCNT => (libc::GETZCNT, semun::NONE),
+        };
+        let res = unsafe { libc::semctl(self.id, semnum, cmd, arg) };
+        Errno::result(res)
+    }
+
+    /// Returns the value of the semaphore `semnum`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::sem::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let set = SemaphoreSet::create(MY_KEY, 1, IpcMode::owner_rw())?;
+    /// set.set_value(0, 3)?;
+    /// assert_eq!(set.value(0)?, 3);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    #[cfg(not(target_os = "freebsd"))]
+    pub fn value(&self, semnum: u16) -> Result<u16> {
+        // Semaphores never exceed `SEMVMX`, which is at most 32767
+        self.semctl(semnum, SemctlArg::GETVAL)
+            .map(|value| value as u16)
+    }
+
+    /// Sets the value of the semaphore `semnum`, clearing its adjustments
+    /// in every process.
+    ///
+    /// Fails with [`Errno::ERANGE`] if `value` exceeds `SEMVMX`.
+    #[cfg(not(target_os = "freebsd"))]
+    pub fn set_value(&self, semnum: u16, value: u16) -> Result<()> {
+        self.semctl(semnum, SemctlArg::SETVAL(value.into()))
+            .map(drop)
+    }
+
+    /// Returns the values of every semaphore of the set.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::sem::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let set = SemaphoreSet::create(MY_KEY, 3, IpcMode::owner_rw())?;
+    /// set.set_values(&[1, 2, 3])?;
+    /// assert_eq!(set.values()?, [1, 2, 3]);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    #[cfg(not(target_os = "freebsd"))]
+    pub fn values(&self) -> Result<Vec<u16>> {
+        let mut values = vec![0; self.nsems()?];
+        let arg = semun {
+            array: values.as_mut_ptr(),
+        };
+        Errno::result(unsafe { libc::semctl(self.id, 0, libc::GETALL, arg) })?;
+        Ok(values)
+    }
+
+    /// Sets the values of every semaphore of the set at once, clearing
+    /// their adjustments in every process.
+    ///
+    /// Fails with [`Errno::EINVAL`] if `values` does not hold a value for
+    /// each semaphore, and with [`Errno::ERANGE`] if a value exceeds
+    /// `SEMVMX`.
+    #[cfg(not(target_os = "freebsd"))]
+    pub fn set_values(&self, values: &[u16]) -> Result<()> {
+        if values.len() != self.nsems()? {
+            return Err(Errno::EINVAL);
+        }
+        // The kernel only reads the values
+        let arg = semun {
+            array: values.as_ptr() as *mut c_ushort,
+        };
+        let res = unsafe { libc::semctl(self.id, 0, libc::SETALL, arg) };
+        Errno::result(res).map(drop)
+    }
+
+    /// Returns the number of processes waiting for the semaphore `semnum`
+    /// to increase.
+    #[cfg(not(target_os = "freebsd"))]
+    pub fn waiting_for_increase(&self, semnum: u16) -> Result<usize> {
+        let count = self.semctl(semnum, SemctlArg::GETNCNT)?;
+        Ok(count as usize)
+    }
+
+    /// Returns the number of processes waiting for the semaphore `semnum`
+    /// to become 0.
+    #[cfg(not(target_os = "freebsd"))]
+    pub fn waiting_for_zero(&self, semnum: u16) -> Result<usize> {
+        let count = self.semctl(semnum, SemctlArg::GETZCNT)?;
+        Ok(count as usize)
+    }
+
+    /// Returns the PID of the last process to operate on the semaphore
+    /// `semnum`.
+    #[cfg(not(target_os = "freebsd"))]
+    pub fn last_pid(&self, semnum: u16) -> Result<Pid> {
+        self.semctl(semnum, SemctlArg::GETPID).map(Pid::from_raw)
+    }
+
+    /// Decrements the semaphore `semnum`, blocking while it is 0, and
+    /// returns a guard incrementing it back once dropped.
+    ///
+    /// Both operations use [`SemopFlag::SEM_UNDO`], so the semaphore is also
+    /// released if the process exits while holding the guard.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::sem::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let set = SemaphoreSet::create(MY_KEY, 1, IpcMode::owner_rw())?;
+    /// set.semctl(0, SemctlArg::SETVAL(1))?;
+    /// let guard = set.acquire(0)?;
+    /// // The semaphore is taken until `guard` is dropped
+    /// # Ok::<(), Errno>(())
+    /// ```
+    #[cfg(not(target_os = "freebsd"))]
+    pub fn acquire(&self, semnum: u16) -> Result<SemaphoreGuard<'_>> {
+        self.semop(&[SemOp::decrement(semnum, 1).undo(true)])?;
+        Ok(SemaphoreGuard { set: self, semnum })
+    }
+
+    /// Returns the status of the set.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::sem::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let set = SemaphoreSet::create(MY_KEY, 4, IpcMode::owner_rw())?;
+    /// assert_eq!(set.stat()?.nsems, 4);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn stat(&self) -> Result<SemStat> {
+        let mut ds = mem::MaybeUninit::<libc::semid_ds>::uninit();
+        let arg = semun {
+            buf: ds.as_mut_ptr(),
+        };
+        Errno::result(unsafe {
+            libc::semctl(self.id, 0, libc::IPC_STAT, arg)
+        })?;
+        Ok(SemStat::from_raw(unsafe { &ds.assume_init() }))
+    }
+
+    /// Returns the identity of the set, to tell it apart in logs.
+    pub fn identity(&self) -> IpcIdentity {
+        IpcIdentity::new(IpcKind::SemaphoreSet, self.key, self.id)
+    }
+
+    // -- Private --
+
+    /// Returns the number of semaphores of the set.
+    #[cfg(not(target_os = "freebsd"))]
+    fn nsems(&self) -> Result<usize> {
+        self.stat().map(|stat| stat.nsems)
+    }
+
+    fn semop_timeout(&self, sops: &[SemOp], timeout: Duration) -> Result<()> {
+        let deadline = std::time::Instant::now() + timeout;
+        let sops: Vec<_> = sops
+            .iter()
+            .map(|sop| {
+                let flags = sop.flags() | SemopFlag::IPC_NOWAIT;
+                SemOp::new(sop.semnum(), sop.op(), flags)
+            })
+            .collect();
+        let mut backoff = super::Backoff::new();
+        loop {
+            match self.semop(&sops) {
+                Err(Errno::EAGAIN) => (),
+                res => return res,
+            }
+            let now = std::time::Instant::now();
+            if now >= deadline {
+                return Err(Errno::EAGAIN);
+            }
+            std::thread::sleep(backoff.next_delay().min(deadline - now));
+        }
+    }
+}
+
+feature! {
+#![feature = "async"]
+
+/// Semaphore set used from asynchronous code, with the tokio runtime.
+///
+/// Blocking in `semop` would stall the runtime, so the operations are
+/// attempted with [`SemopFlag::IPC_NOWAIT`], sleeping with
+/// [`tokio::time::sleep`] in between. The sleeps get longer while the
+/// operations cannot be performed, up to 10ms: processes blocked in
+/// [`SemaphoreSet::semop`] thus get the semaphores first.
+///
+/// The futures are cancel safe: the operations are either all performed or
+/// not at all when they are dropped.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::sem::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// # async fn run() -> Result<(), Errno> {
+/// const MY_KEY: Key = Key::new(1337);
+/// let set = SemaphoreSet::create(MY_KEY, 1, IpcMode::owner_rw())?;
+/// set.set_value(0, 1)?;
+/// let set = AsyncSemaphoreSet::new(set);
+/// {
+///     let _guard = set.acquire(0).await?;
+///     // Only one process at a time gets here...
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct AsyncSemaphoreSet {
+    set: SemaphoreSet,
+}
+
+impl AsyncSemaphoreSet {
+    /// Wraps `set` to use it from asynchronous code.
+    pub fn new(set: SemaphoreSet) -> Self {
+        Self { set }
+    }
+
+    /// Returns the wrapped set, to use its synchronous methods.
+    pub fn get_ref(&self) -> &SemaphoreSet {
+        &self.set
+    }
+
+    /// Returns the wrapped set.
+    pub fn into_inner(self) -> SemaphoreSet {
+        self.set
+    }
+
+    /// Performs atomically every operation of `sops`, waiting until they
+    /// can all be performed.
+    ///
+    /// See [`SemaphoreSet::semop`].
+    pub async fn semop(&self, sops: &[SemOp]) -> Result<()> {
+        let sops: Vec<_> = sops
+            .iter()
+            .map(|sop| sop.nowait(true))
+            .collect();
+        super::poll_nowait(Errno::EAGAIN, || self.set.semop(&sops)).await
+    }
+
+    /// Decrements the semaphore `semnum`, waiting while it is 0.
+    pub async fn wait(&self, semnum: u16) -> Result<()> {
+        self.semop(&[SemOp::decrement(semnum, 1)]).await
+    }
+
+    /// Decrements the semaphore `semnum`, waiting while it is 0, and returns
+    /// a guard incrementing it back once dropped.
+    ///
+    /// See [`SemaphoreSet::acquire`].
+    #[cfg(not(target_os = "freebsd"))]
+    pub async fn acquire(&self, semnum: u16) -> Result<SemaphoreGuard<'_>> {
+        self.semop(&[SemOp::decrement(semnum, 1).undo(true)]).await?;
+        Ok(SemaphoreGuard {
+            set: &self.set,
+            semnum,
+        })
+    }
+}
+}
+
+/// Status of a semaphore set, as reported by `IPC_STAT`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SemStat {
+    /// Number of semaphores in the set.
+    pub nsems: usize,
+    /// Owner of the set.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_impls::uid"))]
+    pub uid: Uid,
+    /// Group of the set.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_impls::gid"))]
+    pub gid: Gid,
+    /// Permissions of the set.
+    pub mode: IpcMode,
+    /// Time of the last [`SemaphoreSet::semop`], if any.
+    pub op_time: Option<SystemTime>,
+    /// Time of the creation or of the last change of the set.
+    pub change_time: SystemTime,
+}
+
+impl SemStat {
+    /// Converts the structure filled by `IPC_STAT`.
+    fn from_raw(ds: &libc::semid_ds) -> Self {
+        #[allow(clippy::unnecessary_cast)]
+        Self {
+            nsems: ds.sem_nsems as usize,
+            uid: Uid::from_raw(ds.sem_perm.uid),
+            gid: Gid::from_raw(ds.sem_perm.gid),
+            mode: Mode::from_bits_truncate(ds.sem_perm.mode as mode_t).into(),
+            op_time: optional_time(ds.sem_otime as i64),
+            change_time: time(ds.sem_ctime as i64),
+        }
+    }
+}
+
+/// Guard returned by [`SemaphoreSet::acquire`], releasing the semaphore
+/// when dropped.
+#[derive(Debug)]
+#[cfg(not(target_os = "freebsd"))]
+pub struct SemaphoreGuard<'a> {
+    set: &'a SemaphoreSet,
+    semnum: u16,
+}
+
+#[cfg(not(target_os = "freebsd"))]
+impl Drop for SemaphoreGuard<'_> {
+    fn drop(&mut self) {
+        let op = SemOp::increment(self.semnum, 1).undo(true);
+        // Can only fail if the set has been removed in the meantime.
+        let _ = self.set.semop(&[op]);
+    }
+}
+
+/// Watcher of a semaphore, returned by [`SemaphoreSet::watch`], to gate
+/// a process on the progress of another one.
+///
+/// Waiting for a value performs operations leaving the semaphore unchanged,
+/// which still update the time of the last operation and the PID of the
+/// last process of the semaphore.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::sem::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// let set = SemaphoreSet::create(MY_KEY, 1, IpcMode::owner_rw())?;
+/// // Another stage increments the semaphore once per finished step
+/// if !set.watch(0).until(3, Duration::from_secs(10))? {
+///     eprintln!("The previous stage did not finish in time");
+/// }
+/// # Ok::<(), Errno>(())
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct SemWatch<'a> {
+    set: &'a SemaphoreSet,
+    semnum: u16,
+}
+
+impl SemWatch<'_> {
+    /// Waits for at most `timeout` until the semaphore is `value`, and
+    /// returns whether it was.
+    ///
+    /// The semaphore is decremented by `value` and must then be 0, before
+    /// being incremented back, all atomically: the semaphore is thus never
+    /// modified, and is seen at `value` even if it only stays there for a
+    /// moment.
+    ///
+    /// Fails with [`Errno::ERANGE`] if `value` exceeds `SEMVMX`.
+    pub fn until(&self, value: u16, timeout: Duration) -> Result<bool> {
+        let n = i16::try_from(value).map_err(|_| Errno::ERANGE)?;
+        let semnum = self.semnum;
+        let sops: &[SemOp] = if n == 0 {
+            &[SemOp::wait_zero(semnum)]
+        } else {
+            &[
+                SemOp::decrement(semnum, n),
+                SemOp::wait_zero(semnum),
+                SemOp::increment(semnum, n),
+            ]
+        };
+        self.set.semtimedop(sops, timeout)
+    }
+
+    /// Waits for at most `timeout` until the semaphore differs from its
+    /// current value, and returns its new value, if it changed.
+    ///
+    /// No operation waits for a semaphore to change, so its value is
+    /// polled, sleeping longer and longer in between, up to 10ms: a change
+    /// undone before being polled goes unnoticed.
+    #[cfg(not(target_os = "freebsd"))]
+    pub fn changed(&self, timeout: Duration) -> Result<Option<u16>> {
+        let deadline = std::time::Instant::now() + timeout;
+        let initial = self.set.value(self.semnum)?;
+        let mut backoff = super::Backoff::new();
+        loop {
+            let value = self.set.value(self.semnum)?;
+            if value != initial {
+                return Ok(Some(value));
+            }
+            let now = std::time::Instant::now();
+            if now >= deadline {
+                return Ok(None);
+            }
+            std::thread::sleep(backoff.next_delay().min(deadline - now));
+        }
+    }
+}
+
+/// Operations on several semaphores of a set, performed atomically,
+/// returned by [`SemaphoreSet::transaction`].
+///
+/// The operations are all performed by a single [`SemaphoreSet::semop`]
+/// once committed: if one of them would block, the call waits until all of
+/// them can be performed, and if one of them fails, none of them is. There
+/// is thus nothing to roll back, whichever operation fails.
+///
+/// The kernel limits the number of operations of a call to `SEMOPM`, and
+/// fails with [`Errno::E2BIG`] beyond it: [`SemTransaction::validate`]
+/// checks the transaction up front, without performing anything.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::sem::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// // The `SEMOPM` limit of the kernel, see `max_operations` on Linux
+/// const MAX_OPS: usize = 32;
+/// let set = SemaphoreSet::create(MY_KEY, 3, IpcMode::owner_rw())?;
+/// // Takes a unit of the first two semaphores once the third one is 0
+/// let transaction = set
+///     .transaction()
+///     .decrement(0, 1)
+///     .decrement(1, 1)
+///     .wait_zero(2);
+/// transaction.validate(MAX_OPS)?;
+/// transaction.commit()?;
+/// # Ok::<(), Errno>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct SemTransaction<'a> {
+    set: &'a SemaphoreSet,
+    sops: Vec<SemOp>,
+}
+
+impl SemTransaction<'_> {
+    /// Adds the operation `sop`.
+    pub fn op(mut self, sop: SemOp) -> Self {
+        self.sops.push(sop);
+        self
+    }
+
+    /// Adds `n` to the semaphore `semnum`, see [`SemOp::increment`].
+    pub fn increment(self, semnum: u16, n: i16) -> Self {
+        self.op(SemOp::increment(semnum, n))
+    }
+
+    /// Subtracts `n` from the semaphore `semnum`, see [`SemOp::decrement`].
+    pub fn decrement(self, semnum: u16, n: i16) -> Self {
+        self.op(SemOp::decrement(semnum, n))
+    }
+
+    /// Waits until the semaphore `semnum` is 0, see [`SemOp::wait_zero`].
+    pub fn wait_zero(self, semnum: u16) -> Self {
+        self.op(SemOp::wait_zero(semnum))
+    }
+
+    /// Returns the operations of the transaction.
+    pub fn ops(&self) -> &[SemOp] {
+        &self.sops
+    }
+
+    /// Checks the transaction without performing it, with `max_ops` the
+    /// `SEMOPM` limit of the kernel, see [`max_operations`] on Linux.
+    ///
+    /// Fails like [`SemaphoreSet::semop`] would: with [`Errno::EINVAL`] if
+    /// there is no operation, with [`Errno::E2BIG`] if there are more than
+    /// `max_ops`, and with [`Errno::EFBIG`] if an operation applies to a
+    /// semaphore outside of the set.
+    pub fn validate(&self, max_ops: usize) -> Result<()> {
+        if self.sops.is_empty() {
+            return Err(Errno::EINVAL);
+        }
+        if self.sops.len() > max_ops {
+            return Err(Errno::E2BIG);
+        }
+        let nsems = self.set.stat()?.nsems;
+        if self
+            .sops
+            .iter()
+            .any(|sop| usize::from(sop.semnum()) >= nsems)
+        {
+            return Err(Errno::EFBIG);
+        }
+        Ok(())
+    }
+
+    /// Performs atomically every operation, waiting until they can all be
+    /// performed.
+    pub fn commit(self) -> Result<()> {
+        self.set.semop(&self.sops)
+    }
+
+    /// Performs atomically every operation, waiting for at most `timeout`
+    /// until they can all be performed, and returns whether they were.
+    ///
+    /// See [`SemaphoreSet::semtimedop`].
+    pub fn commit_timeout(self, timeout: Duration) -> Result<bool> {
+        self.set.semtimedop(&self.sops, timeout)
+    }
+}
+
+/// Returns the maximum number of operations of a call to
+/// [`SemaphoreSet::semop`] (`SEMOPM`), read from `/proc/sys/kernel/sem`.
+#[cfg(target_os = "linux")]
+pub fn max_operations() -> Result<usize> {
+    let limits = std::fs::read_to_string("/proc/sys/kernel/sem")
+        .map_err(|e| Errno::try_from(e).unwrap_or(Errno::EIO))?;
+    // The fields are SEMMSL, SEMMNS, SEMOPM and SEMMNI
+    limits
+        .split_whitespace()
+        .nth(2)
+        .and_then(|field| field.parse().ok())
+        .ok_or(Errno::EINVAL)
+}
+
+/// A single operation of a call to [`SemaphoreSet::semop`].
+///
+/// A positive `op` is added to the semaphore. A negative `op` waits until
+/// the semaphore is at least `-op`, then subtracts it. An `op` of 0 waits
+/// until the semaphore is 0.
+///
+/// The operations are built with [`SemOp::new`], or with
+/// [`SemOp::increment`], [`SemOp::decrement`] and [`SemOp::wait_zero`], and
+/// their flags set one by one. Building them does not perform anything:
+/// the operations of a call to [`SemaphoreSet::semop`] stay atomic.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::sem::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// let set = SemaphoreSet::create(MY_KEY, 2, IpcMode::owner_rw())?;
+/// // Takes two units of the first semaphore once the second one is 0, and
+/// // gives them back if the process exits
+/// set.semop(&[
+///     SemOp::wait_zero(1),
+///     SemOp::decrement(0, 2).undo(true),
+/// ])?;
+/// # Ok::<(), Errno>(())
+/// ```
+#[repr(transparent)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SemOp(libc::sembuf);
+
+impl SemOp {
+    /// Creates an operation adding `op` to the semaphore `semnum`.
+    pub const fn new(semnum: u16, op: i16, flags: SemopFlag) -> Self {
+        Self(libc::sembuf {
+            sem_num: semnum,
+            sem_op: op,
+            sem_flg: flags.bits(),
+        })
+    }
+
+    /// Creates an operation adding `n` to the semaphore `semnum`.
+    pub const fn increment(semnum: u16, n: i16) -> Self {
+        Self::new(semnum, n, SemopFlag::empty())
+    }
+
+    /// Creates an operation waiting until the semaphore `semnum` is at
+    /// least `n`, then subtracting `n` from it.
+    pub const fn decrement(semnum: u16, n: i16) -> Self {
+        Self::new(semnum, -n, SemopFlag::empty())
+    }
+
+    /// Creates an operation waiting until the semaphore `semnum` is 0.
+    pub const fn wait_zero(semnum: u16) -> Self {
+        Self::new(semnum, 0, SemopFlag::empty())
+    }
+
+    /// Sets whether the kernel undoes the operation when the process exits,
+    /// with [`SemopFlag::SEM_UNDO`].
+    ///
+    /// The adjustments of the process are kept per semaphore, and cleared
+    /// when the value of the semaphore is set with `semctl`.
+    #[cfg(not(target_os = "freebsd"))]
+    pub const fn undo(self, undo: bool) -> Self {
+        self.with_flag(SemopFlag::SEM_UNDO, undo)
+    }
+
+    /// Sets whether the operation fails with `EAGAIN` instead of blocking,
+    /// with [`SemopFlag::IPC_NOWAIT`].
+    pub const fn nowait(self, nowait: bool) -> Self {
+        self.with_flag(SemopFlag::IPC_NOWAIT, nowait)
+    }
+
+    /// Returns the index of the semaphore the operation applies to.
+    pub const fn semnum(&self) -> u16 {
+        self.0.sem_num
+    }
+
+    /// Returns the value added to the semaphore.
+    pub const fn op(&self) -> i16 {
+        self.0.sem_op
+    }
+
+    /// Returns the flags of the operation.
+    pub const fn flags(&self) -> SemopFlag {
+        SemopFlag::from_bits_truncate(self.0.sem_flg)
+    }
+
+    // -- Private --
+
+    const fn with_flag(mut self, flag: SemopFlag, set: bool) -> Self {
+        if set {
+            self.0.sem_flg |= flag.bits();
+        } else {
+            self.0.sem_flg &= !flag.bits();
+        }
+        self
+    }
+}
+
+/// Control operations of [`SemaphoreSet::semctl`], with their argument.
+#[allow(non_camel_case_types)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum SemctlArg {
+    /// Immediately remove the semaphore set, awakening every process
+    /// blocked on it with `EIDRM`. `semnum` is ignored.
+    IPC_RMID,
+    /// Returns the value of the semaphore.
+    #[cfg(not(target_os = "freebsd"))]
+    GETVAL,
+    /// Sets the value of the semaphore, clearing its adjustments in every
+    /// process.
+    #[cfg(not(target_os = "freebsd"))]
+    SETVAL(c_int),
+    /// Returns the PID of the last process to operate on the semaphore.
+    #[cfg(not(target_os = "freebsd"))]
+    GETPID,
+    /// Returns the number of processes waiting for the semaphore to
+    /// increase.
+    #[cfg(not(target_os = "freebsd"))]
+    GETNCNT,
+    /// Returns the number of processes waiting for the semaphore to become 0.
+    #[cfg(not(target_os = "freebsd"))]
+    GETZCNT,
+}
+
+libc_bitflags!(
+    /// Valid flags for the third parameter of the function
+    /// [`SemaphoreSet::semget`].
+    pub struct SemgetFlag: c_int
+    {
+        /// Create a new set if none is associated with the key.
+        IPC_CREAT;
+        /// This flag is used with IPC_CREAT to ensure that this call creates
+        /// the set.  If the set already exists, the call fails.
+        IPC_EXCL;
+    }
+);
+
+libc_bitflags!(
+    /// Flags of a [`SemOp`].
+    pub struct SemopFlag: c_short
+    {
+        /// Fail with `EAGAIN` instead of blocking.
+        IPC_NOWAIT as c_short;
+        /// Undo the operation when the process exits.
+        #[cfg(not(target_os = "freebsd"))]
+        SEM_UNDO as c_short;
+    }
+);
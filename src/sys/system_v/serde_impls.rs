@@ -0,0 +1,145 @@
+//! Serialization of the nix types held by the plain-data structs
+//!
+//! Identifiers and permissions are written as the raw numbers the kernel
+//! reports, which is what `ipcs` prints and what exporters expect.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::sys::stat::Mode;
+use crate::unistd::{Gid, Uid};
+
+use super::{IpcMode, Key};
+
+use libc::{gid_t, mode_t, uid_t};
+
+pub(super) mod uid {
+    use super::*;
+
+    pub(in super::super) fn serialize<S: Serializer>(
+        uid: &Uid,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        uid.as_raw().serialize(serializer)
+    }
+
+    pub(in super::super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Uid, D::Error> {
+        uid_t::deserialize(deserializer).map(Uid::from_raw)
+    }
+}
+
+pub(super) mod gid {
+    use super::*;
+
+    pub(in super::super) fn serialize<S: Serializer>(
+        gid: &Gid,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        gid.as_raw().serialize(serializer)
+    }
+
+    pub(in super::super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Gid, D::Error> {
+        gid_t::deserialize(deserializer).map(Gid::from_raw)
+    }
+}
+
+#[cfg(feature = "sysvipc_shm")]
+pub(super) mod pid {
+    use super::*;
+    use crate::unistd::Pid;
+    use libc::pid_t;
+
+    pub(in super::super) fn serialize<S: Serializer>(
+        pid: &Pid,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        pid.as_raw().serialize(serializer)
+    }
+
+    pub(in super::super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Pid, D::Error> {
+        pid_t::deserialize(deserializer).map(Pid::from_raw)
+    }
+}
+
+#[cfg(all(
+    feature = "sysvipc_msg",
+    any(target_os = "android", target_os = "freebsd", target_os = "linux")
+))]
+pub(super) mod optional_pid {
+    use super::*;
+    use crate::unistd::Pid;
+    use libc::pid_t;
+
+    pub(in super::super) fn serialize<S: Serializer>(
+        pid: &Option<Pid>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        pid.map(Pid::as_raw).serialize(serializer)
+    }
+
+    pub(in super::super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Pid>, D::Error> {
+        Option::<pid_t>::deserialize(deserializer)
+            .map(|pid| pid.map(Pid::from_raw))
+    }
+}
+
+pub(super) mod mode {
+    use super::*;
+
+    pub(in super::super) fn serialize<S: Serializer>(
+        mode: &Mode,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        mode.bits().serialize(serializer)
+    }
+
+    /// Unknown bits are dropped.
+    pub(in super::super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Mode, D::Error> {
+        mode_t::deserialize(deserializer).map(Mode::from_bits_truncate)
+    }
+}
+
+impl Serialize for Key {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        self.as_raw().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Key {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        Deserialize::deserialize(deserializer).map(Key::new)
+    }
+}
+
+impl Serialize for IpcMode {
+    fn serialize<S: Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        mode::serialize(&self.mode(), serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpcMode {
+    /// Bits other than the permissions are dropped, like
+    /// [`From<Mode>`](IpcMode::from) does.
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        mode::deserialize(deserializer).map(IpcMode::from)
+    }
+}
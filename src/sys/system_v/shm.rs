@@ -0,0 +1,4654 @@
+//! Safe wrapper around a SystemV shared memory segment
+//!
+//! A segment and its attachments are distinct types:
+//!
+//! * [`Shm`] holds the identifier of a segment, without mapping it. It
+//!   queries, changes or removes the segment, and attaches it.
+//! * [`SharedMemory`] is an attachment, mapping the segment in the address
+//!   space of the process until it is dropped. [`SharedMemoryRo`] and, on
+//!   Linux, [`SharedMemoryExec`] only give read access.
+//!
+//! A segment can be attached any number of times, with different flags,
+//! and [`SharedMemory::segment`] returns the [`Shm`] of an attachment. Each
+//! attachment is detached on its own, leaving the others mapped. On Linux,
+//! [`Shm::attach_mirrored`] attaches a segment twice back to back, for ring
+//! buffers.
+
+use std::{
+    fmt,
+    marker::PhantomData,
+    mem::{self, ManuallyDrop, MaybeUninit},
+    ops::{Deref, DerefMut},
+    ptr::{self, NonNull},
+    time::{Duration, SystemTime},
+};
+
+use crate::unistd::{Gid, Pid, Uid};
+use crate::Result;
+use crate::{errno::Errno, sys::stat::Mode};
+
+use super::{
+    optional_time, time, IpcIdentity, IpcKind, IpcMode, Key, Operation,
+    SysvError, SysvResult,
+};
+pub use super::{Pod, SysvSafe, Zeroable};
+
+use super::ffi::{self as libc, c_int, c_void, mode_t, shmid_ds};
+
+#[derive(Debug)]
+/// Safe wrapper to create and connect to a SystemV shared memory segment.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::shm::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// struct MyData(i64);
+/// unsafe impl SysvSafe for MyData {}
+///
+/// const MY_KEY: Key = Key::new(1337);
+/// let mem_segment = Shm::<MyData>::create_and_connect(
+///     MY_KEY,
+///     IpcMode::owner_rw(),
+/// )?;
+/// let shared_memory = mem_segment.attach(AttachAddress::Any, ShmatFlag::empty())?;
+/// // Do stuff with shared memory...
+/// # Ok::<(), Errno>(())
+/// ```
+pub struct Shm<T> {
+    key: Key,
+    id: c_int,
+    _phantom: PhantomData<T>,
+}
+
+impl<T> Shm<T> {
+    /// Attach to the current SystemV shared memory segment.
+    ///
+    /// To create a new segment, use [`Shm::create_and_connect`].\
+    /// If you need more customisation, use the unsafe version,
+    /// [`Shm::shmget`], with the key [`ShmgetFlag::IPC_CREAT`].\
+    ///
+    /// To delete a shared memory segment, use [`Shm::remove`].
+    ///
+    /// The content of the segment is not trusted to be a valid `T`: it must
+    /// be initialized with [`SharedMemory::write`], or asserted to be
+    /// initialized with [`SharedMemory::assume_init`] when another process
+    /// already did it.
+    ///
+    /// Read-only attachments are made with [`Shm::attach_ro`]; passing
+    /// [`ShmatFlag::SHM_RDONLY`] here fails with [`Errno::EINVAL`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// struct MyData(i64);
+    /// # unsafe impl SysvSafe for MyData {}
+    ///
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let mem_segment = Shm::<MyData>::create_and_connect(
+    ///     MY_KEY,
+    ///     IpcMode::owner_rw(),
+    /// )?;
+    /// let mut shared_memory = mem_segment
+    ///     .attach(AttachAddress::Any, ShmatFlag::empty())?
+    ///     .write(MyData(0));
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn attach(
+        &self,
+        address: AttachAddress,
+        shmat_flag: ShmatFlag,
+    ) -> SysvResult<SharedMemory<MaybeUninit<T>>>
+    where
+        T: SysvSafe,
+    {
+        if shmat_flag.contains(ShmatFlag::SHM_RDONLY) {
+            return Err(SysvError::new(Operation::Shmat, Errno::EINVAL));
+        }
+        let (shmaddr, shmat_flag) = address
+            .resolve(shmat_flag)
+            .map_err(|e| SysvError::new(Operation::Shmat, e))?;
+        Ok(SharedMemory {
+            id: self.id,
+            key: self.key,
+            ptr: shmat(self.id, shmaddr, shmat_flag)?.cast(),
+            access: shmat_flag & ShmatFlag::access(),
+        })
+    }
+
+    /// Attach to the current SystemV shared memory segment, for reading
+    /// only.
+    ///
+    /// [`ShmatFlag::SHM_RDONLY`] is added to `shmat_flag`, and the returned
+    /// [`SharedMemoryRo`] does not give mutable access to the segment.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::Key;
+    /// # use nix::sys::stat::Mode;
+    /// #
+    /// struct MyData(i64);
+    /// # unsafe impl SysvSafe for MyData {}
+    ///
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let mem_segment = unsafe {
+    ///     Shm::<MyData>::shmget(MY_KEY, ShmgetFlag::empty(), Mode::empty())
+    /// }?;
+    /// let memory = mem_segment.attach_ro(AttachAddress::Any, ShmatFlag::empty())?;
+    /// // The creator of the segment initialized it
+    /// let memory = unsafe { memory.assume_init() };
+    /// println!("{}", memory.0);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn attach_ro(
+        &self,
+        address: AttachAddress,
+        shmat_flag: ShmatFlag,
+    ) -> SysvResult<SharedMemoryRo<MaybeUninit<T>>>
+    where
+        T: SysvSafe,
+    {
+        let (shmaddr, shmat_flag) = address
+            .resolve(shmat_flag)
+            .map_err(|e| SysvError::new(Operation::Shmat, e))?;
+        let shmat_flag = shmat_flag | ShmatFlag::SHM_RDONLY;
+        Ok(SharedMemoryRo(SharedMemory {
+            id: self.id,
+            key: self.key,
+            ptr: shmat(self.id, shmaddr, shmat_flag)?.cast(),
+            access: shmat_flag & ShmatFlag::access(),
+        }))
+    }
+
+    /// Attach to the current SystemV shared memory segment, for reading and
+    /// executing its content.
+    ///
+    /// [`ShmatFlag::SHM_EXEC`] and [`ShmatFlag::SHM_RDONLY`] are added to
+    /// `shmat_flag`, and the caller must have read and execute permission on
+    /// the segment. An attachment that is both writable and executable can
+    /// still be created by passing [`ShmatFlag::SHM_EXEC`] to
+    /// [`Shm::attach`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// # use nix::sys::stat::Mode;
+    /// #
+    /// struct Code([u8; 4096]);
+    /// # unsafe impl SysvSafe for Code {}
+    ///
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let mode = IpcMode::owner_rw().with(Mode::S_IXUSR);
+    /// let mem_segment = Shm::<Code>::create_and_connect(MY_KEY, mode)?;
+    /// let mut code = mem_segment
+    ///     .attach(AttachAddress::Any, ShmatFlag::empty())?
+    ///     .write(Code([0; 4096]));
+    /// // x86_64: mov eax, 42; ret
+    /// code.0[..6].copy_from_slice(&[0xb8, 0x2a, 0x00, 0x00, 0x00, 0xc3]);
+    ///
+    /// let exec = mem_segment.attach_exec(AttachAddress::Any, ShmatFlag::empty())?;
+    /// let exec = unsafe { exec.assume_init() };
+    /// let f: extern "C" fn() -> i32 = unsafe { std::mem::transmute(exec.as_ptr()) };
+    /// assert_eq!(f(), 42);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn attach_exec(
+        &self,
+        address: AttachAddress,
+        shmat_flag: ShmatFlag,
+    ) -> SysvResult<SharedMemoryExec<MaybeUninit<T>>>
+    where
+        T: SysvSafe,
+    {
+        let (shmaddr, shmat_flag) = address
+            .resolve(shmat_flag)
+            .map_err(|e| SysvError::new(Operation::Shmat, e))?;
+        let shmat_flag =
+            shmat_flag | ShmatFlag::SHM_RDONLY | ShmatFlag::SHM_EXEC;
+        let size = self
+            .size()
+            .map_err(|e| SysvError::new(Operation::Shmat, e))?;
+        let page_size = page_size();
+        Ok(SharedMemoryExec {
+            memory: SharedMemory {
+                id: self.id,
+                key: self.key,
+                ptr: shmat(self.id, shmaddr, shmat_flag)?.cast(),
+                access: shmat_flag & ShmatFlag::access(),
+            },
+            len: (size + page_size - 1) / page_size * page_size,
+        })
+    }
+
+    /// Attach to the current SystemV shared memory segment in place of the
+    /// memory mapped at `target`, with [`ShmatFlag::SHM_REMAP`].
+    ///
+    /// `len_check` is the number of bytes the caller expects to replace,
+    /// which must be the size of the segment rounded up to a multiple of the
+    /// page size. Otherwise the attachment fails with
+    /// [`SysvError::SizeMismatch`], with the mapped size as the actual size.
+    ///
+    /// The whole range must already be mapped, as listed by
+    /// `/proc/self/maps`, and the attachment fails with [`Errno::ENOMEM`]
+    /// otherwise: attaching to free addresses does not need to replace
+    /// anything. A `target` that is not aligned on a page boundary fails with
+    /// [`Errno::EINVAL`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// # use std::ptr::NonNull;
+    /// #
+    /// struct Page([u8; 4096]);
+    /// # unsafe impl SysvSafe for Page {}
+    ///
+    /// # fn reserved_address() -> NonNull<std::ffi::c_void> { unimplemented!() }
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let mem_segment = Shm::<Page>::create_and_connect(MY_KEY, IpcMode::owner_rw())?;
+    /// // Address of a page reserved earlier with `mmap`, and not used since
+    /// let address = reserved_address();
+    /// let target = unsafe { RemapTarget::new(address) };
+    /// let page = mem_segment.attach_remap_at(target, 4096)?.write(Page([0; 4096]));
+    /// assert_eq!(SharedMemory::as_ptr(&page).cast(), address.as_ptr());
+    /// # Ok::<(), Errno>(())
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn attach_remap_at(
+        &self,
+        target: RemapTarget,
+        len_check: usize,
+    ) -> SysvResult<SharedMemory<MaybeUninit<T>>>
+    where
+        T: SysvSafe,
+    {
+        let start = target.address.as_ptr() as usize;
+        let page_size = page_size();
+        if start % page_size != 0 {
+            return Err(SysvError::new(Operation::Shmat, Errno::EINVAL));
+        }
+        let size = self
+            .size()
+            .map_err(|e| SysvError::new(Operation::Shmat, e))?;
+        let mapped = (size + page_size - 1) / page_size * page_size;
+        if len_check != mapped {
+            return Err(SysvError::SizeMismatch {
+                operation: Operation::Shmat,
+                expected: len_check,
+                actual: mapped,
+            });
+        }
+        let end = start
+            .checked_add(mapped)
+            .ok_or_else(|| SysvError::new(Operation::Shmat, Errno::EINVAL))?;
+        if !is_range_mapped(start, end)
+            .map_err(|e| SysvError::new(Operation::Shmat, e))?
+        {
+            return Err(SysvError::new(Operation::Shmat, Errno::ENOMEM));
+        }
+        let shmat_flag = ShmatFlag::SHM_REMAP;
+        Ok(SharedMemory {
+            id: self.id,
+            key: self.key,
+            ptr: shmat(self.id, target.address.as_ptr(), shmat_flag)?.cast(),
+            access: shmat_flag & ShmatFlag::access(),
+        })
+    }
+
+    /// Attaches the segment twice, back to back, for ring buffers to read
+    /// and write across the end of the segment without wrapping around.
+    ///
+    /// The size of the segment must be a multiple of the page size,
+    /// otherwise this fails with [`Errno::EINVAL`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let mem_segment = Shm::<[u8; 65536]>::create_and_connect(MY_KEY, IpcMode::owner_rw())?;
+    /// let mut ring = mem_segment.attach_mirrored()?;
+    /// // Written at the end and at the start of the segment
+    /// ring.write_at(65534, b"abcd");
+    /// let mut start = [0; 2];
+    /// ring.read_at(0, &mut start);
+    /// assert_eq!(&start, b"cd");
+    /// # Ok::<(), Errno>(())
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn attach_mirrored(&self) -> SysvResult<SharedMemoryMirrored>
+    where
+        T: SysvSafe,
+    {
+        let len = self
+            .size()
+            .map_err(|e| SysvError::new(Operation::Shmat, e))?;
+        if len % page_size() != 0 {
+            return Err(SysvError::new(Operation::Shmat, Errno::EINVAL));
+        }
+        let total = len
+            .checked_mul(2)
+            .ok_or_else(|| SysvError::new(Operation::Shmat, Errno::ENOMEM))?;
+        // Reserves the address range, for both attachments to replace it
+        let reserved = unsafe {
+            ::libc::mmap(
+                ptr::null_mut(),
+                total,
+                ::libc::PROT_NONE,
+                ::libc::MAP_PRIVATE
+                    | ::libc::MAP_ANONYMOUS
+                    | ::libc::MAP_NORESERVE,
+                -1,
+                0,
+            )
+        };
+        if reserved == ::libc::MAP_FAILED {
+            return Err(SysvError::new(Operation::Shmat, Errno::last()));
+        }
+        let first = reserved.cast::<u8>();
+        let second = unsafe { first.add(len) };
+        let unreserve = |start: *mut u8, len| unsafe {
+            ::libc::munmap(start.cast(), len);
+        };
+        if let Err(e) = shmat(self.id, first.cast(), ShmatFlag::SHM_REMAP) {
+            unreserve(first, total);
+            return Err(e);
+        }
+        if let Err(e) = shmat(self.id, second.cast(), ShmatFlag::SHM_REMAP) {
+            unsafe { libc::shmdt(first.cast()) };
+            unreserve(second, len);
+            return Err(e);
+        }
+        Ok(SharedMemoryMirrored {
+            key: self.key,
+            id: self.id,
+            ptr: NonNull::new(first).expect("mmap never returns null"),
+            len,
+        })
+    }
+
+    /// Creates and returns a new System V shared memory segment identifier.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// struct MyData(i64);
+    /// const MY_KEY: Key = Key::new(1337);
+    ///
+    /// let mem_segment = Shm::<MyData>::create_and_connect(
+    ///     MY_KEY,
+    ///     IpcMode::owner_rw(),
+    /// )?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn create_and_connect(
+        key: Key,
+        mode: impl Into<IpcMode>,
+    ) -> SysvResult<Self> {
+        let size = std::mem::size_of::<T>();
+        // This is the main difference between this function and [`Shm::shmget`]
+        // Because we are always creating a new segment, we can be sure that the size match
+        let shmget_flag = ShmgetFlag::IPC_CREAT | ShmgetFlag::IPC_EXCL;
+        let id = segment_id(key, size, shmget_flag, mode)?;
+        Ok(Self {
+            key,
+            id,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Performs the control operation `cmd` on the current System V shared
+    /// memory segment.
+    ///
+    /// The purpose-specific methods, like [`Shm::stat`] or [`Shm::remove`],
+    /// are usually more convenient.
+    ///
+    /// For more information, see [`shmctl(2)`].
+    ///
+    /// # Example
+    ///
+    /// ## Deleting a shared memory segment
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// struct MyData(i64);
+    /// const MY_KEY: Key = Key::new(1337);
+    ///
+    /// let mem_segment = Shm::<MyData>::create_and_connect(
+    ///     MY_KEY,
+    ///     IpcMode::owner_rw(),
+    /// )?;
+    /// let _ = mem_segment.shmctl(ShmctlArg::IPC_RMID)?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    ///
+    /// [`shmctl(2)`]: https://man7.org/linux/man-pages/man2/shmctl.2.html
+    pub fn shmctl(&self, cmd: ShmctlArg<'_>) -> Result<c_int> {
+        let res = unsafe {
+            match cmd {
+                ShmctlArg::IPC_SET(buf) => libc::shmctl(
+                    self.id,
+                    libc::IPC_SET,
+                    // IPC_SET only reads the structure
+                    buf as *const shmid_ds as *mut shmid_ds,
+                ),
+                ShmctlArg::IPC_STAT(buf) => {
+                    libc::shmctl(self.id, libc::IPC_STAT, buf)
+                }
+                ShmctlArg::IPC_RMID => {
+                    libc::shmctl(self.id, libc::IPC_RMID, ptr::null_mut())
+                }
+                #[cfg(target_os = "linux")]
+                ShmctlArg::SHM_LOCK => {
+                    libc::shmctl(self.id, libc::SHM_LOCK, ptr::null_mut())
+                }
+                #[cfg(target_os = "linux")]
+                ShmctlArg::SHM_UNLOCK => {
+                    libc::shmctl(self.id, libc::SHM_UNLOCK, ptr::null_mut())
+                }
+            }
+        };
+        Errno::result(res)
+    }
+
+    /// Changes the owner and the permissions of the segment.
+    ///
+    /// The caller must be the owner or the creator of the segment, or be
+    /// privileged.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// # use nix::unistd::{getgid, getuid};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let segment = Shm::<u64>::create_and_connect(MY_KEY, IpcMode::owner_rw())?;
+    /// segment.set_permissions(getuid(), getgid(), IpcMode::group_rw())?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn set_permissions(
+        &self,
+        uid: Uid,
+        gid: Gid,
+        mode: impl Into<IpcMode>,
+    ) -> Result<()> {
+        let mode = mode.into();
+        shm_set(self.id, |perm| {
+            perm.uid = uid.as_raw();
+            perm.gid = gid.as_raw();
+            perm.mode = mode.mode().bits() as _;
+        })
+    }
+
+    /// Marks the segment to be destroyed, once every process detached it.
+    ///
+    /// The caller must be the owner or the creator of the segment, or be
+    /// privileged.
+    pub fn remove(&self) -> Result<()> {
+        self.shmctl(ShmctlArg::IPC_RMID).map(drop)
+    }
+
+    /// Prevents the segment from being swapped out.
+    ///
+    /// Pages that are not in memory yet are not faulted in by this call.
+    #[cfg(target_os = "linux")]
+    pub fn lock(&self) -> Result<()> {
+        self.shmctl(ShmctlArg::SHM_LOCK).map(drop)
+    }
+
+    /// Allows the segment to be swapped out again.
+    #[cfg(target_os = "linux")]
+    pub fn unlock(&self) -> Result<()> {
+        self.shmctl(ShmctlArg::SHM_UNLOCK).map(drop)
+    }
+
+    /// Creates and returns a new, or returns an existing, System V shared memory
+    /// segment identifier.
+    ///
+    /// For more information, see [`shmget(2)`].
+    ///
+    /// # Safety
+    ///
+    /// If you are using this function to connect to an existing memory segment,
+    /// care must be taken that the generic type `T` matches what is actually
+    /// stored on the memory segment.\
+    /// A segment smaller than a `T` is rejected with
+    /// [`SysvError::SizeMismatch`], but a larger one, created for another
+    /// type, is not.
+    ///
+    /// # Example
+    ///
+    /// ## Connecting to an existing shared memory segment
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// struct MyData(i64);
+    /// const MY_KEY: Key = Key::new(1337);
+    ///
+    /// let mem_segment = unsafe { Shm::<MyData>::shmget(
+    ///     MY_KEY,
+    ///     ShmgetFlag::empty(),
+    ///     IpcMode::owner_rw(),
+    /// )}?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    ///
+    /// [`shmget(2)`]: https://man7.org/linux/man-pages/man2/shmget.2.html
+    pub unsafe fn shmget(
+        key: Key,
+        shmget_flag: ShmgetFlag,
+        mode: impl Into<IpcMode>,
+    ) -> SysvResult<Self> {
+        let size = std::mem::size_of::<T>();
+        let id = segment_id(key, size, shmget_flag, mode)?;
+        Ok(Self {
+            key,
+            id,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Creates and returns a new, or returns an existing, System V shared
+    /// memory segment identifier, of `size` bytes rather than the size of a
+    /// `T`.
+    ///
+    /// This reserves room past the `T`, for its future versions or for
+    /// trailing data, reachable through [`SharedMemory::as_bytes`]. Fails
+    /// with [`SysvError::SizeMismatch`] if `size` is smaller than a `T`, or
+    /// if the existing segment is smaller than `size`.
+    ///
+    /// # Safety
+    ///
+    /// See [`Shm::shmget`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let mem_segment = unsafe {
+    ///     Shm::<u64>::shmget_with_size(
+    ///         MY_KEY,
+    ///         4096,
+    ///         ShmgetFlag::IPC_CREAT | ShmgetFlag::IPC_EXCL,
+    ///         IpcMode::owner_rw(),
+    ///     )
+    /// }?;
+    /// assert_eq!(mem_segment.size()?, 4096);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub unsafe fn shmget_with_size(
+        key: Key,
+        size: usize,
+        shmget_flag: ShmgetFlag,
+        mode: impl Into<IpcMode>,
+    ) -> SysvResult<Self> {
+        let expected = mem::size_of::<T>();
+        if size < expected {
+            return Err(SysvError::SizeMismatch {
+                operation: Operation::Shmget,
+                expected,
+                actual: size,
+            });
+        }
+        let id = segment_id(key, size, shmget_flag, mode)?;
+        Ok(Self {
+            key,
+            id,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Returns the status of the segment.
+    ///
+    /// See [`SharedMemory::stat`].
+    pub fn stat(&self) -> Result<ShmStat> {
+        shm_stat(self.id)
+    }
+
+    /// Returns the number of processes attached to the segment.
+    ///
+    /// See [`SharedMemory::nattch`].
+    pub fn nattch(&self) -> Result<usize> {
+        self.stat().map(|stat| stat.attachments)
+    }
+
+    /// Returns the size of the segment, in bytes.
+    ///
+    /// See [`SharedMemory::segment_size`].
+    pub fn size(&self) -> Result<usize> {
+        self.stat().map(|stat| stat.size)
+    }
+
+    /// Returns the identity of the segment, to tell it apart in logs.
+    pub fn identity(&self) -> IpcIdentity {
+        IpcIdentity::new(IpcKind::SharedMemory, self.key, self.id)
+    }
+
+    /// Returns the identifier of the segment, as returned by `shmget`.
+    pub fn id(&self) -> c_int {
+        self.id
+    }
+
+    /// Creates a handle to the existing segment `id`, for example received
+    /// from another process, after checking its size with `IPC_STAT`.
+    ///
+    /// `key` is only used by [`Shm::identity`], and may be
+    /// [`Key::IPC_PRIVATE`] if it is unknown.
+    ///
+    /// Fails with [`SysvError::SizeMismatch`] if the segment is smaller than
+    /// a `T`. Larger segments are accepted, and [`Shm::from_raw`] skips the
+    /// check.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::Key;
+    /// # let id = 0;
+    /// #
+    /// // `id` was received from the process that created the segment
+    /// let segment = Shm::<[u64; 16]>::from_id(Key::IPC_PRIVATE, id)?;
+    /// let memory = segment.attach_ro(AttachAddress::Any, ShmatFlag::empty())?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn from_id(key: Key, id: c_int) -> SysvResult<Self> {
+        let expected = mem::size_of::<T>();
+        let actual = shm_stat(id)
+            .map_err(|errno| match errno {
+                // The identifier is not valid, or not anymore
+                Errno::EINVAL => SysvError::SegmentRemoved {
+                    operation: Operation::Shmctl,
+                    errno,
+                },
+                errno => SysvError::new(Operation::Shmctl, errno),
+            })?
+            .size;
+        if actual < expected {
+            return Err(SysvError::SizeMismatch {
+                operation: Operation::Shmctl,
+                expected,
+                actual,
+            });
+        }
+        Ok(Self {
+            key,
+            id,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Creates a handle to the existing segment `id`, without checking its
+    /// size.
+    ///
+    /// `key` is only used by [`Shm::identity`], and may be
+    /// [`Key::IPC_PRIVATE`] if it is unknown.
+    ///
+    /// # Safety
+    ///
+    /// The segment must be large enough to hold a `T`, see [`Shm::from_id`].
+    pub unsafe fn from_raw(key: Key, id: c_int) -> Self {
+        Self {
+            key,
+            id,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Passes the segment to the processes spawned by `command`, in the
+    /// [`SHM_ID_ENV`] and [`SHM_KEY_ENV`] environment variables, for them to
+    /// call [`Shm::from_env`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// # use std::process::Command;
+    /// #
+    /// let mem_segment = Shm::<[u64; 16]>::create_and_connect(Key::IPC_PRIVATE, IpcMode::owner_rw())?;
+    /// let mut worker = Command::new("worker");
+    /// mem_segment.export_to(&mut worker).spawn().expect("The worker starts");
+    ///
+    /// // In the worker
+    /// let mem_segment = Shm::<[u64; 16]>::from_env()?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn export_to<'a>(
+        &self,
+        command: &'a mut std::process::Command,
+    ) -> &'a mut std::process::Command {
+        command
+            .env(SHM_ID_ENV, self.id.to_string())
+            .env(SHM_KEY_ENV, self.key.to_string())
+    }
+
+    /// Creates a handle to the segment passed by the parent process with
+    /// [`Shm::export_to`], after checking its size like [`Shm::from_id`].
+    ///
+    /// Fails with [`Errno::ENOENT`] if [`SHM_ID_ENV`] is not set, and with
+    /// [`Errno::EINVAL`] if it or [`SHM_KEY_ENV`] is malformed. The key is
+    /// only used by [`Shm::identity`], and is [`Key::IPC_PRIVATE`] when
+    /// [`SHM_KEY_ENV`] is not set.
+    pub fn from_env() -> SysvResult<Self> {
+        let (key, id) = segment_from_env()
+            .map_err(|e| SysvError::new(Operation::Shmget, e))?;
+        Self::from_id(key, id)
+    }
+
+    /// Forks a child process, which attaches the segment and calls `f` with
+    /// the attachment, then exits with the status returned by `f`.
+    ///
+    /// Returns the child to the parent, which should eventually wait for it
+    /// with [`waitpid`](crate::sys::wait::waitpid). The child exits with
+    /// status 127 if it cannot attach the segment, and 101 if `f` panics.
+    ///
+    /// # Safety
+    ///
+    /// The same as [`fork`](crate::unistd::fork): in a multithreaded
+    /// program, `f` may only call async-signal-safe functions.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// # use nix::sys::wait::waitpid;
+    /// #
+    /// let memory = SharedMemory::new_init(Key::IPC_PRIVATE, IpcMode::owner_rw(), 0u64)?;
+    /// let child = unsafe {
+    ///     memory.segment().spawn_attached(|memory| {
+    ///         // The parent initialized the segment before forking
+    ///         let mut memory = unsafe { memory.assume_init() };
+    ///         *memory = 42;
+    ///         0
+    ///     })
+    /// }?;
+    /// waitpid(child, None)?;
+    /// assert_eq!(*memory, 42);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub unsafe fn spawn_attached<F>(&self, f: F) -> Result<Pid>
+    where
+        T: SysvSafe,
+        F: FnOnce(SharedMemory<MaybeUninit<T>>) -> i32,
+    {
+        match unsafe { crate::unistd::fork() }? {
+            crate::unistd::ForkResult::Parent { child } => Ok(child),
+            crate::unistd::ForkResult::Child => {
+                let status =
+                    match self.attach(AttachAddress::Any, ShmatFlag::empty()) {
+                        Ok(memory) => std::panic::catch_unwind(
+                            std::panic::AssertUnwindSafe(|| f(memory)),
+                        )
+                        .unwrap_or(101),
+                        Err(_) => 127,
+                    };
+                unsafe { ::libc::_exit(status) }
+            }
+        }
+    }
+}
+
+/// Reads the key and identifier of the segment passed by [`Shm::export_to`].
+fn segment_from_env() -> Result<(Key, c_int)> {
+    let var = |name| match std::env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(std::env::VarError::NotPresent) => Ok(None),
+        Err(std::env::VarError::NotUnicode(_)) => Err(Errno::EINVAL),
+    };
+    let id = var(SHM_ID_ENV)?.ok_or(Errno::ENOENT)?;
+    let id = id.parse().map_err(|_| Errno::EINVAL)?;
+    let key = match var(SHM_KEY_ENV)? {
+        // Formatted in hexadecimal by `Display`
+        Some(key) => {
+            let key = key.strip_prefix("0x").ok_or(Errno::EINVAL)?;
+            let key =
+                u32::from_str_radix(key, 16).map_err(|_| Errno::EINVAL)?;
+            Key::new(key as _)
+        }
+        None => Key::IPC_PRIVATE,
+    };
+    Ok((key, id))
+}
+
+/// Environment variable in which [`Shm::export_to`] passes the identifier of
+/// a segment to child processes.
+pub const SHM_ID_ENV: &str = "SYSV_SHM_ID";
+
+/// Environment variable in which [`Shm::export_to`] passes the key of a
+/// segment to child processes.
+pub const SHM_KEY_ENV: &str = "SYSV_SHM_KEY";
+
+/// Address at which [`Shm::attach`] maps a segment.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum AttachAddress {
+    /// Let the kernel choose a suitable address.
+    #[default]
+    Any,
+    /// Attach at exactly this address, which must be aligned on a page
+    /// boundary.
+    ///
+    /// On Linux, [`ShmatFlag::SHM_REMAP`] replaces any existing mapping in
+    /// the range, which otherwise makes the attachment fail with `EINVAL`.
+    /// [`Shm::attach_remap_at`] checks the range before replacing it.
+    Fixed(NonNull<c_void>),
+    /// Attach at this address, rounded down to a multiple of `SHMLBA`
+    /// (`SHM_RND`).
+    Rounded(usize),
+}
+
+/// Memory that [`Shm::attach_remap_at`] may replace with a segment.
+///
+/// Building one is the explicit opt-in to discard whatever is mapped at the
+/// address, which [`ShmatFlag::SHM_REMAP`] does silently. It is consumed by
+/// the attachment, so that it is not `Copy`.
+#[cfg(target_os = "linux")]
+#[derive(Debug, Eq, PartialEq)]
+#[allow(missing_copy_implementations)]
+pub struct RemapTarget {
+    address: NonNull<c_void>,
+}
+
+#[cfg(target_os = "linux")]
+impl RemapTarget {
+    /// Allows replacing the memory mapped at `address`.
+    ///
+    /// # Safety
+    ///
+    /// Nothing may access the memory mapped in the range passed to
+    /// [`Shm::attach_remap_at`] anymore: no reference may point to it, and
+    /// no other owner, like a [`SharedMemory`] or an allocator, may unmap it
+    /// later.
+    pub unsafe fn new(address: NonNull<c_void>) -> Self {
+        Self { address }
+    }
+
+    /// Returns the address of the memory to replace.
+    pub fn address(&self) -> NonNull<c_void> {
+        self.address
+    }
+}
+
+impl AttachAddress {
+    /// Returns the arguments of `shmat` for this address and `shmat_flag`.
+    ///
+    /// The flags selecting the address are derived from the variant, so
+    /// passing [`ShmatFlag::SHM_RND`], or [`ShmatFlag::SHM_REMAP`] without a
+    /// fixed address, fails with `EINVAL`.
+    fn resolve(
+        self,
+        shmat_flag: ShmatFlag,
+    ) -> Result<(*const c_void, ShmatFlag)> {
+        if shmat_flag.contains(ShmatFlag::SHM_RND) {
+            return Err(Errno::EINVAL);
+        }
+        match self {
+            AttachAddress::Fixed(address) => {
+                Ok((address.as_ptr().cast_const(), shmat_flag))
+            }
+            #[cfg(target_os = "linux")]
+            _ if shmat_flag.contains(ShmatFlag::SHM_REMAP) => {
+                Err(Errno::EINVAL)
+            }
+            AttachAddress::Any => Ok((ptr::null(), shmat_flag)),
+            AttachAddress::Rounded(address) => {
+                Ok((address as *const c_void, shmat_flag | ShmatFlag::SHM_RND))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+/// Safe wrapper around a SystemV shared memory segment data
+///
+/// This is a smart pointer, and so implements the [`Deref`] and [`DerefMut`] traits.
+/// This means that you can work with the shared memory segment like you would with a [`Box`].
+///
+/// Attaching a segment returns a `SharedMemory<MaybeUninit<T>>`, which must
+/// be initialized with [`SharedMemory::write`] or
+/// [`SharedMemory::assume_init`] before the `T` can be used.
+///
+/// This type does not automatically destroy the shared memory segment, but
+/// only detach from it using RAII. Errors when detaching are ignored, see
+/// [`SharedMemory::detach`] to handle them.
+///
+/// # Concurrency
+///
+/// Like a [`Box`], a `SharedMemory<T>` is [`Send`] if `T` is, and [`Sync`]
+/// if `T` is. This only accounts for the threads of the calling process:
+/// nothing prevents another process from writing to the segment while it is
+/// borrowed here, which is a data race unless `T` is only made of atomics.
+/// When other processes write to the segment concurrently, access it through
+/// [`SharedMemory::atomic_view`], or copy it out with
+/// [`SharedMemory::read_volatile`], or synchronize the processes with a
+/// process-shared lock.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::shm::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// struct MyData(i64);
+/// # unsafe impl SysvSafe for MyData {}
+/// const MY_KEY: Key = Key::new(1337);
+///
+/// let mem_segment = Shm::<MyData>::create_and_connect(
+///     MY_KEY,
+///     IpcMode::owner_rw(),
+/// )?;
+/// let mut shared_memory = mem_segment
+///     .attach(AttachAddress::Any, ShmatFlag::empty())?
+///     .write(MyData(0));
+///
+/// // This is writing to the stored [`MyData`] struct
+/// shared_memory.0 = 0xDEADBEEF;
+///
+/// // Detach here on shared_memory being dropped
+/// # Ok::<(), Errno>(())
+/// ```
+///
+pub struct SharedMemory<T: ?Sized> {
+    id: c_int,
+    key: Key,
+    ptr: NonNull<T>,
+    /// Flags of the attachment reused by [`SharedMemory::try_clone`].
+    access: ShmatFlag,
+}
+
+// The attachment is owned like a `Box`, and is valid in every thread
+unsafe impl<T: ?Sized + Send> Send for SharedMemory<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for SharedMemory<T> {}
+
+impl<T: ?Sized> Deref for SharedMemory<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        // The attachment is valid for as long as `self`, and only holds a
+        // `SharedMemory<T>` once initialized.
+        unsafe { self.ptr.as_ref() }
+    }
+}
+impl<T: ?Sized> DerefMut for SharedMemory<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { self.ptr.as_mut() }
+    }
+}
+
+impl<T: ?Sized> Drop for SharedMemory<T> {
+    fn drop(&mut self) {
+        // Panicking here would abort the process while unwinding, use
+        // `detach` to handle the error.
+        let _ = Self::shmdt(self);
+    }
+}
+
+impl<T> SharedMemory<MaybeUninit<T>> {
+    /// Initializes the segment with `value`, overwriting its content without
+    /// dropping it.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let mode = IpcMode::owner_rw();
+    /// let segment = Shm::<u64>::create_and_connect(MY_KEY, mode)?;
+    /// let memory = segment.attach(AttachAddress::Any, ShmatFlag::empty())?;
+    /// let memory = memory.write(42);
+    /// assert_eq!(*memory, 42);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn write(mut self, value: T) -> SharedMemory<T> {
+        MaybeUninit::write(&mut self, value);
+        unsafe { self.assume_init() }
+    }
+
+    /// Converts to a `SharedMemory<T>`, trusting the segment to already hold
+    /// a valid `T`.
+    ///
+    /// # Safety
+    ///
+    /// The segment must hold a valid `T`, for example written by another
+    /// process. A freshly created segment is filled with zeros, which is
+    /// only valid for some types.
+    pub unsafe fn assume_init(self) -> SharedMemory<T> {
+        let this = ManuallyDrop::new(self);
+        SharedMemory {
+            id: this.id,
+            key: this.key,
+            ptr: this.ptr.cast(),
+            access: this.access,
+        }
+    }
+}
+
+impl<T: ?Sized> SharedMemory<T> {
+    /// Returns the status of the attached segment, retrieved with
+    /// `IPC_STAT`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let mode = IpcMode::owner_rw();
+    /// let segment = Shm::<u64>::create_and_connect(MY_KEY, mode)?;
+    /// let memory = segment.attach(AttachAddress::Any, ShmatFlag::empty())?;
+    /// let stat = memory.stat()?;
+    /// assert_eq!(stat.attachments, 1);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn stat(&self) -> Result<ShmStat> {
+        shm_stat(self.id)
+    }
+
+    /// Returns the number of processes attached to the segment, this one
+    /// included.
+    ///
+    /// The count is `shm_nattch`, as reported by `IPC_STAT`.
+    pub fn nattch(&self) -> Result<usize> {
+        self.stat().map(|stat| stat.attachments)
+    }
+
+    /// Returns the size of the segment, in bytes.
+    ///
+    /// This is `shm_segsz`, as reported by `IPC_STAT`, which is larger than
+    /// the `T` when the segment was created with
+    /// [`Shm::shmget_with_size`] or by another program.
+    pub fn segment_size(&self) -> Result<usize> {
+        self.stat().map(|stat| stat.size)
+    }
+
+    /// Marks the segment to be destroyed if this attachment is the only one
+    /// left, and returns whether it did.
+    ///
+    /// This lets cooperating processes remove a segment once the last of
+    /// them is done with it, by calling this before dropping their
+    /// attachment.
+    ///
+    /// The count is read before the segment is marked, so a process
+    /// attaching in between still sees the segment destroyed once every
+    /// attachment is gone. On Linux, a marked segment cannot be attached by
+    /// its key anymore.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let builder = SharedMemory::<u64>::builder().key(MY_KEY).open_or_create();
+    /// let memory = builder.attach()?;
+    /// // Work with the segment...
+    /// if memory.remove_if_last_detach()? {
+    ///     // The segment is destroyed once `memory` is dropped
+    /// }
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn remove_if_last_detach(&self) -> Result<bool> {
+        if self.nattch()? != 1 {
+            return Ok(false);
+        }
+        Errno::result(unsafe {
+            libc::shmctl(self.id, libc::IPC_RMID, ptr::null_mut())
+        })?;
+        Ok(true)
+    }
+
+    /// Returns the identity of the attached segment, to tell it apart in
+    /// logs or to register it with a [`Scope`](super::Scope).
+    pub fn identity(&self) -> IpcIdentity {
+        IpcIdentity::new(IpcKind::SharedMemory, self.key, self.id)
+    }
+
+    /// Converts to an [`OwnedSegment`], removing the segment once dropped.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let memory = SharedMemory::new_init(MY_KEY, IpcMode::owner_rw(), 0u64)?;
+    /// let mut memory = memory.into_owner();
+    /// **memory += 1;
+    /// // The segment is removed here, even if the code above panics
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn into_owner(self) -> OwnedSegment<T> {
+        OwnedSegment(self)
+    }
+
+    /// Changes the owner of the attached segment, keeping its permissions.
+    ///
+    /// The caller must be the owner or the creator of the segment, or be
+    /// privileged.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// # use nix::unistd::{Gid, Uid};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let memory = SharedMemory::new_init(MY_KEY, IpcMode::owner_rw(), 0u64)?;
+    /// // Hand the segment over to a service account
+    /// memory.set_owner(Uid::from_raw(1000), Gid::from_raw(1000))?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn set_owner(&self, uid: Uid, gid: Gid) -> Result<()> {
+        shm_set(self.id, |perm| {
+            perm.uid = uid.as_raw();
+            perm.gid = gid.as_raw();
+        })
+    }
+
+    /// Changes the permissions of the attached segment, keeping its owner.
+    ///
+    /// The caller must be the owner or the creator of the segment, or be
+    /// privileged.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let memory = SharedMemory::new_init(MY_KEY, IpcMode::owner_rw(), 0u64)?;
+    /// memory.set_mode(IpcMode::group_ro())?;
+    /// assert_eq!(memory.stat()?.mode, IpcMode::group_ro());
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn set_mode(&self, mode: impl Into<IpcMode>) -> Result<()> {
+        let mode = mode.into();
+        shm_set(self.id, |perm| perm.mode = mode.mode().bits() as _)
+    }
+
+    /// Prevents the attached segment from being swapped out.
+    ///
+    /// See [`Shm::lock`].
+    #[cfg(target_os = "linux")]
+    pub fn lock(&self) -> Result<()> {
+        shm_lock(self.id, libc::SHM_LOCK)
+    }
+
+    /// Allows the attached segment to be swapped out again.
+    ///
+    /// See [`Shm::unlock`].
+    #[cfg(target_os = "linux")]
+    pub fn unlock(&self) -> Result<()> {
+        shm_lock(self.id, libc::SHM_UNLOCK)
+    }
+
+    /// Faults in every page of the attached segment.
+    ///
+    /// Locking a segment does not fault in its pages, so the first access to
+    /// each of them still stalls. As recommended by [`shmctl(2)`], call this
+    /// after [`SharedMemory::lock`] to make the whole segment resident up
+    /// front.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let memory =
+    ///     SharedMemory::<[u64; 4096]>::new_zeroed(MY_KEY, IpcMode::owner_rw())?;
+    /// memory.lock()?;
+    /// memory.prefault()?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    ///
+    /// [`shmctl(2)`]: https://man7.org/linux/man-pages/man2/shmctl.2.html
+    #[cfg(target_os = "linux")]
+    pub fn prefault(&self) -> Result<()> {
+        let start = self.ptr.as_ptr().cast::<MaybeUninit<u8>>();
+        let size = self.stat()?.size;
+        for offset in (0..size).step_by(page_size()) {
+            // Reading is enough to fault the page in, even when attached
+            // read-only
+            unsafe { ptr::read_volatile(start.add(offset)) };
+        }
+        Ok(())
+    }
+
+    /// Returns the identifier of the attached segment.
+    pub fn id(&self) -> c_int {
+        self.id
+    }
+
+    /// Returns a view of the segment as an `A`, only made of atomics, that
+    /// other processes can safely modify while it is borrowed.
+    ///
+    /// The attachment stays borrowed mutably while the view is in use, so
+    /// that it is not accessed as a `T` meanwhile. The view itself can be
+    /// shared between threads.
+    ///
+    /// Fails with [`Errno::EINVAL`] if the attachment is too small for an
+    /// `A`, or not aligned for it.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// # use std::sync::atomic::{AtomicU64, Ordering};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let flags = ShmgetFlag::IPC_CREAT;
+    /// let mut buffer = SharedMemoryBuffer::shmget(MY_KEY, 64, flags, IpcMode::owner_rw())?;
+    /// let counters = buffer.atomic_view::<[AtomicU64; 8]>()?;
+    /// counters[0].fetch_add(1, Ordering::Relaxed);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn atomic_view<A: AtomicSafe>(&mut self) -> Result<&A> {
+        let address = self.ptr.as_ptr().cast::<A>();
+        // The attachment is initialized, or made of `MaybeUninit`
+        let size = mem::size_of_val(unsafe { self.ptr.as_ref() });
+        if size < mem::size_of::<A>()
+            || address as usize % mem::align_of::<A>() != 0
+        {
+            return Err(Errno::EINVAL);
+        }
+        // Atomics can be read whatever their content, and other processes
+        // can store to them
+        Ok(unsafe { &*address })
+    }
+
+    /// Returns the address the segment is attached at.
+    ///
+    /// This is an associated function, so that it does not hide the methods
+    /// of the same name of `T`, such as [`MaybeUninit::as_ptr`].
+    pub fn as_ptr(this: &Self) -> *const T {
+        this.ptr.as_ptr()
+    }
+
+    /// Gives up the attachment, returning the identifier of the segment and
+    /// the address it is attached at, for example to hand them over to C
+    /// code.
+    ///
+    /// The segment is not detached anymore: rebuild the attachment with
+    /// [`SharedMemory::from_raw_parts`], or call `shmdt` on the address.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let memory = SharedMemory::new_init(MY_KEY, IpcMode::owner_rw(), 0u64)?;
+    /// let (id, ptr) = SharedMemory::into_raw(memory);
+    /// // Use the attachment from C...
+    /// let memory = unsafe { SharedMemory::from_raw_parts(MY_KEY, id, ptr) };
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn into_raw(this: Self) -> (c_int, *mut T) {
+        let this = ManuallyDrop::new(this);
+        (this.id, this.ptr.as_ptr())
+    }
+
+    /// Rebuilds an attachment from the identifier of the segment and the
+    /// address it is attached at.
+    ///
+    /// `key` is only used by [`SharedMemory::identity`], and may be
+    /// [`Key::IPC_PRIVATE`] if it is unknown. [`SharedMemory::try_clone`]
+    /// attaches the segment again for reading and writing.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be the address the segment `id` is attached at for reading
+    /// and writing, for example as returned by [`SharedMemory::into_raw`], and
+    /// must not be detached by anything else. The segment must hold a valid
+    /// `T`.
+    pub unsafe fn from_raw_parts(key: Key, id: c_int, ptr: *mut T) -> Self {
+        Self {
+            id,
+            key,
+            ptr: unsafe { NonNull::new_unchecked(ptr) },
+            access: ShmatFlag::empty(),
+        }
+    }
+
+    /// Gives the kernel a hint about how the segment will be used, with
+    /// `madvise` over the whole attachment.
+    ///
+    /// For more information, see [`madvise(2)`].
+    ///
+    /// [`madvise(2)`]: https://man7.org/linux/man-pages/man2/madvise.2.html
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let table = SharedMemory::<[u64; 1 << 20]>::new_zeroed(
+    ///     MY_KEY,
+    ///     IpcMode::owner_rw(),
+    /// )?;
+    /// table.advise(Advice::Sequential)?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn advise(&self, advice: Advice) -> Result<()> {
+        let size = self.stat()?.size;
+        Errno::result(unsafe {
+            libc::madvise(self.ptr.as_ptr().cast(), size, advice.raw())
+        })
+        .map(drop)
+    }
+
+    /// Detaches the segment, returning the error that dropping the
+    /// attachment ignores.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let memory = SharedMemory::new_init(MY_KEY, IpcMode::owner_rw(), 0u64)?;
+    /// memory.detach()?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn detach(self) -> Result<()> {
+        let this = ManuallyDrop::new(self);
+        this.shmdt()
+    }
+
+    // -- Private --
+
+    /// Performs the reverse of `shmat`, detaching the shared memory segment at
+    /// the given address from the address space of the calling process.
+    ///
+    /// This is called automatically on [`Drop`], and by
+    /// [`SharedMemory::detach`].
+    ///
+    /// For more information, see [`shmdt(2)`].
+    ///
+    /// [`shmdt(2)`]: https://man7.org/linux/man-pages/man2/shmdt.2.html
+    fn shmdt(&self) -> Result<()> {
+        Errno::result(unsafe { libc::shmdt(self.ptr.as_ptr().cast()) })
+            .map(drop)
+    }
+}
+
+impl<T: Copy> SharedMemory<T> {
+    /// Copies the value out of the segment with a volatile read.
+    ///
+    /// The compiler does not assume the value is unchanged since the last
+    /// access, which matters when another process writes to the segment.
+    /// The copy can still be torn by a concurrent write: use
+    /// [`SharedMemory::atomic_view`] or a lock when this matters.
+    pub fn read_volatile(&self) -> T {
+        unsafe { ptr::read_volatile(self.ptr.as_ptr()) }
+    }
+
+    /// Copies `value` into the segment with a volatile write.
+    ///
+    /// See [`SharedMemory::read_volatile`].
+    pub fn write_volatile(&mut self, value: T) {
+        unsafe { ptr::write_volatile(self.ptr.as_ptr(), value) }
+    }
+}
+
+impl<T: ?Sized + Pod> SharedMemory<T> {
+    /// Returns the bytes of the whole segment.
+    ///
+    /// The segment can be larger than a `T`, when it was created by another
+    /// process: its size is read with `IPC_STAT`, so the view covers every
+    /// byte a C program sharing the segment may have written.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let memory = SharedMemory::<[u32; 4]>::new_zeroed(MY_KEY, IpcMode::owner_rw())?;
+    /// for chunk in memory.as_bytes()?.chunks(16) {
+    ///     println!("{chunk:02x?}");
+    /// }
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn as_bytes(&self) -> Result<&[u8]> {
+        let size = self.segment_size()?;
+        Ok(unsafe {
+            std::slice::from_raw_parts(self.ptr.as_ptr().cast(), size)
+        })
+    }
+
+    /// Returns the bytes of the whole segment, for writing.
+    ///
+    /// See [`SharedMemory::as_bytes`].
+    pub fn as_bytes_mut(&mut self) -> Result<&mut [u8]> {
+        let size = self.segment_size()?;
+        Ok(unsafe {
+            std::slice::from_raw_parts_mut(self.ptr.as_ptr().cast(), size)
+        })
+    }
+}
+
+impl<T> SharedMemory<T> {
+    /// Returns a [`SharedMemoryBuilder`], to get and attach a segment in a
+    /// single call.
+    pub fn builder() -> SharedMemoryBuilder<T> {
+        SharedMemoryBuilder {
+            key: Key::IPC_PRIVATE,
+            mode: IpcMode::owner_rw(),
+            shmget_flag: ShmgetFlag::empty(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Creates a new System V shared memory segment holding `value`, and
+    /// attaches it.
+    ///
+    /// Fails with [`Errno::EEXIST`] if a segment already exists for `key`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let memory = SharedMemory::new_init(MY_KEY, IpcMode::owner_rw(), 42u64)?;
+    /// assert_eq!(*memory, 42);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn new_init(
+        key: Key,
+        mode: impl Into<IpcMode>,
+        value: T,
+    ) -> SysvResult<Self>
+    where
+        T: SysvSafe,
+    {
+        Ok(Self::new_uninit(key, mode)?.write(value))
+    }
+
+    /// Creates a new System V shared memory segment, initialized in place by
+    /// `f`, and attaches it.
+    ///
+    /// `f` must return the reference obtained when initializing its
+    /// argument, usually from [`MaybeUninit::write`]. This avoids building
+    /// large values on the stack before moving them to the segment.
+    ///
+    /// Fails with [`Errno::EEXIST`] if a segment already exists for `key`,
+    /// and with [`Errno::EINVAL`] if `f` returns a reference to another
+    /// value, in which case the segment is removed.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let memory = SharedMemory::new_with(MY_KEY, IpcMode::owner_rw(), |m| {
+    ///     m.write([0u64; 1024])
+    /// })?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn new_with<F>(
+        key: Key,
+        mode: impl Into<IpcMode>,
+        f: F,
+    ) -> SysvResult<Self>
+    where
+        T: SysvSafe,
+        F: for<'a> FnOnce(&'a mut MaybeUninit<T>) -> &'a mut T,
+    {
+        let mut memory = Self::new_uninit(key, mode)?;
+        let expected = memory.as_mut_ptr();
+        let initialized: *mut T = f(&mut memory);
+        if initialized != expected {
+            let id = memory.id;
+            let _ = memory.detach();
+            let _ = remove_segment_by_id(id);
+            return Err(SysvError::new(Operation::Shmat, Errno::EINVAL));
+        }
+        Ok(unsafe { memory.assume_init() })
+    }
+
+    /// Creates a new System V shared memory segment, and attaches it without
+    /// initializing it further, as the kernel fills it with zeros.
+    ///
+    /// Fails with [`Errno::EEXIST`] if a segment already exists for `key`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let memory =
+    ///     SharedMemory::<[u32; 16]>::new_zeroed(MY_KEY, IpcMode::owner_rw())?;
+    /// assert_eq!(*memory, [0; 16]);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn new_zeroed(key: Key, mode: impl Into<IpcMode>) -> SysvResult<Self>
+    where
+        T: SysvSafe + Zeroable,
+    {
+        let memory = Self::new_uninit(key, mode)?;
+        Ok(unsafe { memory.assume_init() })
+    }
+
+    /// Creates a new System V shared memory segment at a random key, and
+    /// attaches it without initializing it.
+    ///
+    /// Random keys are tried with `IPC_CREAT | IPC_EXCL` until one is free,
+    /// so the segment never collides with one left behind by a previous
+    /// run. The chosen key is returned with the attachment, to be passed to
+    /// the other processes. When they receive the id of the segment instead,
+    /// creating it at [`Key::IPC_PRIVATE`] avoids collisions altogether.
+    ///
+    /// Fails with [`Errno::EEXIST`] if no free key was found after a few
+    /// attempts.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::IpcMode;
+    /// #
+    /// let (key, memory) = SharedMemory::<u64>::create_unique(IpcMode::owner_rw())?;
+    /// let memory = memory.write(42);
+    /// println!("Attach to {key} to read the value");
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn create_unique(
+        mode: impl Into<IpcMode>,
+    ) -> SysvResult<(Key, SharedMemory<MaybeUninit<T>>)>
+    where
+        T: SysvSafe,
+    {
+        const ATTEMPTS: usize = 16;
+
+        let mode = mode.into();
+        for _ in 0..ATTEMPTS {
+            let key = Key::random();
+            match Self::new_uninit(key, mode) {
+                Err(e) if e.errno() == Errno::EEXIST => continue,
+                res => return res.map(|memory| (key, memory)),
+            }
+        }
+        Err(SysvError::new(Operation::Shmget, Errno::EEXIST))
+    }
+
+    /// Attaches the existing System V shared memory segment associated with
+    /// `key`, without trusting its content.
+    ///
+    /// Nothing guarantees that the process that created the segment already
+    /// initialized it: once it is known to hold a valid `T`, the attachment
+    /// is converted with [`SharedMemory::assume_init`].
+    ///
+    /// Fails with [`Errno::ENOENT`] if no segment exists for `key`, and with
+    /// [`SysvError::SizeMismatch`] if the segment is too small for a `T`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let mode = IpcMode::owner_rw();
+    /// let memory = SharedMemory::<u64>::attach_uninit(MY_KEY, mode)?;
+    /// // The creator of the segment initialized it before publishing the key
+    /// let memory = unsafe { memory.assume_init() };
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn attach_uninit(
+        key: Key,
+        mode: impl Into<IpcMode>,
+    ) -> SysvResult<SharedMemory<MaybeUninit<T>>>
+    where
+        T: SysvSafe,
+    {
+        let id =
+            segment_id(key, mem::size_of::<T>(), ShmgetFlag::empty(), mode)?;
+        Ok(SharedMemory {
+            id,
+            key,
+            ptr: shmat(id, ptr::null(), ShmatFlag::empty())?.cast(),
+            access: ShmatFlag::empty(),
+        })
+    }
+
+    // -- Private --
+
+    /// Creates a new segment sized for a `T`, and attaches it.
+    fn new_uninit(
+        key: Key,
+        mode: impl Into<IpcMode>,
+    ) -> SysvResult<SharedMemory<MaybeUninit<T>>>
+    where
+        T: SysvSafe,
+    {
+        let shm = Shm::<T>::create_and_connect(key, mode)?;
+        shm.attach(AttachAddress::Any, ShmatFlag::empty())
+    }
+
+    /// Attaches the same segment once more, with the same access rights.
+    ///
+    /// The new attachment lives at another address, and is detached
+    /// independently of `self`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let mode = IpcMode::owner_rw();
+    /// let segment = Shm::<u64>::create_and_connect(MY_KEY, mode)?;
+    /// let memory = segment.attach(AttachAddress::Any, ShmatFlag::empty())?;
+    /// let memory = memory.write(1);
+    /// let other = memory.try_clone()?;
+    /// assert_eq!(*other, 1);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn try_clone(&self) -> SysvResult<Self> {
+        Ok(Self {
+            id: self.id,
+            key: self.key,
+            ptr: shmat(self.id, ptr::null(), self.access)?.cast(),
+            access: self.access,
+        })
+    }
+
+    /// Returns a handle to the attached segment, which stays usable once
+    /// the attachment is gone.
+    ///
+    /// The handle attaches the segment again, possibly with other flags, or
+    /// removes it, without keeping it attached.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let memory = SharedMemory::new_init(MY_KEY, IpcMode::owner_rw(), 42u64)?;
+    /// let segment = memory.segment();
+    /// drop(memory);
+    ///
+    /// let reader = segment.attach_ro(AttachAddress::Any, ShmatFlag::empty())?;
+    /// assert_eq!(*unsafe { reader.assume_init() }, 42);
+    /// segment.remove()?;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn segment(&self) -> Shm<T> {
+        Shm {
+            key: self.key,
+            id: self.id,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Changes the access to the pages of the attachment with `mprotect`.
+    ///
+    /// Protecting the attachment with [`ReadOnly`] once the segment is
+    /// initialized turns it into a [`SharedMemoryRo`], so a bug or a
+    /// compromised peer cannot get this process to write to the segment
+    /// anymore. Its clones are read-only as well.
+    ///
+    /// The whole segment is protected, even beyond the `T`. The attachment
+    /// is detached if `mprotect` fails.
+    ///
+    /// For more information, see [`mprotect(2)`].
+    ///
+    /// [`mprotect(2)`]: https://man7.org/linux/man-pages/man2/mprotect.2.html
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let config = SharedMemory::new_init(MY_KEY, IpcMode::owner_rw(), 42u64)?;
+    /// let config: SharedMemoryRo<u64> = config.protect(ReadOnly)?;
+    /// assert_eq!(*config, 42);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn protect<P: Protection>(
+        self,
+        protection: P,
+    ) -> Result<P::Attachment<T>> {
+        let _ = protection;
+        let size = self.stat()?.size;
+        Errno::result(unsafe {
+            libc::mprotect(self.ptr.as_ptr().cast(), size, P::PROT)
+        })?;
+        Ok(P::convert(self))
+    }
+}
+
+/// Builder of a [`SharedMemory`], picking the right combination of
+/// [`ShmgetFlag`] for how the segment is expected to exist.
+///
+/// By default, it attaches an existing segment, with the key
+/// [`Key::IPC_PRIVATE`] and the mode [`IpcMode::owner_rw`].
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::shm::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// let counter = SharedMemory::<u64>::builder()
+///     .key(MY_KEY)
+///     .mode(IpcMode::group_rw())
+///     .create_new()
+///     .attach()?
+///     .write(0);
+/// # Ok::<(), Errno>(())
+/// ```
+#[derive(Debug)]
+pub struct SharedMemoryBuilder<T> {
+    key: Key,
+    mode: IpcMode,
+    shmget_flag: ShmgetFlag,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for SharedMemoryBuilder<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for SharedMemoryBuilder<T> {}
+
+impl<T> SharedMemoryBuilder<T> {
+    /// Sets the key of the segment.
+    pub fn key(mut self, key: Key) -> Self {
+        self.key = key;
+        self
+    }
+
+    /// Sets the permissions of the segment, if it gets created.
+    pub fn mode(mut self, mode: impl Into<IpcMode>) -> Self {
+        self.mode = mode.into();
+        self
+    }
+
+    /// Creates a new segment, failing with [`Errno::EEXIST`] if one already
+    /// exists for the key.
+    pub fn create_new(mut self) -> Self {
+        self.shmget_flag |= ShmgetFlag::IPC_CREAT | ShmgetFlag::IPC_EXCL;
+        self
+    }
+
+    /// Attaches the segment associated with the key, creating it if it does
+    /// not exist yet.
+    ///
+    /// An existing segment must be large enough to hold a `T`.
+    pub fn open_or_create(mut self) -> Self {
+        self.shmget_flag.remove(ShmgetFlag::IPC_EXCL);
+        self.shmget_flag |= ShmgetFlag::IPC_CREAT;
+        self
+    }
+
+    /// Allocates the segment using huge pages, if it gets created.
+    ///
+    /// See [`ShmgetFlag::SHM_HUGETLB`].
+    #[cfg(target_os = "linux")]
+    pub fn huge_pages(mut self, huge_pages: bool) -> Self {
+        self.shmget_flag.set(ShmgetFlag::SHM_HUGETLB, huge_pages);
+        self
+    }
+
+    /// Allocates the segment using huge pages of `size`, if it gets created.
+    ///
+    /// See [`HugePageSize`].
+    #[cfg(target_os = "linux")]
+    pub fn huge_page_size(mut self, size: HugePageSize) -> Self {
+        let mask = libc::MAP_HUGE_MASK << libc::MAP_HUGE_SHIFT;
+        self.shmget_flag =
+            ShmgetFlag::from_bits_retain(self.shmget_flag.bits() & !mask)
+                | size.flags();
+        self
+    }
+
+    /// Gets the segment and attaches it.
+    ///
+    /// Like [`Shm::attach`], the content of the segment must then be
+    /// initialized, or asserted to be initialized.
+    pub fn attach(self) -> SysvResult<SharedMemory<MaybeUninit<T>>>
+    where
+        T: SysvSafe,
+    {
+        self.shm()?.attach(AttachAddress::Any, ShmatFlag::empty())
+    }
+
+    /// Gets the segment and attaches it for reading only.
+    ///
+    /// See [`Shm::attach_ro`].
+    pub fn attach_ro(self) -> SysvResult<SharedMemoryRo<MaybeUninit<T>>>
+    where
+        T: SysvSafe,
+    {
+        self.shm()?
+            .attach_ro(AttachAddress::Any, ShmatFlag::empty())
+    }
+
+    // -- Private --
+
+    fn shm(&self) -> SysvResult<Shm<T>> {
+        let id = segment_id(
+            self.key,
+            mem::size_of::<T>(),
+            self.shmget_flag,
+            self.mode,
+        )?;
+        Ok(Shm {
+            key: self.key,
+            id,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+/// Types only made of atomics, that other processes can modify while they
+/// are borrowed, see [`SharedMemory::atomic_view`].
+///
+/// # Safety
+///
+/// Every byte of the type must belong to an atomic, and any bit pattern must
+/// be a valid value of the type.
+///
+/// # Example
+///
+/// ```
+/// # use nix::sys::system_v::shm::{AtomicSafe, SysvSafe};
+/// # use std::sync::atomic::{AtomicU32, AtomicU64};
+/// #[repr(C)]
+/// struct Counters {
+///     sequence: AtomicU64,
+///     readers: AtomicU32,
+///     writers: AtomicU32,
+/// }
+///
+/// unsafe impl SysvSafe for Counters {}
+/// // No padding, and only atomics
+/// unsafe impl AtomicSafe for Counters {}
+/// ```
+pub unsafe trait AtomicSafe: SysvSafe + Sync {}
+
+macro_rules! impl_atomic_safe {
+    ($($t:ty),*) => {
+        $(unsafe impl AtomicSafe for $t {})*
+    };
+}
+
+impl_atomic_safe!(
+    std::sync::atomic::AtomicU8,
+    std::sync::atomic::AtomicU16,
+    std::sync::atomic::AtomicU32,
+    std::sync::atomic::AtomicUsize,
+    std::sync::atomic::AtomicI8,
+    std::sync::atomic::AtomicI16,
+    std::sync::atomic::AtomicI32,
+    std::sync::atomic::AtomicIsize
+);
+#[cfg(target_has_atomic = "64")]
+impl_atomic_safe!(std::sync::atomic::AtomicU64, std::sync::atomic::AtomicI64);
+unsafe impl<T: AtomicSafe, const N: usize> AtomicSafe for [T; N] {}
+
+/// Runtime-sized attachment of a SystemV shared memory segment,
+/// dereferencing to its bytes.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::shm::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// let mut frame = SharedMemoryBuffer::shmget(
+///     MY_KEY,
+///     1920 * 1080 * 4,
+///     ShmgetFlag::IPC_CREAT,
+///     IpcMode::owner_rw(),
+/// )?;
+/// frame.fill(0xff);
+/// # Ok::<(), Errno>(())
+/// ```
+pub type SharedMemoryBuffer = SharedMemory<[u8]>;
+
+impl SharedMemory<[u8]> {
+    /// Creates and returns a new, or returns an existing, System V shared
+    /// memory segment of `size` bytes, and attaches it.
+    ///
+    /// When attaching to an existing segment, `size` may be 0, and the
+    /// length of the buffer is always the size of the segment, as reported
+    /// by `IPC_STAT`.
+    ///
+    /// For more information, see [`shmget(2)`].
+    ///
+    /// [`shmget(2)`]: https://man7.org/linux/man-pages/man2/shmget.2.html
+    pub fn shmget(
+        key: Key,
+        size: usize,
+        shmget_flag: ShmgetFlag,
+        mode: impl Into<IpcMode>,
+    ) -> SysvResult<Self> {
+        let id = segment_id(key, size, shmget_flag, mode)?;
+        Self::attach_id(key, id, ShmatFlag::empty())
+    }
+}
+
+impl<T> SharedMemory<[T]> {
+    /// Creates and returns a new, or returns an existing, System V shared
+    /// memory segment holding `len` values of type `T`, and attaches it.
+    ///
+    /// When attaching to an existing segment, `len` may be 0, and the length
+    /// of the slice is always derived from the size of the segment, as
+    /// reported by `IPC_STAT`. Trailing bytes that cannot hold a whole `T`
+    /// are left out.
+    ///
+    /// Like [`Shm::attach`], the values must then be initialized, with
+    /// [`SharedMemory::init_with`] or [`SharedMemory::assume_init`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::shm::*;
+    /// # use nix::sys::system_v::{IpcMode, Key};
+    /// #
+    /// #[derive(Clone, Copy, Default)]
+    /// struct Sample {
+    ///     timestamp: u64,
+    ///     value: f64,
+    /// }
+    ///
+    /// unsafe impl SysvSafe for Sample {}
+    ///
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let samples = SharedMemory::<[Sample]>::shmget_array(
+    ///     MY_KEY,
+    ///     4096,
+    ///     ShmgetFlag::IPC_CREAT | ShmgetFlag::IPC_EXCL,
+    ///     IpcMode::owner_rw(),
+    /// )?;
+    /// let samples = samples.init_with(|_| Sample::default());
+    /// assert_eq!(samples.len(), 4096);
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn shmget_array(
+        key: Key,
+        len: usize,
+        shmget_flag: ShmgetFlag,
+        mode: impl Into<IpcMode>,
+    ) -> SysvResult<SharedMemory<[MaybeUninit<T>]>>
+    where
+        T: SysvSafe,
+    {
+        let size = len
+            .checked_mul(mem::size_of::<T>())
+            .ok_or(SysvError::new(Operation::Shmget, Errno::EINVAL))?;
+        let id = segment_id(key, size, shmget_flag, mode)?;
+        SharedMemory::attach_id(key, id, ShmatFlag::empty())
+    }
+
+    /// Attaches the same segment once more, with the same access rights.
+    ///
+    /// See [`SharedMemory::try_clone`].
+    pub fn try_clone(&self) -> SysvResult<Self> {
+        Self::attach_id(self.key, self.id, self.access)
+    }
+
+    // -- Private --
+
+    /// Attaches the segment `id` as a slice covering the whole segment.
+    fn attach_id(key: Key, id: c_int, access: ShmatFlag) -> SysvResult<Self> {
+        if mem::size_of::<T>() == 0 {
+            return Err(SysvError::new(Operation::Shmat, Errno::EINVAL));
+        }
+        let data = shmat(id, ptr::null(), access)?;
+        let detach = || unsafe { libc::shmdt(data.as_ptr()) };
+        let len = match shm_stat(id) {
+            Ok(stat) => stat.size / mem::size_of::<T>(),
+            Err(e) => {
+                detach();
+                return Err(SysvError::new(Operation::Shmctl, e));
+            }
+        };
+        let slice = ptr::slice_from_raw_parts_mut(data.as_ptr().cast(), len);
+        Ok(Self {
+            id,
+            key,
+            // A slice built from a non-null pointer is never null
+            ptr: NonNull::new(slice)
+                .ok_or(SysvError::new(Operation::Shmat, Errno::EFAULT))?,
+            access,
+        })
+    }
+}
+
+impl<T> SharedMemory<[MaybeUninit<T>]> {
+    /// Initializes every value of the slice with the result of `f`, called
+    /// with the index of the value, overwriting the content of the segment
+    /// without dropping it.
+    pub fn init_with(
+        mut self,
+        mut f: impl FnMut(usize) -> T,
+    ) -> SharedMemory<[T]> {
+        for (i, value) in self.iter_mut().enumerate() {
+            value.write(f(i));
+        }
+        unsafe { self.assume_init() }
+    }
+
+    /// Converts to a `SharedMemory<[T]>`, trusting the segment to already
+    /// hold valid values of type `T`.
+    ///
+    /// # Safety
+    ///
+    /// See [`SharedMemory::assume_init`].
+    pub unsafe fn assume_init(self) -> SharedMemory<[T]> {
+        let this = ManuallyDrop::new(self);
+        let slice = this.ptr.as_ptr() as *mut [T];
+        SharedMemory {
+            id: this.id,
+            key: this.key,
+            ptr: unsafe { NonNull::new_unchecked(slice) },
+            access: this.access,
+        }
+    }
+}
+
+/// Attachment of a SystemV shared memory segment holding a fixed header
+/// followed by a runtime-sized array, like the metadata and the slots of a
+/// ring buffer.
+///
+/// It dereferences to the header `H`, while [`SharedMemoryWithTail::tail`]
+/// returns the values of type `T` fitting in the rest of the segment. The
+/// tail starts at the first offset past the header that is aligned for `T`.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::shm::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// struct Ring {
+///     head: u32,
+///     tail: u32,
+/// }
+///
+/// unsafe impl SysvSafe for Ring {}
+///
+/// const MY_KEY: Key = Key::new(1337);
+/// let ring = SharedMemoryWithTail::<Ring, u64>::shmget(
+///     MY_KEY,
+///     1024,
+///     ShmgetFlag::IPC_CREAT | ShmgetFlag::IPC_EXCL,
+///     IpcMode::owner_rw(),
+/// )?;
+/// let mut ring = ring.init_with(Ring { head: 0, tail: 0 }, |_| 0);
+/// assert_eq!(ring.tail().len(), 1024);
+/// ring.tail_mut()[0] = 42;
+/// ring.tail = 1;
+/// # Ok::<(), Errno>(())
+/// ```
+#[derive(Debug)]
+pub struct SharedMemoryWithTail<H, T> {
+    header: SharedMemory<H>,
+    /// Number of values in the tail.
+    len: usize,
+    _phantom: PhantomData<[T]>,
+}
+
+impl<H, T> Deref for SharedMemoryWithTail<H, T> {
+    type Target = H;
+
+    fn deref(&self) -> &Self::Target {
+        &self.header
+    }
+}
+
+impl<H, T> DerefMut for SharedMemoryWithTail<H, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.header
+    }
+}
+
+impl<H, T> SharedMemoryWithTail<H, T> {
+    /// Offset of the tail from the start of the segment, in bytes.
+    const TAIL_OFFSET: usize = (mem::size_of::<H>() + mem::align_of::<T>() - 1)
+        / mem::align_of::<T>()
+        * mem::align_of::<T>();
+
+    /// Creates and returns a new, or returns an existing, System V shared
+    /// memory segment holding a header and `len` values of type `T`, and
+    /// attaches it.
+    ///
+    /// When attaching to an existing segment, `len` may be 0, and the length
+    /// of the tail is always derived from the size of the segment, as
+    /// reported by `IPC_STAT`. Trailing bytes that cannot hold a whole `T`
+    /// are left out.
+    ///
+    /// The header and the tail must then be initialized, with
+    /// [`SharedMemoryWithTail::init_with`] or
+    /// [`SharedMemoryWithTail::assume_init`].
+    pub fn shmget(
+        key: Key,
+        len: usize,
+        shmget_flag: ShmgetFlag,
+        mode: impl Into<IpcMode>,
+    ) -> SysvResult<SharedMemoryWithTail<MaybeUninit<H>, MaybeUninit<T>>>
+    where
+        H: SysvSafe,
+        T: SysvSafe,
+    {
+        let size = len
+            .checked_mul(mem::size_of::<T>())
+            .and_then(|size| size.checked_add(Self::TAIL_OFFSET))
+            .ok_or(SysvError::new(Operation::Shmget, Errno::EINVAL))?;
+        let id = segment_id(key, size, shmget_flag, mode)?;
+        SharedMemoryWithTail::attach_id(key, id, ShmatFlag::empty())
+    }
+
+    /// Returns the values following the header.
+    pub fn tail(&self) -> &[T] {
+        unsafe { std::slice::from_raw_parts(self.tail_ptr(), self.len) }
+    }
+
+    /// Returns the values following the header, for writing.
+    pub fn tail_mut(&mut self) -> &mut [T] {
+        unsafe { std::slice::from_raw_parts_mut(self.tail_ptr(), self.len) }
+    }
+
+    /// Attaches the same segment once more, with the same access rights.
+    ///
+    /// See [`SharedMemory::try_clone`].
+    pub fn try_clone(&self) -> SysvResult<Self> {
+        Self::attach_id(self.header.key, self.header.id, self.header.access)
+    }
+
+    /// Returns the status of the attached segment.
+    ///
+    /// See [`SharedMemory::stat`].
+    pub fn stat(&self) -> Result<ShmStat> {
+        self.header.stat()
+    }
+
+    /// Returns the identity of the attached segment, to tell it apart in
+    /// logs.
+    pub fn identity(&self) -> IpcIdentity {
+        self.header.identity()
+    }
+
+    /// Returns a handle to the attached segment.
+    ///
+    /// See [`SharedMemory::segment`].
+    pub fn segment(&self) -> Shm<H> {
+        self.header.segment()
+    }
+
+    /// Detaches the segment.
+    ///
+    /// See [`SharedMemory::detach`].
+    pub fn detach(self) -> Result<()> {
+        self.header.detach()
+    }
+
+    // -- Private --
+
+    /// Returns the address of the first value of the tail.
+    fn tail_ptr(&self) -> *mut T {
+        unsafe {
+            self.header
+                .ptr
+                .as_ptr()
+                .cast::<u8>()
+                .add(Self::TAIL_OFFSET)
+                .cast()
+        }
+    }
+
+    /// Attaches the segment `id`, with a tail covering the rest of the
+    /// segment.
+    fn attach_id(key: Key, id: c_int, access: ShmatFlag) -> SysvResult<Self> {
+        if mem::size_of::<T>() == 0 {
+            return Err(SysvError::new(Operation::Shmat, Errno::EINVAL));
+        }
+        let data = shmat(id, ptr::null(), access)?;
+        let header = SharedMemory::<H> {
+            id,
+            key,
+            ptr: data.cast(),
+            access,
+        };
+        let size = header
+            .segment_size()
+            .map_err(|e| SysvError::new(Operation::Shmctl, e))?;
+        if size < Self::TAIL_OFFSET {
+            return Err(SysvError::SizeMismatch {
+                operation: Operation::Shmat,
+                expected: Self::TAIL_OFFSET,
+                actual: size,
+            });
+        }
+        Ok(Self {
+            header,
+            len: (size - Self::TAIL_OFFSET) / mem::size_of::<T>(),
+            _phantom: PhantomData,
+        })
+    }
+}
+
+impl<H, T> SharedMemoryWithTail<MaybeUninit<H>, MaybeUninit<T>> {
+    /// Initializes the header with `header`, and every value of the tail
+    /// with the result of `f`, called with the index of the value,
+    /// overwriting the content of the segment without dropping it.
+    pub fn init_with(
+        mut self,
+        header: H,
+        mut f: impl FnMut(usize) -> T,
+    ) -> SharedMemoryWithTail<H, T> {
+        self.write(header);
+        for (i, value) in self.tail_mut().iter_mut().enumerate() {
+            value.write(f(i));
+        }
+        unsafe { self.assume_init() }
+    }
+
+    /// Converts to a `SharedMemoryWithTail<H, T>`, trusting the segment to
+    /// already hold a valid header and valid values of type `T`.
+    ///
+    /// # Safety
+    ///
+    /// See [`SharedMemory::assume_init`].
+    pub unsafe fn assume_init(self) -> SharedMemoryWithTail<H, T> {
+        SharedMemoryWithTail {
+            header: unsafe { self.header.assume_init() },
+            len: self.len,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// Returns the id of the segment of `size` bytes associated with `key`.
+///
+/// `shmget` fails with [`Errno::EINVAL`] both when `size` is out of the
+/// limits of the system and when the existing segment is smaller: the size
+/// of the latter is read to tell them apart.
+fn segment_id(
+    key: Key,
+    size: usize,
+    shmget_flag: ShmgetFlag,
+    mode: impl Into<IpcMode>,
+) -> SysvResult<c_int> {
+    let flags = mode.into().mode().bits() as i32 | shmget_flag.bits();
+    Errno::result(unsafe { libc::shmget(key.as_raw(), size, flags) }).map_err(
+        |errno| {
+            let existing = match errno {
+                Errno::EINVAL => existing_size(key),
+                _ => None,
+            };
+            match existing {
+                Some(actual) if actual < size => SysvError::SizeMismatch {
+                    operation: Operation::Shmget,
+                    expected: size,
+                    actual,
+                },
+                _ => SysvError::new(Operation::Shmget, errno),
+            }
+        },
+    )
+}
+
+/// Returns the size of the existing segment associated with `key`, if it can
+/// be read.
+fn existing_size(key: Key) -> Option<usize> {
+    if key == Key::IPC_PRIVATE {
+        return None;
+    }
+    let id = Errno::result(unsafe { libc::shmget(key.as_raw(), 0, 0) }).ok()?;
+    shm_stat(id).ok().map(|stat| stat.size)
+}
+
+/// Attachment of a SystemV shared memory segment that also owns the segment.
+///
+/// This is returned by [`SharedMemory::into_owner`]. Once dropped, it
+/// detaches the segment and marks it with `IPC_RMID`, so that the segment
+/// is destroyed when every other process detached it, including when
+/// unwinding from a panic.
+///
+/// It dereferences to the underlying [`SharedMemory`].
+#[derive(Debug)]
+pub struct OwnedSegment<T: ?Sized>(SharedMemory<T>);
+
+impl<T: ?Sized> OwnedSegment<T> {
+    /// Gives up the ownership of the segment, which is not removed anymore,
+    /// and returns the attachment.
+    pub fn into_inner(self) -> SharedMemory<T> {
+        let this = ManuallyDrop::new(self);
+        // `this` is never dropped, so the attachment is only moved once
+        unsafe { ptr::read(&this.0) }
+    }
+}
+
+impl<T: ?Sized> Deref for OwnedSegment<T> {
+    type Target = SharedMemory<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T: ?Sized> DerefMut for OwnedSegment<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<T: ?Sized> Drop for OwnedSegment<T> {
+    fn drop(&mut self) {
+        // Can only fail if the segment has already been removed
+        let _ =
+            unsafe { libc::shmctl(self.0.id, libc::IPC_RMID, ptr::null_mut()) };
+    }
+}
+
+/// Read-only attachment of a SystemV shared memory segment.
+///
+/// This is returned by [`Shm::attach_ro`], and behaves like a
+/// [`SharedMemory`] that only implements [`Deref`], as the kernel forbids
+/// writing to the segment through this attachment.
+#[derive(Debug)]
+pub struct SharedMemoryRo<T>(SharedMemory<T>);
+
+impl<T> Deref for SharedMemoryRo<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> SharedMemoryRo<MaybeUninit<T>> {
+    /// Converts to a `SharedMemoryRo<T>`, trusting the segment to already
+    /// hold a valid `T`.
+    ///
+    /// # Safety
+    ///
+    /// See [`SharedMemory::assume_init`].
+    pub unsafe fn assume_init(self) -> SharedMemoryRo<T> {
+        SharedMemoryRo(unsafe { self.0.assume_init() })
+    }
+}
+
+impl<T> SharedMemoryRo<T> {
+    /// Returns the status of the attached segment.
+    ///
+    /// See [`SharedMemory::stat`].
+    pub fn stat(&self) -> Result<ShmStat> {
+        self.0.stat()
+    }
+
+    /// Returns the number of processes attached to the segment.
+    ///
+    /// See [`SharedMemory::nattch`].
+    pub fn nattch(&self) -> Result<usize> {
+        self.0.nattch()
+    }
+
+    /// Returns the size of the segment, in bytes.
+    ///
+    /// See [`SharedMemory::segment_size`].
+    pub fn segment_size(&self) -> Result<usize> {
+        self.0.segment_size()
+    }
+
+    /// Gives the kernel a hint about how the segment will be used.
+    ///
+    /// See [`SharedMemory::advise`].
+    pub fn advise(&self, advice: Advice) -> Result<()> {
+        self.0.advise(advice)
+    }
+
+    /// Marks the segment to be destroyed if this attachment is the only one
+    /// left.
+    ///
+    /// See [`SharedMemory::remove_if_last_detach`].
+    pub fn remove_if_last_detach(&self) -> Result<bool> {
+        self.0.remove_if_last_detach()
+    }
+
+    /// Returns the identity of the attached segment.
+    ///
+    /// See [`SharedMemory::identity`].
+    pub fn identity(&self) -> IpcIdentity {
+        self.0.identity()
+    }
+
+    /// Returns the identifier of the attached segment.
+    pub fn id(&self) -> c_int {
+        self.0.id
+    }
+
+    /// Prevents the attached segment from being swapped out.
+    ///
+    /// See [`SharedMemory::lock`].
+    #[cfg(target_os = "linux")]
+    pub fn lock(&self) -> Result<()> {
+        self.0.lock()
+    }
+
+    /// Allows the attached segment to be swapped out again.
+    ///
+    /// See [`SharedMemory::unlock`].
+    #[cfg(target_os = "linux")]
+    pub fn unlock(&self) -> Result<()> {
+        self.0.unlock()
+    }
+
+    /// Faults in every page of the attached segment.
+    ///
+    /// See [`SharedMemory::prefault`].
+    #[cfg(target_os = "linux")]
+    pub fn prefault(&self) -> Result<()> {
+        self.0.prefault()
+    }
+
+    /// Attaches the same segment once more, for reading only.
+    ///
+    /// See [`SharedMemory::try_clone`].
+    pub fn try_clone(&self) -> SysvResult<Self> {
+        self.0.try_clone().map(Self)
+    }
+
+    /// Returns a handle to the attached segment.
+    ///
+    /// See [`SharedMemory::segment`].
+    pub fn segment(&self) -> Shm<T> {
+        self.0.segment()
+    }
+
+    /// Detaches the segment.
+    ///
+    /// See [`SharedMemory::detach`].
+    pub fn detach(self) -> Result<()> {
+        self.0.detach()
+    }
+}
+
+impl<T: Pod> SharedMemoryRo<T> {
+    /// Returns the bytes of the whole segment.
+    ///
+    /// See [`SharedMemory::as_bytes`].
+    pub fn as_bytes(&self) -> Result<&[u8]> {
+        self.0.as_bytes()
+    }
+}
+
+/// Value of a segment whose type grows across versions of an application,
+/// along with its size and version, so that processes built against older
+/// versions can still read it with [`attach_prefix`].
+///
+/// New versions of `T` must only append fields to the previous ones, and
+/// `T` must be `#[repr(C)]`: the fields of every older version are then a
+/// prefix of the newer ones. The value starts 16 bytes into the segment,
+/// whatever its version, which requires `T` to be aligned on at most 16
+/// bytes; this is checked at compile time.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::shm::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// #[repr(C)]
+/// struct StatsV2 {
+///     requests: u64,
+///     // Added in the second version
+///     errors: u64,
+/// }
+/// # unsafe impl SysvSafe for StatsV2 {}
+///
+/// const MY_KEY: Key = Key::new(1337);
+/// let stats = Versioned::new(2, StatsV2 { requests: 0, errors: 0 });
+/// let memory = SharedMemory::new_init(MY_KEY, IpcMode::owner_rw(), stats)?;
+/// assert_eq!(memory.version(), 2);
+/// # Ok::<(), Errno>(())
+/// ```
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[repr(C)]
+pub struct Versioned<T> {
+    size: u64,
+    version: u32,
+    _reserved: u32,
+    value: T,
+}
+
+// Only made of numbers and of a `T`
+unsafe impl<T: SysvSafe> SysvSafe for Versioned<T> {}
+
+impl<T> Versioned<T> {
+    /// Fails to build values that would not start 16 bytes into the
+    /// segment.
+    const ALIGN: () = assert!(
+        mem::align_of::<T>() <= 16,
+        "The value must be aligned on at most 16 bytes"
+    );
+
+    /// Wraps `value`, recording its size and `version`.
+    pub fn new(version: u32, value: T) -> Self {
+        let () = Self::ALIGN;
+        Self {
+            size: mem::size_of::<T>() as u64,
+            version,
+            _reserved: 0,
+            value,
+        }
+    }
+
+    /// Returns the version recorded by the process that wrote the value.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Returns the size of the value written, in bytes, which is larger
+    /// than a `T` if it was written by a newer version.
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+}
+
+impl<T> Deref for Versioned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for Versioned<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.value
+    }
+}
+
+/// Attaches the segment associated with `key`, holding a [`Versioned`]
+/// value, for reading its first fields, `P`, only.
+///
+/// This lets a process built against an older, smaller, version of the
+/// value read a segment written by a newer version: the attachment is
+/// read-only, as writing the prefix could break the invariants of the
+/// fields it does not know, and the fields past `P` are never accessed.
+///
+/// Fails with [`SysvError::SizeMismatch`] if the segment, or the value
+/// recorded in it, is smaller than a `P`, such as when it was written by an
+/// older version.
+///
+/// # Safety
+///
+/// The segment must have been initialized with a [`Versioned`] value whose
+/// type starts with the fields of `P`, as described by [`Versioned`].
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::shm::{self, SysvSafe};
+/// # use nix::sys::system_v::Key;
+/// #
+/// #[repr(C)]
+/// struct StatsV1 {
+///     requests: u64,
+/// }
+/// # unsafe impl SysvSafe for StatsV1 {}
+///
+/// const MY_KEY: Key = Key::new(1337);
+/// // Written by a newer version, which added fields
+/// let stats = unsafe { shm::attach_prefix::<StatsV1>(MY_KEY) }?;
+/// println!("{} requests (version {})", stats.requests, stats.version());
+/// # Ok::<(), Errno>(())
+/// ```
+pub unsafe fn attach_prefix<P: SysvSafe>(
+    key: Key,
+) -> SysvResult<SharedMemoryRo<Versioned<P>>> {
+    let () = Versioned::<P>::ALIGN;
+    // A segment holding a newer, larger, version is accepted
+    let shm = unsafe {
+        Shm::<Versioned<P>>::shmget(
+            key,
+            ShmgetFlag::empty(),
+            IpcMode::owner_rw(),
+        )
+    }?;
+    let memory = shm.attach_ro(AttachAddress::Any, ShmatFlag::empty())?;
+    // The header is made of numbers, valid whatever their bits
+    let actual = unsafe {
+        ptr::addr_of!(
+            (*SharedMemory::as_ptr(&memory.0).cast::<Versioned<P>>()).size
+        )
+        .read()
+    } as usize;
+    let expected = mem::size_of::<P>();
+    if actual < expected {
+        return Err(SysvError::SizeMismatch {
+            operation: Operation::Shmat,
+            expected,
+            actual,
+        });
+    }
+    Ok(unsafe { memory.assume_init() })
+}
+
+/// Executable, read-only attachment of a SystemV shared memory segment.
+///
+/// This is returned by [`Shm::attach_exec`]. Like a [`SharedMemoryRo`], it
+/// only implements [`Deref`], so the segment is never writable and
+/// executable through the same attachment. Code is usually written through
+/// another, writable, attachment, possibly in another process.
+///
+/// The base address and the length of the attachment are exposed to cast
+/// the code to function pointers.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct SharedMemoryExec<T> {
+    memory: SharedMemory<T>,
+    /// Size of the segment, rounded up to a multiple of the page size.
+    len: usize,
+}
+
+#[cfg(target_os = "linux")]
+impl<T> Deref for SharedMemoryExec<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.memory
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl<T> SharedMemoryExec<MaybeUninit<T>> {
+    /// Converts to a `SharedMemoryExec<T>`, trusting the segment to already
+    /// hold a valid `T`.
+    ///
+    /// # Safety
+    ///
+    /// See [`SharedMemory::assume_init`].
+    pub unsafe fn assume_init(self) -> SharedMemoryExec<T> {
+        SharedMemoryExec {
+            memory: unsafe { self.memory.assume_init() },
+            len: self.len,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl<T> SharedMemoryExec<T> {
+    /// Returns the address the segment is attached at.
+    pub fn as_ptr(&self) -> *const c_void {
+        self.memory.ptr.as_ptr().cast_const().cast()
+    }
+
+    /// Returns the length of the attachment, in bytes: the size of the
+    /// segment, rounded up to a multiple of the page size.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the attachment is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the status of the attached segment.
+    ///
+    /// See [`SharedMemory::stat`].
+    pub fn stat(&self) -> Result<ShmStat> {
+        self.memory.stat()
+    }
+
+    /// Returns the identity of the attached segment.
+    ///
+    /// See [`SharedMemory::identity`].
+    pub fn identity(&self) -> IpcIdentity {
+        self.memory.identity()
+    }
+
+    /// Returns the identifier of the attached segment.
+    pub fn id(&self) -> c_int {
+        self.memory.id
+    }
+
+    /// Detaches the segment.
+    ///
+    /// See [`SharedMemory::detach`].
+    pub fn detach(self) -> Result<()> {
+        self.memory.detach()
+    }
+}
+
+/// Two adjacent attachments of the same segment, returned by
+/// [`Shm::attach_mirrored`].
+///
+/// The byte at `offset + len()` is the byte at `offset`, so that reads and
+/// writes of up to [`len`](Self::len) bytes never need to wrap around the
+/// end of the segment. The content is only accessed by copies, since
+/// references to both mappings would alias.
+///
+/// Both attachments are detached when this is dropped, independently of the
+/// other attachments of the segment.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct SharedMemoryMirrored {
+    key: Key,
+    id: c_int,
+    /// Start of the first attachment, followed by the second one.
+    ptr: NonNull<u8>,
+    /// Size of the segment, and of each attachment.
+    len: usize,
+}
+
+#[cfg(target_os = "linux")]
+unsafe impl Send for SharedMemoryMirrored {}
+#[cfg(target_os = "linux")]
+unsafe impl Sync for SharedMemoryMirrored {}
+
+#[cfg(target_os = "linux")]
+impl SharedMemoryMirrored {
+    /// Returns the address of the first attachment, which the second one
+    /// directly follows.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    /// Returns the size of the segment, in bytes, which is half the size of
+    /// the mapping.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the segment is empty, which is never the case.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copies the bytes starting at `offset` into `buf`, wrapping around the
+    /// end of the segment.
+    ///
+    /// # Panics
+    ///
+    /// If `offset` is past the end of the segment, or `buf` is longer than
+    /// the segment.
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) {
+        self.check_range(offset, buf.len());
+        unsafe {
+            ptr::copy_nonoverlapping(
+                self.ptr.as_ptr().add(offset),
+                buf.as_mut_ptr(),
+                buf.len(),
+            )
+        }
+    }
+
+    /// Copies `data` into the segment starting at `offset`, wrapping around
+    /// the end of the segment.
+    ///
+    /// # Panics
+    ///
+    /// If `offset` is past the end of the segment, or `data` is longer than
+    /// the segment.
+    pub fn write_at(&mut self, offset: usize, data: &[u8]) {
+        self.check_range(offset, data.len());
+        unsafe {
+            ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.ptr.as_ptr().add(offset),
+                data.len(),
+            )
+        }
+    }
+
+    fn check_range(&self, offset: usize, len: usize) {
+        assert!(
+            offset < self.len && len <= self.len,
+            "range of {len} bytes at {offset} does not fit in a segment of {} bytes",
+            self.len
+        );
+    }
+
+    /// Returns the status of the attached segment.
+    ///
+    /// See [`SharedMemory::stat`].
+    pub fn stat(&self) -> Result<ShmStat> {
+        shm_stat(self.id)
+    }
+
+    /// Returns the identity of the attached segment.
+    ///
+    /// See [`SharedMemory::identity`].
+    pub fn identity(&self) -> IpcIdentity {
+        IpcIdentity::new(IpcKind::SharedMemory, self.key, self.id)
+    }
+
+    /// Returns the segment of the attachments.
+    pub fn segment<T>(&self) -> SysvResult<Shm<T>> {
+        Shm::from_id(self.key, self.id)
+    }
+
+    /// Detaches both attachments.
+    ///
+    /// Unlike dropping the attachments, this reports the first error.
+    pub fn detach(self) -> Result<()> {
+        let this = ManuallyDrop::new(self);
+        this.shmdt()
+    }
+
+    fn shmdt(&self) -> Result<()> {
+        let first =
+            Errno::result(unsafe { libc::shmdt(self.ptr.as_ptr().cast()) });
+        let second = Errno::result(unsafe {
+            libc::shmdt(self.ptr.as_ptr().add(self.len).cast())
+        });
+        first.and(second).map(drop)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for SharedMemoryMirrored {
+    fn drop(&mut self) {
+        let _ = self.shmdt();
+    }
+}
+
+/// Access to the pages of an attachment, see [`SharedMemory::protect`].
+///
+/// This trait is sealed, and implemented by [`ReadOnly`] and [`ReadWrite`].
+pub trait Protection: private::SealedProtection {}
+
+/// The attachment can only be read, and becomes a [`SharedMemoryRo`].
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ReadOnly;
+
+/// The attachment can be read and written, and stays a [`SharedMemory`].
+///
+/// This restores the access of an attachment whose protection was changed
+/// through other means, like `mprotect`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct ReadWrite;
+
+impl Protection for ReadOnly {}
+impl Protection for ReadWrite {}
+
+/// Hint about how an attachment will be used, see [`SharedMemory::advise`].
+///
+/// Only the hints leaving the content of the segment and the attachment
+/// itself untouched are available.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum Advice {
+    /// No special treatment, the default (`MADV_NORMAL`).
+    Normal,
+    /// Pages are accessed in random order, reading ahead is useless
+    /// (`MADV_RANDOM`).
+    Random,
+    /// Pages are accessed in sequential order, and can be freed soon after
+    /// (`MADV_SEQUENTIAL`).
+    Sequential,
+    /// Pages will be accessed soon, and can be read ahead
+    /// (`MADV_WILLNEED`).
+    WillNeed,
+    /// Pages will not be accessed soon. The segment keeps its content, which
+    /// is read back on the next access (`MADV_DONTNEED`).
+    DontNeed,
+    /// Back the attachment with transparent huge pages, when the kernel
+    /// enables them for shared memory (`MADV_HUGEPAGE`).
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    HugePage,
+    /// Do not back the attachment with transparent huge pages
+    /// (`MADV_NOHUGEPAGE`).
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    NoHugePage,
+    /// Leave the attachment out of core dumps, for example if it holds
+    /// secrets or is too large (`MADV_DONTDUMP`).
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    DontDump,
+    /// Include the attachment in core dumps again (`MADV_DODUMP`).
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    DoDump,
+}
+
+impl Advice {
+    fn raw(self) -> c_int {
+        match self {
+            Advice::Normal => libc::MADV_NORMAL,
+            Advice::Random => libc::MADV_RANDOM,
+            Advice::Sequential => libc::MADV_SEQUENTIAL,
+            Advice::WillNeed => libc::MADV_WILLNEED,
+            Advice::DontNeed => libc::MADV_DONTNEED,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            Advice::HugePage => libc::MADV_HUGEPAGE,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            Advice::NoHugePage => libc::MADV_NOHUGEPAGE,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            Advice::DontDump => libc::MADV_DONTDUMP,
+            #[cfg(any(target_os = "android", target_os = "linux"))]
+            Advice::DoDump => libc::MADV_DODUMP,
+        }
+    }
+}
+
+/// An attachment of a SystemV shared memory segment, either a
+/// [`SharedMemory`], a [`SharedMemoryRo`], a `SharedMemoryExec`, a
+/// [`SharedMemoryWithTail`], a `SharedMemoryMirrored` or an
+/// [`OwnedSegment`].
+pub trait Attachment: private::Sealed {}
+
+impl<T: ?Sized> Attachment for SharedMemory<T> {}
+impl<T> Attachment for SharedMemoryRo<T> {}
+#[cfg(target_os = "linux")]
+impl<T> Attachment for SharedMemoryExec<T> {}
+impl<H, T> Attachment for SharedMemoryWithTail<H, T> {}
+#[cfg(target_os = "linux")]
+impl Attachment for SharedMemoryMirrored {}
+impl<T: ?Sized> Attachment for OwnedSegment<T> {}
+
+mod private {
+    use super::libc::{self, c_int};
+    use super::{SharedMemory, SharedMemoryRo, ShmatFlag};
+
+    pub trait Sealed {
+        /// Returns the address the segment is attached at.
+        fn address(&self) -> usize;
+    }
+
+    impl<T: ?Sized> Sealed for super::SharedMemory<T> {
+        fn address(&self) -> usize {
+            self.ptr.as_ptr().cast::<u8>() as usize
+        }
+    }
+
+    impl<T> Sealed for super::SharedMemoryRo<T> {
+        fn address(&self) -> usize {
+            self.0.address()
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    impl<T> Sealed for super::SharedMemoryExec<T> {
+        fn address(&self) -> usize {
+            self.memory.address()
+        }
+    }
+
+    impl<H, T> Sealed for super::SharedMemoryWithTail<H, T> {
+        fn address(&self) -> usize {
+            self.header.address()
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    impl Sealed for super::SharedMemoryMirrored {
+        fn address(&self) -> usize {
+            self.ptr.as_ptr() as usize
+        }
+    }
+
+    impl<T: ?Sized> Sealed for super::OwnedSegment<T> {
+        fn address(&self) -> usize {
+            self.0.address()
+        }
+    }
+
+    pub trait SealedProtection {
+        /// Attachment resulting from the protection.
+        type Attachment<T>;
+        /// Protection passed to `mprotect`.
+        const PROT: c_int;
+
+        /// Converts an attachment once protected.
+        fn convert<T>(memory: SharedMemory<T>) -> Self::Attachment<T>;
+    }
+
+    impl SealedProtection for super::ReadOnly {
+        type Attachment<T> = SharedMemoryRo<T>;
+        const PROT: c_int = libc::PROT_READ;
+
+        fn convert<T>(mut memory: SharedMemory<T>) -> SharedMemoryRo<T> {
+            memory.access |= ShmatFlag::SHM_RDONLY;
+            SharedMemoryRo(memory)
+        }
+    }
+
+    impl SealedProtection for super::ReadWrite {
+        type Attachment<T> = SharedMemory<T>;
+        const PROT: c_int = libc::PROT_READ | libc::PROT_WRITE;
+
+        fn convert<T>(memory: SharedMemory<T>) -> SharedMemory<T> {
+            memory
+        }
+    }
+}
+
+/// Attaches the System V shared memory segment identified by a shmid to
+/// the address space of the calling process.
+///
+/// This is called automatically on [`Shm::attach`].
+///
+/// For more information, see [`shmat(2)`].
+///
+/// [`shmat(2)`]: https://man7.org/linux/man-pages/man2/shmat.2.html
+fn shmat(
+    id: c_int,
+    shmaddr: *const c_void,
+    shmat_flag: ShmatFlag,
+) -> SysvResult<NonNull<c_void>> {
+    let ptr =
+        Errno::result(unsafe { libc::shmat(id, shmaddr, shmat_flag.bits()) })
+            .map_err(|errno| match errno {
+            // Invalid addresses are rejected with `EINVAL` too: the
+            // segment is gone if it cannot be queried anymore.
+            Errno::EINVAL
+                if matches!(
+                    shm_stat(id),
+                    Err(Errno::EINVAL | Errno::EIDRM)
+                ) =>
+            {
+                SysvError::SegmentRemoved {
+                    operation: Operation::Shmat,
+                    errno,
+                }
+            }
+            errno => SysvError::new(Operation::Shmat, errno),
+        })?;
+    NonNull::new(ptr).ok_or(SysvError::new(Operation::Shmat, Errno::EFAULT))
+}
+
+/// Status of a shared memory segment, as reported by `IPC_STAT`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ShmStat {
+    /// Size of the segment, in bytes.
+    pub size: usize,
+    /// Owner of the segment.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_impls::uid"))]
+    pub uid: Uid,
+    /// Group of the segment.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_impls::gid"))]
+    pub gid: Gid,
+    /// Permissions of the segment.
+    pub mode: IpcMode,
+    /// Number of current attachments.
+    pub attachments: usize,
+    /// Process that created the segment.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_impls::pid"))]
+    pub creator_pid: Pid,
+    /// Process that last attached or detached the segment.
+    #[cfg_attr(feature = "serde", serde(with = "super::serde_impls::pid"))]
+    pub last_pid: Pid,
+    /// Time of the last attachment, if any.
+    pub attach_time: Option<SystemTime>,
+    /// Time of the last detachment, if any.
+    pub detach_time: Option<SystemTime>,
+    /// Time of the creation or of the last `IPC_SET`.
+    pub change_time: SystemTime,
+}
+
+/// Retrieves the status of the segment `id`.
+fn shm_stat(id: c_int) -> Result<ShmStat> {
+    let mut ds = mem::MaybeUninit::<shmid_ds>::uninit();
+    Errno::result(unsafe {
+        libc::shmctl(id, libc::IPC_STAT, ds.as_mut_ptr())
+    })?;
+    Ok(ShmStat::from_raw(unsafe { &ds.assume_init() }))
+}
+
+impl ShmStat {
+    /// Converts the structure filled by `IPC_STAT`.
+    pub(super) fn from_raw(ds: &shmid_ds) -> Self {
+        #[allow(clippy::unnecessary_cast)]
+        Self {
+            size: ds.shm_segsz as usize,
+            uid: Uid::from_raw(ds.shm_perm.uid),
+            gid: Gid::from_raw(ds.shm_perm.gid),
+            // Linux stores its SHM_DEST and SHM_LOCKED flags above the
+            // permission bits.
+            mode: Mode::from_bits_truncate(ds.shm_perm.mode as mode_t).into(),
+            attachments: ds.shm_nattch as usize,
+            creator_pid: Pid::from_raw(ds.shm_cpid),
+            last_pid: Pid::from_raw(ds.shm_lpid),
+            attach_time: optional_time(ds.shm_atime as i64),
+            detach_time: optional_time(ds.shm_dtime as i64),
+            change_time: time(ds.shm_ctime as i64),
+        }
+    }
+}
+
+/// Change of a shared memory segment, reported by a [`SegmentWatcher`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SegmentEvent {
+    /// The number of attachments increased.
+    Attached {
+        /// Number of attachments after the change.
+        attachments: usize,
+        /// Process that attached the segment last.
+        last_pid: Pid,
+    },
+    /// The number of attachments decreased.
+    Detached {
+        /// Number of attachments after the change.
+        attachments: usize,
+        /// Process that detached the segment last.
+        last_pid: Pid,
+    },
+    /// The owner or the permissions of the segment changed, with `IPC_SET`.
+    Changed,
+    /// The segment was removed, and will be destroyed after its last
+    /// detachment.
+    ///
+    /// Only reported on Linux, for segments created with a key: the key of
+    /// a removed segment reverts to [`Key::IPC_PRIVATE`], so that it cannot
+    /// be retrieved anymore.
+    RemovalPending,
+    /// The segment does not exist anymore.
+    Destroyed,
+}
+
+/// Watches a shared memory segment for changes, by polling its status.
+///
+/// Each poll compares the status of the segment with the one of the
+/// previous poll, so changes happening in between are coalesced: a process
+/// attaching and detaching the segment between two polls goes unnoticed.
+///
+/// The watcher is also an [`Iterator`], blocking until the next event, and
+/// ending after [`SegmentEvent::Destroyed`] or an error.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::shm::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// # use std::time::Duration;
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// let mem_segment = Shm::<[u8; 4096]>::create_and_connect(MY_KEY, IpcMode::owner_rw())?;
+/// let watcher = SegmentWatcher::new(&mem_segment, Duration::from_millis(100))?;
+/// for event in watcher {
+///     match event? {
+///         SegmentEvent::Attached { last_pid, .. } => println!("{last_pid} joined"),
+///         SegmentEvent::Detached { last_pid, .. } => println!("{last_pid} left"),
+///         event => println!("{event:?}"),
+///     }
+/// }
+/// # Ok::<(), Errno>(())
+/// ```
+#[derive(Debug)]
+pub struct SegmentWatcher {
+    /// Key of the segment, until it was removed.
+    #[cfg(target_os = "linux")]
+    key: Key,
+    id: c_int,
+    interval: Duration,
+    /// Status of the last poll, `None` once the segment is destroyed.
+    last: Option<ShmStat>,
+    pending: std::collections::VecDeque<SegmentEvent>,
+}
+
+impl SegmentWatcher {
+    /// Starts watching `shm`, polling it every `interval` when waiting for
+    /// events.
+    pub fn new<T>(shm: &Shm<T>, interval: Duration) -> Result<Self> {
+        Ok(Self {
+            #[cfg(target_os = "linux")]
+            key: shm.key,
+            id: shm.id,
+            interval,
+            last: Some(shm_stat(shm.id)?),
+            pending: Default::default(),
+        })
+    }
+
+    /// Returns the status of the segment at the last poll, or `None` once
+    /// it was destroyed.
+    pub fn stat(&self) -> Option<&ShmStat> {
+        self.last.as_ref()
+    }
+
+    /// Returns the interval between polls.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Sets the interval between polls.
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    /// Queries the status of the segment once, and returns the changes
+    /// since the previous poll.
+    ///
+    /// Fails with [`Errno::EIDRM`] once [`SegmentEvent::Destroyed`] was
+    /// reported.
+    pub fn poll(&mut self) -> Result<Vec<SegmentEvent>> {
+        let Some(last) = self.last else {
+            return Err(Errno::EIDRM);
+        };
+        let stat = match shm_stat(self.id) {
+            Ok(stat) => stat,
+            Err(Errno::EINVAL | Errno::EIDRM) => {
+                self.last = None;
+                return Ok(vec![SegmentEvent::Destroyed]);
+            }
+            Err(errno) => return Err(errno),
+        };
+        let mut events = Vec::new();
+        if stat.attachments > last.attachments {
+            events.push(SegmentEvent::Attached {
+                attachments: stat.attachments,
+                last_pid: stat.last_pid,
+            });
+        } else if stat.attachments < last.attachments {
+            events.push(SegmentEvent::Detached {
+                attachments: stat.attachments,
+                last_pid: stat.last_pid,
+            });
+        }
+        // The change time only has a precision of a second
+        if stat.change_time != last.change_time
+            || stat.mode != last.mode
+            || stat.uid != last.uid
+            || stat.gid != last.gid
+        {
+            events.push(SegmentEvent::Changed);
+        }
+        #[cfg(target_os = "linux")]
+        if self.key != Key::IPC_PRIVATE && !self.key_matches()? {
+            // Reported once, as the key does not come back
+            self.key = Key::IPC_PRIVATE;
+            events.push(SegmentEvent::RemovalPending);
+        }
+        self.last = Some(stat);
+        Ok(events)
+    }
+
+    /// Returns whether the key of the segment still retrieves it.
+    #[cfg(target_os = "linux")]
+    fn key_matches(&self) -> Result<bool> {
+        match Errno::result(unsafe { libc::shmget(self.key.as_raw(), 0, 0) }) {
+            Ok(id) => Ok(id == self.id),
+            Err(Errno::ENOENT) => Ok(false),
+            Err(errno) => Err(errno),
+        }
+    }
+
+    /// Polls the segment every [`interval`](Self::interval) until it
+    /// changes, and returns the changes.
+    pub fn wait(&mut self) -> Result<Vec<SegmentEvent>> {
+        loop {
+            let events = self.poll()?;
+            if !events.is_empty() {
+                return Ok(events);
+            }
+            std::thread::sleep(self.interval);
+        }
+    }
+}
+
+impl Iterator for SegmentWatcher {
+    type Item = Result<SegmentEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(event) = self.pending.pop_front() {
+            return Some(Ok(event));
+        }
+        self.last?;
+        match self.wait() {
+            Ok(events) => {
+                self.pending.extend(events);
+                self.pending.pop_front().map(Ok)
+            }
+            Err(errno) => {
+                self.last = None;
+                Some(Err(errno))
+            }
+        }
+    }
+}
+
+/// Updates the owner and permissions of the segment `id` with `f`, using
+/// `IPC_STAT` then `IPC_SET`.
+fn shm_set(id: c_int, f: impl FnOnce(&mut libc::ipc_perm)) -> Result<()> {
+    let mut ds = mem::MaybeUninit::<shmid_ds>::uninit();
+    Errno::result(unsafe {
+        libc::shmctl(id, libc::IPC_STAT, ds.as_mut_ptr())
+    })?;
+    let mut ds = unsafe { ds.assume_init() };
+    f(&mut ds.shm_perm);
+    Errno::result(unsafe { libc::shmctl(id, libc::IPC_SET, &mut ds) }).map(drop)
+}
+
+/// Locks or unlocks the segment `id`, depending on `cmd`.
+#[cfg(target_os = "linux")]
+fn shm_lock(id: c_int, cmd: c_int) -> Result<()> {
+    Errno::result(unsafe { libc::shmctl(id, cmd, ptr::null_mut()) }).map(drop)
+}
+
+/// Marks the segment `id` to be destroyed, once every process detached it,
+/// like `ipcrm -m` does.
+///
+/// The caller must be the owner or the creator of the segment, or be
+/// privileged. On Linux, [`info::purge_owned_segments`] removes every
+/// segment of the caller.
+///
+/// [`info::purge_owned_segments`]: super::info::purge_owned_segments
+pub fn remove_segment_by_id(id: c_int) -> Result<()> {
+    Errno::result(unsafe { libc::shmctl(id, libc::IPC_RMID, ptr::null_mut()) })
+        .map(drop)
+}
+
+/// Marks the segment associated with `key` to be destroyed, once every
+/// process detached it, like `ipcrm -M` does.
+///
+/// Fails with [`Errno::ENOENT`] if no segment exists for `key`, and with
+/// [`Errno::EINVAL`] for [`Key::IPC_PRIVATE`], which is not associated with
+/// any segment.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::{shm, Key};
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// // Clean up after a previous run that crashed
+/// match shm::remove_segment_by_key(MY_KEY) {
+///     Ok(()) | Err(Errno::ENOENT) => {}
+///     Err(e) => return Err(e),
+/// }
+/// # Ok::<(), Errno>(())
+/// ```
+pub fn remove_segment_by_key(key: Key) -> Result<()> {
+    if key == Key::IPC_PRIVATE {
+        return Err(Errno::EINVAL);
+    }
+    let id = Errno::result(unsafe { libc::shmget(key.as_raw(), 0, 0) })?;
+    remove_segment_by_id(id)
+}
+
+/// Huge pages missing from the pool to create a segment with
+/// [`ShmgetFlag::SHM_HUGETLB`], see [`hugetlb_shortfall`].
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct HugePageShortfall {
+    /// Size of the default huge pages, in bytes.
+    pub page_size: usize,
+    /// Number of huge pages the segment needs.
+    pub required: usize,
+    /// Number of huge pages still available in the pool.
+    pub available: usize,
+}
+
+#[cfg(target_os = "linux")]
+impl HugePageShortfall {
+    /// Number of huge pages that must be added to the pool.
+    pub fn missing(&self) -> usize {
+        self.required - self.available
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl fmt::Display for HugePageShortfall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} huge pages of {} kB are needed, but only {} are available \
+             (reserve {} more in /proc/sys/vm/nr_hugepages)",
+            self.required,
+            self.page_size / 1024,
+            self.available,
+            self.missing()
+        )
+    }
+}
+
+/// Explains an [`Errno::ENOMEM`] returned when creating a segment of `size`
+/// bytes with [`ShmgetFlag::SHM_HUGETLB`].
+///
+/// Huge pages must be reserved by the administrator before they can back a
+/// segment. This reads the state of the default huge page pool from
+/// `/proc/meminfo`, and returns how many pages the segment lacks.
+///
+/// Returns `None` if the pool can hold the segment, in which case the error
+/// has another cause, such as the `shmall` limit.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::shm::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// struct Table([u64; 1 << 18]);
+///
+/// let res = unsafe {
+///     Shm::<Table>::shmget(
+///         Key::new(1337),
+///         ShmgetFlag::IPC_CREAT | ShmgetFlag::SHM_HUGETLB,
+///         IpcMode::owner_rw(),
+///     )
+/// };
+/// if matches!(res, Err(e) if e.errno() == Errno::ENOMEM) {
+///     if let Some(shortfall) = hugetlb_shortfall(std::mem::size_of::<Table>())? {
+///         eprintln!("Cannot create the table: {shortfall}");
+///     }
+/// }
+/// # Ok::<(), Errno>(())
+/// ```
+#[cfg(target_os = "linux")]
+pub fn hugetlb_shortfall(size: usize) -> Result<Option<HugePageShortfall>> {
+    let meminfo = read_proc("/proc/meminfo")?;
+    // Every value we need is a number, optionally followed by " kB"
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| {
+                value.split_whitespace().next()?.parse::<usize>().ok()
+            })
+            .ok_or(Errno::ENOTSUP)
+    };
+    let page_size: usize = field("Hugepagesize")? * 1024;
+    // Reserved pages are promised to existing mappings, but not faulted yet
+    let available =
+        field("HugePages_Free")?.saturating_sub(field("HugePages_Rsvd")?);
+    let required = size / page_size + usize::from(size % page_size != 0);
+    Ok((required > available).then_some(HugePageShortfall {
+        page_size,
+        required,
+        available,
+    }))
+}
+
+/// Mapping backing an attachment, as reported by the kernel, see
+/// [`mapping_info`].
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MappingInfo {
+    /// First address of the mapping.
+    pub start: usize,
+    /// First address past the end of the mapping.
+    pub end: usize,
+    /// Whether the mapping can be read.
+    pub readable: bool,
+    /// Whether the mapping can be written to.
+    pub writable: bool,
+    /// Whether the mapping can be executed.
+    pub executable: bool,
+    /// Size of the pages backing the mapping, in bytes.
+    pub page_size: usize,
+}
+
+#[cfg(target_os = "linux")]
+impl MappingInfo {
+    /// Size of the mapping, in bytes.
+    ///
+    /// This is the size of the segment, rounded up to a multiple of the page
+    /// size.
+    pub fn size(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Whether the mapping is backed by huge pages.
+    pub fn is_huge_pages(&self) -> bool {
+        self.page_size > page_size()
+    }
+}
+
+/// Returns the mapping the kernel created for `shm`.
+///
+/// This parses `/proc/self/smaps`, and is meant as a debugging aid when
+/// fixed-address attaches, [`ShmatFlag::SHM_RND`] or [`ShmatFlag::SHM_REMAP`]
+/// behave unexpectedly, or to check that a segment created with
+/// [`ShmgetFlag::SHM_HUGETLB`] really is backed by huge pages.
+///
+/// Fails with [`Errno::ENOENT`] if the attachment cannot be found.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::shm::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// struct MyData([u8; 4096]);
+/// # unsafe impl SysvSafe for MyData {}
+///
+/// const MY_KEY: Key = Key::new(1337);
+/// let mode = IpcMode::owner_rw();
+/// let mem_segment = Shm::<MyData>::create_and_connect(MY_KEY, mode)?;
+/// let shared_memory = mem_segment.attach_ro(AttachAddress::Any, ShmatFlag::empty())?;
+/// let info = mapping_info(&shared_memory)?;
+/// assert!(info.readable && !info.writable);
+/// # Ok::<(), Errno>(())
+/// ```
+#[cfg(target_os = "linux")]
+pub fn mapping_info(shm: &impl Attachment) -> Result<MappingInfo> {
+    let start = shm.address();
+    let smaps = read_proc("/proc/self/smaps")?;
+    let mut lines = smaps.lines();
+    while let Some(line) = lines.next() {
+        // Each mapping starts with a header such as
+        // `7f1a2b3c4000-7f1a2b3c5000 rw-s 00000000 00:01 32768 /SYSV00000539`
+        let mut fields = line.split_whitespace();
+        let range = fields.next().and_then(|range| range.split_once('-'));
+        let Some((first, last)) = range else {
+            continue;
+        };
+        if usize::from_str_radix(first, 16) != Ok(start) {
+            continue;
+        }
+        let end = usize::from_str_radix(last, 16).map_err(|_| Errno::EINVAL)?;
+        let perms = fields.next().ok_or(Errno::EINVAL)?.as_bytes();
+        // The size of the pages is listed among the fields of the mapping
+        let page_size = lines
+            .find_map(|line| line.strip_prefix("KernelPageSize:"))
+            .and_then(|value| {
+                value.split_whitespace().next()?.parse::<usize>().ok()
+            })
+            .ok_or(Errno::EINVAL)?
+            * 1024;
+        return Ok(MappingInfo {
+            start,
+            end,
+            readable: perms.first() == Some(&b'r'),
+            writable: perms.get(1) == Some(&b'w'),
+            executable: perms.get(2) == Some(&b'x'),
+            page_size,
+        });
+    }
+    Err(Errno::ENOENT)
+}
+
+/// Returns whether every page between `start` and `end` is mapped, according
+/// to `/proc/self/maps`.
+#[cfg(target_os = "linux")]
+fn is_range_mapped(start: usize, end: usize) -> Result<bool> {
+    let maps = read_proc("/proc/self/maps")?;
+    let mut covered = start;
+    // Mappings are listed in increasing order of address, as
+    // `7f1a2b3c4000-7f1a2b3c5000 rw-s 00000000 00:01 32768 /SYSV00000539`
+    for line in maps.lines() {
+        let range = line
+            .split_whitespace()
+            .next()
+            .and_then(|range| range.split_once('-'));
+        let Some((first, last)) = range else {
+            continue;
+        };
+        let first =
+            usize::from_str_radix(first, 16).map_err(|_| Errno::EINVAL)?;
+        let last =
+            usize::from_str_radix(last, 16).map_err(|_| Errno::EINVAL)?;
+        if first > covered {
+            break;
+        }
+        covered = covered.max(last);
+        if covered >= end {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Returns the size of the base pages of the system.
+#[cfg(target_os = "linux")]
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// Reads a whole file of procfs.
+#[cfg(target_os = "linux")]
+fn read_proc(path: &str) -> Result<String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| Errno::try_from(e).unwrap_or(Errno::EIO))
+}
+
+/// Expected state of a SystemV shared memory segment in an [`IpcTopology`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SegmentSpec {
+    /// Key the segment is identified by.
+    pub key: Key,
+    /// Size of the segment, in bytes.
+    pub size: usize,
+    /// Permissions of the segment.
+    pub mode: IpcMode,
+}
+
+/// Expected state of a SystemV semaphore set in an [`IpcTopology`].
+#[cfg(all(
+    feature = "sysvipc_sem",
+    any(
+        apple_targets,
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "linux"
+    )
+))]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SemaphoreSetSpec {
+    /// Key the set is identified by.
+    pub key: Key,
+    /// Number of semaphores of the set.
+    pub nsems: usize,
+    /// Permissions of the set.
+    pub mode: IpcMode,
+}
+
+/// Expected state of a SystemV message queue in an [`IpcTopology`].
+#[cfg(all(
+    feature = "sysvipc_msg",
+    any(target_os = "android", target_os = "freebsd", target_os = "linux")
+))]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueueSpec {
+    /// Key the queue is identified by.
+    pub key: Key,
+    /// Permissions of the queue.
+    pub mode: IpcMode,
+}
+
+/// Declarative description of the SystemV resources a set of processes
+/// expects to exist.
+///
+/// Use [`IpcTopology::reconcile`] at startup to bring the system in line with
+/// the description. Semaphore sets and message queues are part of it with
+/// the `sysvipc_sem` and `sysvipc_msg` features.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::shm::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// let report = IpcTopology::new()
+///     .segment(Key::new(1337), 4096, IpcMode::owner_rw())
+///     .segment(Key::new(1338), 64, IpcMode::group_ro())
+///     .reconcile()?;
+/// for mismatch in report.mismatched {
+///     eprintln!("Segment {} does not match: {mismatch:?}", mismatch.key);
+/// }
+/// # Ok::<(), Errno>(())
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IpcTopology {
+    segments: Vec<SegmentSpec>,
+    #[cfg(all(
+        feature = "sysvipc_sem",
+        any(
+            apple_targets,
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        )
+    ))]
+    semaphore_sets: Vec<SemaphoreSetSpec>,
+    #[cfg(all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    queues: Vec<QueueSpec>,
+    #[cfg(target_os = "linux")]
+    stray_keys: Option<std::ops::RangeInclusive<Key>>,
+}
+
+impl IpcTopology {
+    /// Creates an empty topology.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a shared memory segment to the topology.
+    pub fn segment(
+        mut self,
+        key: Key,
+        size: usize,
+        mode: impl Into<IpcMode>,
+    ) -> Self {
+        self.segments.push(SegmentSpec {
+            key,
+            size,
+            mode: mode.into(),
+        });
+        self
+    }
+
+    /// Adds a semaphore set of `nsems` semaphores to the topology.
+    ///
+    /// A set created by [`IpcTopology::reconcile`] starts with every
+    /// semaphore at 0.
+    #[cfg(all(
+        feature = "sysvipc_sem",
+        any(
+            apple_targets,
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        )
+    ))]
+    pub fn semaphore_set(
+        mut self,
+        key: Key,
+        nsems: usize,
+        mode: impl Into<IpcMode>,
+    ) -> Self {
+        self.semaphore_sets.push(SemaphoreSetSpec {
+            key,
+            nsems,
+            mode: mode.into(),
+        });
+        self
+    }
+
+    /// Adds a message queue to the topology.
+    #[cfg(all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    pub fn queue(mut self, key: Key, mode: impl Into<IpcMode>) -> Self {
+        self.queues.push(QueueSpec {
+            key,
+            mode: mode.into(),
+        });
+        self
+    }
+
+    /// Makes [`IpcTopology::reconcile`] remove the strays: the resources of
+    /// the effective user of the calling process with a key in `keys`, but
+    /// absent from the topology.
+    ///
+    /// The range must be reserved to the processes sharing the topology, as
+    /// the resources of any other program using these keys are removed too.
+    #[cfg(target_os = "linux")]
+    pub fn remove_strays(
+        mut self,
+        keys: std::ops::RangeInclusive<Key>,
+    ) -> Self {
+        self.stray_keys = Some(keys);
+        self
+    }
+
+    /// Returns the shared memory segments of the topology.
+    pub fn segments(&self) -> &[SegmentSpec] {
+        &self.segments
+    }
+
+    /// Returns the semaphore sets of the topology.
+    #[cfg(all(
+        feature = "sysvipc_sem",
+        any(
+            apple_targets,
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        )
+    ))]
+    pub fn semaphore_sets(&self) -> &[SemaphoreSetSpec] {
+        &self.semaphore_sets
+    }
+
+    /// Returns the message queues of the topology.
+    #[cfg(all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    pub fn queues(&self) -> &[QueueSpec] {
+        &self.queues
+    }
+
+    /// Creates the missing resources of the topology, validates the existing
+    /// ones against their description, and then removes the strays if
+    /// [`IpcTopology::remove_strays`] was called.
+    ///
+    /// Existing resources that do not match their description are left
+    /// untouched, and reported in the `mismatched` fields of the
+    /// [`Reconciliation`].
+    ///
+    /// If a resource can neither be created nor inspected, the resources
+    /// created so far are removed before returning the error, leaving the
+    /// system as it was. Strays are only removed once the whole topology is
+    /// in place, and an error removing one is returned as is.
+    pub fn reconcile(&self) -> Result<Reconciliation> {
+        let mut report = Reconciliation::default();
+        if let Err(e) = self.bring_up(&mut report) {
+            for identity in &report.created {
+                let _ = super::remove_resource(identity.kind, identity.id);
+            }
+            return Err(e);
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(keys) = &self.stray_keys {
+            self.remove_stray_resources(keys, &mut report)?;
+        }
+        Ok(report)
+    }
+
+    // -- Private --
+
+    /// Creates or validates every resource of the topology, recording the
+    /// outcome in `report`.
+    fn bring_up(&self, report: &mut Reconciliation) -> Result<()> {
+        for spec in &self.segments {
+            let shmget_flag = ShmgetFlag::IPC_CREAT | ShmgetFlag::IPC_EXCL;
+            let flags = spec.mode.mode().bits() as c_int | shmget_flag.bits();
+            match Errno::result(unsafe {
+                libc::shmget(spec.key.as_raw(), spec.size, flags)
+            }) {
+                Ok(id) => report.created.push(IpcIdentity::new(
+                    IpcKind::SharedMemory,
+                    spec.key,
+                    id,
+                )),
+                Err(Errno::EEXIST) => {
+                    let id = Errno::result(unsafe {
+                        libc::shmget(spec.key.as_raw(), 0, 0)
+                    })?;
+                    let stat = shm_stat(id)?;
+                    let size = stat.size;
+                    let mode = stat.mode;
+                    if size == spec.size && mode == spec.mode {
+                        report.validated.push(IpcIdentity::new(
+                            IpcKind::SharedMemory,
+                            spec.key,
+                            id,
+                        ));
+                    } else {
+                        report.mismatched.push(SegmentMismatch {
+                            key: spec.key,
+                            expected_size: spec.size,
+                            actual_size: size,
+                            expected_mode: spec.mode,
+                            actual_mode: mode,
+                        });
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        #[cfg(all(
+            feature = "sysvipc_sem",
+            any(
+                apple_targets,
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "linux"
+            )
+        ))]
+        for spec in &self.semaphore_sets {
+            use super::sem::{SemaphoreSet, SemgetFlag};
+
+            match SemaphoreSet::semget(
+                spec.key,
+                spec.nsems,
+                SemgetFlag::IPC_CREAT | SemgetFlag::IPC_EXCL,
+                spec.mode,
+            ) {
+                Ok(set) => report.created.push(set.identity()),
+                Err(e) if e.errno() == Errno::EEXIST => {
+                    let set = SemaphoreSet::semget(
+                        spec.key,
+                        0,
+                        SemgetFlag::empty(),
+                        Mode::empty(),
+                    )?;
+                    let stat = set.stat()?;
+                    if stat.nsems == spec.nsems && stat.mode == spec.mode {
+                        report.validated.push(set.identity());
+                    } else {
+                        report.mismatched_sets.push(SemaphoreSetMismatch {
+                            key: spec.key,
+                            expected_nsems: spec.nsems,
+                            actual_nsems: stat.nsems,
+                            expected_mode: spec.mode,
+                            actual_mode: stat.mode,
+                        });
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        #[cfg(all(
+            feature = "sysvipc_msg",
+            any(
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "linux"
+            )
+        ))]
+        for spec in &self.queues {
+            use super::msg::{MessageQueue, MsggetFlag};
+
+            match MessageQueue::<[u8]>::msgget(
+                spec.key,
+                MsggetFlag::IPC_CREAT | MsggetFlag::IPC_EXCL,
+                spec.mode,
+            ) {
+                Ok(queue) => report.created.push(queue.identity()),
+                Err(e) if e.errno() == Errno::EEXIST => {
+                    let queue = MessageQueue::<[u8]>::msgget(
+                        spec.key,
+                        MsggetFlag::empty(),
+                        Mode::empty(),
+                    )?;
+                    let mode = queue.stat()?.mode;
+                    if mode == spec.mode {
+                        report.validated.push(queue.identity());
+                    } else {
+                        report.mismatched_queues.push(QueueMismatch {
+                            key: spec.key,
+                            expected_mode: spec.mode,
+                            actual_mode: mode,
+                        });
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes the resources of the caller with a key in `keys`, but absent
+    /// from the topology, recording them in `report`.
+    #[cfg(target_os = "linux")]
+    fn remove_stray_resources(
+        &self,
+        keys: &std::ops::RangeInclusive<Key>,
+        report: &mut Reconciliation,
+    ) -> Result<()> {
+        use super::info;
+
+        let uid = crate::unistd::geteuid();
+        // Resources of the caller in the range, whatever the topology
+        let in_range = |key: Key, owner: Uid| {
+            key != Key::IPC_PRIVATE && keys.contains(&key) && owner == uid
+        };
+        let mut strays = Vec::new();
+        for segment in info::shared_memory_segments()? {
+            if in_range(segment.key, segment.stat.uid)
+                && !self.segments.iter().any(|spec| spec.key == segment.key)
+            {
+                strays.push(IpcIdentity::new(
+                    IpcKind::SharedMemory,
+                    segment.key,
+                    segment.id,
+                ));
+            }
+        }
+        #[cfg(feature = "sysvipc_sem")]
+        for set in info::semaphore_sets()? {
+            if in_range(set.key, set.uid)
+                && !self.semaphore_sets.iter().any(|spec| spec.key == set.key)
+            {
+                strays.push(IpcIdentity::new(
+                    IpcKind::SemaphoreSet,
+                    set.key,
+                    set.id,
+                ));
+            }
+        }
+        #[cfg(feature = "sysvipc_msg")]
+        for queue in info::message_queues()? {
+            if in_range(queue.key, queue.uid)
+                && !self.queues.iter().any(|spec| spec.key == queue.key)
+            {
+                strays.push(IpcIdentity::new(
+                    IpcKind::MessageQueue,
+                    queue.key,
+                    queue.id,
+                ));
+            }
+        }
+        for identity in strays {
+            match super::remove_resource(identity.kind, identity.id) {
+                Ok(()) => report.removed.push(identity),
+                // Removed meanwhile by another process
+                Err(Errno::EINVAL | Errno::EIDRM) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Existing segment that does not match its [`SegmentSpec`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SegmentMismatch {
+    /// Key of the segment.
+    pub key: Key,
+    /// Size requested by the topology.
+    pub expected_size: usize,
+    /// Size of the existing segment.
+    pub actual_size: usize,
+    /// Permissions requested by the topology.
+    pub expected_mode: IpcMode,
+    /// Permissions of the existing segment.
+    pub actual_mode: IpcMode,
+}
+
+/// Existing semaphore set that does not match its [`SemaphoreSetSpec`].
+#[cfg(all(
+    feature = "sysvipc_sem",
+    any(
+        apple_targets,
+        target_os = "android",
+        target_os = "freebsd",
+        target_os = "linux"
+    )
+))]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SemaphoreSetMismatch {
+    /// Key of the set.
+    pub key: Key,
+    /// Number of semaphores requested by the topology.
+    pub expected_nsems: usize,
+    /// Number of semaphores of the existing set.
+    pub actual_nsems: usize,
+    /// Permissions requested by the topology.
+    pub expected_mode: IpcMode,
+    /// Permissions of the existing set.
+    pub actual_mode: IpcMode,
+}
+
+/// Existing message queue that does not match its [`QueueSpec`].
+#[cfg(all(
+    feature = "sysvipc_msg",
+    any(target_os = "android", target_os = "freebsd", target_os = "linux")
+))]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QueueMismatch {
+    /// Key of the queue.
+    pub key: Key,
+    /// Permissions requested by the topology.
+    pub expected_mode: IpcMode,
+    /// Permissions of the existing queue.
+    pub actual_mode: IpcMode,
+}
+
+/// Outcome of [`IpcTopology::reconcile`].
+///
+/// The fields of the semaphore sets, message queues and strays only exist
+/// with the matching features and platforms, so the struct cannot be built
+/// outside of this crate.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub struct Reconciliation {
+    /// Resources that were missing, and have been created.
+    pub created: Vec<IpcIdentity>,
+    /// Resources that already existed, and match the topology.
+    pub validated: Vec<IpcIdentity>,
+    /// Segments that already existed, but do not match the topology.
+    pub mismatched: Vec<SegmentMismatch>,
+    /// Semaphore sets that already existed, but do not match the topology.
+    #[cfg(all(
+        feature = "sysvipc_sem",
+        any(
+            apple_targets,
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux"
+        )
+    ))]
+    pub mismatched_sets: Vec<SemaphoreSetMismatch>,
+    /// Message queues that already existed, but do not match the topology.
+    #[cfg(all(
+        feature = "sysvipc_msg",
+        any(target_os = "android", target_os = "freebsd", target_os = "linux")
+    ))]
+    pub mismatched_queues: Vec<QueueMismatch>,
+    /// Strays that have been removed, see [`IpcTopology::remove_strays`].
+    #[cfg(target_os = "linux")]
+    pub removed: Vec<IpcIdentity>,
+}
+
+feature! {
+#![feature = "sysvipc_serialized"]
+
+use std::sync::atomic::{AtomicU32, AtomicU8, AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Value of any serializable type, stored in a SystemV shared memory segment
+/// of fixed capacity.
+///
+/// [`SharedMemory`] only holds [`SysvSafe`] types, whose bytes keep their
+/// meaning in every process. Values holding `String`s or `Vec`s, such as a
+/// configuration, are instead serialized with `bincode` on
+/// [`SerializedShm::store`], and deserialized on [`SerializedShm::load`].
+///
+/// The segment starts with a header recording its layout version and the
+/// length of the payload. Stores from several processes are serialized, and
+/// loads retry when a store is in progress, so they always see a complete
+/// value. They give up with [`Errno::EAGAIN`] after waiting for a second:
+/// this happens for good when a process was killed in the middle of a
+/// store, and the segment must then be recreated.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::shm::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// # use serde::{Deserialize, Serialize};
+/// #
+/// #[derive(Deserialize, Serialize)]
+/// struct Config {
+///     name: String,
+///     workers: Vec<u32>,
+/// }
+///
+/// const MY_KEY: Key = Key::new(1337);
+/// let mut config = SerializedShm::create(MY_KEY, 4096, IpcMode::owner_rw())?;
+/// config.store(&Config { name: "render".to_string(), workers: vec![1, 2] })?;
+///
+/// // In another process
+/// let config = SerializedShm::<Config>::open(MY_KEY, IpcMode::owner_rw())?;
+/// if let Some(config) = config.load()? {
+///     println!("{}: {:?}", config.name, config.workers);
+/// }
+/// # Ok::<(), Errno>(())
+/// ```
+pub struct SerializedShm<T> {
+    memory: SharedMemory<[u8]>,
+    _phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> fmt::Debug for SerializedShm<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SerializedShm")
+            .field("key", &self.memory.key)
+            .field("id", &self.memory.id)
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+impl<T> SerializedShm<T>
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Creates a new segment able to hold `capacity` bytes of serialized
+    /// payload, and attaches it.
+    ///
+    /// The segment holds no value until the first [`SerializedShm::store`].
+    /// Fails with [`Errno::EEXIST`] if a segment already exists for `key`.
+    pub fn create(
+        key: Key,
+        capacity: usize,
+        mode: impl Into<IpcMode>,
+    ) -> SysvResult<Self> {
+        let size = capacity
+            .checked_add(mem::size_of::<SerializedHeader>())
+            .ok_or(SysvError::new(Operation::Shmget, Errno::EINVAL))?;
+        let memory = SharedMemory::shmget(
+            key,
+            size,
+            ShmgetFlag::IPC_CREAT | ShmgetFlag::IPC_EXCL,
+            mode,
+        )?;
+        let this = Self {
+            memory,
+            _phantom: PhantomData,
+        };
+        // The kernel zeroed the segment: only the tag is left to publish
+        this.header()
+            .tag
+            .store(SerializedHeader::TAG, Ordering::Release);
+        Ok(this)
+    }
+
+    /// Attaches the existing segment associated with `key`.
+    ///
+    /// Fails with [`Errno::EAGAIN`] if its creator did not initialize it
+    /// yet, and with [`Errno::EINVAL`] if it was not created by
+    /// [`SerializedShm::create`], or by an incompatible version of this
+    /// crate.
+    pub fn open(key: Key, mode: impl Into<IpcMode>) -> SysvResult<Self> {
+        let memory =
+            SharedMemory::shmget(key, 0, ShmgetFlag::empty(), mode)?;
+        if memory.len() < mem::size_of::<SerializedHeader>() {
+            return Err(SysvError::new(Operation::Shmat, Errno::EINVAL));
+        }
+        let this = Self {
+            memory,
+            _phantom: PhantomData,
+        };
+        match this.header().tag.load(Ordering::Acquire) {
+            SerializedHeader::TAG => Ok(this),
+            0 => Err(SysvError::new(Operation::Shmat, Errno::EAGAIN)),
+            _ => Err(SysvError::new(Operation::Shmat, Errno::EINVAL)),
+        }
+    }
+
+    /// Serializes `value` into the segment, replacing the previous value.
+    ///
+    /// Fails with [`Errno::E2BIG`] if the serialized value exceeds the
+    /// capacity of the segment, in which case the previous value is kept,
+    /// and with [`Errno::EAGAIN`] if another store did not complete within
+    /// [`SerializedShm::TIMEOUT`].
+    pub fn store(&mut self, value: &T) -> Result<()> {
+        let payload = bincode::serialize(value).map_err(|_| Errno::EINVAL)?;
+        if payload.len() > self.capacity() {
+            return Err(Errno::E2BIG);
+        }
+        let header = self.header();
+        let deadline = Instant::now() + Self::TIMEOUT;
+        // An odd sequence number marks a store in progress
+        let mut sequence = header.sequence.load(Ordering::Relaxed);
+        loop {
+            if sequence % 2 == 1 {
+                if Instant::now() >= deadline {
+                    return Err(Errno::EAGAIN);
+                }
+                std::thread::yield_now();
+                sequence = header.sequence.load(Ordering::Relaxed);
+                continue;
+            }
+            match header.sequence.compare_exchange_weak(
+                sequence,
+                sequence + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => sequence = current,
+            }
+        }
+        std::sync::atomic::fence(Ordering::Release);
+        for (byte, &value) in self.payload().iter().zip(&payload) {
+            byte.store(value, Ordering::Relaxed);
+        }
+        header.len.store(payload.len(), Ordering::Relaxed);
+        header.sequence.store(sequence + 2, Ordering::Release);
+        Ok(())
+    }
+
+    /// Deserializes the value last stored in the segment, or returns `None`
+    /// if no value was stored yet.
+    ///
+    /// Fails with [`Errno::EBADMSG`] if the payload cannot be deserialized
+    /// as a `T`, and with [`Errno::EAGAIN`] if no store completed without
+    /// overlapping the load within [`SerializedShm::TIMEOUT`].
+    pub fn load(&self) -> Result<Option<T>> {
+        let header = self.header();
+        let deadline = Instant::now() + Self::TIMEOUT;
+        let payload = loop {
+            let sequence = header.sequence.load(Ordering::Acquire);
+            if sequence == 0 {
+                return Ok(None);
+            }
+            if sequence % 2 == 0 {
+                let len = header.len.load(Ordering::Relaxed);
+                // Stores may be writing the bytes concurrently: they are
+                // read atomically, and discarded if a store overlapped
+                let payload: Vec<u8> = self.payload()[..len.min(self.capacity())]
+                    .iter()
+                    .map(|byte| byte.load(Ordering::Relaxed))
+                    .collect();
+                std::sync::atomic::fence(Ordering::Acquire);
+                if header.sequence.load(Ordering::Relaxed) == sequence {
+                    break payload;
+                }
+            }
+            if Instant::now() >= deadline {
+                return Err(Errno::EAGAIN);
+            }
+            std::thread::yield_now();
+        };
+        bincode::deserialize(&payload)
+            .map(Some)
+            .map_err(|_| Errno::EBADMSG)
+    }
+}
+
+impl<T> SerializedShm<T> {
+    /// How long [`SerializedShm::load`] and [`SerializedShm::store`] wait
+    /// for the store in progress to complete.
+    pub const TIMEOUT: Duration = Duration::from_secs(1);
+
+    /// Returns the maximum size of a serialized value, in bytes.
+    pub fn capacity(&self) -> usize {
+        self.memory.len() - mem::size_of::<SerializedHeader>()
+    }
+
+    /// Returns the identity of the segment.
+    pub fn identity(&self) -> IpcIdentity {
+        self.memory.identity()
+    }
+
+    /// Returns the attachment of the whole segment, header included.
+    pub fn into_inner(self) -> SharedMemory<[u8]> {
+        self.memory
+    }
+
+    // -- Private --
+
+    fn header(&self) -> &SerializedHeader {
+        // The segment is page aligned, and at least as large as the header
+        unsafe { &*SharedMemory::as_ptr(&self.memory).cast() }
+    }
+
+    fn payload(&self) -> &[AtomicU8] {
+        let base = SharedMemory::as_ptr(&self.memory).cast::<AtomicU8>();
+        unsafe {
+            let payload = base.add(mem::size_of::<SerializedHeader>());
+            std::slice::from_raw_parts(payload, self.capacity())
+        }
+    }
+}
+
+/// Header of the segment of a [`SerializedShm`].
+#[repr(C)]
+struct SerializedHeader {
+    /// `TAG` once the segment is initialized.
+    tag: AtomicU32,
+    /// Odd while a store is in progress.
+    sequence: AtomicUsize,
+    /// Length of the serialized value.
+    len: AtomicUsize,
+}
+
+impl SerializedHeader {
+    /// "NSE", followed by the version of the layout.
+    const TAG: u32 = u32::from_be_bytes(*b"NSE1");
+}
+}
+
+libc_bitflags!(
+    /// Valid flags for the third parameter of the function [`Shm::shmget`].
+    pub struct ShmgetFlag: c_int
+    {
+        /// Create a new segment.
+        /// If this flag is not used, then shmget() will find the segment
+        /// associated with key and check to see if the user has permission
+        /// to access the segment.
+        IPC_CREAT;
+        /// This flag is used with IPC_CREAT to ensure that this call creates
+        /// the segment.  If the segment already exists, the call fails.
+        IPC_EXCL;
+        /// Allocate the segment using "huge" pages.  See the Linux kernel
+        /// source file Documentation/admin-guide/mm/hugetlbpage.rst for
+        /// further information.
+        ///
+        /// The default huge page size is used, unless another one is chosen
+        /// with [`HugePageSize`].
+        #[cfg(target_os = "linux")]
+        SHM_HUGETLB;
+        /// This flag serves the same purpose as the mmap(2) MAP_NORESERVE flag.
+        /// Do not reserve swap space for this segment. When swap space is
+        /// reserved, one has the guarantee that it is possible to modify the
+        /// segment. When swap space is not reserved one might get SIGSEGV upon
+        /// a write if no physical memory is available. See also the discussion
+        /// of the file /proc/sys/vm/overcommit_memory in proc(5).
+        #[cfg(target_os = "linux")]
+        SHM_NORESERVE;
+    }
+);
+libc_bitflags! {
+    /// Valid flags for the third parameter of the function [`shmat`]
+    pub struct ShmatFlag: c_int
+    {
+        /// Allow the contents of the segment to be executed. The caller must
+        /// have execute permission on the segment.
+        #[cfg(target_os = "linux")]
+        SHM_EXEC;
+        /// This flag specifies that the mapping of the segment should replace
+        /// any existing mapping in the range starting at shmaddr and
+        /// continuing for the size of the segment.
+        /// (Normally, an EINVAL error would result if a mapping already exists
+        /// in this address range.)
+        /// In this case, shmaddr must not be NULL.
+        ///
+        /// Only valid with [`AttachAddress::Fixed`]. Prefer
+        /// [`Shm::attach_remap_at`], which checks the replaced range.
+        #[cfg(target_os = "linux")]
+        SHM_REMAP;
+        /// Attach the segment for read-only access. The process must have read
+        /// permission for the segment. If this flag is not specified, the
+        /// segment is attached for read and write access, and the process must
+        /// have read and write permission for the segment.
+        /// There is no notion of a write-only shared memory segment.
+        ///
+        /// This flag is set by [`Shm::attach_ro`], and rejected by
+        /// [`Shm::attach`].
+        SHM_RDONLY;
+        /// If shmaddr isn't NULL and SHM_RND is specified in shmflg, the
+        /// attach occurs at the address equal to shmaddr rounded down to the
+        /// nearest multiple of SHMLBA.
+        ///
+        /// Set by [`AttachAddress::Rounded`], and rejected otherwise.
+        SHM_RND;
+    }
+}
+
+/// Size of the huge pages backing a segment, on systems supporting several
+/// of them.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::shm::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// struct Table([u64; 1 << 18]);
+///
+/// let flags = ShmgetFlag::IPC_CREAT | HugePageSize::SIZE_2MB.flags();
+/// let shm = unsafe {
+///     Shm::<Table>::shmget(Key::new(1337), flags, IpcMode::owner_rw())
+/// }?;
+/// # Ok::<(), Errno>(())
+/// ```
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct HugePageSize {
+    /// Base-2 logarithm of the size, always below `usize::BITS`.
+    shift: u8,
+}
+
+#[cfg(target_os = "linux")]
+impl HugePageSize {
+    /// Pages of 2 MiB (`SHM_HUGE_2MB`).
+    pub const SIZE_2MB: Self = Self { shift: 21 };
+    /// Pages of 1 GiB (`SHM_HUGE_1GB`).
+    pub const SIZE_1GB: Self = Self { shift: 30 };
+
+    /// Pages of `1 << shift` bytes, for the other sizes listed in
+    /// `/sys/kernel/mm/hugepages`.
+    ///
+    /// Fails with [`Errno::EINVAL`] if `shift` does not fit in the flags of
+    /// `shmget`, above 63, or if the size does not fit in a `usize`.
+    pub fn new(shift: u8) -> Result<Self> {
+        let max = (libc::MAP_HUGE_MASK as u32).min(usize::BITS - 1);
+        if u32::from(shift) > max {
+            return Err(Errno::EINVAL);
+        }
+        Ok(Self { shift })
+    }
+
+    /// Returns the base-2 logarithm of the size of the pages.
+    pub fn shift(self) -> u8 {
+        self.shift
+    }
+
+    /// Returns the size of the pages, in bytes.
+    pub fn size(self) -> usize {
+        1usize
+            .checked_shl(u32::from(self.shift))
+            .expect("The shift is checked on construction")
+    }
+
+    /// Returns [`ShmgetFlag::SHM_HUGETLB`], with the size of the pages
+    /// encoded as `shmget` expects it, which is the encoding of `mmap`.
+    pub fn flags(self) -> ShmgetFlag {
+        let shift = c_int::from(self.shift);
+        ShmgetFlag::SHM_HUGETLB
+            | ShmgetFlag::from_bits_retain(shift << libc::MAP_HUGE_SHIFT)
+    }
+}
+
+impl ShmatFlag {
+    /// Flags describing the access rights of an attachment, rather than its
+    /// address.
+    fn access() -> Self {
+        #[cfg(target_os = "linux")]
+        return ShmatFlag::SHM_RDONLY | ShmatFlag::SHM_EXEC;
+        #[cfg(not(target_os = "linux"))]
+        return ShmatFlag::SHM_RDONLY;
+    }
+}
+
+/// Control operations of [`Shm::shmctl`], with their argument.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum ShmctlArg<'a> {
+    /// Write the `shm_perm.uid`, `shm_perm.gid` and (the least significant
+    /// 9 bits of) `shm_perm.mode` members of the structure to the kernel
+    /// data structure associated with this shared memory segment, updating
+    /// also its `shm_ctime` member.
+    ///
+    /// The effective UID of the calling process must match the owner
+    /// (`shm_perm.uid`) or creator (`shm_perm.cuid`) of the shared memory
+    /// segment, or the caller must be privileged.
+    IPC_SET(&'a shmid_ds),
+    /// Copy information from the kernel data structure associated with the
+    /// segment into the structure.
+    /// The caller must have read permission on the shared memory segment.
+    IPC_STAT(&'a mut shmid_ds),
+    /// Mark the segment to be destroyed. The segment will actually be
+    /// destroyed only after the last process detaches it
+    /// (i.e., when the shm_nattch member of the associated structure
+    /// shmid_ds is zero).
+    /// The caller must be the owner or creator of the segment,
+    /// or be privileged.
+    ///
+    /// If a segment has been marked for destruction, then the
+    /// (nonstandard) SHM_DEST flag of the shm_perm.mode field in the
+    /// associated data structure retrieved by IPC_STAT will be set.
+    ///
+    /// The caller must ensure that a segment is eventually destroyed;
+    /// otherwise its pages that were faulted in will remain in memory
+    /// or swap.
+    ///
+    /// See also the description of /proc/sys/kernel/shm_rmid_forced
+    /// in proc(5).
+    IPC_RMID,
+    /// Prevent swapping of the shared memory segment. The caller must
+    /// fault in any pages that are required to be present after locking is
+    /// enabled.
+    /// If a segment has been locked, then the (nonstandard) SHM_LOCKED
+    /// flag of the shm_perm.mode field in the associated data structure
+    /// retrieved by IPC_STAT will be set.
+    ///
+    /// FreeBSD defines `SHM_LOCK` and `SHM_UNLOCK`, but its kernel rejects
+    /// them, so both are only available on Linux.
+    #[cfg(target_os = "linux")]
+    SHM_LOCK,
+    /// Unlock the segment, allowing it to be swapped out.
+    #[cfg(target_os = "linux")]
+    SHM_UNLOCK,
+}
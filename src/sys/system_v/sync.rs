@@ -0,0 +1,1447 @@
+//! Synchronization primitives shared between processes
+//!
+//! The primitives are built on SystemV semaphore sets, and protect data
+//! stored in SystemV shared memory segments. Unlike a `pthread_mutex_t`, a
+//! semaphore is released by the kernel when the process holding it exits,
+//! so a crashed process cannot leave the other ones locked out.
+//! [`Mutex::lock_robust`] also tells the next owner that the protected value
+//! may have been left half-updated.
+//!
+//! [`SeqLock`] is the exception, only made of atomics stored in the segment,
+//! so that reading it never makes a system call. On Linux,
+//! [`ShmPriorityMutex`] is another one, a `pthread_mutex_t` with priority
+//! inheritance for real-time processes.
+
+use std::cell::UnsafeCell;
+#[cfg(target_os = "linux")]
+use std::marker::PhantomData;
+use std::mem::MaybeUninit;
+use std::ops::Deref;
+#[cfg(target_os = "linux")]
+use std::ops::DerefMut;
+use std::sync::atomic::{self, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use std::{fmt, hint, ptr};
+
+use crate::errno::Errno;
+use crate::Result;
+
+#[cfg(target_os = "linux")]
+use super::ffi as libc;
+use super::sem::{SemOp, SemaphoreSet, SemctlArg, SemgetFlag, SemopFlag};
+use super::shm::{AttachAddress, SharedMemory, Shm, ShmatFlag, ShmgetFlag};
+use super::{IpcIdentity, IpcMode, Key, SysvSafe, Zeroable};
+
+/// The version of `lock_api` implemented by [`RawSemaphoreMutex`].
+pub use lock_api;
+
+/// Raw lock of a [`Mutex`], held while its first semaphore is 0.
+///
+/// The second semaphore counts the owners that did not release the lock. It
+/// is changed without [`SemopFlag::SEM_UNDO`], so it stays incremented when
+/// a process exits while holding the lock, which the kernel then releases:
+/// the next owner learns it from [`RawSemaphoreMutex::owner_died`].
+///
+/// This implements [`lock_api::RawMutex`] and [`lock_api::RawMutexTimed`],
+/// and can protect other data than a shared memory segment with
+/// [`lock_api::Mutex`]. As `lock_api` cannot report errors, locking panics
+/// if the semaphore set has been removed.
+///
+/// [`RawMutex::INIT`](lock_api::RawMutex::INIT) is not connected to any
+/// semaphore set, and panics when used: create the lock with
+/// [`RawSemaphoreMutex::create`] or [`RawSemaphoreMutex::open`] instead.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::sync::{lock_api, RawSemaphoreMutex};
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// let raw = RawSemaphoreMutex::create(MY_KEY, IpcMode::owner_rw())?;
+/// let lock = lock_api::Mutex::from_raw(raw, ());
+/// drop(lock.lock());
+/// # Ok::<(), Errno>(())
+/// ```
+#[derive(Debug)]
+pub struct RawSemaphoreMutex {
+    set: SemaphoreSet,
+}
+
+impl RawSemaphoreMutex {
+    /// Index of the semaphore held by the owner.
+    const LOCK: u16 = 0;
+    /// Index of the semaphore counting the owners.
+    const OWNERS: u16 = 1;
+
+    /// Creates a new semaphore set of two semaphores, initially unlocked.
+    ///
+    /// Fails with [`Errno::EEXIST`] if a set already exists for `key`.
+    pub fn create(key: Key, mode: impl Into<IpcMode>) -> Result<Self> {
+        let raw = Self::create_locked(key, mode.into())?;
+        if let Err(e) = raw.init_unlocked() {
+            let _ = raw.remove();
+            return Err(e);
+        }
+        Ok(raw)
+    }
+
+    /// Creates a new semaphore set of two semaphores, initially locked
+    /// without an owner.
+    fn create_locked(key: Key, mode: IpcMode) -> Result<Self> {
+        Ok(Self {
+            set: SemaphoreSet::create(key, 2, mode)?,
+        })
+    }
+
+    /// Unlocks a lock created by [`RawSemaphoreMutex::create_locked`].
+    ///
+    /// Unlike [`RawSemaphoreMutex::release`], this does not record an
+    /// adjustment undone when the process exits.
+    fn init_unlocked(&self) -> Result<()> {
+        self.set.set_value(Self::LOCK, 1)
+    }
+
+    /// Connects to the existing semaphore set associated with `key`.
+    ///
+    /// Fails with [`Errno::ENOENT`] if no set exists for `key`.
+    pub fn open(key: Key) -> Result<Self> {
+        let set = SemaphoreSet::semget(
+            key,
+            2,
+            SemgetFlag::empty(),
+            IpcMode::owner_rw(),
+        )?;
+        Ok(Self { set })
+    }
+
+    /// Removes the semaphore set, waking up every process waiting for the
+    /// lock with [`Errno::EIDRM`].
+    pub fn remove(&self) -> Result<()> {
+        self.set.semctl(0, SemctlArg::IPC_RMID).map(drop)
+    }
+
+    /// Acquires the lock, blocking until it is available.
+    ///
+    /// The lock is released by the kernel if the process exits while
+    /// holding it.
+    pub fn acquire(&self) -> Result<()> {
+        semop_restarted(&self.set, &self.acquire_ops(SemopFlag::empty()))
+    }
+
+    /// Attempts to acquire the lock without blocking, and returns whether it
+    /// did.
+    pub fn try_acquire(&self) -> Result<bool> {
+        try_semop(&self.set, &self.acquire_ops(SemopFlag::IPC_NOWAIT))
+    }
+
+    /// Acquires the lock, blocking for at most `timeout`, and returns whether
+    /// it did.
+    ///
+    /// See [`SemaphoreSet::semtimedop`] for the precision of the timeout.
+    pub fn acquire_timeout(&self, timeout: Duration) -> Result<bool> {
+        let sops = self.acquire_ops(SemopFlag::empty());
+        self.set.semtimedop(&sops, timeout)
+    }
+
+    /// Releases the lock.
+    ///
+    /// Releasing a lock held by another process lets several processes
+    /// acquire it, and releasing a lock that nobody holds fails with
+    /// [`Errno::EAGAIN`].
+    pub fn release(&self) -> Result<()> {
+        self.set.semop(&[
+            SemOp::new(Self::OWNERS, -1, SemopFlag::IPC_NOWAIT),
+            SemOp::new(Self::LOCK, 1, SemopFlag::SEM_UNDO),
+        ])
+    }
+
+    /// Returns whether a previous owner exited while holding the lock, since
+    /// the lock was last marked consistent.
+    ///
+    /// This must be called while holding the lock.
+    pub fn owner_died(&self) -> Result<bool> {
+        Ok(self.set.semctl(Self::OWNERS, SemctlArg::GETVAL)? > 1)
+    }
+
+    /// Forgets the owners that exited while holding the lock, once the data
+    /// it protects has been repaired.
+    ///
+    /// This must be called while holding the lock.
+    pub fn mark_consistent(&self) -> Result<()> {
+        self.set.set_value(Self::OWNERS, 1)
+    }
+
+    // -- Private --
+
+    /// Operations taking the lock, and counting the new owner.
+    fn acquire_ops(&self, flags: SemopFlag) -> [SemOp; 2] {
+        [
+            SemOp::new(Self::LOCK, -1, flags | SemopFlag::SEM_UNDO),
+            SemOp::new(Self::OWNERS, 1, flags),
+        ]
+    }
+}
+
+unsafe impl lock_api::RawMutex for RawSemaphoreMutex {
+    const INIT: Self = Self {
+        set: SemaphoreSet::INVALID,
+    };
+
+    // Semaphores are not owned by a thread
+    type GuardMarker = lock_api::GuardSend;
+
+    fn lock(&self) {
+        self.acquire()
+            .expect("Failed to lock the SystemV semaphore");
+    }
+
+    fn try_lock(&self) -> bool {
+        self.try_acquire()
+            .expect("Failed to lock the SystemV semaphore")
+    }
+
+    unsafe fn unlock(&self) {
+        // Can only fail if the set has been removed in the meantime.
+        let _ = self.release();
+    }
+}
+
+unsafe impl lock_api::RawMutexTimed for RawSemaphoreMutex {
+    type Duration = Duration;
+    type Instant = Instant;
+
+    fn try_lock_for(&self, timeout: Duration) -> bool {
+        self.acquire_timeout(timeout)
+            .expect("Failed to lock the SystemV semaphore")
+    }
+
+    fn try_lock_until(&self, deadline: Instant) -> bool {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        self.try_lock_for(timeout)
+    }
+}
+
+/// Mutex shared between processes, protecting a `T` stored in a SystemV
+/// shared memory segment with a SystemV semaphore.
+///
+/// The segment and the semaphore set are both associated with the key of
+/// the mutex. The mutex dereferences to a [`lock_api::Mutex`] holding the
+/// attachment of the segment, so it is locked like any `lock_api` mutex, and
+/// the guard dereferences to the [`SharedMemory`].
+///
+/// Neither the segment nor the semaphore set are removed when the mutex is
+/// dropped, see [`Mutex::remove`].
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::sync::Mutex;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// let mutex = Mutex::create(MY_KEY, IpcMode::owner_rw(), 0u64)?;
+/// **mutex.lock() += 1;
+///
+/// // In another process
+/// let mutex = unsafe { Mutex::<u64>::open(MY_KEY) }?;
+/// assert_eq!(**mutex.lock(), 1);
+/// # Ok::<(), Errno>(())
+/// ```
+#[derive(Debug)]
+pub struct Mutex<T> {
+    shm: Shm<T>,
+    inner: lock_api::Mutex<RawSemaphoreMutex, SharedMemory<T>>,
+}
+
+/// Guard of a locked [`Mutex`], dereferencing to the [`SharedMemory`] it
+/// protects.
+pub type MutexGuard<'a, T> =
+    lock_api::MutexGuard<'a, RawSemaphoreMutex, SharedMemory<T>>;
+
+impl<T: SysvSafe> Mutex<T> {
+    /// Creates a new mutex protecting `value`.
+    ///
+    /// Fails with [`Errno::EEXIST`] if a segment or a semaphore set already
+    /// exists for `key`.
+    pub fn create(
+        key: Key,
+        mode: impl Into<IpcMode>,
+        value: T,
+    ) -> Result<Self> {
+        let mode = mode.into();
+        let raw = RawSemaphoreMutex::create_locked(key, mode)?;
+        match create_segment(key, mode, value, || raw.init_unlocked()) {
+            Ok((shm, memory)) => Ok(Self {
+                shm,
+                inner: lock_api::Mutex::from_raw(raw, memory),
+            }),
+            Err(e) => {
+                // The mutex is unusable, don't leave it behind
+                let _ = raw.remove();
+                Err(e)
+            }
+        }
+    }
+
+    /// Connects to an existing mutex.
+    ///
+    /// Fails with [`Errno::ENOENT`] if the mutex does not exist.
+    ///
+    /// # Safety
+    ///
+    /// The mutex must have been created by [`Mutex::create`], with the same
+    /// type `T`.
+    pub unsafe fn open(key: Key) -> Result<Self> {
+        let raw = RawSemaphoreMutex::open(key)?;
+        let (shm, memory) = unsafe { open_segment(key) }?;
+        Ok(Self {
+            shm,
+            inner: lock_api::Mutex::from_raw(raw, memory),
+        })
+    }
+}
+
+impl<T> Mutex<T> {
+    /// Locks the mutex, like [`lock_api::Mutex::lock`], and fails if a
+    /// previous owner exited while holding it.
+    ///
+    /// The kernel releases the mutex of a process that exits, so the other
+    /// processes are never locked out, but the value may have been left
+    /// half-updated. The mutex is then locked, and reported as
+    /// [`LockError::OwnerDied`] to every owner until one of them repairs the
+    /// value and calls [`OwnerDied::recover`].
+    ///
+    /// Unlike [`lock_api::Mutex::lock`], this does not panic if the
+    /// semaphore set cannot be used, for example because it has been
+    /// removed, but fails with [`LockError::Errno`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use nix::errno::Errno;
+    /// # use nix::sys::system_v::sync::{LockError, Mutex};
+    /// # use nix::sys::system_v::Key;
+    /// #
+    /// const MY_KEY: Key = Key::new(1337);
+    /// let mutex = unsafe { Mutex::<[u64; 2]>::open(MY_KEY) }?;
+    /// let mut guard = match mutex.lock_robust() {
+    ///     Ok(guard) => guard,
+    ///     Err(LockError::OwnerDied(died)) => {
+    ///         let mut guard = died.recover()?;
+    ///         // The previous owner may have only updated the first value
+    ///         guard[1] = guard[0];
+    ///         guard
+    ///     }
+    ///     Err(LockError::Errno(e)) => return Err(e),
+    /// };
+    /// guard[0] += 1;
+    /// guard[1] += 1;
+    /// # Ok::<(), Errno>(())
+    /// ```
+    pub fn lock_robust(
+        &self,
+    ) -> std::result::Result<MutexGuard<'_, T>, LockError<'_, T>> {
+        // The guard is made once the lock is held
+        let raw = unsafe { self.inner.raw() };
+        raw.acquire().map_err(LockError::Errno)?;
+        let guard = unsafe { self.inner.make_guard_unchecked() };
+        // Dropping the guard on error releases the lock
+        if raw.owner_died().map_err(LockError::Errno)? {
+            return Err(LockError::OwnerDied(OwnerDied { guard }));
+        }
+        Ok(guard)
+    }
+
+    /// Removes the semaphore set and marks the segment for destruction.
+    ///
+    /// Processes waiting for the mutex panic, see [`RawSemaphoreMutex`]. The
+    /// segment is destroyed once every process has detached from it.
+    pub fn remove(&self) -> Result<()> {
+        // The raw mutex is neither locked nor unlocked here
+        let removed = unsafe { self.inner.raw() }.remove();
+        removed.and(self.shm.remove())
+    }
+}
+
+impl<T> Deref for Mutex<T> {
+    type Target = lock_api::Mutex<RawSemaphoreMutex, SharedMemory<T>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+/// Error of [`Mutex::lock_robust`].
+#[derive(Debug)]
+pub enum LockError<'a, T> {
+    /// A previous owner exited while holding the mutex, which is now held.
+    OwnerDied(OwnerDied<'a, T>),
+    /// The semaphore set could not be used, and the mutex is not held.
+    Errno(Errno),
+}
+
+impl<T> fmt::Display for LockError<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockError::OwnerDied(died) => fmt::Display::fmt(died, f),
+            LockError::Errno(errno) => fmt::Display::fmt(errno, f),
+        }
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for LockError<'_, T> {}
+
+/// Error of [`Mutex::lock_robust`] when a previous owner exited while
+/// holding the mutex.
+///
+/// The mutex is held until this is dropped, like with its guard.
+#[derive(Debug)]
+pub struct OwnerDied<'a, T> {
+    guard: MutexGuard<'a, T>,
+}
+
+impl<'a, T> OwnerDied<'a, T> {
+    /// Marks the mutex as consistent, and returns its guard.
+    ///
+    /// This must be called once the value has been repaired, or once it is
+    /// known to be valid, so that the next owners lock the mutex normally.
+    pub fn recover(self) -> Result<MutexGuard<'a, T>> {
+        let raw = unsafe { MutexGuard::mutex(&self.guard).raw() };
+        raw.mark_consistent()?;
+        Ok(self.guard)
+    }
+
+    /// Returns the guard, leaving the mutex reported as [`OwnerDied`] to its
+    /// next owners.
+    pub fn into_inner(self) -> MutexGuard<'a, T> {
+        self.guard
+    }
+}
+
+impl<T> fmt::Display for OwnerDied<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a previous owner exited while holding the mutex")
+    }
+}
+
+impl<T: fmt::Debug> std::error::Error for OwnerDied<'_, T> {}
+
+/// Raw lock of a [`RwLock`], on a set of two semaphores.
+///
+/// The first semaphore is 1 while a writer holds the lock, and the second
+/// one counts the readers holding it. Readers are admitted as long as no
+/// writer holds the lock, so a steady flow of readers can starve writers.
+///
+/// This implements [`lock_api::RawRwLock`], with the same caveats as
+/// [`RawSemaphoreMutex`].
+#[derive(Debug)]
+pub struct RawSemaphoreRwLock {
+    set: SemaphoreSet,
+}
+
+impl RawSemaphoreRwLock {
+    /// Index of the semaphore held by writers.
+    const WRITER: u16 = 0;
+    /// Index of the semaphore counting readers.
+    const READERS: u16 = 1;
+
+    /// Creates a new semaphore set of two semaphores, initially unlocked.
+    ///
+    /// Fails with [`Errno::EEXIST`] if a set already exists for `key`.
+    pub fn create(key: Key, mode: impl Into<IpcMode>) -> Result<Self> {
+        let raw = Self::create_locked(key, mode.into())?;
+        if let Err(e) = raw.init_unlocked() {
+            let _ = raw.remove();
+            return Err(e);
+        }
+        Ok(raw)
+    }
+
+    /// Creates a new semaphore set of two semaphores, initially locked for
+    /// writing.
+    fn create_locked(key: Key, mode: IpcMode) -> Result<Self> {
+        let raw = SemaphoreSet::create(key, 2, mode).map(|set| Self { set })?;
+        if let Err(e) = raw.set.set_value(Self::WRITER, 1) {
+            let _ = raw.remove();
+            return Err(e);
+        }
+        Ok(raw)
+    }
+
+    /// Unlocks a lock created by [`RawSemaphoreRwLock::create_locked`].
+    fn init_unlocked(&self) -> Result<()> {
+        self.set.set_value(Self::WRITER, 0)
+    }
+
+    /// Connects to the existing semaphore set associated with `key`.
+    ///
+    /// Fails with [`Errno::ENOENT`] if no set exists for `key`.
+    pub fn open(key: Key) -> Result<Self> {
+        let set = SemaphoreSet::semget(
+            key,
+            2,
+            SemgetFlag::empty(),
+            IpcMode::owner_rw(),
+        )?;
+        Ok(Self { set })
+    }
+
+    /// Removes the semaphore set, waking up every process waiting for the
+    /// lock with [`Errno::EIDRM`].
+    pub fn remove(&self) -> Result<()> {
+        self.set.semctl(0, SemctlArg::IPC_RMID).map(drop)
+    }
+
+    /// Acquires the lock for reading, blocking while a writer holds it.
+    pub fn acquire_shared(&self) -> Result<()> {
+        semop_restarted(&self.set, &self.shared_ops(SemopFlag::empty()))
+    }
+
+    /// Attempts to acquire the lock for reading without blocking, and
+    /// returns whether it did.
+    pub fn try_acquire_shared(&self) -> Result<bool> {
+        try_semop(&self.set, &self.shared_ops(SemopFlag::IPC_NOWAIT))
+    }
+
+    /// Releases the lock acquired for reading.
+    pub fn release_shared(&self) -> Result<()> {
+        let flags = SemopFlag::SEM_UNDO;
+        self.set.semop(&[SemOp::new(Self::READERS, -1, flags)])
+    }
+
+    /// Acquires the lock for writing, blocking while anyone else holds it.
+    pub fn acquire_exclusive(&self) -> Result<()> {
+        semop_restarted(&self.set, &self.exclusive_ops(SemopFlag::empty()))
+    }
+
+    /// Attempts to acquire the lock for writing without blocking, and
+    /// returns whether it did.
+    pub fn try_acquire_exclusive(&self) -> Result<bool> {
+        try_semop(&self.set, &self.exclusive_ops(SemopFlag::IPC_NOWAIT))
+    }
+
+    /// Releases the lock acquired for writing.
+    pub fn release_exclusive(&self) -> Result<()> {
+        let flags = SemopFlag::SEM_UNDO;
+        self.set.semop(&[SemOp::new(Self::WRITER, -1, flags)])
+    }
+
+    // -- Private --
+
+    /// Operations waiting for the writer to leave, then adding a reader.
+    fn shared_ops(&self, flags: SemopFlag) -> [SemOp; 2] {
+        [
+            SemOp::new(Self::WRITER, 0, flags),
+            SemOp::new(Self::READERS, 1, flags | SemopFlag::SEM_UNDO),
+        ]
+    }
+
+    /// Operations waiting for everyone to leave, then taking the lock.
+    fn exclusive_ops(&self, flags: SemopFlag) -> [SemOp; 3] {
+        [
+            SemOp::new(Self::WRITER, 0, flags),
+            SemOp::new(Self::READERS, 0, flags),
+            SemOp::new(Self::WRITER, 1, flags | SemopFlag::SEM_UNDO),
+        ]
+    }
+}
+
+unsafe impl lock_api::RawRwLock for RawSemaphoreRwLock {
+    const INIT: Self = Self {
+        set: SemaphoreSet::INVALID,
+    };
+
+    // Semaphores are not owned by a thread
+    type GuardMarker = lock_api::GuardSend;
+
+    fn lock_shared(&self) {
+        self.acquire_shared()
+            .expect("Failed to lock the SystemV semaphore");
+    }
+
+    fn try_lock_shared(&self) -> bool {
+        self.try_acquire_shared()
+            .expect("Failed to lock the SystemV semaphore")
+    }
+
+    unsafe fn unlock_shared(&self) {
+        // Can only fail if the set has been removed in the meantime.
+        let _ = self.release_shared();
+    }
+
+    fn lock_exclusive(&self) {
+        self.acquire_exclusive()
+            .expect("Failed to lock the SystemV semaphore");
+    }
+
+    fn try_lock_exclusive(&self) -> bool {
+        self.try_acquire_exclusive()
+            .expect("Failed to lock the SystemV semaphore")
+    }
+
+    unsafe fn unlock_exclusive(&self) {
+        // Can only fail if the set has been removed in the meantime.
+        let _ = self.release_exclusive();
+    }
+}
+
+/// Reader-writer lock shared between processes, protecting a `T` stored in
+/// a SystemV shared memory segment with SystemV semaphores.
+///
+/// Any number of processes can read the value at the same time, while a
+/// writer has exclusive access to it. See [`RawSemaphoreRwLock`] for the
+/// fairness between them.
+///
+/// Like a [`Mutex`], the lock dereferences to a [`lock_api::RwLock`] holding
+/// the attachment of the segment, and is not removed when dropped, see
+/// [`RwLock::remove`].
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::sync::RwLock;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// #[derive(Clone, Copy)]
+/// struct Config {
+///     rate: u32,
+///     burst: u32,
+/// }
+/// # unsafe impl nix::sys::system_v::SysvSafe for Config {}
+///
+/// const MY_KEY: Key = Key::new(1337);
+/// let config = Config { rate: 100, burst: 10 };
+/// let lock = RwLock::create(MY_KEY, IpcMode::group_ro(), config)?;
+/// lock.write().rate = 200;
+///
+/// // In many other processes
+/// let lock = unsafe { RwLock::<Config>::open(MY_KEY) }?;
+/// let rate = lock.read().rate;
+/// # Ok::<(), Errno>(())
+/// ```
+#[derive(Debug)]
+pub struct RwLock<T> {
+    shm: Shm<T>,
+    inner: lock_api::RwLock<RawSemaphoreRwLock, SharedMemory<T>>,
+}
+
+/// Guard of a [`RwLock`] locked for reading, dereferencing to the
+/// [`SharedMemory`] it protects.
+pub type RwLockReadGuard<'a, T> =
+    lock_api::RwLockReadGuard<'a, RawSemaphoreRwLock, SharedMemory<T>>;
+
+/// Guard of a [`RwLock`] locked for writing, dereferencing to the
+/// [`SharedMemory`] it protects.
+pub type RwLockWriteGuard<'a, T> =
+    lock_api::RwLockWriteGuard<'a, RawSemaphoreRwLock, SharedMemory<T>>;
+
+impl<T: SysvSafe> RwLock<T> {
+    /// Creates a new lock protecting `value`.
+    ///
+    /// Fails with [`Errno::EEXIST`] if a segment or a semaphore set already
+    /// exists for `key`.
+    pub fn create(
+        key: Key,
+        mode: impl Into<IpcMode>,
+        value: T,
+    ) -> Result<Self> {
+        let mode = mode.into();
+        let raw = RawSemaphoreRwLock::create_locked(key, mode)?;
+        match create_segment(key, mode, value, || raw.init_unlocked()) {
+            Ok((shm, memory)) => Ok(Self {
+                shm,
+                inner: lock_api::RwLock::from_raw(raw, memory),
+            }),
+            Err(e) => {
+                // The lock is unusable, don't leave it behind
+                let _ = raw.remove();
+                Err(e)
+            }
+        }
+    }
+
+    /// Connects to an existing lock.
+    ///
+    /// Fails with [`Errno::ENOENT`] if the lock does not exist.
+    ///
+    /// # Safety
+    ///
+    /// The lock must have been created by [`RwLock::create`], with the same
+    /// type `T`.
+    pub unsafe fn open(key: Key) -> Result<Self> {
+        let raw = RawSemaphoreRwLock::open(key)?;
+        let (shm, memory) = unsafe { open_segment(key) }?;
+        Ok(Self {
+            shm,
+            inner: lock_api::RwLock::from_raw(raw, memory),
+        })
+    }
+}
+
+impl<T> RwLock<T> {
+    /// Removes the semaphore set and marks the segment for destruction.
+    ///
+    /// See [`Mutex::remove`].
+    pub fn remove(&self) -> Result<()> {
+        // The raw lock is neither locked nor unlocked here
+        let removed = unsafe { self.inner.raw() }.remove();
+        removed.and(self.shm.remove())
+    }
+}
+
+impl<T> Deref for RwLock<T> {
+    type Target = lock_api::RwLock<RawSemaphoreRwLock, SharedMemory<T>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+/// Barrier shared between processes, letting `n` processes rendezvous.
+///
+/// Like [`std::sync::Barrier`], it blocks `n - 1` processes calling
+/// [`Barrier::wait`] until the `n`-th one calls it, and can be reused once
+/// they have all been woken up. Every process must use the same `n`.
+///
+/// The barrier is a set of two semaphores. It is not removed when dropped,
+/// see [`Barrier::remove`]. A process exiting while others wait for it
+/// leaves them blocked until the barrier is removed.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::sync::Barrier;
+/// # use nix::sys::system_v::Key;
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// // In each of the 4 processes
+/// let barrier = Barrier::new(MY_KEY, 4)?;
+/// if barrier.wait()?.is_leader() {
+///     // Only one process gets here, once all of them have arrived
+/// }
+/// # Ok::<(), Errno>(())
+/// ```
+#[derive(Debug)]
+pub struct Barrier {
+    set: SemaphoreSet,
+    n: i16,
+}
+
+/// Returned by [`Barrier::wait`] to tell whether the process was the leader.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Whether this process was the one waking up the others.
+    ///
+    /// Only one process is the leader for each use of the barrier.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    /// Number of processes left to arrive, or 0 while the barrier is reset.
+    const ARRIVING: u16 = 0;
+    /// Number of processes allowed to leave the barrier.
+    const LEAVING: u16 = 1;
+
+    /// Creates the barrier for `n` processes associated with `key`, or
+    /// connects to it if it already exists.
+    ///
+    /// The barrier is created readable and writable by its owner only. A
+    /// barrier for 0 processes behaves like one for a single process.
+    ///
+    /// Fails with [`Errno::EINVAL`] if `n` exceeds the maximum value of a
+    /// semaphore.
+    pub fn new(key: Key, n: usize) -> Result<Self> {
+        let n = i16::try_from(n.max(1)).map_err(|_| Errno::EINVAL)?;
+        match SemaphoreSet::create(key, 2, IpcMode::owner_rw()) {
+            Ok(set) => {
+                // Processes connecting in the meantime wait for the reset
+                let barrier = Self { set, n };
+                let init = barrier.set.set_value(Self::ARRIVING, n as u16);
+                if let Err(e) = init {
+                    let _ = barrier.remove();
+                    return Err(e);
+                }
+                Ok(barrier)
+            }
+            Err(e) if e.errno() == Errno::EEXIST => {
+                let set = SemaphoreSet::semget(
+                    key,
+                    2,
+                    SemgetFlag::empty(),
+                    IpcMode::owner_rw(),
+                )?;
+                Ok(Self { set, n })
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Removes the semaphore set, waking up every process waiting at the
+    /// barrier with [`Errno::EIDRM`].
+    pub fn remove(&self) -> Result<()> {
+        self.set.semctl(0, SemctlArg::IPC_RMID).map(drop)
+    }
+
+    /// Blocks until all the processes have called this method.
+    ///
+    /// The last process to arrive is the leader: it wakes up the others,
+    /// then resets the barrier once they have all left.
+    pub fn wait(&self) -> Result<BarrierWaitResult> {
+        let flags = SemopFlag::IPC_NOWAIT;
+        loop {
+            // Arrive last, as 1 is the only value that can drop to 0
+            let last = [
+                SemOp::new(Self::ARRIVING, -1, flags),
+                SemOp::new(Self::ARRIVING, 0, flags),
+            ];
+            if try_semop(&self.set, &last)? {
+                return self.lead().map(|()| BarrierWaitResult(true));
+            }
+            // Arrive before the last one, as the value is at least 2
+            let early = [
+                SemOp::new(Self::ARRIVING, -2, flags),
+                SemOp::new(Self::ARRIVING, 1, flags),
+            ];
+            if try_semop(&self.set, &early)? {
+                let leave = [SemOp::new(Self::LEAVING, -1, SemopFlag::empty())];
+                semop_restarted(&self.set, &leave)?;
+                return Ok(BarrierWaitResult(false));
+            }
+            // The barrier is being reset, wait for it to be done
+            let reset = [
+                SemOp::new(Self::ARRIVING, -1, SemopFlag::empty()),
+                SemOp::new(Self::ARRIVING, 1, SemopFlag::empty()),
+            ];
+            semop_restarted(&self.set, &reset)?;
+        }
+    }
+
+    // -- Private --
+
+    /// Lets the other processes leave, then resets the barrier.
+    fn lead(&self) -> Result<()> {
+        if self.n > 1 {
+            let leave =
+                SemOp::new(Self::LEAVING, self.n - 1, SemopFlag::empty());
+            self.set.semop(&[leave])?;
+        }
+        // Processes arriving for the next use wait until everyone has left
+        let reset = [
+            SemOp::new(Self::LEAVING, 0, SemopFlag::empty()),
+            SemOp::new(Self::ARRIVING, self.n, SemopFlag::empty()),
+        ];
+        semop_restarted(&self.set, &reset)
+    }
+}
+
+/// Event shared between processes, waking every waiting process once set.
+///
+/// Like an event flag, it is either set or clear: [`Event::wait`] blocks
+/// while it is clear, and every waiting process is woken up by
+/// [`Event::set`]. It then stays set, letting the next calls to
+/// [`Event::wait`] return immediately, until [`Event::clear`] is called.
+///
+/// The event is a set of one semaphore, 1 while the event is set. It is not
+/// removed when dropped, see [`Event::remove`].
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::sync::Event;
+/// # use nix::sys::system_v::Key;
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// let ready = Event::new(MY_KEY)?;
+/// // Write the data to a shared memory segment, then announce it
+/// ready.set()?;
+///
+/// // In the other processes
+/// let ready = Event::new(MY_KEY)?;
+/// ready.wait()?;
+/// # Ok::<(), Errno>(())
+/// ```
+#[derive(Debug)]
+pub struct Event {
+    set: SemaphoreSet,
+}
+
+impl Event {
+    /// Creates the event associated with `key`, initially clear, or
+    /// connects to it if it already exists.
+    ///
+    /// The event is created readable and writable by its owner only.
+    pub fn new(key: Key) -> Result<Self> {
+        let set = SemaphoreSet::semget(
+            key,
+            1,
+            SemgetFlag::IPC_CREAT,
+            IpcMode::owner_rw(),
+        )?;
+        Ok(Self { set })
+    }
+
+    /// Removes the semaphore set, waking up every process waiting for the
+    /// event with [`Errno::EIDRM`].
+    pub fn remove(&self) -> Result<()> {
+        self.set.semctl(0, SemctlArg::IPC_RMID).map(drop)
+    }
+
+    /// Sets the event, waking up every process waiting for it.
+    pub fn set(&self) -> Result<()> {
+        self.set.set_value(0, 1)
+    }
+
+    /// Clears the event, so that the next calls to [`Event::wait`] block.
+    pub fn clear(&self) -> Result<()> {
+        self.set.set_value(0, 0)
+    }
+
+    /// Returns whether the event is set.
+    pub fn is_set(&self) -> Result<bool> {
+        Ok(self.set.semctl(0, SemctlArg::GETVAL)? != 0)
+    }
+
+    /// Blocks until the event is set.
+    pub fn wait(&self) -> Result<()> {
+        semop_restarted(&self.set, &Self::wait_ops())
+    }
+
+    /// Blocks for at most `timeout` until the event is set, and returns
+    /// whether it is.
+    ///
+    /// See [`SemaphoreSet::semtimedop`] for the precision of the timeout.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<bool> {
+        self.set.semtimedop(&Self::wait_ops(), timeout)
+    }
+
+    // -- Private --
+
+    /// Operations waiting for the semaphore to be 1, leaving it unchanged.
+    fn wait_ops() -> [SemOp; 2] {
+        [
+            SemOp::new(0, -1, SemopFlag::empty()),
+            SemOp::new(0, 1, SemopFlag::empty()),
+        ]
+    }
+}
+
+/// Sequence lock, stored in a shared memory segment, letting processes read
+/// a value while another one updates it, without blocking it.
+///
+/// Writers make the sequence counter odd while they update the value, and
+/// readers retry until they copy the value without the counter changing in
+/// the meantime. Reads are thus wait-free for the writer, and never make
+/// a system call, but spin while a write is in progress: this suits small
+/// values, written by one process and read by many, like telemetry.
+///
+/// Concurrent writers are serialized by the counter. A writer exiting in
+/// the middle of an update leaves the counter odd, blocking every reader
+/// and writer.
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::shm::SharedMemory;
+/// # use nix::sys::system_v::sync::SeqLock;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// #[derive(Clone, Copy)]
+/// struct Telemetry {
+///     temperature: f64,
+///     pressure: f64,
+/// }
+/// # unsafe impl nix::sys::system_v::SysvSafe for Telemetry {}
+///
+/// const MY_KEY: Key = Key::new(1337);
+/// let initial = Telemetry { temperature: 20.0, pressure: 1.0 };
+/// let lock = SharedMemory::new_init(MY_KEY, IpcMode::owner_rw(), SeqLock::new(initial))?;
+/// lock.write(Telemetry { temperature: 21.5, pressure: 1.1 });
+///
+/// // In many other processes
+/// let lock = SharedMemory::<SeqLock<Telemetry>>::attach_uninit(MY_KEY, IpcMode::owner_rw())?;
+/// let lock = unsafe { lock.assume_init() };
+/// println!("{}°C", lock.read().temperature);
+/// # Ok::<(), Errno>(())
+/// ```
+#[repr(C)]
+pub struct SeqLock<T> {
+    /// Odd while a writer updates the value.
+    seq: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+// Readers only get copies, and writers are serialized
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+unsafe impl<T: SysvSafe + Copy> SysvSafe for SeqLock<T> {}
+unsafe impl<T: Zeroable> Zeroable for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    /// Creates a lock holding `value`.
+    pub const fn new(value: T) -> Self {
+        Self {
+            seq: AtomicU32::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns a copy of the value, spinning while it is being written.
+    pub fn read(&self) -> T {
+        loop {
+            if let Some(value) = self.try_read() {
+                return value;
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Returns a copy of the value, or `None` if it was being written.
+    pub fn try_read(&self) -> Option<T> {
+        let seq = self.seq.load(Ordering::Acquire);
+        if seq % 2 != 0 {
+            return None;
+        }
+        // The copy may be torn by a writer, and is only trusted if the
+        // counter did not change
+        let value = unsafe {
+            ptr::read_volatile(self.value.get().cast::<MaybeUninit<T>>())
+        };
+        atomic::fence(Ordering::Acquire);
+        if self.seq.load(Ordering::Relaxed) != seq {
+            return None;
+        }
+        Some(unsafe { value.assume_init() })
+    }
+
+    /// Replaces the value, waiting for the other writers.
+    pub fn write(&self, value: T) {
+        let mut seq = self.seq.load(Ordering::Relaxed);
+        loop {
+            if seq % 2 != 0 {
+                hint::spin_loop();
+                seq = self.seq.load(Ordering::Relaxed);
+                continue;
+            }
+            match self.seq.compare_exchange_weak(
+                seq,
+                seq.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => seq = current,
+            }
+        }
+        atomic::fence(Ordering::Release);
+        unsafe { ptr::write_volatile(self.value.get(), value) };
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for SeqLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut d = f.debug_struct("SeqLock");
+        match self.try_read() {
+            Some(value) => d.field("value", &value),
+            None => d.field("value", &format_args!("<locked>")),
+        };
+        d.finish()
+    }
+}
+
+/// Layout of a [`ShmPriorityMutex`] inside its segment.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Debug)]
+struct PriorityMutexSegment<T> {
+    /// Set once the mutex and the data are initialized, the kernel zeroes new
+    /// segments.
+    ready: std::sync::atomic::AtomicBool,
+    mutex: UnsafeCell<libc::pthread_mutex_t>,
+    data: UnsafeCell<T>,
+}
+
+// The mutex is initialized with `PTHREAD_PROCESS_SHARED`
+#[cfg(target_os = "linux")]
+unsafe impl<T: SysvSafe> SysvSafe for PriorityMutexSegment<T> {}
+
+/// Process-shared mutex with priority inheritance, stored in a SystemV shared
+/// memory segment alongside the data it protects.
+///
+/// The lock is a `pthread_mutex_t` initialized with `PTHREAD_PROCESS_SHARED`
+/// and `PTHREAD_PRIO_INHERIT`: while a low priority process holds the lock, it
+/// runs with the priority of the highest priority process waiting for it.
+/// This bounds the priority inversions real-time processes would otherwise
+/// suffer under load.
+///
+/// The segment is not removed when the mutex is dropped, see
+/// [`ShmPriorityMutex::remove`].
+///
+/// # Example
+///
+/// ```no_run
+/// # use nix::errno::Errno;
+/// # use nix::sys::system_v::sync::*;
+/// # use nix::sys::system_v::{IpcMode, Key};
+/// #
+/// const MY_KEY: Key = Key::new(1337);
+/// let mutex = ShmPriorityMutex::create(MY_KEY, IpcMode::owner_rw(), 0u64)?;
+/// *mutex.lock()? += 1;
+/// # Ok::<(), Errno>(())
+/// ```
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct ShmPriorityMutex<T> {
+    shm: Shm<PriorityMutexSegment<T>>,
+    memory: SharedMemory<PriorityMutexSegment<T>>,
+    /// Last value read by [`ShmPriorityMutex::try_read_snapshot`].
+    snapshot: std::sync::Mutex<Option<T>>,
+}
+
+#[cfg(target_os = "linux")]
+unsafe impl<T: Send> Send for ShmPriorityMutex<T> {}
+#[cfg(target_os = "linux")]
+unsafe impl<T: Send> Sync for ShmPriorityMutex<T> {}
+
+#[cfg(target_os = "linux")]
+impl<T> ShmPriorityMutex<T> {
+    /// Creates a new segment holding a mutex protecting `value`.
+    ///
+    /// Fails with [`Errno::EEXIST`] if a segment already exists for `key`.
+    pub fn create(key: Key, mode: impl Into<IpcMode>, value: T) -> Result<Self>
+    where
+        T: SysvSafe,
+    {
+        let shm =
+            Shm::<PriorityMutexSegment<T>>::create_and_connect(key, mode)?;
+        let mut memory = shm.attach(AttachAddress::Any, ShmatFlag::empty())?;
+        let ptr = memory.as_mut_ptr();
+        if let Err(e) = unsafe { Self::init(ptr) } {
+            // The segment is unusable, don't leave it behind
+            drop(memory);
+            let _ = shm.remove();
+            return Err(e);
+        }
+        unsafe { ptr::addr_of_mut!((*ptr).data).write(UnsafeCell::new(value)) };
+        let memory = unsafe { memory.assume_init() };
+        // Publish the segment to `open`
+        memory
+            .ready
+            .store(true, std::sync::atomic::Ordering::Release);
+        Ok(Self {
+            shm,
+            memory,
+            snapshot: std::sync::Mutex::new(None),
+        })
+    }
+
+    /// Connects to the mutex stored in an existing segment.
+    ///
+    /// Fails with [`Errno::EAGAIN`] if the segment exists, but its creator has
+    /// not finished initializing it yet.
+    ///
+    /// # Safety
+    ///
+    /// The segment must have been created by [`ShmPriorityMutex::create`],
+    /// with the same type `T`.
+    pub unsafe fn open(key: Key) -> Result<Self>
+    where
+        T: SysvSafe,
+    {
+        let shm = unsafe {
+            Shm::<PriorityMutexSegment<T>>::shmget(
+                key,
+                ShmgetFlag::empty(),
+                IpcMode::owner_rw(),
+            )
+        }?;
+        let memory = shm.attach(AttachAddress::Any, ShmatFlag::empty())?;
+        // `ready` is valid in a zeroed segment, but the rest may not be yet
+        let ready = unsafe { &(*memory.as_ptr()).ready };
+        if !ready.load(std::sync::atomic::Ordering::Acquire) {
+            return Err(Errno::EAGAIN);
+        }
+        let memory = unsafe { memory.assume_init() };
+        Ok(Self {
+            shm,
+            memory,
+            snapshot: std::sync::Mutex::new(None),
+        })
+    }
+
+    /// Acquires the mutex, blocking until it is available.
+    pub fn lock(&self) -> Result<ShmPriorityMutexGuard<'_, T>> {
+        let res = unsafe { libc::pthread_mutex_lock(self.memory.mutex.get()) };
+        pthread_result(res)?;
+        Ok(ShmPriorityMutexGuard::new(self))
+    }
+
+    /// Attempts to acquire the mutex without blocking.
+    ///
+    /// Fails with [`Errno::EBUSY`] if the mutex is already locked.
+    pub fn try_lock(&self) -> Result<ShmPriorityMutexGuard<'_, T>> {
+        let res =
+            unsafe { libc::pthread_mutex_trylock(self.memory.mutex.get()) };
+        pthread_result(res)?;
+        Ok(ShmPriorityMutexGuard::new(self))
+    }
+
+    /// Reads a copy of the protected value, without blocking for longer than
+    /// `timeout`.
+    ///
+    /// If the mutex can be acquired in time, the value is copied, remembered
+    /// in process-private memory, and returned as [`Snapshot::Fresh`].
+    /// Otherwise, the last value remembered this way is returned as
+    /// [`Snapshot::Stale`], so a writer holding the lock for too long cannot
+    /// stall latency-critical readers.
+    ///
+    /// Fails with [`Errno::ETIMEDOUT`] if the mutex could not be acquired in
+    /// time, and no value was ever read.
+    pub fn try_read_snapshot(&self, timeout: Duration) -> Result<Snapshot<T>>
+    where
+        T: Clone,
+    {
+        let deadline = realtime_deadline(timeout)?;
+        let res = unsafe {
+            libc::pthread_mutex_timedlock(self.memory.mutex.get(), &deadline)
+        };
+        // The cache is only locked once the timed wait is over, so that
+        // concurrent readers of this process don't queue up behind it
+        match pthread_result(res) {
+            Ok(()) => {
+                let guard = ShmPriorityMutexGuard::new(self);
+                let value = (*guard).clone();
+                drop(guard);
+                *self.cached_snapshot() = Some(value.clone());
+                Ok(Snapshot::Fresh(value))
+            }
+            Err(Errno::ETIMEDOUT) => self
+                .cached_snapshot()
+                .clone()
+                .map(Snapshot::Stale)
+                .ok_or(Errno::ETIMEDOUT),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns the identity of the segment holding the mutex.
+    pub fn identity(&self) -> IpcIdentity {
+        self.shm.identity()
+    }
+
+    /// Marks the segment holding the mutex for destruction.
+    ///
+    /// The segment is destroyed once every process has detached from it.
+    pub fn remove(&self) -> Result<()> {
+        self.shm.remove()
+    }
+
+    // -- Private --
+
+    /// Locks the value last read by [`ShmPriorityMutex::try_read_snapshot`].
+    fn cached_snapshot(&self) -> std::sync::MutexGuard<'_, Option<T>> {
+        self.snapshot
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Initializes the process-shared, priority inheriting, mutex at `ptr`.
+    unsafe fn init(ptr: *mut PriorityMutexSegment<T>) -> Result<()> {
+        let mut attr = MaybeUninit::<libc::pthread_mutexattr_t>::uninit();
+        pthread_result(unsafe {
+            libc::pthread_mutexattr_init(attr.as_mut_ptr())
+        })?;
+        let res = pthread_result(unsafe {
+            libc::pthread_mutexattr_setpshared(
+                attr.as_mut_ptr(),
+                libc::PTHREAD_PROCESS_SHARED,
+            )
+        })
+        .and_then(|_| {
+            pthread_result(unsafe {
+                libc::pthread_mutexattr_setprotocol(
+                    attr.as_mut_ptr(),
+                    libc::PTHREAD_PRIO_INHERIT,
+                )
+            })
+        })
+        .and_then(|_| {
+            pthread_result(unsafe {
+                libc::pthread_mutex_init(
+                    UnsafeCell::raw_get(ptr::addr_of!((*ptr).mutex)),
+                    attr.as_ptr(),
+                )
+            })
+        });
+        unsafe { libc::pthread_mutexattr_destroy(attr.as_mut_ptr()) };
+        res
+    }
+}
+
+/// Value read by [`ShmPriorityMutex::try_read_snapshot`].
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Snapshot<T> {
+    /// The value currently stored in the segment.
+    Fresh(T),
+    /// The last value read from the segment, as the mutex could not be
+    /// acquired in time.
+    Stale(T),
+}
+
+#[cfg(target_os = "linux")]
+impl<T> Snapshot<T> {
+    /// Returns the value, whether it is fresh or not.
+    pub fn into_inner(self) -> T {
+        match self {
+            Snapshot::Fresh(value) | Snapshot::Stale(value) => value,
+        }
+    }
+}
+
+/// Returns the `CLOCK_REALTIME` time point `timeout` from now, as expected by
+/// the `pthread_*_timed*` functions.
+#[cfg(target_os = "linux")]
+fn realtime_deadline(timeout: Duration) -> Result<libc::timespec> {
+    let mut now = MaybeUninit::<libc::timespec>::uninit();
+    Errno::result(unsafe {
+        libc::clock_gettime(libc::CLOCK_REALTIME, now.as_mut_ptr())
+    })?;
+    let now = unsafe { now.assume_init() };
+    let nanos = now.tv_nsec as u64 + u64::from(timeout.subsec_nanos());
+    let secs = (now.tv_sec as u64)
+        .saturating_add(timeout.as_secs())
+        .saturating_add(nanos / 1_000_000_000);
+    let mut deadline = now;
+    deadline.tv_sec = secs.min(libc::time_t::MAX as u64) as libc::time_t;
+    deadline.tv_nsec = (nanos % 1_000_000_000) as _;
+    Ok(deadline)
+}
+
+/// RAII guard of a locked [`ShmPriorityMutex`], unlocking it when dropped.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct ShmPriorityMutexGuard<'a, T> {
+    mutex: &'a ShmPriorityMutex<T>,
+    /// The mutex must be unlocked by the thread which locked it.
+    _not_send: PhantomData<*const ()>,
+}
+
+#[cfg(target_os = "linux")]
+unsafe impl<T: Sync> Sync for ShmPriorityMutexGuard<'_, T> {}
+
+#[cfg(target_os = "linux")]
+impl<'a, T> ShmPriorityMutexGuard<'a, T> {
+    /// Wraps the already locked `mutex`.
+    fn new(mutex: &'a ShmPriorityMutex<T>) -> Self {
+        Self {
+            mutex,
+            _not_send: PhantomData,
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl<T> Deref for ShmPriorityMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.memory.data.get() }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl<T> DerefMut for ShmPriorityMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.memory.data.get() }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl<T> Drop for ShmPriorityMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        unsafe { libc::pthread_mutex_unlock(self.mutex.memory.mutex.get()) };
+    }
+}
+
+/// Converts the return value of a `pthread_*` function into a [`Result`].
+#[cfg(target_os = "linux")]
+fn pthread_result(res: libc::c_int) -> Result<()> {
+    match res {
+        0 => Ok(()),
+        e => Err(Errno::from_raw(e)),
+    }
+}
+
+/// Performs `sops`, restarting when interrupted by a signal.
+fn semop_restarted(set: &SemaphoreSet, sops: &[SemOp]) -> Result<()> {
+    loop {
+        match set.semop(sops) {
+            Err(Errno::EINTR) => continue,
+            res => return res,
+        }
+    }
+}
+
+/// Performs `sops`, which use [`SemopFlag::IPC_NOWAIT`], and returns whether
+/// they could be performed without blocking.
+fn try_semop(set: &SemaphoreSet, sops: &[SemOp]) -> Result<bool> {
+    match set.semop(sops) {
+        Ok(()) => Ok(true),
+        Err(Errno::EAGAIN) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Creates the segment of a lock protecting `value`, then calls `publish` to
+/// let other processes take the lock.
+///
+/// The segment is removed if anything fails.
+fn create_segment<T: SysvSafe>(
+    key: Key,
+    mode: IpcMode,
+    value: T,
+    publish: impl FnOnce() -> Result<()>,
+) -> Result<(Shm<T>, SharedMemory<T>)> {
+    let shm = Shm::<T>::create_and_connect(key, mode)?;
+    let res = shm
+        .attach(AttachAddress::Any, ShmatFlag::empty())
+        .map_err(Errno::from)
+        .and_then(|memory| {
+            let memory = memory.write(value);
+            publish()?;
+            Ok(memory)
+        });
+    match res {
+        Ok(memory) => Ok((shm, memory)),
+        Err(e) => {
+            let _ = shm.remove();
+            Err(e)
+        }
+    }
+}
+
+/// Attaches the segment of an existing lock.
+///
+/// # Safety
+///
+/// The segment must hold a valid `T`.
+unsafe fn open_segment<T: SysvSafe>(
+    key: Key,
+) -> Result<(Shm<T>, SharedMemory<T>)> {
+    let shm = unsafe {
+        Shm::<T>::shmget(key, ShmgetFlag::empty(), IpcMode::owner_rw())
+    }?;
+    let memory = shm.attach(AttachAddress::Any, ShmatFlag::empty())?;
+    Ok((shm, unsafe { memory.assume_init() }))
+}
@@ -0,0 +1,93 @@
+//! Helpers for the tests of code using SystemV IPC
+//!
+//! Tests running in parallel, or on several machines sharing a kernel such
+//! as containers, stomp on each other's resources when they use hardcoded
+//! keys. [`unique_key`] returns a key no resource uses instead.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use nix::errno::Errno;
+//! # use nix::sys::system_v::{scope, testing, IpcMode};
+//! #
+//! let key = testing::unique_key()?;
+//! scope(|s| {
+//!     let memory = s.shared_memory::<u64>(key, IpcMode::owner_rw())?;
+//!     // Test the code using the segment...
+//!     Ok(())
+//! })?;
+//! # Ok::<(), Errno>(())
+//! ```
+
+use crate::errno::Errno;
+use crate::Result;
+
+use super::ffi::{self as libc, c_int};
+use super::Key;
+
+/// Returns a random key that identifies no resource of the enabled
+/// families.
+///
+/// Random keys are tried until one is free in every family. Nothing
+/// reserves the key though: another process may still create a resource
+/// with it before the caller does, which is unlikely but makes creating
+/// the resources with `IPC_EXCL` worthwhile.
+///
+/// When the [`mock`](super::mock) is installed, the key is checked against
+/// its resources.
+///
+/// Fails with [`Errno::EEXIST`] if no free key was found after a few
+/// attempts, and with the error of the kernel if it cannot tell whether a
+/// key is free, such as [`Errno::ENOSYS`] without SystemV IPC support.
+pub fn unique_key() -> Result<Key> {
+    const ATTEMPTS: usize = 16;
+
+    for _ in 0..ATTEMPTS {
+        let key = Key::random();
+        if is_free(key)? {
+            return Ok(key);
+        }
+    }
+    Err(Errno::EEXIST)
+}
+
+// -- Private --
+
+/// Whether no resource of the enabled families is associated with `key`.
+fn is_free(key: Key) -> Result<bool> {
+    let raw = key.as_raw();
+    let free: &[bool] = &[
+        #[cfg(feature = "sysvipc_shm")]
+        lookup(unsafe { libc::shmget(raw, 0, 0) })?,
+        #[cfg(all(
+            feature = "sysvipc_sem",
+            any(
+                apple_targets,
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "linux"
+            )
+        ))]
+        lookup(unsafe { libc::semget(raw, 0, 0) })?,
+        #[cfg(all(
+            feature = "sysvipc_msg",
+            any(
+                target_os = "android",
+                target_os = "freebsd",
+                target_os = "linux"
+            )
+        ))]
+        lookup(unsafe { libc::msgget(raw, 0) })?,
+    ];
+    Ok(free.iter().all(|&free| free))
+}
+
+/// Interprets the result of looking a key up: whether no resource is
+/// associated with it.
+fn lookup(res: c_int) -> Result<bool> {
+    match Errno::result(res) {
+        Ok(_) | Err(Errno::EACCES) => Ok(false),
+        Err(Errno::ENOENT) => Ok(true),
+        Err(e) => Err(e),
+    }
+}
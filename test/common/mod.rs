@@ -147,3 +147,38 @@ cfg_if! {
         }
     }
 }
+
+/// Returns a SystemV IPC key that no other test, or test run, is using.
+///
+/// The key is derived with `ftok(3)` from a temporary file, which must be kept
+/// alive for as long as the key is in use, so the inode is not recycled.
+/// As `ftok(3)` only keeps some bits of the inode number, keys that already
+/// identify a resource are skipped.
+#[cfg(all(
+    any(bsd, target_os = "linux"),
+    any(
+        feature = "sysvipc_msg",
+        feature = "sysvipc_sem",
+        feature = "sysvipc_shm"
+    )
+))]
+pub fn unique_key() -> (tempfile::NamedTempFile, nix::sys::system_v::Key) {
+    loop {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let key = nix::sys::system_v::Key::ftok(file.path(), b'N').unwrap();
+        let raw = key.as_raw();
+        let taken = unsafe { libc::shmget(raw, 0, 0) } != -1
+            || nix::errno::Errno::last() != nix::errno::Errno::ENOENT;
+        #[cfg(any(apple_targets, target_os = "linux"))]
+        let taken = taken
+            || unsafe { libc::semget(raw, 0, 0) } != -1
+            || nix::errno::Errno::last() != nix::errno::Errno::ENOENT;
+        #[cfg(any(target_os = "freebsd", target_os = "linux"))]
+        let taken = taken
+            || unsafe { libc::msgget(raw, 0) } != -1
+            || nix::errno::Errno::last() != nix::errno::Errno::ENOENT;
+        if !taken {
+            return (file, key);
+        }
+    }
+}
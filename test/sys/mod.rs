@@ -12,6 +12,16 @@ mod test_signal;
     target_os = "netbsd"
 ))]
 mod test_aio;
+#[cfg(all(
+    any(apple_targets, freebsdlike, target_os = "linux"),
+    feature = "sysvipc_shm"
+))]
+mod test_bench;
+#[cfg(all(
+    any(apple_targets, freebsdlike, target_os = "linux"),
+    feature = "sysvipc_shm"
+))]
+mod test_channel;
 #[cfg(not(any(
     target_os = "redox",
     target_os = "fuchsia",
@@ -21,9 +31,22 @@ mod test_aio;
 mod test_ioctl;
 #[cfg(not(target_os = "redox"))]
 mod test_mman;
+#[cfg(all(
+    any(target_os = "freebsd", target_os = "linux"),
+    feature = "sysvipc_msg"
+))]
+mod test_msg;
 #[cfg(not(target_os = "redox"))]
 mod test_select;
-#[cfg(all(any(bsd, target_os = "linux",), feature = "sysvipc",))]
+#[cfg(all(
+    any(apple_targets, target_os = "freebsd", target_os = "linux"),
+    feature = "sysvipc_sem"
+))]
+mod test_sem;
+#[cfg(all(
+    any(apple_targets, freebsdlike, target_os = "linux"),
+    feature = "sysvipc_shm"
+))]
 mod test_shm;
 #[cfg(target_os = "linux")]
 mod test_signalfd;
@@ -32,6 +55,8 @@ mod test_socket;
 #[cfg(not(any(target_os = "redox")))]
 mod test_sockopt;
 mod test_stat;
+#[cfg(all(any(apple_targets, target_os = "linux"), feature = "sysvipc_sync"))]
+mod test_sync;
 #[cfg(linux_android)]
 mod test_sysinfo;
 #[cfg(not(any(
@@ -0,0 +1,19 @@
+use nix::sys::system_v::bench;
+use nix::Result;
+
+use crate::SYSTEMV_MTX;
+
+#[test]
+fn probe() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+
+    let probe = bench::probe()?;
+    assert!(probe.attach_latency > std::time::Duration::ZERO);
+    assert!(probe.ring_round_trip > std::time::Duration::ZERO);
+    #[cfg(all(
+        any(target_os = "freebsd", target_os = "linux"),
+        feature = "sysvipc_msg"
+    ))]
+    assert!(probe.queue_throughput > 0.0);
+    Ok(())
+}
@@ -0,0 +1,160 @@
+use nix::errno::Errno;
+use nix::sys::system_v::channel::ring::*;
+#[cfg(all(
+    any(target_os = "freebsd", target_os = "linux"),
+    feature = "sysvipc_msg"
+))]
+use nix::sys::system_v::channel::{self, Receiver, Sender};
+use nix::sys::system_v::IpcMode;
+use nix::Result;
+
+use crate::common::unique_key;
+use crate::SYSTEMV_MTX;
+
+#[test]
+fn ring_push_pop() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let mut producer = Producer::<u32, 4>::create(key, IpcMode::owner_rw())?;
+    let mut consumer = unsafe { Consumer::<u32, 4>::open(key) }?;
+    assert_eq!(None, consumer.pop());
+    for i in 0..4 {
+        producer.push(i).expect("The ring is not full");
+    }
+    assert!(producer.is_full());
+    assert_eq!(Err(4), producer.push(4));
+    assert_eq!(4, consumer.len());
+    assert_eq!(Some(0), consumer.pop());
+    assert_eq!(3, producer.push_slice(&[4, 5, 6]) + 2);
+
+    let mut values = [0; 8];
+    assert_eq!(4, consumer.pop_slice(&mut values));
+    assert_eq!([1, 2, 3, 4], values[..4]);
+    assert!(consumer.is_empty());
+
+    let expected = Errno::EINVAL;
+    let actual = unsafe { Consumer::<u32, 64>::open(key) }
+        .expect_err("The ring has another capacity");
+    assert_eq!(expected, actual);
+
+    producer.remove()?;
+    Ok(())
+}
+
+#[test]
+fn ring_across_threads() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    const COUNT: u64 = 10_000;
+    let mut consumer = Consumer::<u64, 64>::create(key, IpcMode::owner_rw())?;
+    let mut producer = unsafe { Producer::<u64, 64>::open(key) }?;
+    std::thread::scope(|s| {
+        s.spawn(move || {
+            for i in 0..COUNT {
+                while producer.push(i).is_err() {
+                    std::thread::yield_now();
+                }
+            }
+        });
+        for i in 0..COUNT {
+            loop {
+                if let Some(value) = consumer.pop() {
+                    assert_eq!(i, value);
+                    break;
+                }
+                std::thread::yield_now();
+            }
+        }
+    });
+    assert!(consumer.is_empty());
+    consumer.remove()?;
+    Ok(())
+}
+
+#[test]
+#[cfg(all(
+    any(target_os = "freebsd", target_os = "linux"),
+    feature = "sysvipc_msg"
+))]
+fn mpsc_channel() -> Result<()> {
+    use std::time::Duration;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let (sender, receiver) =
+        channel::channel::<[u64; 2]>(key, IpcMode::owner_rw())?;
+    let other = unsafe { Sender::<[u64; 2]>::open(key) }?;
+    std::thread::scope(|s| {
+        for id in 0..4 {
+            let sender = other.clone();
+            s.spawn(move || {
+                for i in 0..10 {
+                    sender.send([id, i]).unwrap();
+                }
+            });
+        }
+        let mut next = [0; 4];
+        for _ in 0..40 {
+            let [id, i] = receiver.recv().unwrap();
+            assert_eq!(next[id as usize], i);
+            next[id as usize] += 1;
+        }
+    });
+    assert_eq!(None, receiver.try_recv()?);
+    assert_eq!(None, receiver.recv_timeout(Duration::from_millis(20))?);
+    sender.send([4, 2])?;
+    assert_eq!(
+        Some([4, 2]),
+        receiver.recv_timeout(Duration::from_millis(20))?
+    );
+
+    let expected = Errno::EEXIST;
+    let actual = channel::channel::<u8>(key, IpcMode::owner_rw())
+        .expect_err("Channel already exists");
+    assert_eq!(expected, actual);
+
+    receiver.remove()?;
+    let expected = Errno::ENOENT;
+    let actual = unsafe { Receiver::<[u64; 2]>::open(key) }
+        .expect_err("Channel has been removed");
+    assert_eq!(expected, actual);
+    Ok(())
+}
+
+#[test]
+fn log_write_read() -> Result<()> {
+    use nix::sys::system_v::channel::log::*;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let mut reader = ShmLogReader::<4>::create(key, IpcMode::owner_rw())?;
+    let mut writer = unsafe { ShmLogWriter::<4>::open(key) }?;
+    assert_eq!(None, reader.read());
+    assert!(writer.write(LogLevel::Warn, "test", "first"));
+    let long = "é".repeat(MAX_MESSAGE_LEN);
+    assert!(writer.write(LogLevel::Debug, "test", &long));
+
+    let entry = reader.read().expect("A record was written");
+    assert_eq!(LogLevel::Warn, entry.level);
+    assert_eq!(nix::unistd::getpid(), entry.pid);
+    assert_eq!("test", entry.target);
+    assert_eq!("first", entry.message);
+    assert!(entry.time <= std::time::SystemTime::now());
+    // Truncated on a character boundary
+    let entry = reader.read().expect("A record was written");
+    assert_eq!(long[..MAX_MESSAGE_LEN], entry.message);
+
+    for _ in 0..4 {
+        assert!(writer.write(LogLevel::Info, "test", "filler"));
+    }
+    assert!(!writer.write(LogLevel::Error, "test", "dropped"));
+    assert_eq!(1, writer.dropped());
+    assert_eq!(4, reader.len());
+
+    reader.remove()?;
+    Ok(())
+}
@@ -0,0 +1,464 @@
+use nix::errno::Errno;
+use nix::sys::system_v::msg::*;
+use nix::sys::system_v::{scope, IpcKind, IpcMode};
+use nix::Result;
+
+use crate::common::unique_key;
+use crate::SYSTEMV_MTX;
+
+#[test]
+fn create_msg() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let queue = s.message_queue(key, IpcMode::owner_rw())?;
+        assert_eq!(queue.identity().kind, IpcKind::MessageQueue);
+
+        let expected = Errno::EEXIST;
+        let actual = MessageQueue::create(key, IpcMode::owner_rw())
+            .expect_err("Queue already exists");
+        assert_eq!(expected, actual);
+        Ok(())
+    })
+}
+
+#[test]
+fn send_receive_msg() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let queue = s.message_queue(key, IpcMode::owner_rw())?;
+        queue.msgsnd(2, b"second", MsgsndFlag::empty())?;
+        queue.msgsnd(1, b"first", MsgsndFlag::empty())?;
+        queue.msgsnd(3, b"", MsgsndFlag::empty())?;
+
+        let expected = Message {
+            mtype: 1,
+            data: b"first".to_vec(),
+        };
+        assert_eq!(expected, queue.msgrcv(1, 64, MsgrcvFlag::empty())?);
+
+        let expected = Message {
+            mtype: 2,
+            data: b"second".to_vec(),
+        };
+        assert_eq!(expected, queue.msgrcv(0, 64, MsgrcvFlag::empty())?);
+
+        let expected = Message {
+            mtype: 3,
+            data: Vec::new(),
+        };
+        assert_eq!(expected, queue.msgrcv(-3, 64, MsgrcvFlag::empty())?);
+
+        let expected = Errno::ENOMSG;
+        let actual = queue
+            .msgrcv(0, 64, MsgrcvFlag::IPC_NOWAIT)
+            .expect_err("Queue is empty");
+        assert_eq!(expected, actual);
+        Ok(())
+    })
+}
+
+#[test]
+fn receive_too_long_msg() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let queue = s.message_queue(key, IpcMode::owner_rw())?;
+        queue.msgsnd(1, b"0123456789", MsgsndFlag::empty())?;
+
+        let expected = Errno::E2BIG;
+        let actual = queue
+            .msgrcv(0, 4, MsgrcvFlag::IPC_NOWAIT)
+            .expect_err("Message is too long");
+        assert_eq!(expected, actual);
+
+        let message = queue.msgrcv(0, 4, MsgrcvFlag::MSG_NOERROR)?;
+        assert_eq!(message.data, b"0123");
+        Ok(())
+    })
+}
+
+#[test]
+fn send_invalid_type_msg() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let queue = s.message_queue(key, IpcMode::owner_rw())?;
+        let expected = Errno::EINVAL;
+        let actual = queue
+            .msgsnd(0, b"data", MsgsndFlag::empty())
+            .expect_err("Message types must be positive");
+        assert_eq!(expected, actual);
+        Ok(())
+    })
+}
+
+#[test]
+fn remove_msg() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let queue = MessageQueue::create(key, IpcMode::owner_rw())?;
+    queue.msgctl(MsgctlArg::IPC_RMID)?;
+
+    let expected = Errno::ENOENT;
+    let actual =
+        MessageQueue::msgget(key, MsggetFlag::empty(), IpcMode::owner_rw())
+            .expect_err("Queue has been removed");
+    assert_eq!(expected, actual);
+    Ok(())
+}
+
+#[test]
+fn typed_msg() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let queue = s.message_queue(key, IpcMode::owner_rw())?;
+        let raw = queue.clone();
+        let queue = unsafe { queue.with_type::<[u32; 2]>() };
+        queue.send(3, &[3, 3])?;
+        queue.send(1, &[1, 1])?;
+        queue.send(2, &[2, 2])?;
+
+        let expected = Message {
+            mtype: 2,
+            data: [2, 2],
+        };
+        let actual = queue.recv_filtered(TypeSelector::Exactly(2))?;
+        assert_eq!(expected, actual);
+
+        let expected = Message {
+            mtype: 1,
+            data: [1, 1],
+        };
+        let actual = queue.recv_filtered(TypeSelector::AtMost(3))?;
+        assert_eq!(expected, actual);
+
+        let expected = Some(Message {
+            mtype: 3,
+            data: [3, 3],
+        });
+        let actual = queue.try_recv_filtered(TypeSelector::Any)?;
+        assert_eq!(expected, actual);
+        assert_eq!(None, queue.try_recv_filtered(TypeSelector::Any)?);
+
+        raw.msgsnd(1, b"too long for two u32", MsgsndFlag::empty())?;
+        let expected = Errno::E2BIG;
+        let actual = queue
+            .recv_filtered(TypeSelector::Any)
+            .expect_err("Message is too long");
+        assert_eq!(expected, actual);
+
+        let expected = Errno::EINVAL;
+        let actual = queue
+            .recv_filtered(TypeSelector::AtMost(0))
+            .expect_err("Type must be positive");
+        assert_eq!(expected, actual);
+        Ok(())
+    })
+}
+
+#[test]
+fn try_receive_msg() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let queue = s.message_queue(key, IpcMode::owner_rw())?;
+        assert_eq!(None, queue.try_msgrcv(0, 64, MsgrcvFlag::empty())?);
+        queue.msgsnd(1, b"first", MsgsndFlag::empty())?;
+        queue.msgsnd(2, b"second", MsgsndFlag::empty())?;
+
+        #[cfg(target_os = "linux")]
+        {
+            let expected = Message {
+                mtype: 1,
+                data: b"fir".to_vec(),
+            };
+            let actual = queue.try_msgrcv(
+                2,
+                3,
+                MsgrcvFlag::MSG_EXCEPT | MsgrcvFlag::MSG_NOERROR,
+            )?;
+            assert_eq!(Some(expected), actual);
+        }
+        #[cfg(not(target_os = "linux"))]
+        queue.msgrcv(1, 64, MsgrcvFlag::empty())?;
+
+        assert_eq!(None, queue.try_msgrcv(1, 64, MsgrcvFlag::empty())?);
+        let message = queue.try_msgrcv(0, 64, MsgrcvFlag::empty())?;
+        assert_eq!(Some(b"second".to_vec()), message.map(|m| m.data));
+        Ok(())
+    })
+}
+
+#[test]
+fn receive_timeout_msg() -> Result<()> {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let queue = s.message_queue(key, IpcMode::owner_rw())?;
+        let start = Instant::now();
+        let timeout = Duration::from_millis(20);
+        assert_eq!(
+            None,
+            queue.msgrcv_timeout(0, 64, MsgrcvFlag::empty(), timeout)?
+        );
+        assert!(start.elapsed() >= timeout);
+
+        let sender = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                queue.msgsnd(1, b"late", MsgsndFlag::empty())
+            })
+        };
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let message = queue
+            .msgrcv_deadline(0, 64, MsgrcvFlag::empty(), deadline)?
+            .expect("The message was sent in time");
+        assert_eq!(message.data, b"late");
+        sender.join().unwrap()?;
+
+        let queue = unsafe { queue.with_type::<u64>() };
+        let selector = TypeSelector::Exactly(2);
+        queue.send(1, &1)?;
+        assert_eq!(None, queue.recv_timeout(selector, Duration::ZERO)?);
+        queue.send(2, &2)?;
+        let message = queue.recv_timeout(selector, Duration::MAX)?;
+        assert_eq!(message.map(|message| message.data), Some(2));
+        let past = Instant::now();
+        assert!(queue.recv_deadline(TypeSelector::Any, past)?.is_some());
+        Ok(())
+    })
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn peek_msg() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let queue = s.message_queue(key, IpcMode::owner_rw())?;
+        queue.msgsnd(1, b"first", MsgsndFlag::empty())?;
+        queue.msgsnd(2, b"second", MsgsndFlag::empty())?;
+
+        let expected = Message {
+            mtype: 2,
+            data: b"second".to_vec(),
+        };
+        match queue.peek(1, 64) {
+            // The kernel lacks `CONFIG_CHECKPOINT_RESTORE`
+            Err(Errno::ENOSYS) => return Ok(()),
+            res => assert_eq!(Some(expected), res?),
+        }
+        assert_eq!(None, queue.peek(2, 64)?);
+
+        // Peeking left the messages in the queue
+        let message = queue.msgrcv(0, 64, MsgrcvFlag::empty())?;
+        assert_eq!(b"first", &message.data[..]);
+        Ok(())
+    })
+}
+
+#[test]
+fn msg_stat() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let queue = s.message_queue(key, IpcMode::owner_rw())?;
+        let stat = queue.stat()?;
+        assert_eq!(0, stat.messages);
+        assert_eq!(None, stat.last_send_pid);
+        assert_eq!(None, stat.send_time);
+        assert_eq!(IpcMode::owner_rw(), stat.mode);
+
+        queue.msgsnd(1, b"hello", MsgsndFlag::empty())?;
+        let stat = queue.stat()?;
+        assert_eq!(1, stat.messages);
+        assert_eq!(5, stat.bytes);
+        assert_eq!(Some(nix::unistd::getpid()), stat.last_send_pid);
+        assert!(stat.send_time.is_some());
+
+        queue.set_max_bytes(8)?;
+        assert_eq!(8, queue.stat()?.max_bytes);
+        let expected = Errno::EAGAIN;
+        let actual = queue
+            .msgsnd(1, b"world", MsgsndFlag::IPC_NOWAIT)
+            .expect_err("Queue is full");
+        assert_eq!(expected, actual);
+        Ok(())
+    })
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn async_msg() -> Result<()> {
+    use std::time::Duration;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+
+    scope(|s| {
+        let queue = s.message_queue(key, IpcMode::owner_rw())?;
+        let typed = unsafe { queue.clone().with_type::<u64>() };
+        let queue = AsyncMessageQueue::new(queue);
+        let typed = AsyncMessageQueue::new(typed);
+
+        runtime.block_on(async {
+            // A cancelled receive leaves the queue untouched
+            let recv = typed.recv(TypeSelector::Any);
+            let timeout = Duration::from_millis(20);
+            assert!(tokio::time::timeout(timeout, recv).await.is_err());
+
+            let sender = {
+                let typed = typed.get_ref().clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(Duration::from_millis(20));
+                    typed.send(2, &42)
+                })
+            };
+            let expected = Message { mtype: 2, data: 42 };
+            let actual = typed.recv(TypeSelector::Exactly(2)).await?;
+            assert_eq!(expected, actual);
+            sender.join().unwrap()?;
+
+            queue.msgsnd(1, b"hello").await?;
+            let actual = queue.msgrcv(0, 5, MsgrcvFlag::empty()).await?;
+            assert_eq!(b"hello", &actual.data[..]);
+            Ok(())
+        })
+    })
+}
+
+#[test]
+fn route_msg() -> Result<()> {
+    use std::ops::ControlFlow;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let queue = s.message_queue(key, IpcMode::owner_rw())?;
+        queue.msgsnd(2, b"low", MsgsndFlag::empty())?;
+        queue.msgsnd(7, b"high", MsgsndFlag::empty())?;
+        queue.msgsnd(42, b"other", MsgsndFlag::empty())?;
+        queue.msgsnd(9, b"stop", MsgsndFlag::empty())?;
+        queue.msgsnd(3, b"after", MsgsndFlag::empty())?;
+
+        let mut low = Vec::new();
+        let mut high = Vec::new();
+        let mut other = Vec::new();
+        MsgRouter::new(64)
+            .route(1..=5, |message| {
+                low.push(message.data);
+                ControlFlow::Continue(())
+            })
+            .route(6..=8, |message| {
+                high.push(message.data);
+                ControlFlow::Continue(())
+            })
+            .route(9..=9, |_| ControlFlow::Break(()))
+            .fallback(|message| {
+                other.push(message.mtype);
+                ControlFlow::Continue(())
+            })
+            .run(&queue)?;
+        assert_eq!(vec![b"low".to_vec()], low);
+        assert_eq!(vec![b"high".to_vec()], high);
+        assert_eq!(vec![42], other);
+
+        // Messages matching no route are discarded without a fallback
+        let mut router = MsgRouter::new(64);
+        assert_eq!(
+            Some(ControlFlow::Continue(())),
+            router.try_dispatch(&queue)?
+        );
+        assert_eq!(None, router.try_dispatch(&queue)?);
+        Ok(())
+    })
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn route_msg_except() -> Result<()> {
+    use std::ops::ControlFlow;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let queue = s.message_queue(key, IpcMode::owner_rw())?;
+        queue.msgsnd(1, b"reserved", MsgsndFlag::empty())?;
+        queue.msgsnd(2, b"mine", MsgsndFlag::empty())?;
+
+        let mut received = Vec::new();
+        let mut router = MsgRouter::new(64).except(1).fallback(|message| {
+            received.push(message.mtype);
+            ControlFlow::Continue(())
+        });
+        assert!(router.try_dispatch(&queue)?.is_some());
+        assert_eq!(None, router.try_dispatch(&queue)?);
+        drop(router);
+        assert_eq!(vec![2], received);
+        assert_eq!(1, queue.stat()?.messages);
+        Ok(())
+    })
+}
+
+#[test]
+fn close_graceful_msg() -> Result<()> {
+    use std::thread;
+    use std::time::Duration;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let queue = MessageQueue::create(key, IpcMode::owner_rw())?;
+    queue.msgsnd(1, b"first", MsgsndFlag::empty())?;
+    queue.msgsnd(2, b"second", MsgsndFlag::empty())?;
+    let consumer = {
+        let queue = queue.clone();
+        thread::spawn(move || -> Result<Vec<_>> {
+            let mut received = Vec::new();
+            loop {
+                thread::sleep(Duration::from_millis(5));
+                let message = queue.msgrcv(0, 64, MsgrcvFlag::empty())?;
+                if message.is_close() {
+                    return Ok(received);
+                }
+                received.push(message.mtype);
+            }
+        })
+    };
+    queue.close_graceful(Duration::from_secs(5))?;
+    assert_eq!(vec![1, 2], consumer.join().unwrap()?);
+    assert_eq!(Err(Errno::EINVAL), queue.stat().map(drop));
+
+    // Without consumer, the queue is left with its messages
+    scope(|s| {
+        let queue = s.message_queue(key, IpcMode::owner_rw())?;
+        queue.msgsnd(1, b"pending", MsgsndFlag::empty())?;
+        let actual = queue.close_graceful(Duration::from_millis(20));
+        assert_eq!(Err(Errno::ETIMEDOUT), actual);
+        assert_eq!(2, queue.stat()?.messages);
+        Ok(())
+    })
+}
@@ -0,0 +1,353 @@
+use nix::errno::Errno;
+use nix::sys::system_v::sem::*;
+use nix::sys::system_v::{scope, IpcKind, IpcMode};
+use nix::Result;
+
+use crate::common::unique_key;
+use crate::SYSTEMV_MTX;
+
+#[test]
+fn create_sem() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let set = s.semaphore_set(key, 2, IpcMode::owner_rw())?;
+        #[cfg(not(target_os = "freebsd"))]
+        {
+            assert_eq!(set.semctl(0, SemctlArg::GETVAL)?, 0);
+            assert_eq!(set.semctl(1, SemctlArg::GETVAL)?, 0);
+        }
+        assert_eq!(set.identity().kind, IpcKind::SemaphoreSet);
+
+        let expected = Errno::EEXIST;
+        let actual = SemaphoreSet::create(key, 2, IpcMode::owner_rw())
+            .expect_err("Set already exists");
+        assert_eq!(expected, actual);
+        Ok(())
+    })
+}
+
+#[cfg(not(target_os = "freebsd"))]
+#[test]
+fn semop_sem() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let set = s.semaphore_set(key, 2, IpcMode::owner_rw())?;
+        set.semctl(1, SemctlArg::SETVAL(2))?;
+        set.semop(&[
+            SemOp::new(1, -1, SemopFlag::empty()),
+            SemOp::new(0, 1, SemopFlag::empty()),
+        ])?;
+        assert_eq!(set.semctl(0, SemctlArg::GETVAL)?, 1);
+        assert_eq!(set.semctl(1, SemctlArg::GETVAL)?, 1);
+        assert_eq!(
+            set.semctl(0, SemctlArg::GETPID)?,
+            nix::unistd::getpid().as_raw()
+        );
+
+        // Nothing is applied if a single operation would block
+        let expected = Errno::EAGAIN;
+        let actual = set
+            .semop(&[
+                SemOp::new(0, 1, SemopFlag::empty()),
+                SemOp::new(1, -2, SemopFlag::IPC_NOWAIT),
+            ])
+            .expect_err("Semaphore 1 is too low");
+        assert_eq!(expected, actual);
+        assert_eq!(set.semctl(0, SemctlArg::GETVAL)?, 1);
+        Ok(())
+    })
+}
+
+#[cfg(not(target_os = "freebsd"))]
+#[test]
+fn acquire_sem() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let set = s.semaphore_set(key, 1, IpcMode::owner_rw())?;
+        set.semctl(0, SemctlArg::SETVAL(1))?;
+        {
+            let _guard = set.acquire(0)?;
+            assert_eq!(set.semctl(0, SemctlArg::GETVAL)?, 0);
+            let expected = Errno::EAGAIN;
+            let actual = set
+                .semop(&[SemOp::new(0, -1, SemopFlag::IPC_NOWAIT)])
+                .expect_err("Semaphore is taken");
+            assert_eq!(expected, actual);
+        }
+        assert_eq!(set.semctl(0, SemctlArg::GETVAL)?, 1);
+        Ok(())
+    })
+}
+
+#[test]
+fn remove_sem() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let set = SemaphoreSet::create(key, 1, IpcMode::owner_rw())?;
+    set.semctl(0, SemctlArg::IPC_RMID)?;
+
+    let expected = Errno::ENOENT;
+    let actual =
+        SemaphoreSet::semget(key, 0, SemgetFlag::empty(), IpcMode::owner_rw())
+            .expect_err("Set has been removed");
+    assert_eq!(expected, actual);
+    Ok(())
+}
+
+#[cfg(not(target_os = "freebsd"))]
+#[test]
+fn sem_op_accessors() {
+    let op = SemOp::new(3, -2, SemopFlag::SEM_UNDO);
+    assert_eq!(op.semnum(), 3);
+    assert_eq!(op.op(), -2);
+    assert_eq!(op.flags(), SemopFlag::SEM_UNDO);
+}
+
+#[cfg(not(target_os = "freebsd"))]
+#[test]
+fn semaphore_timeout() -> Result<()> {
+    use std::time::{Duration, Instant};
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let set = s.semaphore_set(key, 2, IpcMode::owner_rw())?;
+        let start = Instant::now();
+        assert!(!set.wait_timeout(0, Duration::from_millis(20))?);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        set.semctl(0, SemctlArg::SETVAL(1))?;
+        assert!(set.wait_timeout(0, Duration::from_secs(5))?);
+        assert_eq!(0, set.semctl(0, SemctlArg::GETVAL)?);
+
+        // Either every operation is performed, or none
+        set.semctl(0, SemctlArg::SETVAL(1))?;
+        let sops = [
+            SemOp::new(0, -1, SemopFlag::empty()),
+            SemOp::new(1, -1, SemopFlag::empty()),
+        ];
+        assert!(!set.semtimedop(&sops, Duration::ZERO)?);
+        assert_eq!(1, set.semctl(0, SemctlArg::GETVAL)?);
+        Ok(())
+    })
+}
+
+#[test]
+fn semaphore_transaction() -> Result<()> {
+    use std::time::Duration;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let set = s.semaphore_set(key, 2, IpcMode::owner_rw())?;
+        let transaction = set.transaction().increment(0, 2).increment(1, 1);
+        assert_eq!(2, transaction.ops().len());
+        transaction.validate(2)?;
+        assert_eq!(Err(Errno::E2BIG), transaction.validate(1));
+        assert_eq!(Err(Errno::EINVAL), set.transaction().validate(8));
+        let outside = set.transaction().wait_zero(2);
+        assert_eq!(Err(Errno::EFBIG), outside.validate(8));
+        transaction.commit()?;
+
+        // The second semaphore only holds a unit, so neither is taken
+        let transaction = set.transaction().decrement(0, 2).decrement(1, 2);
+        assert!(!transaction.commit_timeout(Duration::ZERO)?);
+        let transaction = set.transaction().decrement(0, 2).decrement(1, 1);
+        assert!(transaction.commit_timeout(Duration::ZERO)?);
+        Ok(())
+    })
+}
+
+#[test]
+fn semaphore_watch() -> Result<()> {
+    use std::thread;
+    use std::time::Duration;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let set = s.semaphore_set(key, 1, IpcMode::owner_rw())?;
+        let watch = set.watch(0);
+        assert!(watch.until(0, Duration::ZERO)?);
+        assert!(!watch.until(2, Duration::from_millis(10))?);
+        assert_eq!(Err(Errno::ERANGE), watch.until(u16::MAX, Duration::ZERO));
+
+        thread::scope(|t| {
+            t.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                set.semop(&[SemOp::increment(0, 2)])
+            });
+            assert_eq!(Ok(true), watch.until(2, Duration::from_secs(5)));
+        });
+        // Waiting left the semaphore unchanged
+        assert!(!set.semtimedop(&[SemOp::decrement(0, 3)], Duration::ZERO)?);
+        assert!(set.semtimedop(&[SemOp::decrement(0, 2)], Duration::ZERO)?);
+
+        #[cfg(not(target_os = "freebsd"))]
+        {
+            assert_eq!(None, watch.changed(Duration::from_millis(10))?);
+            thread::scope(|t| {
+                t.spawn(|| {
+                    thread::sleep(Duration::from_millis(10));
+                    set.semop(&[SemOp::increment(0, 3)])
+                });
+                assert_eq!(Ok(Some(3)), watch.changed(Duration::from_secs(5)));
+            });
+        }
+        Ok(())
+    })
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn semaphore_max_operations() -> Result<()> {
+    assert!(max_operations()? > 0);
+    Ok(())
+}
+
+#[cfg(not(target_os = "freebsd"))]
+#[test]
+fn semaphore_undo() -> Result<()> {
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{fork, ForkResult};
+
+    let _m = SYSTEMV_MTX.lock();
+    let _f = crate::FORK_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let op = SemOp::decrement(1, 2).undo(true).nowait(true);
+    assert_eq!(1, op.semnum());
+    assert_eq!(-2, op.op());
+    assert_eq!(SemopFlag::SEM_UNDO | SemopFlag::IPC_NOWAIT, op.flags());
+    assert_eq!(SemopFlag::IPC_NOWAIT, op.undo(false).flags());
+
+    scope(|s| {
+        let set = s.semaphore_set(key, 2, IpcMode::owner_rw())?;
+        // Safe: the child only calls `semop` and `_exit`, which are
+        // async-signal-safe.
+        match unsafe { fork() }? {
+            ForkResult::Child => {
+                let res = set.semop(&[
+                    SemOp::increment(0, 3).undo(true),
+                    SemOp::increment(1, 1),
+                ]);
+                unsafe { libc::_exit(res.is_err().into()) }
+            }
+            ForkResult::Parent { child } => {
+                let status = waitpid(child, None)?;
+                assert_eq!(WaitStatus::Exited(child, 0), status);
+            }
+        }
+        // Only the operation with `SEM_UNDO` was undone when the child exited
+        assert_eq!(0, set.semctl(0, SemctlArg::GETVAL)?);
+        assert_eq!(1, set.semctl(1, SemctlArg::GETVAL)?);
+        Ok(())
+    })
+}
+
+#[cfg(not(target_os = "freebsd"))]
+#[test]
+fn semaphore_values() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let set = s.semaphore_set(key, 3, IpcMode::owner_rw())?;
+        assert_eq!(vec![0, 0, 0], set.values()?);
+        set.set_values(&[1, 2, 3])?;
+        assert_eq!(vec![1, 2, 3], set.values()?);
+        set.set_value(1, 5)?;
+        assert_eq!(5, set.value(1)?);
+        assert_eq!(nix::unistd::getpid(), set.last_pid(1)?);
+
+        let expected = Errno::EINVAL;
+        let actual = set.set_values(&[1, 2]).expect_err("Set has 3 semaphores");
+        assert_eq!(expected, actual);
+
+        let expected = Errno::ERANGE;
+        let actual =
+            set.set_value(0, u16::MAX).expect_err("Value is too large");
+        assert_eq!(expected, actual);
+
+        set.set_value(0, 0)?;
+        assert_eq!(0, set.waiting_for_increase(0)?);
+        assert_eq!(0, set.waiting_for_zero(1)?);
+        std::thread::scope(|s| {
+            s.spawn(|| set.semop(&[SemOp::decrement(0, 1)]));
+            s.spawn(|| set.semop(&[SemOp::wait_zero(1)]));
+            while set.waiting_for_increase(0)? != 1
+                || set.waiting_for_zero(1)? != 1
+            {
+                std::thread::yield_now();
+            }
+            set.set_values(&[1, 0, 0])
+        })?;
+        assert_eq!(vec![0, 0, 0], set.values()?);
+        Ok(())
+    })
+}
+
+#[test]
+fn semaphore_stat() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let set = s.semaphore_set(key, 3, IpcMode::owner_rw())?;
+        let stat = set.stat()?;
+        assert_eq!(3, stat.nsems);
+        assert_eq!(nix::unistd::getuid(), stat.uid);
+        assert_eq!(IpcMode::owner_rw(), stat.mode);
+        assert_eq!(None, stat.op_time);
+
+        set.semop(&[SemOp::increment(0, 1)])?;
+        assert!(set.stat()?.op_time.is_some());
+        Ok(())
+    })
+}
+
+#[cfg(all(feature = "async", not(target_os = "freebsd")))]
+#[test]
+fn async_semaphore() -> Result<()> {
+    use std::time::Duration;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap();
+
+    scope(|s| {
+        let set = s.semaphore_set(key, 1, IpcMode::owner_rw())?;
+        let set = AsyncSemaphoreSet::new(set);
+
+        // A cancelled wait leaves the semaphore untouched
+        let timeout = Duration::from_millis(20);
+        let wait = async { tokio::time::timeout(timeout, set.wait(0)).await };
+        assert!(runtime.block_on(wait).is_err());
+        assert_eq!(0, set.get_ref().value(0)?);
+
+        std::thread::scope(|t| {
+            let release = t.spawn(|| {
+                std::thread::sleep(Duration::from_millis(20));
+                set.get_ref().set_value(0, 1)
+            });
+            let guard = runtime.block_on(set.acquire(0))?;
+            assert_eq!(0, set.get_ref().value(0)?);
+            drop(guard);
+            assert_eq!(1, set.get_ref().value(0)?);
+            release.join().unwrap()
+        })
+    })
+}
@@ -1,14 +1,12 @@
-use std::ptr;
-
 use nix::errno::Errno;
-use nix::sys::shm::*;
 use nix::sys::stat::Mode;
+use nix::sys::system_v::shm::*;
+use nix::sys::system_v::{scope, IpcKind, IpcMode, Key, Operation, SysvError};
 use nix::Result;
 
+use crate::common::unique_key;
 use crate::SYSTEMV_MTX;
 
-const SHM_TEST: i32 = 1337;
-
 #[derive(Debug, Default)]
 /// Test struct used to store some data on the shared memory segment
 ///
@@ -16,6 +14,8 @@ struct TestData {
     data: i64,
 }
 
+unsafe impl SysvSafe for TestData {}
+
 #[derive(Debug)]
 struct FixtureShm {
     shm: Shm<TestData>,
@@ -23,19 +23,21 @@ struct FixtureShm {
 }
 
 impl FixtureShm {
-    fn setup() -> Result<Self> {
+    fn setup(key: Key) -> Result<Self> {
         let shm = Shm::<TestData>::create_and_connect(
-            SHM_TEST,
+            key,
             Mode::S_IRWXU | Mode::S_IRWXG | Mode::S_IRWXO,
         )?;
-        let memory = shm.attach(ptr::null(), ShmatFlag::empty())?;
+        let memory = shm
+            .attach(AttachAddress::Any, ShmatFlag::empty())?
+            .write(TestData::default());
         Ok(Self { shm, memory })
     }
 }
 
 impl Drop for FixtureShm {
     fn drop(&mut self) {
-        let _ = self.shm.shmctl(ShmctlFlag::IPC_RMID, None).map_err(|_| {
+        let _ = self.shm.shmctl(ShmctlArg::IPC_RMID).map_err(|_| {
             panic!("Failed to delete the test shared memory segment")
         });
     }
@@ -44,19 +46,21 @@ impl Drop for FixtureShm {
 #[test]
 fn create_shm() -> Result<()> {
     let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
 
-    FixtureShm::setup()?;
+    FixtureShm::setup(key)?;
     Ok(())
 }
 
 #[test]
 fn create_shm_already_exist() -> Result<()> {
     let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
 
     // Keep the fixture in scope, so we don't destroy it
-    let _fixture = FixtureShm::setup()?;
+    let _fixture = FixtureShm::setup(key)?;
     let expected = Errno::EEXIST;
-    let actual = FixtureShm::setup().expect_err("Return EExist");
+    let actual = FixtureShm::setup(key).expect_err("Return EExist");
 
     assert_eq!(expected, actual);
     Ok(())
@@ -65,12 +69,14 @@ fn create_shm_already_exist() -> Result<()> {
 #[test]
 fn create_shm_and_get_value() -> Result<()> {
     let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
 
-    let mut fixture = FixtureShm::setup()?;
+    let mut fixture = FixtureShm::setup(key)?;
     let expected = 0xDEADBEEF;
     fixture.memory.data = expected;
 
-    let actual = fixture.shm.attach(ptr::null(), ShmatFlag::empty())?.data;
+    let memory = fixture.shm.attach(AttachAddress::Any, ShmatFlag::empty())?;
+    let actual = unsafe { memory.assume_init() }.data;
     assert_eq!(expected, actual);
     Ok(())
 }
@@ -78,22 +84,1775 @@ fn create_shm_and_get_value() -> Result<()> {
 #[test]
 fn connect_already_existing_shm() -> Result<()> {
     let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
 
-    let mut fixture = FixtureShm::setup()?;
+    let mut fixture = FixtureShm::setup(key)?;
     let expected = 0xDEADBEEF;
     fixture.memory.data = expected;
 
     let existing_mem_segment = unsafe {
         Shm::<TestData>::shmget(
-            SHM_TEST,
+            key,
             ShmgetFlag::empty(),
             Mode::S_IRWXU | Mode::S_IRWXG | Mode::S_IRWXO,
         )
     }?;
-    let actual = existing_mem_segment
-        .attach(ptr::null(), ShmatFlag::empty())?
-        .data;
+    let memory =
+        existing_mem_segment.attach(AttachAddress::Any, ShmatFlag::empty())?;
+    let actual = unsafe { memory.assume_init() }.data;
+
+    assert_eq!(expected, actual);
+    Ok(())
+}
+
+#[test]
+fn scope_removes_segments() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let shm = s.shared_memory::<TestData>(
+            key,
+            Mode::S_IRWXU | Mode::S_IRWXG | Mode::S_IRWXO,
+        )?;
+        shm.attach(AttachAddress::Any, ShmatFlag::empty())?
+            .write(TestData { data: 0xDEADBEEF });
+        Ok(())
+    })?;
+
+    let expected = Errno::ENOENT;
+    let actual = unsafe {
+        Shm::<TestData>::shmget(key, ShmgetFlag::empty(), Mode::empty())
+    }
+    .expect_err("Segment removed by the scope");
+    assert_eq!(expected, actual);
+    Ok(())
+}
+
+#[test]
+fn scope_removes_segments_on_error() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let expected = Errno::EEXIST;
+    let actual = scope(|s| {
+        s.shared_memory::<TestData>(key, Mode::S_IRWXU)?;
+        s.shared_memory::<TestData>(key, Mode::S_IRWXU)?;
+        Ok(())
+    })
+    .expect_err("Second creation fails");
+    assert_eq!(expected, actual);
+
+    // The first segment must be gone, so we can create it again
+    FixtureShm::setup(key)?;
+    Ok(())
+}
+
+#[test]
+fn reconcile_topology() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let topology = IpcTopology::new().segment(key, 64, IpcMode::owner_rw());
+    let report = topology.reconcile()?;
+    let created: Vec<_> = report.created.iter().map(|i| i.key).collect();
+    assert_eq!(created, vec![key]);
+    assert_eq!(report.created[0].kind, IpcKind::SharedMemory);
+
+    scope(|s| {
+        let shm = unsafe {
+            Shm::<u8>::shmget(key, ShmgetFlag::empty(), Mode::empty())
+        }?;
+        s.register(shm.identity());
+
+        let report = topology.reconcile()?;
+        assert_eq!(report.validated, vec![shm.identity()]);
+
+        let report = IpcTopology::new()
+            .segment(key, 128, IpcMode::group_ro())
+            .reconcile()?;
+        let expected = vec![SegmentMismatch {
+            key,
+            expected_size: 128,
+            actual_size: 64,
+            expected_mode: IpcMode::group_ro(),
+            actual_mode: IpcMode::owner_rw(),
+        }];
+        assert_eq!(report.mismatched, expected);
+        Ok(())
+    })
+}
+
+#[test]
+fn reconcile_topology_rolls_back() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+    let (_other_file, other_key) = unique_key();
+
+    // A segment cannot be empty
+    let expected = Errno::EINVAL;
+    let actual = IpcTopology::new()
+        .segment(key, 64, IpcMode::owner_rw())
+        .segment(other_key, 0, IpcMode::owner_rw())
+        .reconcile()
+        .expect_err("Empty segment");
+    assert_eq!(expected, actual);
+
+    let expected = Errno::ENOENT;
+    let actual =
+        unsafe { Shm::<u8>::shmget(key, ShmgetFlag::empty(), Mode::empty()) }
+            .expect_err("Segment removed on error");
+    assert_eq!(expected, actual);
+    Ok(())
+}
+
+#[test]
+#[cfg(all(
+    target_os = "linux",
+    feature = "sysvipc_sem",
+    feature = "sysvipc_msg"
+))]
+fn reconcile_topology_sets_queues_and_strays() -> Result<()> {
+    use nix::sys::system_v::sem::{SemaphoreSet, SemgetFlag};
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let topology = IpcTopology::new()
+        .semaphore_set(key, 2, IpcMode::owner_rw())
+        .queue(key, IpcMode::owner_rw());
+    let report = topology.reconcile()?;
+    let kinds: Vec<_> = report.created.iter().map(|i| i.kind).collect();
+    assert_eq!(kinds, [IpcKind::SemaphoreSet, IpcKind::MessageQueue]);
+
+    scope(|s| {
+        // The set is removed below, as a stray
+        s.register(report.created[1]);
+        let report = IpcTopology::new()
+            .semaphore_set(key, 3, IpcMode::owner_rw())
+            .queue(key, IpcMode::group_ro())
+            .reconcile()?;
+        assert!(report.created.is_empty());
+        assert_eq!(report.mismatched_sets[0].actual_nsems, 2);
+        assert_eq!(
+            report.mismatched_queues[0].actual_mode,
+            IpcMode::owner_rw()
+        );
+
+        // Only the queue is still expected: the set is a stray
+        let report = IpcTopology::new()
+            .queue(key, IpcMode::owner_rw())
+            .remove_strays(key..=key)
+            .reconcile()?;
+        let removed: Vec<_> = report.removed.iter().map(|i| i.kind).collect();
+        assert_eq!(removed, [IpcKind::SemaphoreSet]);
+        let expected = Errno::ENOENT;
+        let actual =
+            SemaphoreSet::semget(key, 0, SemgetFlag::empty(), Mode::empty())
+                .expect_err("Stray removed");
+        assert_eq!(expected, actual);
+        Ok(())
+    })
+}
+
+#[test]
+fn shm_is_supported() {
+    let _m = SYSTEMV_MTX.lock();
+
+    assert!(nix::sys::system_v::is_supported());
+}
+
+#[test]
+fn shm_identity() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let fixture = FixtureShm::setup(key)?;
+    let identity = fixture.shm.identity();
+    assert_eq!(identity.kind, IpcKind::SharedMemory);
+    assert_eq!(identity.key, key);
+    #[cfg(target_os = "linux")]
+    assert!(identity.namespace.is_some());
+
+    let expected = format!("shm key={:#010x} id={}", key.as_raw(), identity.id);
+    assert!(identity.to_string().starts_with(&expected));
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn hugetlb_shortfall_counts_pages() -> Result<()> {
+    assert_eq!(hugetlb_shortfall(0)?, None);
+
+    let shortfall = hugetlb_shortfall(usize::MAX / 2)?
+        .expect("No pool can hold half the address space");
+    assert_eq!(shortfall.required, usize::MAX / 2 / shortfall.page_size + 1);
+    assert!(shortfall.missing() > 0);
+    Ok(())
+}
+
+#[test]
+fn ipc_mode() {
+    assert_eq!(IpcMode::owner_rw().mode(), Mode::S_IRUSR | Mode::S_IWUSR);
+    assert_eq!(
+        IpcMode::world_rw().without(Mode::S_IWGRP | Mode::S_IWOTH),
+        IpcMode::world_ro()
+    );
+    // Only the permission bits are meaningful for IPC resources
+    assert_eq!(
+        IpcMode::from(Mode::S_ISUID | Mode::S_IRUSR | Mode::S_IWUSR),
+        IpcMode::owner_rw()
+    );
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn shm_mapping_info() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let fixture = FixtureShm::setup(key)?;
+    let info = mapping_info(&fixture.memory)?;
+    assert_eq!(info.start, &*fixture.memory as *const TestData as usize);
+    assert!(info.size() >= std::mem::size_of::<TestData>());
+    assert!(info.readable && info.writable && !info.executable);
+    assert!(!info.is_huge_pages());
+
+    let read_only = fixture
+        .shm
+        .attach_ro(AttachAddress::Any, ShmatFlag::empty())?;
+    let info = mapping_info(&read_only)?;
+    assert!(info.readable && !info.writable);
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn shm_attach_remap_at() -> Result<()> {
+    use nix::libc;
+    use std::ptr::NonNull;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+    let reserved = unsafe {
+        libc::mmap(
+            std::ptr::null_mut(),
+            2 * page_size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    assert_ne!(reserved, libc::MAP_FAILED);
+    let address = NonNull::new(reserved).unwrap();
+    let unaligned =
+        NonNull::new(reserved.cast::<u8>().wrapping_add(1).cast()).unwrap();
+    let second_page = unsafe { reserved.cast::<u8>().add(page_size) };
+
+    scope(|s| {
+        let shm = s.shared_memory::<TestData>(key, IpcMode::owner_rw())?;
+        let target = unsafe { RemapTarget::new(address) };
+        let actual = shm
+            .attach_remap_at(target, 2 * page_size)
+            .expect_err("Replaces one page");
+        assert!(matches!(
+            actual,
+            SysvError::SizeMismatch { expected, actual, .. }
+                if expected == 2 * page_size && actual == page_size
+        ));
+        let target = unsafe { RemapTarget::new(unaligned) };
+        let actual = shm
+            .attach_remap_at(target, page_size)
+            .expect_err("Misaligned address");
+        assert_eq!(Errno::EINVAL, actual);
+
+        // Only memory that is mapped can be replaced
+        assert_eq!(0, unsafe { libc::munmap(second_page.cast(), page_size) });
+        let target = unsafe {
+            RemapTarget::new(NonNull::new(second_page.cast()).unwrap())
+        };
+        let actual = shm
+            .attach_remap_at(target, page_size)
+            .expect_err("Nothing to replace");
+        assert_eq!(Errno::ENOMEM, actual);
+
+        let target = unsafe { RemapTarget::new(address) };
+        let memory = shm
+            .attach_remap_at(target, page_size)?
+            .write(TestData { data: 7 });
+        assert_eq!(SharedMemory::as_ptr(&memory) as usize, reserved as usize);
+        let other = shm.attach(AttachAddress::Any, ShmatFlag::empty())?;
+        assert_eq!(unsafe { other.assume_init() }.data, 7);
+        Ok(())
+    })
+}
+
+#[test]
+fn shm_segment_watcher() -> Result<()> {
+    use std::time::Duration;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let shm = Shm::<TestData>::create_and_connect(key, IpcMode::owner_rw())?;
+    let mut watcher = SegmentWatcher::new(&shm, Duration::from_millis(1))?;
+    assert_eq!(watcher.poll()?, []);
+    assert_eq!(watcher.stat().unwrap().attachments, 0);
+
+    let pid = nix::unistd::getpid();
+    let memory = shm.attach(AttachAddress::Any, ShmatFlag::empty())?;
+    let expected = SegmentEvent::Attached {
+        attachments: 1,
+        last_pid: pid,
+    };
+    assert_eq!(watcher.wait()?, [expected]);
+    let (uid, gid) = (nix::unistd::geteuid(), nix::unistd::getegid());
+    shm.set_permissions(uid, gid, IpcMode::owner_ro())?;
+    assert_eq!(watcher.poll()?, [SegmentEvent::Changed]);
+
+    shm.remove()?;
+    #[cfg(target_os = "linux")]
+    assert!(watcher.poll()?.contains(&SegmentEvent::RemovalPending));
+    #[cfg(target_os = "linux")]
+    assert_eq!(watcher.poll()?, []);
+    drop(memory);
+    let events: Vec<_> = watcher.by_ref().collect::<Result<_>>()?;
+    assert_eq!(events, [SegmentEvent::Destroyed]);
+    assert_eq!(watcher.stat(), None);
+    assert_eq!(watcher.poll(), Err(Errno::EIDRM));
+    Ok(())
+}
+
+#[test]
+fn shm_spawn_attached() -> Result<()> {
+    use nix::sys::wait::{waitpid, WaitStatus};
+
+    let _m = SYSTEMV_MTX.lock();
+    let _f = crate::FORK_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let shm = s.shared_memory::<TestData>(key, IpcMode::owner_rw())?;
+        let memory = shm
+            .attach(AttachAddress::Any, ShmatFlag::empty())?
+            .write(TestData { data: 1 });
+        // Safe: the child only writes to the segment and calls `shmdt` and
+        // `_exit`, which are async-signal-safe.
+        let child = unsafe {
+            shm.spawn_attached(|memory| {
+                let mut memory = memory.assume_init();
+                memory.data += 41;
+                3
+            })
+        }?;
+        assert_eq!(WaitStatus::Exited(child, 3), waitpid(child, None)?);
+        assert_eq!(memory.data, 42);
+        Ok(())
+    })
+}
+
+#[test]
+fn shm_from_env() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let shm = s.shared_memory::<TestData>(key, IpcMode::owner_rw())?;
+        let mut command = std::process::Command::new("true");
+        shm.export_to(&mut command);
+        // The environment variables are only read by this test, which holds
+        // the lock on SystemV resources
+        for (name, value) in command.get_envs() {
+            std::env::set_var(name, value.unwrap());
+        }
+        let received = Shm::<TestData>::from_env();
+        let too_large = Shm::<[TestData; 2]>::from_env();
+        std::env::set_var(SHM_KEY_ENV, "1337");
+        let malformed = Shm::<TestData>::from_env();
+        std::env::remove_var(SHM_KEY_ENV);
+        std::env::remove_var(SHM_ID_ENV);
+        let missing = Shm::<TestData>::from_env();
+
+        assert_eq!(received?.identity(), shm.identity());
+        assert!(matches!(too_large, Err(SysvError::SizeMismatch { .. })));
+        assert_eq!(
+            malformed.expect_err("Key is not in hexadecimal"),
+            Errno::EINVAL
+        );
+        assert_eq!(missing.expect_err("No segment was passed"), Errno::ENOENT);
+        Ok(())
+    })
+}
+
+#[test]
+fn shm_multiple_attachments() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let shm = s.shared_memory::<TestData>(key, IpcMode::owner_rw())?;
+        let mut memory = shm
+            .attach(AttachAddress::Any, ShmatFlag::empty())?
+            .write(TestData { data: 1 });
+        let view = shm.attach_ro(AttachAddress::Any, ShmatFlag::empty())?;
+        let view = unsafe { view.assume_init() };
+        assert_eq!(shm.nattch()?, 2);
+        memory.data = 2;
+        assert_eq!(view.data, 2);
+        view.detach()?;
+        memory.data = 3;
+        assert_eq!(memory.data, 3);
+        assert_eq!(shm.nattch()?, 1);
+        Ok(())
+    })
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn shm_attach_mirrored() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        // A multiple of every page size
+        const LEN: usize = 65536;
+        let shm = s.shared_memory::<[u8; LEN]>(key, IpcMode::owner_rw())?;
+        let mut ring = shm.attach_mirrored()?;
+        assert_eq!(ring.len(), LEN);
+        assert_eq!(shm.nattch()?, 2);
+        ring.write_at(LEN - 2, b"abcd");
+        let mut buf = [0; 4];
+        ring.read_at(LEN - 2, &mut buf);
+        assert_eq!(&buf, b"abcd");
+        ring.read_at(0, &mut buf[..2]);
+        assert_eq!(&buf[..2], b"cd");
+        assert_eq!(ring.segment::<[u8; LEN]>()?.identity(), shm.identity());
+
+        let memory = shm.attach(AttachAddress::Any, ShmatFlag::empty())?;
+        let memory = unsafe { memory.assume_init() };
+        assert_eq!(&memory[LEN - 2..], b"ab");
+        ring.detach()?;
+        assert_eq!(shm.nattch()?, 1);
+        Ok(())
+    })?;
+
+    let (_file, key) = unique_key();
+    scope(|s| {
+        let shm = s.shared_memory::<TestData>(key, IpcMode::owner_rw())?;
+        let actual = shm
+            .attach_mirrored()
+            .expect_err("Not a multiple of the page size");
+        assert_eq!(Errno::EINVAL, actual);
+        Ok(())
+    })
+}
+
+#[test]
+fn key_ftok() -> Result<()> {
+    let file = tempfile::NamedTempFile::new().unwrap();
+    let key = Key::ftok(file.path(), b'N')?;
+    assert_eq!(key, Key::ftok(file.path(), b'N')?);
+    assert_ne!(key, Key::ftok(file.path(), b'M')?);
+    assert_eq!(Key::ftok(file.path(), 0), Err(Errno::EINVAL));
+    assert_eq!(Key::ftok("/nonexistent", b'N'), Err(Errno::ENOENT));
+
+    assert_eq!(Key::IPC_PRIVATE.as_raw(), 0);
+    assert_eq!(Key::new(0x539).to_string(), "0x00000539");
+    Ok(())
+}
+
+#[test]
+fn shm_stat() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let shm = s.shared_memory::<TestData>(key, IpcMode::group_ro())?;
+        let stat = shm.stat()?;
+        assert_eq!(stat.size, std::mem::size_of::<TestData>());
+        assert_eq!(stat.uid, nix::unistd::geteuid());
+        assert_eq!(stat.mode, IpcMode::group_ro());
+        assert_eq!(stat.attachments, 0);
+        assert_eq!(stat.creator_pid, nix::unistd::getpid());
+        assert_eq!(stat.attach_time, None);
+
+        let memory = shm.attach(AttachAddress::Any, ShmatFlag::empty())?;
+        let stat = memory.stat()?;
+        assert_eq!(stat.attachments, 1);
+        assert_eq!(stat.last_pid, nix::unistd::getpid());
+        assert!(stat.attach_time.is_some());
+        assert_eq!(stat.detach_time, None);
+        drop(memory);
+
+        let stat = shm.stat()?;
+        assert_eq!(stat.attachments, 0);
+        assert!(stat.detach_time.is_some());
+        Ok(())
+    })
+}
+
+#[test]
+#[cfg(feature = "sysvipc_serialized")]
+fn serialized_shm() -> Result<()> {
+    #[derive(Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+    struct Config {
+        name: String,
+        workers: Vec<u32>,
+    }
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let mut config = SerializedShm::create(key, 256, IpcMode::owner_rw())?;
+        s.register(config.identity());
+        assert_eq!(config.capacity(), 256);
+        assert_eq!(config.load()?, None);
+
+        let value = Config {
+            name: "render".to_string(),
+            workers: vec![1, 2, 3],
+        };
+        config.store(&value)?;
+        let other = SerializedShm::<Config>::open(key, IpcMode::owner_rw())?;
+        assert_eq!(other.load()?, Some(value));
+
+        let expected = Errno::E2BIG;
+        let actual = config
+            .store(&Config {
+                name: "x".repeat(256),
+                workers: Vec::new(),
+            })
+            .expect_err("Value exceeds the capacity");
+        assert_eq!(expected, actual);
+        assert_eq!(other.load()?.unwrap().workers, [1, 2, 3]);
+
+        // Leave a store in progress, as a process killed in the middle would
+        let mut raw = SharedMemoryBuffer::shmget(
+            key,
+            0,
+            ShmgetFlag::empty(),
+            IpcMode::owner_rw(),
+        )?;
+        let sequence = std::mem::size_of::<usize>();
+        raw[sequence..2 * sequence].copy_from_slice(&3usize.to_ne_bytes());
+        let expected = Errno::EAGAIN;
+        let actual = other.load().expect_err("Store never completes");
+        assert_eq!(expected, actual);
+        let actual = config
+            .store(&Config {
+                name: "y".to_string(),
+                workers: Vec::new(),
+            })
+            .expect_err("Store never completes");
+        assert_eq!(expected, actual);
+        Ok(())
+    })?;
+
+    scope(|s| {
+        let plain = SharedMemoryBuffer::shmget(
+            key,
+            64,
+            ShmgetFlag::IPC_CREAT | ShmgetFlag::IPC_EXCL,
+            IpcMode::owner_rw(),
+        )?;
+        s.register(plain.identity());
+        let expected = Errno::EAGAIN;
+        let actual = SerializedShm::<Config>::open(key, IpcMode::owner_rw())
+            .expect_err("Segment was not initialized");
+        assert_eq!(expected, actual);
+        Ok(())
+    })
+}
+
+#[test]
+#[cfg(feature = "serde")]
+fn shm_stat_serde() -> Result<()> {
+    use serde_test::{assert_tokens, Token};
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    assert_tokens(&key, &[Token::I32(key.as_raw())]);
+    assert_tokens(&IpcMode::group_ro(), &[Token::U32(0o640)]);
+
+    scope(|s| {
+        let shm = s.shared_memory::<TestData>(key, IpcMode::group_ro())?;
+        let stat = shm.stat()?;
+        let change_time = stat
+            .change_time
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            .unwrap();
+        assert_tokens(
+            &stat,
+            &[
+                Token::Struct {
+                    name: "ShmStat",
+                    len: 10,
+                },
+                Token::Str("size"),
+                Token::U64(std::mem::size_of::<TestData>() as u64),
+                Token::Str("uid"),
+                Token::U32(nix::unistd::geteuid().as_raw()),
+                Token::Str("gid"),
+                Token::U32(stat.gid.as_raw()),
+                Token::Str("mode"),
+                Token::U32(0o640),
+                Token::Str("attachments"),
+                Token::U64(0),
+                Token::Str("creator_pid"),
+                Token::I32(nix::unistd::getpid().as_raw()),
+                Token::Str("last_pid"),
+                Token::I32(0),
+                Token::Str("attach_time"),
+                Token::None,
+                Token::Str("detach_time"),
+                Token::None,
+                Token::Str("change_time"),
+                Token::Struct {
+                    name: "SystemTime",
+                    len: 2,
+                },
+                Token::Str("secs_since_epoch"),
+                Token::U64(change_time.as_secs()),
+                Token::Str("nanos_since_epoch"),
+                Token::U32(0),
+                Token::StructEnd,
+                Token::StructEnd,
+            ],
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn shm_uninit_attach() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let shm = s.shared_memory::<TestData>(key, IpcMode::owner_rw())?;
+        let memory = shm.attach(AttachAddress::Any, ShmatFlag::empty())?;
+        let memory = memory.write(TestData { data: 42 });
+        assert_eq!(memory.data, 42);
+
+        // Another attachment sees the value written through the first one
+        let other = shm.attach(AttachAddress::Any, ShmatFlag::empty())?;
+        let other = unsafe { other.assume_init() };
+        assert_eq!(other.data, 42);
+        assert_eq!(shm.stat()?.attachments, 2);
+        drop(memory);
+        assert_eq!(shm.stat()?.attachments, 1);
+        Ok(())
+    })
+}
+
+#[test]
+fn shm_try_clone() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let mut fixture = FixtureShm::setup(key)?;
+    let clone = fixture.memory.try_clone()?;
+    fixture.memory.data = 0xDEADBEEF;
+    assert_eq!(clone.data, 0xDEADBEEF);
+    assert_eq!(fixture.shm.stat()?.attachments, 2);
+    drop(clone);
+    assert_eq!(fixture.shm.stat()?.attachments, 1);
+
+    #[cfg(target_os = "linux")]
+    {
+        let read_only = fixture
+            .shm
+            .attach_ro(AttachAddress::Any, ShmatFlag::empty())?;
+        let info = mapping_info(&read_only.try_clone()?)?;
+        assert!(info.readable && !info.writable);
+    }
+    Ok(())
+}
+
+#[test]
+fn shm_segment() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let memory =
+        SharedMemory::new_init(key, IpcMode::owner_rw(), TestData { data: 7 })?;
+    let segment = memory.segment();
+    assert_eq!(segment.id(), memory.id());
+    let read_only =
+        segment.attach_ro(AttachAddress::Any, ShmatFlag::empty())?;
+    let read_only = unsafe { read_only.assume_init() };
+    assert_eq!(read_only.segment().id(), segment.id());
+    drop(memory);
+    assert_eq!(read_only.data, 7);
+    drop(read_only);
+
+    // The segment outlives its attachments until removed
+    assert_eq!(segment.stat()?.attachments, 0);
+    segment.remove()?;
+    Ok(())
+}
+
+#[test]
+fn shm_read_only() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let mut fixture = FixtureShm::setup(key)?;
+    fixture.memory.data = 0xDEADBEEF;
+
+    let read_only = fixture
+        .shm
+        .attach_ro(AttachAddress::Any, ShmatFlag::empty())?;
+    let read_only = unsafe { read_only.assume_init() };
+    assert_eq!(read_only.data, 0xDEADBEEF);
+    assert_eq!(read_only.stat()?.attachments, 2);
+
+    let expected = Errno::EINVAL;
+    let actual = fixture
+        .shm
+        .attach(AttachAddress::Any, ShmatFlag::SHM_RDONLY)
+        .expect_err("Read-only attachments go through attach_ro");
+    assert_eq!(expected, actual);
+    Ok(())
+}
+
+#[test]
+fn shm_attach_address() -> Result<()> {
+    use std::ptr::NonNull;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let shm = s.shared_memory::<TestData>(key, IpcMode::owner_rw())?;
+        let memory = shm.attach(AttachAddress::Any, ShmatFlag::empty())?;
+        let address = memory.as_ptr() as usize;
+        drop(memory);
+
+        let expected = Errno::EINVAL;
+        let actual = shm
+            .attach(AttachAddress::Any, ShmatFlag::SHM_RND)
+            .expect_err("SHM_RND goes through AttachAddress::Rounded");
+        assert_eq!(expected, actual);
+
+        let rounded = AttachAddress::Rounded(address + 1);
+        let memory = shm.attach(rounded, ShmatFlag::empty())?;
+        assert_eq!(memory.as_ptr() as usize, address);
+        drop(memory);
+
+        let fixed = AttachAddress::Fixed(NonNull::new(address as _).unwrap());
+        let memory = shm.attach(fixed, ShmatFlag::empty())?;
+        assert_eq!(memory.as_ptr() as usize, address);
+
+        #[cfg(target_os = "linux")]
+        {
+            let expected = Errno::EINVAL;
+            let actual = shm
+                .attach(AttachAddress::Any, ShmatFlag::SHM_REMAP)
+                .expect_err("SHM_REMAP needs a fixed address");
+            assert_eq!(expected, actual);
+
+            let remapped = shm.attach(fixed, ShmatFlag::SHM_REMAP)?;
+            assert_eq!(remapped.as_ptr() as usize, address);
+            // Both handles refer to the same mapping, only detach it once
+            std::mem::forget(memory);
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn shm_buffer() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let mut buffer = SharedMemoryBuffer::shmget(
+        key,
+        10_000,
+        ShmgetFlag::IPC_CREAT | ShmgetFlag::IPC_EXCL,
+        IpcMode::owner_rw(),
+    )?;
+    assert_eq!(buffer.len(), 10_000);
+    buffer[9_999] = 42;
+
+    // The length of an existing segment comes from IPC_STAT
+    let existing = SharedMemoryBuffer::shmget(
+        key,
+        0,
+        ShmgetFlag::empty(),
+        IpcMode::owner_rw(),
+    )?;
+    assert_eq!(existing.len(), 10_000);
+    assert_eq!(existing[9_999], 42);
+    assert_eq!(existing.try_clone()?.len(), 10_000);
+
+    drop((buffer, existing));
+    let shm =
+        unsafe { Shm::<u8>::shmget(key, ShmgetFlag::empty(), Mode::empty()) }?;
+    shm.remove()?;
+    Ok(())
+}
+
+#[test]
+fn shm_array() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let samples = SharedMemory::<[TestData]>::shmget_array(
+        key,
+        4096,
+        ShmgetFlag::IPC_CREAT | ShmgetFlag::IPC_EXCL,
+        IpcMode::owner_rw(),
+    )?;
+    let mut samples = samples.init_with(|i| TestData { data: i as i64 });
+    assert_eq!(samples.len(), 4096);
+    samples[4095].data = -1;
+    assert_eq!(samples.stat()?.size, 4096 * std::mem::size_of::<TestData>());
+
+    let existing = SharedMemory::<[TestData]>::shmget_array(
+        key,
+        0,
+        ShmgetFlag::empty(),
+        IpcMode::owner_rw(),
+    )?;
+    let existing = unsafe { existing.assume_init() };
+    assert_eq!(existing.len(), 4096);
+    assert_eq!(existing[10].data, 10);
+    assert_eq!(existing.try_clone()?[4095].data, -1);
+
+    let expected = Errno::EINVAL;
+    let actual = SharedMemory::<[TestData]>::shmget_array(
+        key,
+        usize::MAX,
+        ShmgetFlag::empty(),
+        IpcMode::owner_rw(),
+    )
+    .expect_err("Size overflows");
+    assert_eq!(expected, actual);
+
+    drop((samples, existing));
+    let shm =
+        unsafe { Shm::<u8>::shmget(key, ShmgetFlag::empty(), Mode::empty()) }?;
+    shm.remove()?;
+    Ok(())
+}
+
+#[test]
+fn shm_with_tail() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        // The tail starts at offset 8, aligned for the `u64`
+        let ring = SharedMemoryWithTail::<u8, u64>::shmget(
+            key,
+            16,
+            ShmgetFlag::IPC_CREAT | ShmgetFlag::IPC_EXCL,
+            IpcMode::owner_rw(),
+        )?;
+        s.register(ring.identity());
+        let mut ring = ring.init_with(3, |i| i as u64);
+        assert_eq!(ring.stat()?.size, 8 + 16 * 8);
+        assert_eq!(ring.tail().len(), 16);
+        assert_eq!(ring.tail().as_ptr() as usize % 8, 0);
+        ring.tail_mut()[15] = u64::MAX;
+        *ring = 4;
+
+        let existing = SharedMemoryWithTail::<u8, u64>::shmget(
+            key,
+            0,
+            ShmgetFlag::empty(),
+            IpcMode::owner_rw(),
+        )?;
+        let existing = unsafe { existing.assume_init() };
+        assert_eq!(*existing, 4);
+        assert_eq!(existing.tail()[10], 10);
+        assert_eq!(existing.try_clone()?.tail()[15], u64::MAX);
+
+        let actual = SharedMemoryWithTail::<[u8; 256], u64>::shmget(
+            key,
+            0,
+            ShmgetFlag::empty(),
+            IpcMode::owner_rw(),
+        )
+        .expect_err("The segment is smaller than the header");
+        assert!(matches!(actual, SysvError::SizeMismatch { .. }));
+        Ok(())
+    })
+}
+
+#[test]
+fn shm_constructors() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+    let (_other_file, other_key) = unique_key();
+    let (_zeroed_file, zeroed_key) = unique_key();
+
+    scope(|s| {
+        let memory = SharedMemory::new_init(
+            key,
+            IpcMode::owner_rw(),
+            TestData::default(),
+        )?;
+        s.register(memory.identity());
+        assert_eq!(memory.data, 0);
+        assert_eq!(memory.identity().kind, IpcKind::SharedMemory);
+
+        let expected = Errno::EEXIST;
+        let actual = SharedMemory::new_init(key, IpcMode::owner_rw(), 1u8)
+            .expect_err("Segment already exists");
+        assert_eq!(expected, actual);
+
+        let memory =
+            SharedMemory::new_with(other_key, IpcMode::owner_rw(), |m| {
+                m.write(TestData { data: 42 })
+            })?;
+        s.register(memory.identity());
+        assert_eq!(memory.data, 42);
+
+        // The segment is removed, so the key is reused below
+        let expected = Errno::EINVAL;
+        let actual =
+            SharedMemory::new_with(zeroed_key, IpcMode::owner_rw(), |_| {
+                Box::leak(Box::new(TestData::default()))
+            })
+            .expect_err("Another value was initialized");
+        assert_eq!(expected, actual);
+
+        let memory = SharedMemory::<[u64; 512]>::new_zeroed(
+            zeroed_key,
+            IpcMode::owner_rw(),
+        )?;
+        s.register(memory.identity());
+        assert!(memory.iter().all(|&word| word == 0));
+        Ok(())
+    })
+}
+
+#[test]
+fn shm_create_unique() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+
+    scope(|s| {
+        let (key, memory) =
+            SharedMemory::<u64>::create_unique(IpcMode::owner_rw())?;
+        s.register(memory.identity());
+        assert_ne!(key, Key::IPC_PRIVATE);
+        assert_eq!(memory.identity().key, key);
+        let _memory = memory.write(42);
+
+        let (other, memory) =
+            SharedMemory::<u64>::create_unique(IpcMode::owner_rw())?;
+        s.register(memory.identity());
+        assert_ne!(key, other);
+
+        let peer =
+            SharedMemory::<u64>::attach_uninit(key, IpcMode::owner_rw())?;
+        assert_eq!(*unsafe { peer.assume_init() }, 42);
+        Ok(())
+    })
+}
+
+#[test]
+fn shm_attach_uninit() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let expected = Errno::ENOENT;
+    let actual =
+        SharedMemory::<TestData>::attach_uninit(key, IpcMode::owner_rw())
+            .expect_err("Segment does not exist yet");
+    assert_eq!(expected, actual);
+
+    scope(|s| {
+        let memory = SharedMemory::new_init(
+            key,
+            IpcMode::owner_rw(),
+            TestData { data: 7 },
+        )?;
+        s.register(memory.identity());
+
+        let joined =
+            SharedMemory::<TestData>::attach_uninit(key, IpcMode::owner_rw())?;
+        let joined = unsafe { joined.assume_init() };
+        assert_eq!(joined.data, 7);
+
+        let expected = SysvError::SizeMismatch {
+            operation: Operation::Shmget,
+            expected: 2 * std::mem::size_of::<TestData>(),
+            actual: std::mem::size_of::<TestData>(),
+        };
+        let actual = SharedMemory::<[TestData; 2]>::attach_uninit(
+            key,
+            IpcMode::owner_rw(),
+        )
+        .expect_err("Segment is too small");
+        assert_eq!(expected, actual);
+        assert_eq!(Errno::EINVAL, actual);
+        Ok(())
+    })
+}
+
+#[test]
+fn shm_error_kinds() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let shm = Shm::<TestData>::create_and_connect(key, IpcMode::owner_rw())?;
+    shm.remove()?;
+    let actual = shm
+        .attach(AttachAddress::Any, ShmatFlag::empty())
+        .expect_err("Segment was removed");
+    let expected = SysvError::SegmentRemoved {
+        operation: Operation::Shmat,
+        errno: Errno::EINVAL,
+    };
+    assert_eq!(expected, actual);
+    assert_eq!(Errno::from(actual), Errno::EINVAL);
+    assert_eq!(
+        actual.to_string(),
+        "shmat: the resource was removed (EINVAL: Invalid argument)"
+    );
+
+    let actual = Shm::<TestData>::create_and_connect(key, IpcMode::owner_rw())
+        .and_then(|shm| {
+            let res = shm.attach(AttachAddress::Any, ShmatFlag::SHM_RDONLY);
+            shm.remove().map_err(|errno| SysvError::Other {
+                operation: Operation::Shmctl,
+                errno,
+            })?;
+            res
+        })
+        .expect_err("SHM_RDONLY is rejected");
+    assert_eq!(actual.operation(), Operation::Shmat);
+    assert!(matches!(actual, SysvError::Other { .. }));
+
+    let unsupported = SysvError::Unsupported {
+        operation: Operation::Shmget,
+    };
+    assert_eq!(unsupported.errno(), Errno::ENOSYS);
+    assert_eq!(
+        unsupported.to_string(),
+        "shmget: SystemV IPC is not supported"
+    );
+    Ok(())
+}
+
+#[test]
+fn shm_builder() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let expected = Errno::ENOENT;
+    let actual = SharedMemory::<TestData>::builder()
+        .key(key)
+        .attach()
+        .expect_err("Segment does not exist yet");
+    assert_eq!(expected, actual);
+
+    scope(|s| {
+        let builder = SharedMemory::<TestData>::builder()
+            .key(key)
+            .mode(IpcMode::group_rw());
+        let memory = builder.create_new().attach()?.write(TestData { data: 3 });
+        s.register(memory.identity());
+        assert_eq!(memory.stat()?.mode, IpcMode::group_rw());
+
+        let expected = Errno::EEXIST;
+        let actual = builder
+            .create_new()
+            .attach()
+            .expect_err("Segment already exists");
+        assert_eq!(expected, actual);
+
+        let other = builder.open_or_create().attach()?;
+        assert_eq!(unsafe { other.assume_init() }.data, 3);
+        let read_only = builder.attach_ro()?;
+        assert_eq!(unsafe { read_only.assume_init() }.data, 3);
+        Ok(())
+    })
+}
+
+#[test]
+fn shm_owned_segment() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let result = std::panic::catch_unwind(|| {
+        let memory = SharedMemory::new_init(
+            key,
+            IpcMode::owner_rw(),
+            TestData::default(),
+        )
+        .unwrap()
+        .into_owner();
+        assert_eq!(memory.stat().unwrap().attachments, 1);
+        panic!("Segment is still removed");
+    });
+    assert!(result.is_err());
+
+    let expected = Errno::ENOENT;
+    let actual =
+        SharedMemory::<TestData>::attach_uninit(key, IpcMode::owner_rw())
+            .expect_err("Segment has been removed");
+    assert_eq!(expected, actual);
+
+    let memory =
+        SharedMemory::new_init(key, IpcMode::owner_rw(), TestData::default())?
+            .into_owner()
+            .into_inner();
+    drop(memory);
+    let memory =
+        SharedMemory::<TestData>::attach_uninit(key, IpcMode::owner_rw())?;
+    let mut memory = unsafe { memory.assume_init() }.into_owner();
+    memory.data = 1;
+    assert_eq!(memory.data, 1);
+    Ok(())
+}
+
+#[test]
+fn shm_control() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let shm = Shm::<TestData>::create_and_connect(key, IpcMode::owner_rw())?;
+    let stat = shm.stat()?;
+    shm.set_permissions(stat.uid, stat.gid, IpcMode::group_ro())?;
+    assert_eq!(shm.stat()?.mode, IpcMode::group_ro());
+
+    #[cfg(target_os = "linux")]
+    {
+        shm.lock()?;
+        shm.unlock()?;
+    }
+
+    shm.remove()?;
+    let expected = Errno::ENOENT;
+    let actual =
+        SharedMemory::<TestData>::attach_uninit(key, IpcMode::owner_rw())
+            .expect_err("Segment has been removed");
+    assert_eq!(expected, actual);
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn shm_lock() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let memory =
+        SharedMemory::<[u64; 4096]>::new_zeroed(key, IpcMode::owner_rw())?
+            .into_owner();
+    memory.lock()?;
+    memory.prefault()?;
+    memory.unlock()?;
+    Ok(())
+}
+
+#[test]
+fn shm_set_owner_and_mode() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let memory =
+        SharedMemory::new_init(key, IpcMode::owner_rw(), TestData::default())?
+            .into_owner();
+    let stat = memory.stat()?;
+    memory.set_mode(IpcMode::group_ro())?;
+    let updated = memory.stat()?;
+    assert_eq!(updated.mode, IpcMode::group_ro());
+    assert_eq!((updated.uid, updated.gid), (stat.uid, stat.gid));
+
+    memory.set_owner(stat.uid, stat.gid)?;
+    assert_eq!(memory.stat()?.mode, IpcMode::group_ro());
+    Ok(())
+}
+
+#[test]
+fn shm_remove_if_last_detach() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
 
+    let builder = SharedMemory::<TestData>::builder()
+        .key(key)
+        .mode(IpcMode::owner_rw())
+        .open_or_create();
+    let first = builder.attach()?;
+    let second = builder.attach()?;
+    assert_eq!(first.nattch()?, 2);
+    assert!(!first.remove_if_last_detach()?);
+    drop(first);
+
+    assert_eq!(second.nattch()?, 1);
+    assert!(second.remove_if_last_detach()?);
+    drop(second);
+    let expected = Errno::ENOENT;
+    let actual =
+        SharedMemory::<TestData>::attach_uninit(key, IpcMode::owner_rw())
+            .expect_err("Segment has been removed");
     assert_eq!(expected, actual);
     Ok(())
 }
+
+#[test]
+#[cfg(target_os = "linux")]
+fn shm_list_segments() -> Result<()> {
+    use nix::sys::system_v::info;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let memory =
+        SharedMemory::new_init(key, IpcMode::owner_rw(), TestData::default())?
+            .into_owner();
+    let id = memory.identity().id;
+    let segment = info::shared_memory_segments()?
+        .into_iter()
+        .find(|segment| segment.id == id)
+        .expect("Segment is listed");
+    assert_eq!(segment.key, key);
+    assert_eq!(segment.stat.size, std::mem::size_of::<TestData>());
+    assert_eq!(segment.stat.mode, IpcMode::owner_rw());
+    assert_eq!(segment.stat.attachments, 1);
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn ipc_snapshot() -> Result<()> {
+    use nix::sys::system_v::info;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+    let uid = nix::unistd::geteuid();
+
+    scope(|s| {
+        let shm = s.shared_memory::<TestData>(key, IpcMode::owner_rw())?;
+        let _memory = shm.attach(AttachAddress::Any, ShmatFlag::empty())?;
+        #[cfg(feature = "sysvipc_msg")]
+        let queue = {
+            let queue = s.message_queue(key, IpcMode::owner_rw())?;
+            queue.msgsnd(
+                1,
+                b"depth",
+                nix::sys::system_v::msg::MsgsndFlag::empty(),
+            )?;
+            queue
+        };
+        #[cfg(feature = "sysvipc_sem")]
+        let set = {
+            let set = s.semaphore_set(key, 2, IpcMode::owner_rw())?;
+            set.set_values(&[3, 4])?;
+            set
+        };
+
+        let snapshot = info::snapshot()?;
+        let size = std::mem::size_of::<TestData>();
+        assert!(snapshot.segments >= 1);
+        assert!(snapshot.segment_bytes >= size);
+        assert!(snapshot.attachments >= 1);
+        let usage = snapshot
+            .users
+            .iter()
+            .find(|usage| usage.uid == uid)
+            .expect("The user owns resources");
+        assert!(usage.segments >= 1 && usage.segment_bytes >= size);
+        #[cfg(feature = "sysvipc_msg")]
+        {
+            let depth = snapshot
+                .queues
+                .iter()
+                .find(|depth| depth.id == queue.identity().id)
+                .expect("Queue is listed");
+            assert_eq!((depth.key, depth.messages, depth.bytes), (key, 1, 5));
+            assert!(usage.queues >= 1);
+        }
+        #[cfg(feature = "sysvipc_sem")]
+        {
+            let values = snapshot
+                .semaphore_sets
+                .iter()
+                .find(|values| values.id == set.identity().id)
+                .expect("Semaphore set is listed");
+            assert_eq!(values.values.as_deref(), Some(&[3, 4][..]));
+            assert!(usage.semaphore_sets >= 1);
+        }
+
+        let metrics = snapshot.to_prometheus();
+        assert!(metrics.contains("# TYPE sysv_shm_segments gauge\n"));
+        assert!(metrics.contains(&format!(
+            "sysv_user_shm_segments{{uid=\"{uid}\"}} {}\n",
+            usage.segments
+        )));
+        #[cfg(feature = "sysvipc_sem")]
+        assert!(metrics.contains(&format!(
+            "sysv_sem_value{{key=\"{key}\",id=\"{}\",num=\"1\"}} 4\n",
+            set.identity().id
+        )));
+        assert_eq!(
+            metrics.contains("sysv_user_msg_queues"),
+            cfg!(feature = "sysvipc_msg")
+        );
+        assert_eq!(
+            metrics.contains("sysv_user_sem_sets"),
+            cfg!(feature = "sysvipc_sem")
+        );
+        Ok(())
+    })
+}
+
+#[test]
+fn shm_remove_segment() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let shm = Shm::<TestData>::create_and_connect(key, IpcMode::owner_rw())?;
+    remove_segment_by_key(key)?;
+    assert_eq!(shm.stat(), Err(Errno::EINVAL));
+    assert_eq!(remove_segment_by_key(key), Err(Errno::ENOENT));
+    assert_eq!(remove_segment_by_key(Key::IPC_PRIVATE), Err(Errno::EINVAL));
+
+    let shm = Shm::<TestData>::create_and_connect(key, IpcMode::owner_rw())?;
+    remove_segment_by_id(shm.id())?;
+    assert_eq!(remove_segment_by_id(shm.id()), Err(Errno::EINVAL));
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn shm_limits() -> Result<()> {
+    use nix::sys::system_v::info;
+
+    let limits = info::shared_memory_limits()?;
+    assert!(limits.min_size <= limits.max_size);
+    assert!(limits.allows_size(std::mem::size_of::<TestData>()));
+    let shmmni = std::fs::read_to_string("/proc/sys/kernel/shmmni").unwrap();
+    assert_eq!(limits.max_segments, shmmni.trim().parse().unwrap());
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn huge_page_size_flags() {
+    let huge = ShmgetFlag::SHM_HUGETLB.bits();
+    assert_eq!(HugePageSize::SIZE_2MB.flags().bits(), huge | 21 << 26);
+    assert_eq!(HugePageSize::SIZE_1GB.flags().bits(), huge | 30 << 26);
+    let custom = HugePageSize::new(16).unwrap();
+    assert_eq!(custom.flags().bits(), huge | 16 << 26);
+    assert_eq!(HugePageSize::SIZE_2MB.size(), 2 * 1024 * 1024);
+    assert_eq!(HugePageSize::SIZE_1GB.shift(), 30);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn huge_page_size_rejects_large_shifts() {
+    let max = (usize::BITS - 1) as u8;
+    assert_eq!(HugePageSize::new(max).unwrap().size(), 1 << max);
+    assert_eq!(HugePageSize::new(max + 1), Err(Errno::EINVAL));
+    assert_eq!(HugePageSize::new(64), Err(Errno::EINVAL));
+    assert_eq!(HugePageSize::new(u8::MAX), Err(Errno::EINVAL));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn shm_attach_exec() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let shm = Shm::<[u8; 64]>::create_and_connect(key, Mode::S_IRWXU)?;
+    let mut code = shm
+        .attach(AttachAddress::Any, ShmatFlag::empty())?
+        .write([0; 64])
+        .into_owner();
+    // x86_64: mov eax, 42; ret
+    code[..6].copy_from_slice(&[0xb8, 0x2a, 0x00, 0x00, 0x00, 0xc3]);
+
+    let exec = shm.attach_exec(AttachAddress::Any, ShmatFlag::empty())?;
+    let exec = unsafe { exec.assume_init() };
+    let page_size = unsafe { nix::libc::sysconf(nix::libc::_SC_PAGESIZE) };
+    assert_eq!(exec.len(), page_size as usize);
+    assert_eq!(exec[5], 0xc3);
+    let info = mapping_info(&exec)?;
+    assert!(info.readable && info.executable && !info.writable);
+    #[cfg(target_arch = "x86_64")]
+    {
+        let f: extern "C" fn() -> i32 =
+            unsafe { std::mem::transmute(exec.as_ptr()) };
+        assert_eq!(f(), 42);
+    }
+    Ok(())
+}
+
+#[test]
+fn shm_detach() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let memory =
+        SharedMemory::new_init(key, IpcMode::owner_rw(), TestData::default())?;
+    let other = memory.try_clone()?;
+    assert_eq!(other.nattch()?, 2);
+    memory.detach()?;
+    assert_eq!(other.nattch()?, 1);
+    assert!(other.remove_if_last_detach()?);
+    other.detach()?;
+    Ok(())
+}
+
+#[test]
+fn shm_raw_parts() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let memory =
+        SharedMemory::new_init(key, IpcMode::owner_rw(), TestData { data: 7 })?;
+    let address = SharedMemory::as_ptr(&memory);
+    let (id, ptr) = SharedMemory::into_raw(memory);
+    assert_eq!(ptr.cast_const(), address);
+    assert_eq!(unsafe { (*ptr).data }, 7);
+
+    let shm = unsafe { Shm::<TestData>::from_raw(Key::IPC_PRIVATE, id) };
+    assert_eq!(shm.id(), id);
+    assert_eq!(shm.nattch()?, 1);
+
+    let memory = unsafe { SharedMemory::from_raw_parts(key, id, ptr) };
+    assert_eq!(memory.id(), id);
+    assert_eq!(memory.identity().key, key);
+    assert_eq!(memory.data, 7);
+    drop(memory.into_owner());
+    assert_eq!(shm.stat(), Err(Errno::EINVAL));
+    Ok(())
+}
+
+#[test]
+fn shm_from_id() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let shm = Shm::<u32>::create_and_connect(key, IpcMode::owner_rw())?;
+    let larger = Shm::<u64>::from_id(key, shm.id())
+        .expect_err("The segment is smaller than a u64");
+    let expected = SysvError::SizeMismatch {
+        operation: Operation::Shmctl,
+        expected: 8,
+        actual: 4,
+    };
+    assert_eq!(expected, larger);
+
+    // Over-allocated segments are accepted
+    let smaller = Shm::<u16>::from_id(key, shm.id())?;
+    assert_eq!(smaller.id(), shm.id());
+    let memory = smaller
+        .attach(AttachAddress::Any, ShmatFlag::empty())?
+        .write(7);
+    assert_eq!(*memory, 7);
+    drop(memory);
+
+    shm.remove()?;
+    let actual =
+        Shm::<u32>::from_id(key, shm.id()).expect_err("Segment was removed");
+    assert!(matches!(actual, SysvError::SegmentRemoved { .. }));
+    Ok(())
+}
+
+#[test]
+fn shm_atomic_view() -> Result<()> {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let mut buffer = SharedMemoryBuffer::shmget(
+        key,
+        64,
+        ShmgetFlag::IPC_CREAT | ShmgetFlag::IPC_EXCL,
+        IpcMode::owner_rw(),
+    )?
+    .into_owner();
+    let mut other = buffer.try_clone()?;
+    let counters = buffer.atomic_view::<[AtomicU64; 8]>()?;
+    std::thread::scope(|s| {
+        for counter in counters {
+            s.spawn(|| counter.fetch_add(1, Ordering::Relaxed));
+        }
+    });
+    let seen = other.atomic_view::<[AtomicU64; 8]>()?;
+    assert!(seen.iter().all(|c| c.load(Ordering::Relaxed) == 1));
+
+    let expected = Errno::EINVAL;
+    let actual = other
+        .atomic_view::<[AtomicU64; 9]>()
+        .expect_err("Segment is too small");
+    assert_eq!(expected, actual);
+    Ok(())
+}
+
+#[test]
+fn shm_volatile() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let mut memory =
+        SharedMemory::new_init(key, IpcMode::owner_rw(), 0u64)?.into_owner();
+    let other = memory.try_clone()?;
+    memory.write_volatile(42);
+    assert_eq!(other.read_volatile(), 42);
+    Ok(())
+}
+
+#[test]
+fn shm_as_bytes() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let buffer = SharedMemoryBuffer::shmget(
+            key,
+            64,
+            ShmgetFlag::IPC_CREAT | ShmgetFlag::IPC_EXCL,
+            IpcMode::owner_rw(),
+        )?;
+        s.register(buffer.identity());
+
+        let memory =
+            SharedMemory::<u32>::attach_uninit(key, IpcMode::owner_rw())?;
+        let mut memory = unsafe { memory.assume_init() };
+        // The view covers the segment, not only the `u32`
+        assert_eq!(memory.as_bytes()?.len(), 64);
+        memory.as_bytes_mut()?[..4].copy_from_slice(&7u32.to_ne_bytes());
+        memory.as_bytes_mut()?[63] = 42;
+        assert_eq!(*memory, 7);
+        assert_eq!(buffer[63], 42);
+
+        let memory = SharedMemory::<u32>::builder().key(key).attach_ro()?;
+        let memory = unsafe { memory.assume_init() };
+        assert_eq!(memory.as_bytes()?[63], 42);
+        Ok(())
+    })
+}
+
+#[test]
+fn shm_shmget_with_size() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let flags = ShmgetFlag::IPC_CREAT | ShmgetFlag::IPC_EXCL;
+        let actual = unsafe {
+            Shm::<u64>::shmget_with_size(key, 4, flags, IpcMode::owner_rw())
+        }
+        .expect_err("The size is smaller than a u64");
+        let expected = SysvError::SizeMismatch {
+            operation: Operation::Shmget,
+            expected: 8,
+            actual: 4,
+        };
+        assert_eq!(expected, actual);
+
+        let shm = unsafe {
+            Shm::<u64>::shmget_with_size(key, 256, flags, IpcMode::owner_rw())
+        }?;
+        s.register(shm.identity());
+        assert_eq!(shm.size()?, 256);
+
+        let mut memory =
+            shm.attach(AttachAddress::Any, ShmatFlag::empty())?.write(7);
+        assert_eq!(memory.segment_size()?, 256);
+        memory.as_bytes_mut()?[255] = 42;
+        let read_only =
+            shm.attach_ro(AttachAddress::Any, ShmatFlag::empty())?;
+        let read_only = unsafe { read_only.assume_init() };
+        assert_eq!(read_only.segment_size()?, 256);
+        assert_eq!(read_only.as_bytes()?[255], 42);
+
+        // The existing segment is smaller than requested
+        let actual = unsafe {
+            Shm::<u64>::shmget_with_size(
+                key,
+                512,
+                ShmgetFlag::empty(),
+                IpcMode::owner_rw(),
+            )
+        }
+        .expect_err("The segment is smaller than 512 bytes");
+        assert!(matches!(
+            actual,
+            SysvError::SizeMismatch {
+                expected: 512,
+                actual: 256,
+                ..
+            }
+        ));
+        Ok(())
+    })
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn shm_protect() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let memory = SharedMemory::new_init(
+            key,
+            IpcMode::owner_rw(),
+            TestData { data: 0 },
+        )?;
+        s.register(memory.identity());
+        let mut memory = memory.protect(ReadWrite)?;
+        assert!(mapping_info(&memory)?.writable);
+        memory.data = 5;
+
+        let memory = memory.protect(ReadOnly)?;
+        let info = mapping_info(&memory)?;
+        assert!(info.readable && !info.writable);
+        assert_eq!(memory.data, 5);
+        // Clones keep the protection
+        assert!(!mapping_info(&memory.try_clone()?)?.writable);
+        Ok(())
+    })
+}
+
+#[test]
+fn shm_advise() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    scope(|s| {
+        let mut memory =
+            SharedMemory::<[u64; 1024]>::new_zeroed(key, IpcMode::owner_rw())?;
+        s.register(memory.identity());
+        memory[1023] = 7;
+        memory.advise(Advice::Sequential)?;
+        memory.advise(Advice::DontNeed)?;
+        #[cfg(target_os = "linux")]
+        memory.advise(Advice::DontDump)?;
+        // The content survives the hints
+        assert_eq!(memory[1023], 7);
+
+        let read_only = memory.protect(ReadOnly)?;
+        read_only.advise(Advice::Normal)?;
+        Ok(())
+    })
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn shm_tracing() -> Result<()> {
+    use std::sync::{Arc, Mutex};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata};
+
+    /// Records the name of every span.
+    #[derive(Default)]
+    struct Spans(Arc<Mutex<Vec<&'static str>>>);
+
+    impl tracing::Subscriber for Spans {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut spans = self.0.lock().unwrap();
+            spans.push(span.metadata().name());
+            Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, _: &Event<'_>) {}
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let spans = Spans::default();
+    let names = Arc::clone(&spans.0);
+    tracing::subscriber::with_default(spans, || {
+        scope(|s| {
+            let shm = s.shared_memory::<TestData>(key, IpcMode::owner_rw())?;
+            shm.attach(AttachAddress::Any, ShmatFlag::empty())?;
+            // The failure is reported without clobbering errno
+            let actual =
+                Shm::<TestData>::create_and_connect(key, IpcMode::owner_rw())
+                    .expect_err("Segment already exists");
+            assert_eq!(Errno::EEXIST, actual);
+            Ok(())
+        })
+    })?;
+    let names = names.lock().unwrap();
+    for name in ["shmget", "shmat", "shmdt", "shmctl"] {
+        assert!(names.contains(&name), "{name} is missing from {names:?}");
+    }
+    Ok(())
+}
+
+#[test]
+fn attach_prefix_of_newer_version() -> Result<()> {
+    #[repr(C)]
+    struct V1 {
+        a: u32,
+        b: u64,
+    }
+    unsafe impl SysvSafe for V1 {}
+
+    #[repr(C)]
+    struct V2 {
+        a: u32,
+        b: u64,
+        c: u16,
+    }
+    unsafe impl SysvSafe for V2 {}
+
+    #[repr(C)]
+    struct V3 {
+        a: u32,
+        b: u64,
+        c: u16,
+        d: [u64; 4],
+    }
+    unsafe impl SysvSafe for V3 {}
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let value = Versioned::new(2, V2 { a: 1, b: 2, c: 3 });
+    let memory = SharedMemory::new_init(key, IpcMode::owner_rw(), value)?;
+    let segment = memory.segment();
+    let res = (|| {
+        let prefix = unsafe { attach_prefix::<V1>(key) }?;
+        assert_eq!(2, prefix.version());
+        assert_eq!(std::mem::size_of::<V2>(), prefix.size());
+        assert_eq!((1, 2), (prefix.a, prefix.b));
+        assert_eq!(3, memory.c);
+
+        let newer = unsafe { attach_prefix::<V3>(key) };
+        assert!(matches!(newer, Err(SysvError::SizeMismatch { .. })));
+        Ok(())
+    })();
+    drop(memory);
+    segment.remove()?;
+    res
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn testing_unique_key() -> Result<()> {
+    use nix::sys::system_v::testing;
+
+    let _m = SYSTEMV_MTX.lock();
+    let key = testing::unique_key()?;
+    assert_ne!(Key::IPC_PRIVATE, key);
+
+    let shm = Shm::<TestData>::create_and_connect(key, IpcMode::owner_rw())?;
+    let res = testing::unique_key().map(|other| assert_ne!(key, other));
+    shm.remove()?;
+    res
+}
@@ -0,0 +1,370 @@
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::sys::system_v::sync::*;
+use nix::sys::system_v::IpcMode;
+use nix::Result;
+
+use crate::common::unique_key;
+use crate::SYSTEMV_MTX;
+
+#[test]
+fn semaphore_mutex() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let mutex = Mutex::create(key, IpcMode::owner_rw(), 0u64)?;
+    let other = unsafe { Mutex::<u64>::open(key) }?;
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..100 {
+                    **other.lock() += 1;
+                }
+            });
+        }
+        for _ in 0..100 {
+            **mutex.lock() += 1;
+        }
+    });
+    assert_eq!(**mutex.lock(), 500);
+
+    let guard = mutex.lock();
+    assert!(other.try_lock().is_none());
+    drop(guard);
+    assert!(other.try_lock().is_some());
+
+    let expected = Errno::EEXIST;
+    let actual = Mutex::create(key, IpcMode::owner_rw(), 0u64)
+        .expect_err("Mutex already exists");
+    assert_eq!(expected, actual);
+
+    mutex.remove()?;
+    let expected = Errno::ENOENT;
+    let actual =
+        unsafe { Mutex::<u64>::open(key) }.expect_err("Mutex has been removed");
+    assert_eq!(expected, actual);
+    Ok(())
+}
+
+#[test]
+fn raw_semaphore_mutex() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let raw = RawSemaphoreMutex::create(key, IpcMode::owner_rw())?;
+    let lock = lock_api::Mutex::from_raw(raw, 0);
+    *lock.lock() += 1;
+    assert!(!lock.is_locked());
+
+    let other = RawSemaphoreMutex::open(key)?;
+    assert!(other.try_acquire()?);
+    assert!(lock.try_lock().is_none());
+    assert!(lock.try_lock_for(Duration::from_millis(10)).is_none());
+    assert!(!other.acquire_timeout(Duration::ZERO)?);
+    other.release()?;
+    assert!(other.acquire_timeout(Duration::from_secs(5))?);
+    other.release()?;
+    assert_eq!(*lock.lock(), 1);
+    other.remove()?;
+    Ok(())
+}
+
+#[test]
+fn semaphore_mutex_owner_died() -> Result<()> {
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{fork, ForkResult};
+
+    let _m = SYSTEMV_MTX.lock();
+    let _f = crate::FORK_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let mutex = Mutex::create(key, IpcMode::owner_rw(), [0u64; 2])?;
+    **mutex.lock_robust().expect("The mutex is consistent") = [1, 1];
+    // Safe: the child only calls `semop` and `_exit`, which are
+    // async-signal-safe, and writes to the segment.
+    match unsafe { fork() }? {
+        ForkResult::Child => {
+            let mut guard = mutex.lock();
+            guard[0] = 2;
+            // Exit while holding the lock, halfway through the update
+            std::mem::forget(guard);
+            unsafe { libc::_exit(0) }
+        }
+        ForkResult::Parent { child } => {
+            let status = waitpid(child, None)?;
+            assert_eq!(WaitStatus::Exited(child, 0), status);
+        }
+    }
+
+    // Reported until recovered
+    let Err(LockError::OwnerDied(died)) = mutex.lock_robust() else {
+        panic!("The child died");
+    };
+    assert_eq!(**died.into_inner(), [2, 1]);
+    let Err(LockError::OwnerDied(died)) = mutex.lock_robust() else {
+        panic!("The mutex was not recovered");
+    };
+    let mut guard = died.recover()?;
+    guard[1] = guard[0];
+    drop(guard);
+    assert_eq!(
+        **mutex.lock_robust().expect("The mutex was recovered"),
+        [2, 2]
+    );
+
+    let raw = RawSemaphoreMutex::open(key)?;
+    assert_eq!(raw.release(), Err(Errno::EAGAIN));
+    mutex.remove()?;
+    let Err(LockError::Errno(errno)) = mutex.lock_robust() else {
+        panic!("The set was removed");
+    };
+    assert_eq!(errno, Errno::EINVAL);
+    Ok(())
+}
+
+#[test]
+fn semaphore_rwlock() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let lock = RwLock::create(key, IpcMode::owner_rw(), [0u32; 2])?;
+    let other = unsafe { RwLock::<[u32; 2]>::open(key) }?;
+    std::thread::scope(|s| {
+        for _ in 0..2 {
+            s.spawn(|| {
+                for _ in 0..100 {
+                    let mut guard = other.write();
+                    guard[0] += 1;
+                    guard[1] += 1;
+                }
+            });
+        }
+        for _ in 0..100 {
+            let guard = lock.read();
+            assert_eq!(guard[0], guard[1]);
+        }
+    });
+    assert_eq!(**lock.read(), [200, 200]);
+
+    let first = lock.read();
+    let second = other.read();
+    assert!(other.try_write().is_none());
+    drop((first, second));
+    let guard = other.write();
+    assert!(lock.try_read().is_none());
+    assert!(lock.try_write().is_none());
+    drop(guard);
+    assert!(lock.try_write().is_some());
+
+    lock.remove()?;
+    let expected = Errno::ENOENT;
+    let actual = unsafe { RwLock::<[u32; 2]>::open(key) }
+        .expect_err("RwLock has been removed");
+    assert_eq!(expected, actual);
+    Ok(())
+}
+
+#[test]
+fn raw_semaphore_rwlock() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let raw = RawSemaphoreRwLock::create(key, IpcMode::owner_rw())?;
+    let other = RawSemaphoreRwLock::open(key)?;
+    assert!(raw.try_acquire_shared()?);
+    assert!(other.try_acquire_shared()?);
+    assert!(!other.try_acquire_exclusive()?);
+    raw.release_shared()?;
+    other.release_shared()?;
+
+    other.acquire_exclusive()?;
+    assert!(!raw.try_acquire_shared()?);
+    other.release_exclusive()?;
+    assert!(raw.try_acquire_exclusive()?);
+    raw.release_exclusive()?;
+    raw.remove()?;
+    Ok(())
+}
+
+#[test]
+fn semaphore_barrier() -> Result<()> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    const N: usize = 4;
+    const ROUNDS: usize = 10;
+    let arrived = AtomicUsize::new(0);
+    let leaders = AtomicUsize::new(0);
+    let barrier = Barrier::new(key, N)?;
+    std::thread::scope(|s| {
+        for _ in 0..N {
+            s.spawn(|| {
+                let barrier = Barrier::new(key, N).unwrap();
+                for round in 1..=ROUNDS {
+                    arrived.fetch_add(1, Ordering::SeqCst);
+                    if barrier.wait().unwrap().is_leader() {
+                        leaders.fetch_add(1, Ordering::SeqCst);
+                    }
+                    assert!(arrived.load(Ordering::SeqCst) >= round * N);
+                    barrier.wait().unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(ROUNDS, leaders.load(Ordering::SeqCst));
+    barrier.remove()?;
+
+    let barrier = Barrier::new(key, 1)?;
+    assert!(barrier.wait()?.is_leader());
+    assert!(barrier.wait()?.is_leader());
+    barrier.remove()?;
+    let expected = Errno::EINVAL;
+    let actual =
+        Barrier::new(key, 1 << 16).expect_err("Too many processes to wait for");
+    assert_eq!(expected, actual);
+    Ok(())
+}
+
+#[test]
+fn semaphore_event() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let event = Event::new(key)?;
+    assert!(!event.is_set()?);
+    assert!(!event.wait_timeout(Duration::from_millis(10))?);
+    std::thread::scope(|s| {
+        let waiters: Vec<_> = (0..4)
+            .map(|_| s.spawn(|| Event::new(key).and_then(|e| e.wait())))
+            .collect();
+        std::thread::sleep(Duration::from_millis(20));
+        event.set()?;
+        for waiter in waiters {
+            waiter.join().unwrap()?;
+        }
+        Ok::<_, Errno>(())
+    })?;
+
+    // The event stays set until cleared
+    assert!(event.is_set()?);
+    event.wait()?;
+    assert!(event.wait_timeout(Duration::ZERO)?);
+    event.clear()?;
+    assert!(!event.is_set()?);
+    assert!(!event.wait_timeout(Duration::ZERO)?);
+
+    event.remove()?;
+    assert_eq!(event.wait(), Err(Errno::EINVAL));
+    Ok(())
+}
+
+#[test]
+fn seq_lock() -> Result<()> {
+    use nix::sys::system_v::shm::SharedMemory;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let lock = SharedMemory::new_init(
+        key,
+        IpcMode::owner_rw(),
+        SeqLock::new([0u64; 4]),
+    )?;
+    let other = SharedMemory::<SeqLock<[u64; 4]>>::attach_uninit(
+        key,
+        IpcMode::owner_rw(),
+    )?;
+    let other = unsafe { other.assume_init() };
+    lock.segment().remove()?;
+    assert_eq!(other.try_read(), Some([0; 4]));
+
+    // Readers never see a value halfway written
+    const WRITES: u64 = 10_000;
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| loop {
+                let value = other.read();
+                assert!(value.iter().all(|&x| x == value[0]));
+                if value[0] == WRITES {
+                    break;
+                }
+            });
+        }
+        for i in 1..=WRITES {
+            lock.write([i; 4]);
+        }
+    });
+    assert_eq!(lock.read(), [WRITES; 4]);
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn priority_mutex() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let mutex = ShmPriorityMutex::create(key, IpcMode::owner_rw(), 0u64)?;
+    let other = unsafe { ShmPriorityMutex::<u64>::open(key) }?;
+    mutex.remove()?;
+
+    {
+        let mut guard = mutex.lock()?;
+        *guard = 0xDEADBEEF;
+        let expected = Errno::EBUSY;
+        let actual = other.try_lock().expect_err("Mutex is already locked");
+        assert_eq!(expected, actual);
+    }
+    assert_eq!(0xDEADBEEF, *other.try_lock()?);
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn priority_mutex_open_uninitialized() -> Result<()> {
+    use nix::sys::system_v::shm::Shm;
+
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    // A zeroed segment, as seen by `open` before `create` initialized it
+    let shm = Shm::<[u8; 4096]>::create_and_connect(key, IpcMode::owner_rw())?;
+    let expected = Errno::EAGAIN;
+    let actual = unsafe { ShmPriorityMutex::<u64>::open(key) }
+        .expect_err("The mutex is not initialized");
+    assert_eq!(expected, actual);
+    shm.remove()?;
+    Ok(())
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn priority_mutex_snapshot() -> Result<()> {
+    let _m = SYSTEMV_MTX.lock();
+    let (_file, key) = unique_key();
+
+    let mutex = ShmPriorityMutex::create(key, IpcMode::owner_rw(), 1u64)?;
+    let reader = unsafe { ShmPriorityMutex::<u64>::open(key) }?;
+    mutex.remove()?;
+
+    let timeout = Duration::from_millis(10);
+    {
+        let _guard = mutex.lock()?;
+        let expected = Errno::ETIMEDOUT;
+        let actual = reader
+            .try_read_snapshot(timeout)
+            .expect_err("No value was ever read");
+        assert_eq!(expected, actual);
+    }
+    assert_eq!(Snapshot::Fresh(1), reader.try_read_snapshot(timeout)?);
+
+    let mut guard = mutex.lock()?;
+    *guard = 2;
+    assert_eq!(Snapshot::Stale(1), reader.try_read_snapshot(timeout)?);
+    drop(guard);
+    assert_eq!(Snapshot::Fresh(2), reader.try_read_snapshot(timeout)?);
+    Ok(())
+}
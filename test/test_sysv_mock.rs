@@ -0,0 +1,81 @@
+// Installing the mock affects the whole process, so these tests have their
+// own binary.
+#![cfg(all(feature = "mock", feature = "sysvipc"))]
+
+use nix::errno::Errno;
+use nix::sys::system_v::shm::*;
+use nix::sys::system_v::{mock, scope, IpcMode, Key};
+use nix::Result;
+
+#[test]
+fn mock_shm() -> Result<()> {
+    mock::install();
+    assert!(mock::is_installed());
+    assert!(nix::sys::system_v::is_supported());
+
+    scope(|s| {
+        let key = Key::new(1);
+        let shm = s.shared_memory::<u64>(key, IpcMode::owner_rw())?;
+        let mut memory =
+            shm.attach(AttachAddress::Any, ShmatFlag::empty())?.write(0);
+        let other = shm.attach_ro(AttachAddress::Any, ShmatFlag::empty())?;
+        let other = unsafe { other.assume_init() };
+        *memory = 0xDEADBEEF;
+        assert_eq!(*other, 0xDEADBEEF);
+        assert_eq!(shm.stat()?.attachments, 2);
+
+        let actual = Shm::<u64>::create_and_connect(key, IpcMode::owner_rw())
+            .expect_err("Segment already exists");
+        assert_eq!(Errno::EEXIST, actual);
+        Ok(())
+    })
+}
+
+#[cfg(any(apple_targets, target_os = "android", target_os = "linux"))]
+#[test]
+fn mock_sem() -> Result<()> {
+    use nix::sys::system_v::sem::*;
+    use std::time::Duration;
+
+    mock::install();
+    scope(|s| {
+        let set = s.semaphore_set(Key::new(2), 1, IpcMode::owner_rw())?;
+        set.semctl(0, SemctlArg::SETVAL(1))?;
+        let guard = set.acquire(0)?;
+        assert_eq!(set.semctl(0, SemctlArg::GETVAL)?, 0);
+        assert!(!set.wait_timeout(0, Duration::from_millis(10))?);
+        drop(guard);
+        assert_eq!(set.semctl(0, SemctlArg::GETVAL)?, 1);
+        Ok(())
+    })
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+#[test]
+fn mock_msg() -> Result<()> {
+    use nix::sys::system_v::msg::*;
+    use std::thread;
+
+    mock::install();
+    scope(|s| {
+        let queue = s.message_queue(Key::new(3), IpcMode::owner_rw())?;
+        let receiver = {
+            let queue = queue.clone();
+            thread::spawn(move || queue.msgrcv(2, 64, MsgrcvFlag::empty()))
+        };
+        queue.msgsnd(1, b"first", MsgsndFlag::empty())?;
+        queue.msgsnd(2, b"second", MsgsndFlag::empty())?;
+
+        let expected = Message {
+            mtype: 2,
+            data: b"second".to_vec(),
+        };
+        assert_eq!(expected, receiver.join().unwrap()?);
+        assert_eq!(queue.stat()?.messages, 1);
+        let actual = queue
+            .msgrcv(0, 2, MsgrcvFlag::IPC_NOWAIT)
+            .expect_err("Message too long");
+        assert_eq!(Errno::E2BIG, actual);
+        Ok(())
+    })
+}